@@ -0,0 +1,103 @@
+//! Precision of the sums convolutions accumulate their samples in.
+//!
+//! A texel next to a bright sun sums thousands of samples spanning many orders
+//! of magnitude, and in f32 the dim ones stop contributing long before the end.
+
+use bevy::math::{DVec3, Vec3};
+
+/// Precision [`crate::prefilter`] sums its samples in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Accumulation {
+    /// Plain f32 sums, the fastest.
+    #[default]
+    F32,
+    /// f64 sums, rounded to f32 once at the end. Use with large sample counts
+    /// over high dynamic range environments.
+    F64,
+}
+
+/// Weighted sum of colors in the precision of an [`Accumulation`].
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum WeightedSum {
+    F32 { color: Vec3, weight: f32 },
+    F64 { color: DVec3, weight: f64 },
+}
+
+impl WeightedSum {
+    pub(crate) fn new(accumulation: Accumulation) -> Self {
+        match accumulation {
+            Accumulation::F32 => WeightedSum::F32 {
+                color: Vec3::ZERO,
+                weight: 0.0,
+            },
+            Accumulation::F64 => WeightedSum::F64 {
+                color: DVec3::ZERO,
+                weight: 0.0,
+            },
+        }
+    }
+
+    pub(crate) fn add(&mut self, color: Vec3, weight: f32) {
+        match self {
+            WeightedSum::F32 {
+                color: sum,
+                weight: weight_sum,
+            } => {
+                *sum += color * weight;
+                *weight_sum += weight;
+            }
+            WeightedSum::F64 {
+                color: sum,
+                weight: weight_sum,
+            } => {
+                *sum += color.as_dvec3() * weight as f64;
+                *weight_sum += weight as f64;
+            }
+        }
+    }
+
+    /// Adds the colors and weights of `other`, which must have the same precision.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        match (self, other) {
+            (
+                WeightedSum::F32 { color, weight },
+                WeightedSum::F32 {
+                    color: other_color,
+                    weight: other_weight,
+                },
+            ) => {
+                *color += *other_color;
+                *weight += other_weight;
+            }
+            (
+                WeightedSum::F64 { color, weight },
+                WeightedSum::F64 {
+                    color: other_color,
+                    weight: other_weight,
+                },
+            ) => {
+                *color += *other_color;
+                *weight += other_weight;
+            }
+            _ => panic!("Merging sums of different precision"),
+        }
+    }
+
+    /// Sum of the weighted colors.
+    pub(crate) fn total(&self) -> Vec3 {
+        match *self {
+            WeightedSum::F32 { color, .. } => color,
+            WeightedSum::F64 { color, .. } => color.as_vec3(),
+        }
+    }
+
+    /// Weighted average of the colors, `None` while the weights sum to zero.
+    pub(crate) fn average(&self) -> Option<Vec3> {
+        match *self {
+            WeightedSum::F32 { color, weight } => (weight > 0.0).then(|| color / weight),
+            WeightedSum::F64 { color, weight } => {
+                (weight > 0.0).then(|| (color / weight).as_vec3())
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use bevy::{prelude::Image, render::render_resource::TextureFormat};
+
+use crate::{
+    cubemap::{
+        rgba16f_bytes_to_rgba_f32, rgba_f32_to_rgba16f_bytes, texel_solid_angle, FACE_COUNT,
+    },
+    source::EnvmapSource,
+};
+
+/// Multiplies the color channels of a linear `Rgba16Float` image by
+/// `intensity * gain`, leaving alpha untouched.
+pub fn apply_gain(image: &Image, intensity: f32, gain: [f32; 3]) -> Image {
+    map_color(image, |rgb| {
+        std::array::from_fn(|c| rgb[c] * intensity * gain[c])
+    })
+}
+
+fn map_color(image: &Image, mut f: impl FnMut([f32; 3]) -> [f32; 3]) -> Image {
+    if image.texture_descriptor.format != TextureFormat::Rgba16Float {
+        panic!("Adjustments only supported for Rgba16Float images");
+    }
+
+    let texels = rgba16f_bytes_to_rgba_f32(&image.data)
+        .into_iter()
+        .map(|[r, g, b, a]| {
+            let [r, g, b] = f([r, g, b]);
+            [r, g, b, a]
+        })
+        .collect::<Vec<_>>();
+
+    let mut adjusted = image.clone();
+    adjusted.data = rgba_f32_to_rgba16f_bytes(&texels);
+    adjusted
+}
+
+/// Rec.709 relative luminance of linear RGB.
+#[inline]
+pub fn luminance(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+/// Statistic used to measure the brightness of an environment map.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LuminanceMeasure {
+    /// Brightest texel.
+    Peak,
+    /// Solid angle weighted geometric mean, a good estimate of perceived brightness.
+    LogAverage,
+    /// Luminance below which this percentage (0 to 100) of the sphere lies.
+    /// Unlike `Peak`, a small sun in frame barely moves the high percentiles.
+    Percentile(f32),
+}
+
+/// Luminance and solid angle of every texel in the top mip level of a linear cubemap.
+pub fn texel_luminances(source: &dyn EnvmapSource) -> Vec<(f32, f32)> {
+    let width = source.face_size();
+    let mut luminances = Vec::new();
+    for face in 0..FACE_COUNT {
+        let texels = source.face_texels(face, 0);
+        for (i, [r, g, b, _]) in texels.into_iter().enumerate() {
+            let (x, y) = (i as u32 % width, i as u32 / width);
+            luminances.push((luminance([r, g, b]), texel_solid_angle(x, y, width)));
+        }
+    }
+    luminances
+}
+
+/// Measures the luminance of the top mip level of a linear cubemap.
+pub fn measure_luminance(source: &dyn EnvmapSource, measure: LuminanceMeasure) -> f32 {
+    let luminances = texel_luminances(source);
+    match measure {
+        LuminanceMeasure::Peak => luminances.iter().map(|(l, _)| *l).fold(0.0, f32::max),
+        LuminanceMeasure::LogAverage => {
+            let (log_sum, weight) =
+                luminances
+                    .iter()
+                    .fold((0.0, 0.0), |(log_sum, weight), (l, solid_angle)| {
+                        (
+                            log_sum + l.max(1e-6).ln() * solid_angle,
+                            weight + solid_angle,
+                        )
+                    });
+            (log_sum / weight).exp()
+        }
+        LuminanceMeasure::Percentile(percentile) => {
+            let mut luminances = luminances;
+            luminances.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let total = luminances
+                .iter()
+                .map(|(_, solid_angle)| solid_angle)
+                .sum::<f32>();
+            let threshold = total * percentile.clamp(0.0, 100.0) / 100.0;
+            let mut covered = 0.0;
+            for (l, solid_angle) in &luminances {
+                covered += solid_angle;
+                if covered >= threshold {
+                    return *l;
+                }
+            }
+            luminances.last().map_or(0.0, |(l, _)| *l)
+        }
+    }
+}
+
+/// Scales a linear `Rgba16Float` cubemap so that its luminance, as measured by
+/// `measure`, equals `target`. Black images are returned unchanged.
+pub fn normalize_luminance(image: &Image, measure: LuminanceMeasure, target: f32) -> Image {
+    let current = measure_luminance(image, measure);
+    if current <= 0.0 {
+        return image.clone();
+    }
+    apply_gain(image, target / current, [1.0; 3])
+}
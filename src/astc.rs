@@ -0,0 +1,38 @@
+//! ASTC HDR decompression through ARM's astcenc, so environment maps shipped
+//! as ASTC HDR can be re-processed like BC6H ones.
+//!
+//! Needs the `astc` feature, which builds astcenc from source.
+
+/// Decompresses ASTC HDR blocks of `block_width`×`block_height` texels in
+/// row-major order into a `width`×`height` image of RGBA texels.
+pub fn decompress_astc_hdr(
+    blocks: &[u8],
+    width: u32,
+    height: u32,
+    block_width: u32,
+    block_height: u32,
+) -> std::io::Result<Vec<[f32; 4]>> {
+    use astcenc::{ConfigBuilder, Context, Extents, Profile, Swizzle};
+
+    let invalid = |e: astcenc::Error| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Decompressing ASTC failed: {e}"),
+        )
+    };
+    let config = ConfigBuilder::new()
+        .with_profile(Profile::Hdr)
+        .with_block_size(Extents::new(block_width, block_height))
+        .build()
+        .map_err(invalid)?;
+    let image = Context::new(config)
+        .map_err(invalid)?
+        .decompress::<f32>(blocks, Extents::new(width, height), Swizzle::rgba())
+        .map_err(invalid)?;
+    Ok(image
+        .data
+        .concat()
+        .chunks_exact(4)
+        .map(|t| [t[0], t[1], t[2], t[3]])
+        .collect())
+}
@@ -0,0 +1,33 @@
+// Largest finite values of the unsigned 11 and 10 bit floats, which share the
+// 5 bit exponent of half floats but drop the sign and some mantissa bits.
+const MAX_UF11: f32 = 65024.0;
+const MAX_UF10: f32 = 64512.0;
+
+/// Rounds a half float bit pattern of a non-negative value down to
+/// `mantissa_bits` mantissa bits.
+#[inline]
+fn half_bits_to_small_float(v: f32, max: f32, mantissa_bits: u32) -> u32 {
+    // Negative values and NaN are not representable and become 0.
+    let v = if v > 0.0 { v.min(max) } else { 0.0 };
+    let shift = 10 - mantissa_bits;
+    let bits = half::f16::from_f32(v).to_bits() as u32;
+    (bits + (1 << (shift - 1))) >> shift
+}
+
+/// Packs linear RGB into `B10G11R11_UFLOAT_PACK32`: 11 bit red and green and
+/// 10 bit blue floats without sign, 5 exponent bits each.
+#[inline]
+pub fn float3_to_b10g11r11(rgb: &[f32]) -> u32 {
+    let r = half_bits_to_small_float(rgb[0], MAX_UF11, 6);
+    let g = half_bits_to_small_float(rgb[1], MAX_UF11, 6);
+    let b = half_bits_to_small_float(rgb[2], MAX_UF10, 5);
+    r | (g << 11) | (b << 22)
+}
+
+#[inline]
+pub fn b10g11r11_to_float3(v: u32) -> [f32; 3] {
+    let r = half::f16::from_bits(((v & 0x7ff) << 4) as u16);
+    let g = half::f16::from_bits((((v >> 11) & 0x7ff) << 4) as u16);
+    let b = half::f16::from_bits((((v >> 22) & 0x3ff) << 5) as u16);
+    [r.to_f32(), g.to_f32(), b.to_f32()]
+}
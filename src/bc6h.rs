@@ -0,0 +1,604 @@
+//! A small BC6H (`BC6H_UFLOAT_BLOCK`) compressor, and a decoder for both BC6H
+//! formats so compressed inputs can be processed again.
+//!
+//! Every compressed block uses mode 11: one region with two unquantized 10 bit
+//! endpoints per channel and 16 interpolation steps. It doesn't search
+//! partitions or the transformed endpoint modes, so it's fast but loses some
+//! quality on blocks with more than one dominant color. The decoder reads all
+//! 14 modes.
+
+/// Largest half float bit pattern BC6H_UFLOAT can represent, 65504.
+const MAX_HALF_BITS: u32 = 0x7bff;
+
+const WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Compresses a `width`×`height` image of linear RGB texels, returning 16 bytes
+/// per 4×4 block, blocks in row-major order. Partial blocks at the right and
+/// bottom edges repeat the last column / row.
+pub fn compress_bc6h(texels: &[[f32; 4]], width: u32, height: u32) -> Vec<u8> {
+    let blocks_x = width.div_ceil(4);
+    let blocks_y = height.div_ceil(4);
+    let mut out = Vec::with_capacity((blocks_x * blocks_y * 16) as usize);
+    for block_y in 0..blocks_y {
+        for block_x in 0..blocks_x {
+            let block: [[u32; 3]; 16] = std::array::from_fn(|i| {
+                let x = (block_x * 4 + i as u32 % 4).min(width - 1);
+                let y = (block_y * 4 + i as u32 / 4).min(height - 1);
+                let [r, g, b, _] = texels[(y * width + x) as usize];
+                [r, g, b].map(half_bits)
+            });
+            out.extend_from_slice(&encode_block(&block));
+        }
+    }
+    out
+}
+
+/// Half float bit pattern of a non-negative value, which BC6H interpolates in.
+fn half_bits(v: f32) -> u32 {
+    let v = if v > 0.0 { v } else { 0.0 };
+    (half::f16::from_f32(v).to_bits() as u32).min(MAX_HALF_BITS)
+}
+
+/// Quantizes a half bit pattern to a 10 bit endpoint, the inverse of the
+/// decoder's unquantization followed by its `* 31 / 64` scale.
+fn quantize(h: u32) -> u32 {
+    // Unquantized endpoints sit at q * 64 + 32, so this rounds to the nearest.
+    let unquantized = (h * 64).div_ceil(31);
+    (unquantized / 64).min(1023)
+}
+
+/// Half bit pattern the decoder produces for a 10 bit endpoint.
+fn unquantize(q: u32) -> u32 {
+    let unquantized = match q {
+        0 => 0,
+        1023 => 0xffff,
+        q => ((q << 16) + 0x8000) >> 10,
+    };
+    unquantized * 31 / 64
+}
+
+fn encode_block(block: &[[u32; 3]; 16]) -> [u8; 16] {
+    // The bounding box diagonal as the endpoint line.
+    let mut min = [u32::MAX; 3];
+    let mut max = [0; 3];
+    for texel in block {
+        for c in 0..3 {
+            min[c] = min[c].min(texel[c]);
+            max[c] = max[c].max(texel[c]);
+        }
+    }
+    let mut e0 = min.map(quantize);
+    let mut e1 = max.map(quantize);
+
+    let palette = |e0: [u32; 3], e1: [u32; 3]| -> [[i64; 3]; 16] {
+        let (a, b) = (e0.map(unquantize), e1.map(unquantize));
+        std::array::from_fn(|i| {
+            let w = WEIGHTS[i];
+            std::array::from_fn(|c| (((64 - w) * a[c] + w * b[c] + 32) >> 6) as i64)
+        })
+    };
+    let colors = palette(e0, e1);
+    let mut indices = block.map(|texel| {
+        (0..16)
+            .min_by_key(|i| {
+                (0..3)
+                    .map(|c| (colors[*i][c] - texel[c] as i64).pow(2))
+                    .sum::<i64>()
+            })
+            .unwrap() as u32
+    });
+
+    // The anchor index is stored without its top bit, so it must be below 8.
+    if indices[0] >= 8 {
+        std::mem::swap(&mut e0, &mut e1);
+        indices = indices.map(|i| 15 - i);
+    }
+
+    let mut bits = BitWriter::default();
+    bits.write(0x03, 5); // Mode 11
+    for endpoint in [e0, e1] {
+        for channel in endpoint {
+            bits.write(channel, 10);
+        }
+    }
+    bits.write(indices[0], 3);
+    for index in &indices[1..] {
+        bits.write(*index, 4);
+    }
+    bits.0.to_le_bytes()
+}
+
+#[derive(Default)]
+struct BitWriter(u128, u32);
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bit_count: u32) {
+        self.0 |= ((value & ((1 << bit_count) - 1)) as u128) << self.1;
+        self.1 += bit_count;
+    }
+}
+
+#[derive(Default)]
+struct BitReader(u128, u32);
+
+impl BitReader {
+    fn read(&mut self, bit_count: u32) -> u32 {
+        let value = (self.0 >> self.1) as u32 & ((1 << bit_count) - 1);
+        self.1 += bit_count;
+        value
+    }
+}
+
+// Endpoint components, indices into `[r0, g0, b0, r1, g1, b1, r2, g2, b2, r3, g3, b3]`.
+const R0: u8 = 0;
+const G0: u8 = 1;
+const B0: u8 = 2;
+const R1: u8 = 3;
+const G1: u8 = 4;
+const B1: u8 = 5;
+const R2: u8 = 6;
+const G2: u8 = 7;
+const B2: u8 = 8;
+const R3: u8 = 9;
+const G3: u8 = 10;
+const B3: u8 = 11;
+
+struct Mode {
+    /// Bits of the first endpoint.
+    endpoint_bits: u32,
+    /// Bits of the other endpoints per channel, deltas from the first endpoint
+    /// when `transformed`.
+    delta_bits: [u32; 3],
+    transformed: bool,
+    two_regions: bool,
+    /// Endpoint bits following the mode bits, as `(component, a, b)` for
+    /// `component[a:b]` in the notation of the specification, i.e. stored from
+    /// bit `b` towards bit `a`.
+    layout: &'static [(u8, u8, u8)],
+}
+
+#[rustfmt::skip]
+const MODES: [Mode; 14] = [
+    Mode { endpoint_bits: 10, delta_bits: [5, 5, 5], transformed: true, two_regions: true, layout: &[
+        (G2, 4, 4), (B2, 4, 4), (B3, 4, 4), (R0, 9, 0), (G0, 9, 0), (B0, 9, 0), (R1, 4, 0),
+        (G3, 4, 4), (G2, 3, 0), (G1, 4, 0), (B3, 0, 0), (G3, 3, 0), (B1, 4, 0), (B3, 1, 1),
+        (B2, 3, 0), (R2, 4, 0), (B3, 2, 2), (R3, 4, 0), (B3, 3, 3),
+    ] },
+    Mode { endpoint_bits: 7, delta_bits: [6, 6, 6], transformed: true, two_regions: true, layout: &[
+        (G2, 5, 5), (G3, 4, 4), (G3, 5, 5), (R0, 6, 0), (B3, 0, 0), (B3, 1, 1), (B2, 4, 4),
+        (G0, 6, 0), (B2, 5, 5), (B3, 2, 2), (G2, 4, 4), (B0, 6, 0), (B3, 3, 3), (B3, 5, 5),
+        (B3, 4, 4), (R1, 5, 0), (G2, 3, 0), (G1, 5, 0), (G3, 3, 0), (B1, 5, 0), (B2, 3, 0),
+        (R2, 5, 0), (R3, 5, 0),
+    ] },
+    Mode { endpoint_bits: 11, delta_bits: [5, 4, 4], transformed: true, two_regions: true, layout: &[
+        (R0, 9, 0), (G0, 9, 0), (B0, 9, 0), (R1, 4, 0), (R0, 10, 10), (G2, 3, 0), (G1, 3, 0),
+        (G0, 10, 10), (B3, 0, 0), (G3, 3, 0), (B1, 3, 0), (B0, 10, 10), (B3, 1, 1), (B2, 3, 0),
+        (R2, 4, 0), (B3, 2, 2), (R3, 4, 0), (B3, 3, 3),
+    ] },
+    Mode { endpoint_bits: 11, delta_bits: [4, 5, 4], transformed: true, two_regions: true, layout: &[
+        (R0, 9, 0), (G0, 9, 0), (B0, 9, 0), (R1, 3, 0), (R0, 10, 10), (G3, 4, 4), (G2, 3, 0),
+        (G1, 4, 0), (G0, 10, 10), (G3, 3, 0), (B1, 3, 0), (B0, 10, 10), (B3, 1, 1), (B2, 3, 0),
+        (R2, 3, 0), (B3, 0, 0), (B3, 2, 2), (R3, 3, 0), (G2, 4, 4), (B3, 3, 3),
+    ] },
+    Mode { endpoint_bits: 11, delta_bits: [4, 4, 5], transformed: true, two_regions: true, layout: &[
+        (R0, 9, 0), (G0, 9, 0), (B0, 9, 0), (R1, 3, 0), (R0, 10, 10), (B2, 4, 4), (G2, 3, 0),
+        (G1, 3, 0), (G0, 10, 10), (B3, 0, 0), (G3, 3, 0), (B1, 4, 0), (B0, 10, 10), (B2, 3, 0),
+        (R2, 3, 0), (B3, 1, 1), (B3, 2, 2), (R3, 3, 0), (B3, 4, 4), (B3, 3, 3),
+    ] },
+    Mode { endpoint_bits: 9, delta_bits: [5, 5, 5], transformed: true, two_regions: true, layout: &[
+        (R0, 8, 0), (B2, 4, 4), (G0, 8, 0), (G2, 4, 4), (B0, 8, 0), (B3, 4, 4), (R1, 4, 0),
+        (G3, 4, 4), (G2, 3, 0), (G1, 4, 0), (B3, 0, 0), (G3, 3, 0), (B1, 4, 0), (B3, 1, 1),
+        (B2, 3, 0), (R2, 4, 0), (B3, 2, 2), (R3, 4, 0), (B3, 3, 3),
+    ] },
+    Mode { endpoint_bits: 8, delta_bits: [6, 5, 5], transformed: true, two_regions: true, layout: &[
+        (R0, 7, 0), (G3, 4, 4), (B2, 4, 4), (G0, 7, 0), (B3, 2, 2), (G2, 4, 4), (B0, 7, 0),
+        (B3, 3, 3), (B3, 4, 4), (R1, 5, 0), (G2, 3, 0), (G1, 4, 0), (B3, 0, 0), (G3, 3, 0),
+        (B1, 4, 0), (B3, 1, 1), (B2, 3, 0), (R2, 5, 0), (R3, 5, 0),
+    ] },
+    Mode { endpoint_bits: 8, delta_bits: [5, 6, 5], transformed: true, two_regions: true, layout: &[
+        (R0, 7, 0), (B3, 0, 0), (B2, 4, 4), (G0, 7, 0), (G2, 5, 5), (G2, 4, 4), (B0, 7, 0),
+        (G3, 5, 5), (B3, 4, 4), (R1, 4, 0), (G3, 4, 4), (G2, 3, 0), (G1, 5, 0), (G3, 3, 0),
+        (B1, 4, 0), (B3, 1, 1), (B2, 3, 0), (R2, 4, 0), (B3, 2, 2), (R3, 4, 0), (B3, 3, 3),
+    ] },
+    Mode { endpoint_bits: 8, delta_bits: [5, 5, 6], transformed: true, two_regions: true, layout: &[
+        (R0, 7, 0), (B3, 1, 1), (B2, 4, 4), (G0, 7, 0), (B2, 5, 5), (G2, 4, 4), (B0, 7, 0),
+        (B3, 5, 5), (B3, 4, 4), (R1, 4, 0), (G3, 4, 4), (G2, 3, 0), (G1, 4, 0), (B3, 0, 0),
+        (G3, 3, 0), (B1, 5, 0), (B2, 3, 0), (R2, 4, 0), (B3, 2, 2), (R3, 4, 0), (B3, 3, 3),
+    ] },
+    Mode { endpoint_bits: 6, delta_bits: [6, 6, 6], transformed: false, two_regions: true, layout: &[
+        (R0, 5, 0), (G3, 4, 4), (B3, 0, 0), (B3, 1, 1), (B2, 4, 4), (G0, 5, 0), (G2, 5, 5),
+        (B2, 5, 5), (B3, 2, 2), (G2, 4, 4), (B0, 5, 0), (G3, 5, 5), (B3, 3, 3), (B3, 5, 5),
+        (B3, 4, 4), (R1, 5, 0), (G2, 3, 0), (G1, 5, 0), (G3, 3, 0), (B1, 5, 0), (B2, 3, 0),
+        (R2, 5, 0), (R3, 5, 0),
+    ] },
+    Mode { endpoint_bits: 10, delta_bits: [10, 10, 10], transformed: false, two_regions: false, layout: &[
+        (R0, 9, 0), (G0, 9, 0), (B0, 9, 0), (R1, 9, 0), (G1, 9, 0), (B1, 9, 0),
+    ] },
+    Mode { endpoint_bits: 11, delta_bits: [9, 9, 9], transformed: true, two_regions: false, layout: &[
+        (R0, 9, 0), (G0, 9, 0), (B0, 9, 0), (R1, 8, 0), (R0, 10, 10), (G1, 8, 0), (G0, 10, 10),
+        (B1, 8, 0), (B0, 10, 10),
+    ] },
+    Mode { endpoint_bits: 12, delta_bits: [8, 8, 8], transformed: true, two_regions: false, layout: &[
+        (R0, 9, 0), (G0, 9, 0), (B0, 9, 0), (R1, 7, 0), (R0, 10, 11), (G1, 7, 0), (G0, 10, 11),
+        (B1, 7, 0), (B0, 10, 11),
+    ] },
+    Mode { endpoint_bits: 16, delta_bits: [4, 4, 4], transformed: true, two_regions: false, layout: &[
+        (R0, 9, 0), (G0, 9, 0), (B0, 9, 0), (R1, 3, 0), (R0, 10, 15), (G1, 3, 0), (G0, 10, 15),
+        (B1, 3, 0), (B0, 10, 15),
+    ] },
+];
+
+/// Texels in region 1 of each two-region partition, bit `y * 4 + x`.
+const PARTITIONS: [u16; 32] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c,
+];
+
+/// Texel storing the index of region 1 without its top bit, per partition.
+const REGION_1_ANCHORS: [usize; 32] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2,
+];
+
+const WEIGHTS_3: [i32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+
+/// Decompresses BC6H blocks in row-major order into a `width`×`height` image
+/// of RGBA texels with an alpha of 1. `signed` selects `BC6H_SFLOAT` over
+/// `BC6H_UFLOAT`. Reserved modes decode to black, like on GPUs.
+pub fn decompress_bc6h(blocks: &[u8], width: u32, height: u32, signed: bool) -> Vec<[f32; 4]> {
+    let blocks_x = width.div_ceil(4);
+    let mut texels = vec![[0.0, 0.0, 0.0, 1.0]; (width * height) as usize];
+    for (i, block) in blocks.chunks_exact(16).enumerate() {
+        let (block_x, block_y) = (i as u32 % blocks_x, i as u32 / blocks_x);
+        let colors = decode_block(block.try_into().unwrap(), signed);
+        for (j, [r, g, b]) in colors.into_iter().enumerate() {
+            let (x, y) = (block_x * 4 + j as u32 % 4, block_y * 4 + j as u32 / 4);
+            if x < width && y < height {
+                texels[(y * width + x) as usize] = [r, g, b, 1.0];
+            }
+        }
+    }
+    texels
+}
+
+fn sign_extend(value: i32, bit_count: u32) -> i32 {
+    let shift = 32 - bit_count;
+    (value << shift) >> shift
+}
+
+/// Scales an endpoint of `bit_count` bits to 16 bits for interpolation.
+fn unquantize_endpoint(value: i32, bit_count: u32, signed: bool) -> i32 {
+    if !signed {
+        return match value {
+            _ if bit_count >= 15 => value,
+            0 => 0,
+            _ if value == (1 << bit_count) - 1 => 0xffff,
+            _ => ((value << 16) + 0x8000) >> bit_count,
+        };
+    }
+    if bit_count >= 16 {
+        return value;
+    }
+    let magnitude = value.abs();
+    let unquantized = if magnitude == 0 {
+        0
+    } else if magnitude >= (1 << (bit_count - 1)) - 1 {
+        0x7fff
+    } else {
+        ((magnitude << 15) + 0x4000) >> (bit_count - 1)
+    };
+    unquantized * value.signum()
+}
+
+/// Half float of an interpolated 16 bit value.
+fn finish_unquantize(value: i32, signed: bool) -> f32 {
+    let bits = if !signed {
+        (value * 31) >> 6
+    } else if value < 0 {
+        0x8000 | ((-value * 31) >> 5)
+    } else {
+        (value * 31) >> 5
+    };
+    half::f16::from_bits(bits as u16).to_f32()
+}
+
+fn decode_block(block: [u8; 16], signed: bool) -> [[f32; 3]; 16] {
+    let mut bits = BitReader(u128::from_le_bytes(block), 0);
+    let mode_bits = match bits.read(2) {
+        short @ (0 | 1) => short,
+        low => low | bits.read(3) << 2,
+    };
+    let mode = match mode_bits {
+        0 => &MODES[0],
+        1 => &MODES[1],
+        2 => &MODES[2],
+        6 => &MODES[3],
+        10 => &MODES[4],
+        14 => &MODES[5],
+        18 => &MODES[6],
+        22 => &MODES[7],
+        26 => &MODES[8],
+        30 => &MODES[9],
+        3 => &MODES[10],
+        7 => &MODES[11],
+        11 => &MODES[12],
+        15 => &MODES[13],
+        _ => return [[0.0; 3]; 16],
+    };
+
+    let mut components = [0i32; 12];
+    for &(component, a, b) in mode.layout {
+        let (low, high) = (a.min(b), a.max(b));
+        for k in 0..=high - low {
+            let bit = if b <= a { low + k } else { high - k };
+            components[component as usize] |= (bits.read(1) as i32) << bit;
+        }
+    }
+
+    let endpoint_count = if mode.two_regions { 4 } else { 2 };
+    let endpoint_bits = mode.endpoint_bits;
+    for channel in 0..3 {
+        let first = components[channel];
+        let first = if signed {
+            sign_extend(first, endpoint_bits)
+        } else {
+            first
+        };
+        components[channel] = first;
+        for endpoint in 1..endpoint_count {
+            let component = &mut components[endpoint * 3 + channel];
+            if mode.transformed {
+                let delta = sign_extend(*component, mode.delta_bits[channel]);
+                *component = (first + delta) & ((1 << endpoint_bits) - 1);
+                if signed {
+                    *component = sign_extend(*component, endpoint_bits);
+                }
+            } else if signed {
+                *component = sign_extend(*component, endpoint_bits);
+            }
+        }
+    }
+    let endpoints = components.map(|c| unquantize_endpoint(c, endpoint_bits, signed));
+
+    let (partition, index_bits, anchors) = if mode.two_regions {
+        let partition = bits.read(5) as usize;
+        (partition, 3, [0, REGION_1_ANCHORS[partition]])
+    } else {
+        (0, 4, [0, 0])
+    };
+    std::array::from_fn(|texel| {
+        let region = if mode.two_regions {
+            (PARTITIONS[partition] >> texel) as usize & 1
+        } else {
+            0
+        };
+        let is_anchor = texel == anchors[0] || (mode.two_regions && texel == anchors[1]);
+        let index = bits.read(index_bits - is_anchor as u32) as usize;
+        let weight = if mode.two_regions {
+            WEIGHTS_3[index]
+        } else {
+            WEIGHTS[index] as i32
+        };
+        std::array::from_fn(|channel| {
+            let a = endpoints[region * 6 + channel];
+            let b = endpoints[region * 6 + 3 + channel];
+            finish_unquantize((a * (64 - weight) + b * weight + 32) >> 6, signed)
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `value[high:low]` as a block field.
+    fn bits(value: i32, high: u32, low: u32) -> (u32, u32) {
+        let bit_count = high - low + 1;
+        ((value as u32 >> low) & ((1 << bit_count) - 1), bit_count)
+    }
+
+    /// `value[low:high]`, which the specification stores from the high bit down.
+    fn reversed(value: i32, low: u32, high: u32) -> (u32, u32) {
+        let (field, bit_count) = bits(value, high, low);
+        (field.reverse_bits() >> (32 - bit_count), bit_count)
+    }
+
+    /// Packs fields from the least significant bit up, in the order the
+    /// specification lists them.
+    fn pack(fields: &[(u32, u32)]) -> [u8; 16] {
+        let mut bits = BitWriter::default();
+        for &(value, bit_count) in fields {
+            bits.write(value, bit_count);
+        }
+        assert_eq!(bits.1, 128);
+        bits.0.to_le_bytes()
+    }
+
+    /// Index fields of a one-region block: texel 0 at endpoint 0, the rest at
+    /// endpoint 1.
+    fn one_region_indices() -> Vec<(u32, u32)> {
+        let mut indices = vec![(0, 3)];
+        indices.extend([(15, 4); 15]);
+        indices
+    }
+
+    /// Index fields of a two-region block whose second region is anchored at
+    /// texel 15: the texels in `at_second_endpoint` at the second endpoint of
+    /// their region, the rest at the first.
+    fn two_region_indices(at_second_endpoint: &[usize]) -> Vec<(u32, u32)> {
+        (0..16)
+            .map(|texel| {
+                let index = if at_second_endpoint.contains(&texel) {
+                    7
+                } else {
+                    0
+                };
+                let bit_count = if texel == 0 || texel == 15 { 2 } else { 3 };
+                (index, bit_count)
+            })
+            .collect()
+    }
+
+    fn decode(block: [u8; 16], signed: bool) -> Vec<[f32; 4]> {
+        decompress_bc6h(&block, 4, 4, signed)
+    }
+
+    fn halves(bits: [u16; 3]) -> [f32; 4] {
+        let [r, g, b] = bits.map(|bits| half::f16::from_bits(bits).to_f32());
+        [r, g, b, 1.0]
+    }
+
+    #[test]
+    fn decodes_one_region_untransformed_mode() {
+        // Mode 11.
+        let mut fields = vec![(0b00011, 5)];
+        for component in [495, 400, 300, 700, 600, 100] {
+            fields.push(bits(component, 9, 0));
+        }
+        fields.extend(one_region_indices());
+        let texels = decode(pack(&fields), false);
+        assert_eq!(texels[0], halves([0x3c00, 0x307f, 0x2463]));
+        assert_eq!(texels[15], halves([0x54d3, 0x48b7, 0x0c2b]));
+
+        let mut fields = vec![(0b00011, 5)];
+        for component in [-495, 300, -1, 511, 300, -495] {
+            fields.push(bits(component, 9, 0));
+        }
+        fields.extend(one_region_indices());
+        let texels = decode(pack(&fields), true);
+        assert_eq!(texels[0], halves([0xf801, 0x48c7, 0x805d]));
+        assert_eq!(texels[15], halves([0x7bff, 0x48c7, 0xf801]));
+    }
+
+    #[test]
+    fn decodes_one_region_transformed_modes() {
+        // Mode 12: 11 bit endpoints and 9 bit deltas.
+        let [r0, g0, b0] = [1000, 1500, 2000];
+        let [r1, g1, b1] = [100, -200, 255];
+        let mut fields = vec![
+            (0b00111, 5),
+            bits(r0, 9, 0),
+            bits(g0, 9, 0),
+            bits(b0, 9, 0),
+            bits(r1, 8, 0),
+            bits(r0, 10, 10),
+            bits(g1, 8, 0),
+            bits(g0, 10, 10),
+            bits(b1, 8, 0),
+            bits(b0, 10, 10),
+        ];
+        fields.extend(one_region_indices());
+        let texels = decode(pack(&fields), false);
+        assert_eq!(texels[0], halves([0x3c93, 0x5ad9, 0x791f]));
+        assert_eq!(texels[15], halves([0x42a1, 0x4ebd, 0x0c90]));
+
+        // Mode 14: 16 bit endpoints with their top bits reversed, 4 bit deltas.
+        let [r0, g0, b0] = [0x7bde, 0x739c, 0x8421];
+        let [r1, g1, b1] = [7, -8, -1];
+        let mut fields = vec![
+            (0b01111, 5),
+            bits(r0, 9, 0),
+            bits(g0, 9, 0),
+            bits(b0, 9, 0),
+            bits(r1, 3, 0),
+            reversed(r0, 10, 15),
+            bits(g1, 3, 0),
+            reversed(g0, 10, 15),
+            bits(b1, 3, 0),
+            reversed(b0, 10, 15),
+        ];
+        fields.extend(one_region_indices());
+        let texels = decode(pack(&fields), false);
+        assert_eq!(texels[0], halves([0x3bff, 0x37ff, 0x3fff]));
+        assert_eq!(texels[15], halves([0x3c02, 0x37fb, 0x3fff]));
+    }
+
+    #[test]
+    fn decodes_two_region_transformed_mode() {
+        // Mode 1: 10 bit endpoints and 5 bit deltas, partition 0 putting the
+        // two right columns in region 1.
+        let [r0, g0, b0] = [600, 500, 400];
+        let [r1, g1, b1] = [15, -16, 3];
+        let [r2, g2, b2] = [-5, 10, -1];
+        let [r3, g3, b3] = [0, -1, 7];
+        let mut fields = vec![
+            (0b00, 2),
+            bits(g2, 4, 4),
+            bits(b2, 4, 4),
+            bits(b3, 4, 4),
+            bits(r0, 9, 0),
+            bits(g0, 9, 0),
+            bits(b0, 9, 0),
+            bits(r1, 4, 0),
+            bits(g3, 4, 4),
+            bits(g2, 3, 0),
+            bits(g1, 4, 0),
+            bits(b3, 0, 0),
+            bits(g3, 3, 0),
+            bits(b1, 4, 0),
+            bits(b3, 1, 1),
+            bits(b2, 3, 0),
+            bits(r2, 4, 0),
+            bits(b3, 2, 2),
+            bits(r3, 4, 0),
+            bits(b3, 3, 3),
+            (0, 5),
+        ];
+        fields.extend(two_region_indices(&[1, 2]));
+        let texels = decode(pack(&fields), false);
+        assert_eq!(texels[0], halves([0x48b7, 0x3c9b, 0x307f]));
+        assert_eq!(texels[1], halves([0x4a88, 0x3aab, 0x30dc]));
+        assert_eq!(texels[15], halves([0x481c, 0x3dd1, 0x3060]));
+        assert_eq!(texels[2], halves([0x48b7, 0x3c7c, 0x3158]));
+    }
+
+    #[test]
+    fn decodes_two_region_untransformed_mode() {
+        // Mode 10: four 6 bit endpoints, partition 13 putting the two bottom
+        // rows in region 1.
+        let [r0, g0, b0] = [10, 20, 30];
+        let [r1, g1, b1] = [63, 0, 40];
+        let [r2, g2, b2] = [1, 33, 62];
+        let [r3, g3, b3] = [5, 6, 7];
+        let mut fields = vec![
+            (0b11110, 5),
+            bits(r0, 5, 0),
+            bits(g3, 4, 4),
+            bits(b3, 0, 0),
+            bits(b3, 1, 1),
+            bits(b2, 4, 4),
+            bits(g0, 5, 0),
+            bits(g2, 5, 5),
+            bits(b2, 5, 5),
+            bits(b3, 2, 2),
+            bits(g2, 4, 4),
+            bits(b0, 5, 0),
+            bits(g3, 5, 5),
+            bits(b3, 3, 3),
+            bits(b3, 5, 5),
+            bits(b3, 4, 4),
+            bits(r1, 5, 0),
+            bits(g2, 3, 0),
+            bits(g1, 5, 0),
+            bits(g3, 3, 0),
+            bits(b1, 5, 0),
+            bits(b2, 3, 0),
+            bits(r2, 5, 0),
+            bits(r3, 5, 0),
+            (13, 5),
+        ];
+        fields.extend(two_region_indices(&[1, 8]));
+        let texels = decode(pack(&fields), false);
+        assert_eq!(texels[0], halves([0x1458, 0x27b8, 0x3b18]));
+        assert_eq!(texels[1], halves([0x7bff, 0x0000, 0x4e78]));
+        assert_eq!(texels[15], halves([0x02e8, 0x40e8, 0x7918]));
+        assert_eq!(texels[8], halves([0x0aa8, 0x0c98, 0x0e88]));
+    }
+
+    #[test]
+    fn reserved_modes_decode_to_black() {
+        let texels = decode(0b10011u128.to_le_bytes(), false);
+        assert!(texels.iter().all(|texel| *texel == [0.0, 0.0, 0.0, 1.0]));
+    }
+}
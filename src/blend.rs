@@ -0,0 +1,92 @@
+//! Compositing several environment maps into one, e.g. a captured ground
+//! hemisphere below a procedural sky, before feeding it to the pipeline.
+
+use bevy::{math::Vec3, prelude::Image};
+
+use crate::cubemap_data::CubemapData;
+
+/// Where an environment contributes to a blend.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum BlendMask {
+    /// Every direction.
+    #[default]
+    All,
+    /// Directions above `elevation` degrees, fading in over `softness`
+    /// degrees around it.
+    AboveHorizon { elevation: f32, softness: f32 },
+    /// Directions below `elevation` degrees, fading out over `softness`
+    /// degrees around it.
+    BelowHorizon { elevation: f32, softness: f32 },
+}
+
+impl BlendMask {
+    /// Coverage in `[0, 1]` of direction `dir`.
+    pub fn coverage(&self, dir: Vec3) -> f32 {
+        let above = |elevation: f32, softness: f32| {
+            let angle = dir.normalize().y.clamp(-1.0, 1.0).asin().to_degrees();
+            let half = softness.max(1e-3) / 2.0;
+            let t = ((angle - elevation + half) / (2.0 * half)).clamp(0.0, 1.0);
+            t * t * (3.0 - 2.0 * t)
+        };
+        match *self {
+            BlendMask::All => 1.0,
+            BlendMask::AboveHorizon {
+                elevation,
+                softness,
+            } => above(elevation, softness),
+            BlendMask::BelowHorizon {
+                elevation,
+                softness,
+            } => 1.0 - above(elevation, softness),
+        }
+    }
+}
+
+/// One environment of a blend.
+#[derive(Clone, Copy, Debug)]
+pub struct BlendLayer<'a> {
+    /// Linear `Rgba16Float` cubemap, its top level is read.
+    pub image: &'a Image,
+    /// Weight relative to the other layers.
+    pub weight: f32,
+    pub mask: BlendMask,
+}
+
+/// Blends `layers` into one linear `Rgba16Float` cubemap with a single mip
+/// level, as large as the largest input.
+///
+/// Every texel is the average of the layers weighted by `weight` times the
+/// coverage of their mask, so equal weights and no masks give the mean, and
+/// complementary horizon masks splice the hemispheres. Directions no layer
+/// covers are black. Inputs of other sizes are resampled bilinearly.
+pub fn blend_environments(layers: &[BlendLayer]) -> Image {
+    let sources = layers
+        .iter()
+        .map(|layer| CubemapData::from_image(layer.image))
+        .collect::<Vec<_>>();
+    let face_size = sources
+        .iter()
+        .map(CubemapData::face_size)
+        .max()
+        .expect("No environments to blend");
+
+    let mut blended = CubemapData::new(face_size, 1, 1);
+    blended.fill(|_, _, dir| {
+        let mut sum = Vec3::ZERO;
+        let mut weight_sum = 0.0;
+        for (layer, source) in layers.iter().zip(&sources) {
+            let weight = layer.weight * layer.mask.coverage(dir);
+            if weight > 0.0 {
+                sum += source.sample(dir) * weight;
+                weight_sum += weight;
+            }
+        }
+        let color = if weight_sum > 0.0 {
+            sum / weight_sum
+        } else {
+            Vec3::ZERO
+        };
+        color.extend(1.0).to_array()
+    });
+    blended.to_image()
+}
@@ -0,0 +1,313 @@
+//! Captures the current Bevy scene into an HDR cubemap.
+//!
+//! Add [`CubemapCapturePlugin`] and insert a [`CubemapCapture`] on an entity
+//! with a `GlobalTransform`. Six 90° HDR cameras render the scene around it,
+//! the faces are read back from the GPU and the finished cubemap is inserted
+//! as [`CapturedCubemap`] and announced with a [`CubemapCaptured`] event. The
+//! result is a linear `Rgba16Float` cubemap ready for
+//! [`crate::pipeline::encode`].
+
+use std::{
+    collections::HashMap,
+    f32::consts::FRAC_PI_2,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Mutex,
+    },
+};
+
+use bevy::{
+    core_pipeline::tonemapping::{DebandDither, Tonemapping},
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        renderer::{RenderDevice, RenderQueue},
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
+    },
+};
+
+use crate::{
+    cubemap::{new_cubemap_image, FACE_COUNT},
+    readback::readback_texture,
+};
+
+/// Frames the capture cameras render before their targets are read back, so
+/// newly spawned cameras and render targets are fully prepared.
+const READBACK_AFTER_FRAMES: u32 = 2;
+
+/// Bytes per `Rgba16Float` texel.
+const TEXEL_SIZE: usize = 8;
+
+/// Forward and up direction of the camera rendering each face, in the
+/// +X, -X, +Y, -Y, +Z, -Z order of [`crate::cubemap::face_uv_to_direction`].
+///
+/// Cubemap faces are mirrored compared to what a camera sees, so the rendered
+/// faces are flipped horizontally on readback.
+const FACE_CAMERAS: [(Vec3, Vec3); FACE_COUNT as usize] = [
+    (Vec3::X, Vec3::Y),
+    (Vec3::NEG_X, Vec3::Y),
+    (Vec3::Y, Vec3::NEG_Z),
+    (Vec3::NEG_Y, Vec3::Z),
+    (Vec3::Z, Vec3::Y),
+    (Vec3::NEG_Z, Vec3::Y),
+];
+
+/// Renders [`CubemapCapture`] requests into HDR cubemaps.
+pub struct CubemapCapturePlugin;
+
+impl Plugin for CubemapCapturePlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = mpsc::channel();
+        app.add_event::<CubemapCaptured>()
+            .insert_resource(CaptureReceiver(Mutex::new(receiver)))
+            .init_resource::<HiddenForCapture>()
+            .add_systems(
+                Update,
+                (finish_captures, advance_captures, start_captures).chain(),
+            );
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .insert_resource(CaptureSender(sender))
+            .init_resource::<PendingReadbacks>()
+            .add_systems(ExtractSchedule, extract_readbacks)
+            .add_systems(
+                Render,
+                readback_captures
+                    .after(RenderSet::Render)
+                    .before(RenderSet::Cleanup),
+            );
+    }
+}
+
+/// Requests a capture of the scene around this entity's `GlobalTransform`.
+/// Removed once the capture finished.
+#[derive(Component, Clone, Debug)]
+pub struct CubemapCapture {
+    /// Width and height of each face in texels.
+    pub face_size: u32,
+    /// Entities hidden while capturing, e.g. the player or the probe's own mesh.
+    pub exclude: Vec<Entity>,
+}
+
+impl Default for CubemapCapture {
+    fn default() -> Self {
+        Self {
+            face_size: 256,
+            exclude: Vec::new(),
+        }
+    }
+}
+
+/// Linear `Rgba16Float` cubemap captured for this entity.
+#[derive(Component, Clone, Debug)]
+pub struct CapturedCubemap(pub Handle<Image>);
+
+/// Sent when the capture requested by `entity` finished.
+#[derive(Event, Clone, Debug)]
+pub struct CubemapCaptured {
+    pub entity: Entity,
+    pub image: Handle<Image>,
+}
+
+/// Cameras and render targets of a running capture.
+#[derive(Component)]
+struct CaptureInProgress {
+    faces: [Handle<Image>; FACE_COUNT as usize],
+    cameras: [Entity; FACE_COUNT as usize],
+    face_size: u32,
+    frames: u32,
+    /// Excluded entities, released from [`HiddenForCapture`] afterwards.
+    hidden: Vec<Entity>,
+}
+
+/// Entities hidden by running captures, with their visibility before the first
+/// one and how many captures hide them. Overlapping captures share the count,
+/// so the original visibility is restored once the last one finishes.
+#[derive(Resource, Default)]
+struct HiddenForCapture(HashMap<Entity, (Visibility, u32)>);
+
+#[derive(Resource)]
+struct CaptureReceiver(Mutex<Receiver<(Entity, Image)>>);
+
+#[derive(Resource)]
+struct CaptureSender(Sender<(Entity, Image)>);
+
+/// Captures whose faces are read back after this frame rendered.
+#[derive(Resource, Default)]
+struct PendingReadbacks(Vec<(Entity, [Handle<Image>; FACE_COUNT as usize], u32)>);
+
+fn start_captures(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    captures: Query<(Entity, &CubemapCapture, &GlobalTransform), Without<CaptureInProgress>>,
+    mut visibilities: Query<&mut Visibility>,
+    mut hidden_for_capture: ResMut<HiddenForCapture>,
+) {
+    for (entity, capture, transform) in &captures {
+        let position = transform.translation();
+        let faces = FACE_CAMERAS.map(|_| images.add(face_target(capture.face_size)));
+        let mut cameras = [Entity::PLACEHOLDER; FACE_COUNT as usize];
+        for (face, (forward, up)) in FACE_CAMERAS.into_iter().enumerate() {
+            cameras[face] = commands
+                .spawn(Camera3dBundle {
+                    camera: Camera {
+                        target: RenderTarget::Image(faces[face].clone()),
+                        hdr: true,
+                        ..default()
+                    },
+                    projection: Projection::Perspective(PerspectiveProjection {
+                        fov: FRAC_PI_2,
+                        aspect_ratio: 1.0,
+                        ..default()
+                    }),
+                    transform: Transform::from_translation(position).looking_to(forward, up),
+                    tonemapping: Tonemapping::None,
+                    deband_dither: DebandDither::Disabled,
+                    ..default()
+                })
+                .id();
+        }
+
+        let hidden = capture
+            .exclude
+            .iter()
+            .filter_map(|&excluded| {
+                let mut visibility = visibilities.get_mut(excluded).ok()?;
+                let (_, count) = hidden_for_capture
+                    .0
+                    .entry(excluded)
+                    .or_insert((*visibility, 0));
+                *count += 1;
+                *visibility = Visibility::Hidden;
+                Some(excluded)
+            })
+            .collect();
+
+        commands.entity(entity).insert(CaptureInProgress {
+            faces,
+            cameras,
+            face_size: capture.face_size,
+            frames: 0,
+            hidden,
+        });
+    }
+}
+
+/// Linear HDR render target for one face.
+fn face_target(face_size: u32) -> Image {
+    let mut target = Image::new_fill(
+        Extent3d {
+            width: face_size,
+            height: face_size,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; TEXEL_SIZE],
+        TextureFormat::Rgba16Float,
+        RenderAssetUsages::default(),
+    );
+    target.texture_descriptor.usage =
+        TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT;
+    target
+}
+
+fn advance_captures(mut captures: Query<&mut CaptureInProgress>) {
+    for mut capture in &mut captures {
+        capture.frames += 1;
+    }
+}
+
+fn extract_readbacks(
+    mut pending: ResMut<PendingReadbacks>,
+    captures: Extract<Query<(Entity, &CaptureInProgress)>>,
+) {
+    for (entity, capture) in captures.iter() {
+        if capture.frames == READBACK_AFTER_FRAMES {
+            pending
+                .0
+                .push((entity, capture.faces.clone(), capture.face_size));
+        }
+    }
+}
+
+fn readback_captures(
+    mut pending: ResMut<PendingReadbacks>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    sender: Res<CaptureSender>,
+) {
+    // Captures whose targets are not prepared yet stay pending for the next frame
+    pending.0.retain(|(entity, faces, face_size)| {
+        let Some(textures) = faces
+            .iter()
+            .map(|face| gpu_images.get(face).map(|gpu_image| &gpu_image.texture))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return true;
+        };
+
+        let row_length = *face_size as usize * TEXEL_SIZE;
+        let mut data = Vec::with_capacity(row_length * *face_size as usize * textures.len());
+        for texture in textures {
+            let face = readback_texture(&render_device, &render_queue, texture);
+            for row in face.data.chunks_exact(row_length) {
+                data.extend(row.chunks_exact(TEXEL_SIZE).rev().flatten());
+            }
+        }
+
+        sender
+            .0
+            .send((*entity, new_cubemap_image(*face_size, 1, data)))
+            .ok();
+        false
+    });
+}
+
+fn finish_captures(
+    mut commands: Commands,
+    receiver: Res<CaptureReceiver>,
+    mut images: ResMut<Assets<Image>>,
+    captures: Query<&CaptureInProgress>,
+    mut visibilities: Query<&mut Visibility>,
+    mut hidden_for_capture: ResMut<HiddenForCapture>,
+    mut captured: EventWriter<CubemapCaptured>,
+) {
+    let receiver = receiver.0.lock().unwrap();
+    for (entity, image) in receiver.try_iter() {
+        let Ok(capture) = captures.get(entity) else {
+            continue;
+        };
+        for camera in capture.cameras {
+            commands.entity(camera).despawn();
+        }
+        for face in &capture.faces {
+            images.remove(face);
+        }
+        for excluded in &capture.hidden {
+            let Some((previous, count)) = hidden_for_capture.0.get_mut(excluded) else {
+                continue;
+            };
+            *count -= 1;
+            if *count > 0 {
+                continue;
+            }
+            if let Ok(mut visibility) = visibilities.get_mut(*excluded) {
+                *visibility = *previous;
+            }
+            hidden_for_capture.0.remove(excluded);
+        }
+
+        let image = images.add(image);
+        commands
+            .entity(entity)
+            .remove::<(CubemapCapture, CaptureInProgress)>()
+            .insert(CapturedCubemap(image.clone()));
+        captured.send(CubemapCaptured { entity, image });
+    }
+}
@@ -0,0 +1,307 @@
+use std::{
+    io::{Error, ErrorKind},
+    str::FromStr,
+};
+
+use bevy::{
+    prelude::Image,
+    render::render_resource::{AstcChannel, TextureFormat},
+};
+
+#[cfg(feature = "astc")]
+use crate::astc::decompress_astc_hdr;
+use crate::{
+    b10g11r11::b10g11r11_to_float3,
+    bc6h::decompress_bc6h,
+    cubemap::{rgba16f_bytes_to_rgba_f32, rgba_f32_to_rgba16f_bytes},
+    rgb9e5::rgb9e5_to_float3,
+    source::mip_size,
+};
+
+/// Transfer function the color channels of an input image are encoded with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub enum TransferFunction {
+    Linear,
+    /// The piecewise sRGB curve.
+    Srgb,
+    /// A pure power curve, `encoded = linear^(1 / gamma)`.
+    Gamma(f32),
+}
+
+impl TransferFunction {
+    /// Transfer function implied by the format label, sRGB for `*Srgb`
+    /// formats and linear for everything else.
+    pub fn from_format(format: TextureFormat) -> Self {
+        if format.is_srgb() {
+            TransferFunction::Srgb
+        } else {
+            TransferFunction::Linear
+        }
+    }
+
+    #[inline]
+    pub fn to_linear(self, v: f32) -> f32 {
+        match self {
+            TransferFunction::Linear => v,
+            TransferFunction::Srgb => {
+                if v <= 0.04045 {
+                    v / 12.92
+                } else {
+                    ((v + 0.055) / 1.055).powf(2.4)
+                }
+            }
+            TransferFunction::Gamma(gamma) => v.max(0.0).powf(gamma),
+        }
+    }
+
+    /// Encodes a linear value, the inverse of [`TransferFunction::to_linear`].
+    #[inline]
+    pub fn from_linear(self, v: f32) -> f32 {
+        match self {
+            TransferFunction::Linear => v,
+            TransferFunction::Srgb => {
+                if v <= 0.003_130_8 {
+                    v * 12.92
+                } else {
+                    1.055 * v.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            TransferFunction::Gamma(gamma) => v.max(0.0).powf(1.0 / gamma),
+        }
+    }
+}
+
+impl FromStr for TransferFunction {
+    type Err = String;
+
+    /// Parses `linear`, `srgb`, or a gamma exponent such as `2.2` or `gamma2.2`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "linear" => Ok(TransferFunction::Linear),
+            "srgb" => Ok(TransferFunction::Srgb),
+            other => other
+                .trim_start_matches("gamma")
+                .parse::<f32>()
+                .ok()
+                .filter(|gamma| *gamma > 0.0)
+                .map(TransferFunction::Gamma)
+                .ok_or_else(|| format!("expected `linear`, `srgb` or a gamma exponent, got `{s}`")),
+        }
+    }
+}
+
+/// Reads every texel of an image as RGBA floats, in storage order.
+/// The color channels are returned as stored, without decoding any transfer function.
+/// BC6H and, with the `astc` feature, ASTC HDR images are decompressed, in the
+/// order of the same image uncompressed.
+/// Fails with `ErrorKind::Unsupported` on formats it can't read.
+pub fn read_texels(image: &Image) -> std::io::Result<Vec<[f32; 4]>> {
+    if image.is_compressed() {
+        return decompress_texels(image);
+    }
+    decode_texels(image.texture_descriptor.format, &image.data)
+}
+
+/// Decompresses every mip level of every layer of a block-compressed image.
+fn decompress_texels(image: &Image) -> std::io::Result<Vec<[f32; 4]>> {
+    let descriptor = &image.texture_descriptor;
+    let format = descriptor.format;
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap();
+    let mut texels = Vec::new();
+    let mut offset = 0;
+    for _ in 0..descriptor.size.depth_or_array_layers {
+        for mip_level in 0..descriptor.mip_level_count {
+            let width = mip_size(descriptor.size.width, mip_level);
+            let height = mip_size(descriptor.size.height, mip_level);
+            let bytes =
+                (width.div_ceil(block_width) * height.div_ceil(block_height) * block_size) as usize;
+            let blocks = image.data.get(offset..offset + bytes).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{format:?} image data is truncated"),
+                )
+            })?;
+            texels.extend(decompress_level(format, blocks, width, height)?);
+            offset += bytes;
+        }
+    }
+    Ok(texels)
+}
+
+/// Decompresses the blocks of a single mip level of one layer.
+fn decompress_level(
+    format: TextureFormat,
+    blocks: &[u8],
+    width: u32,
+    height: u32,
+) -> std::io::Result<Vec<[f32; 4]>> {
+    match format {
+        TextureFormat::Bc6hRgbUfloat => Ok(decompress_bc6h(blocks, width, height, false)),
+        TextureFormat::Bc6hRgbFloat => Ok(decompress_bc6h(blocks, width, height, true)),
+        #[cfg(feature = "astc")]
+        TextureFormat::Astc {
+            channel: AstcChannel::Hdr,
+            ..
+        } => {
+            let (block_width, block_height) = format.block_dimensions();
+            decompress_astc_hdr(blocks, width, height, block_width, block_height)
+        }
+        #[cfg(not(feature = "astc"))]
+        TextureFormat::Astc {
+            channel: AstcChannel::Hdr,
+            ..
+        } => Err(Error::new(
+            ErrorKind::Unsupported,
+            "Decompressing ASTC HDR images needs the `astc` feature",
+        )),
+        format => Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Decompressing {format:?} images is not supported"),
+        )),
+    }
+}
+
+/// Decodes uncompressed texel bytes of `format` into RGBA floats, see [`read_texels`].
+pub fn decode_texels(format: TextureFormat, data: &[u8]) -> std::io::Result<Vec<[f32; 4]>> {
+    Ok(match format {
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => data
+            .chunks_exact(4)
+            .map(|t| [t[0], t[1], t[2], t[3]].map(|c| c as f32 / 255.0))
+            .collect(),
+        TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => data
+            .chunks_exact(4)
+            .map(|t| [t[2], t[1], t[0], t[3]].map(|c| c as f32 / 255.0))
+            .collect(),
+        TextureFormat::Rgba16Float => rgba16f_bytes_to_rgba_f32(data),
+        TextureFormat::Rgba32Float => data
+            .chunks_exact(16)
+            .map(|t| {
+                std::array::from_fn(|c| {
+                    f32::from_le_bytes([t[c * 4], t[c * 4 + 1], t[c * 4 + 2], t[c * 4 + 3]])
+                })
+            })
+            .collect(),
+        TextureFormat::Rgb9e5Ufloat => data
+            .chunks_exact(4)
+            .map(|t| {
+                let [r, g, b] = rgb9e5_to_float3(u32::from_le_bytes([t[0], t[1], t[2], t[3]]));
+                [r, g, b, 1.0]
+            })
+            .collect(),
+        TextureFormat::Rg11b10Float => data
+            .chunks_exact(4)
+            .map(|t| {
+                let [r, g, b] = b10g11r11_to_float3(u32::from_le_bytes([t[0], t[1], t[2], t[3]]));
+                [r, g, b, 1.0]
+            })
+            .collect(),
+        format => {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!("Reading {format:?} images is not supported"),
+            ))
+        }
+    })
+}
+
+/// Converts an uncompressed or compressed image to linear `Rgba16Float`, decoding the color
+/// channels with `transfer` (or the transfer function implied by the format when
+/// `None`). Alpha is always linear. The texel layout is unchanged. Fails on
+/// formats [`read_texels`] can't read.
+pub fn linearize(image: &Image, transfer: Option<TransferFunction>) -> std::io::Result<Image> {
+    let transfer =
+        transfer.unwrap_or_else(|| TransferFunction::from_format(image.texture_descriptor.format));
+
+    let texels = read_texels(image)?
+        .into_iter()
+        .map(|[r, g, b, a]| {
+            [
+                transfer.to_linear(r),
+                transfer.to_linear(g),
+                transfer.to_linear(b),
+                a,
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    let mut linear = image.clone();
+    linear.data = rgba_f32_to_rgba16f_bytes(&texels);
+    linear.texture_descriptor.format = TextureFormat::Rgba16Float;
+    Ok(linear)
+}
+
+/// Color primaries of linear RGB data. All primaries use the D65 white point
+/// except ACEScg, which uses D60.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ColorPrimaries {
+    /// Rec.709 / sRGB, what Bevy renders in.
+    #[default]
+    Rec709,
+    Rec2020,
+    /// ACES AP1.
+    AcesCg,
+    DisplayP3,
+}
+
+impl ColorPrimaries {
+    /// `KHR_DF_PRIMARIES_*` value for the Data-Format Descriptor.
+    pub fn dfd_value(self) -> u8 {
+        match self {
+            ColorPrimaries::Rec709 => 1,
+            ColorPrimaries::Rec2020 => 4,
+            // KHR_DF_PRIMARIES_ACESCC, the AP1 primaries shared by ACEScc and ACEScg.
+            ColorPrimaries::AcesCg => 7,
+            ColorPrimaries::DisplayP3 => 10,
+        }
+    }
+
+    /// Row-major matrix converting linear RGB in these primaries to linear
+    /// Rec.709, with Bradford chromatic adaptation where the white points differ.
+    pub fn to_rec709_matrix(self) -> [[f32; 3]; 3] {
+        match self {
+            ColorPrimaries::Rec709 => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            ColorPrimaries::Rec2020 => [
+                [1.660491, -0.587641, -0.072850],
+                [-0.124550, 1.1329, -0.008349],
+                [-0.018151, -0.100579, 1.11873],
+            ],
+            ColorPrimaries::AcesCg => [
+                [1.705051, -0.621792, -0.083259],
+                [-0.130256, 1.140805, -0.010548],
+                [-0.024003, -0.128969, 1.152972],
+            ],
+            ColorPrimaries::DisplayP3 => [
+                [1.22494, -0.22494, 0.0],
+                [-0.042057, 1.042057, 0.0],
+                [-0.019638, -0.078636, 1.098274],
+            ],
+        }
+    }
+}
+
+/// Converts a linear `Rgba16Float` image from `primaries` to Rec.709.
+/// Out of gamut colors produce negative channels, which are clamped to zero.
+pub fn convert_primaries_to_rec709(image: &Image, primaries: ColorPrimaries) -> Image {
+    if image.texture_descriptor.format != TextureFormat::Rgba16Float {
+        panic!("Primaries conversion only supported for Rgba16Float images");
+    }
+    if primaries == ColorPrimaries::Rec709 {
+        return image.clone();
+    }
+
+    let m = primaries.to_rec709_matrix();
+    let texels = rgba16f_bytes_to_rgba_f32(&image.data)
+        .into_iter()
+        .map(|[r, g, b, a]| {
+            let row = |i: usize| (m[i][0] * r + m[i][1] * g + m[i][2] * b).max(0.0);
+            [row(0), row(1), row(2), a]
+        })
+        .collect::<Vec<_>>();
+
+    let mut converted = image.clone();
+    converted.data = rgba_f32_to_rgba16f_bytes(&texels);
+    converted
+}
@@ -0,0 +1,201 @@
+//! Conformance of [`prefilter_specular`] against a brute-force reference.
+//!
+//! The reference convolves every source texel for every output texel, which
+//! is only affordable at low resolution but has no sampling noise or filtered
+//! importance sampling bias. Run [`check_prefilter`] after changing sample
+//! counts or the roughness mapping to see what it costs in quality.
+
+use std::fmt;
+
+use bevy::{math::Vec3, prelude::Image};
+
+use crate::{
+    accumulate::{Accumulation, WeightedSum},
+    cubemap::{cubemap_from_fn, sample_bilinear, texel_direction, texel_solid_angle, FACE_COUNT},
+    cubemap_data::CubemapData,
+    diff::diff_cubemaps,
+    mips::limit_face_size,
+    prefilter::{d_ggx, prefilter_specular, PrefilterSettings},
+    source::{source_to_image, EnvmapSource},
+};
+
+/// Face size the comparison runs at by default.
+pub const DEFAULT_CONFORMANCE_FACE_SIZE: u32 = 16;
+
+/// Prefilters `source` like [`prefilter_specular`] by integrating over every
+/// texel of its top level, weighting each with the GGX lobe and `n·l`.
+pub fn reference_prefilter(source: &dyn EnvmapSource, settings: &PrefilterSettings) -> Image {
+    let face_size = source.face_size();
+    let full_chain = face_size.max(1).ilog2() + 1;
+    let mip_level_count = settings
+        .mip_level_count
+        .unwrap_or(full_chain)
+        .clamp(1, full_chain);
+
+    // Direction, radiance and solid angle of every source texel.
+    let top_level = (0..FACE_COUNT)
+        .map(|face| source.face_texels(face, 0))
+        .collect::<Vec<_>>();
+    let mut texels = Vec::with_capacity((FACE_COUNT * face_size * face_size) as usize);
+    for (face, face_texels) in (0..FACE_COUNT).zip(&top_level) {
+        for (i, &[r, g, b, _]) in face_texels.iter().enumerate() {
+            let (x, y) = (i as u32 % face_size, i as u32 / face_size);
+            texels.push((
+                texel_direction(face, x, y, face_size),
+                Vec3::new(r, g, b),
+                texel_solid_angle(x, y, face_size),
+            ));
+        }
+    }
+
+    let mut reference = CubemapData::new(face_size, mip_level_count, 1);
+    reference.fill(|_, mip_level, n| {
+        let roughness = settings
+            .roughness_mapping
+            .roughness(mip_level, mip_level_count);
+        if roughness <= 0.0 {
+            return sample_bilinear(&top_level, face_size, n);
+        }
+        let alpha = (roughness * roughness).max(1e-3);
+        let c = convolve(&texels, n, alpha);
+        [c.x, c.y, c.z, 1.0]
+    });
+    reference.to_image()
+}
+
+/// The quantity the importance sampled prefilter estimates: radiance weighted
+/// by `n·l` and the pdf of sampling `l`, `D(h) / 4` when `n = v`.
+fn convolve(texels: &[(Vec3, Vec3, f32)], n: Vec3, alpha: f32) -> Vec3 {
+    // Always in f64, the reference must not drift itself.
+    let mut sum = WeightedSum::new(Accumulation::F64);
+    for &(l, radiance, solid_angle) in texels {
+        let n_dot_l = n.dot(l);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+        let h = (n + l).normalize();
+        sum.add(radiance, d_ggx(n.dot(h), alpha) * n_dot_l * solid_angle);
+    }
+    sum.average().unwrap_or(Vec3::ZERO)
+}
+
+/// Error of one prefiltered mip level against the reference, in stops of
+/// luminance as measured by [`crate::diff`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelConformance {
+    pub mip_level: u32,
+    pub roughness: f32,
+    pub mean_error: f32,
+    pub max_error: f32,
+}
+
+/// Error statistics of every mip level.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConformanceReport {
+    pub face_size: u32,
+    pub levels: Vec<LevelConformance>,
+}
+
+impl ConformanceReport {
+    /// Largest mean error of any level.
+    pub fn max_mean_error(&self) -> f32 {
+        self.levels
+            .iter()
+            .map(|level| level.mean_error)
+            .fold(0.0, f32::max)
+    }
+
+    /// Largest per-texel error of any level.
+    pub fn max_error(&self) -> f32 {
+        self.levels
+            .iter()
+            .map(|level| level.max_error)
+            .fold(0.0, f32::max)
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "face size: {}", self.face_size)?;
+        for level in &self.levels {
+            writeln!(
+                f,
+                "  {} (roughness {:.3}): mean {:.4}, max {:.4} stops",
+                level.mip_level, level.roughness, level.mean_error, level.max_error
+            )?;
+        }
+        write!(
+            f,
+            "worst level: mean {:.4}, max {:.4} stops",
+            self.max_mean_error(),
+            self.max_error()
+        )
+    }
+}
+
+/// Prefilters `source` downsampled to `face_size` with `settings`, and compares
+/// every level against [`reference_prefilter`].
+pub fn check_prefilter(
+    source: &dyn EnvmapSource,
+    face_size: u32,
+    settings: &PrefilterSettings,
+) -> ConformanceReport {
+    let source = limit_face_size(&source_to_image(source), face_size);
+    let prefiltered = prefilter_specular(&source, settings);
+    let reference = reference_prefilter(&source, settings);
+
+    let mip_level_count = prefiltered.texture_descriptor.mip_level_count;
+    let levels = (0..mip_level_count)
+        .map(|mip_level| {
+            let diff = diff_cubemaps(&reference, &prefiltered, mip_level)
+                .expect("The reference has the size and mip levels of the prefiltered cubemap");
+            LevelConformance {
+                mip_level,
+                roughness: settings
+                    .roughness_mapping
+                    .roughness(mip_level, mip_level_count),
+                mean_error: diff.mean_error(),
+                max_error: diff.max_error(),
+            }
+        })
+        .collect();
+    ConformanceReport {
+        face_size: source.texture_descriptor.size.width,
+        levels,
+    }
+}
+
+/// Procedural environment with a bright sun, a sky gradient and a darker
+/// ground, for running [`check_prefilter`] without an input file.
+pub fn test_environment(face_size: u32) -> Image {
+    let sun = Vec3::new(0.3, 0.6, -0.74).normalize();
+    cubemap_from_fn(face_size, |dir| {
+        let sky = if dir.y >= 0.0 {
+            Vec3::new(0.3, 0.5, 1.0).lerp(Vec3::new(0.8, 0.9, 1.0), 1.0 - dir.y)
+        } else {
+            Vec3::new(0.2, 0.15, 0.1)
+        };
+        let c = if dir.dot(sun) > 0.97 {
+            sky + Vec3::splat(50.0)
+        } else {
+            sky
+        };
+        [c.x, c.y, c.z, 1.0]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefilter_conforms_to_reference() {
+        let report = check_prefilter(
+            &test_environment(DEFAULT_CONFORMANCE_FACE_SIZE),
+            DEFAULT_CONFORMANCE_FACE_SIZE,
+            &PrefilterSettings::default(),
+        );
+        assert_eq!(report.levels.len(), 5);
+        assert!(report.max_mean_error() < 0.25, "{report}");
+    }
+}
@@ -0,0 +1,292 @@
+use bevy::{
+    math::Vec3,
+    prelude::Image,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+            TextureViewDescriptor, TextureViewDimension,
+        },
+        texture::ImageSampler,
+    },
+};
+
+use crate::{cubemap_data::CubemapData, mip_level_byte_range};
+
+/// Number of faces in a cubemap. Faces are stored in the KTX2 / wgpu order
+/// +X, -X, +Y, -Y, +Z, -Z.
+pub const FACE_COUNT: u32 = 6;
+
+/// Short names of the faces in face order, as used in file names and metadata.
+pub const FACE_NAMES: [&str; 6] = ["px", "nx", "py", "ny", "pz", "nz"];
+
+/// Face mask with the bits of all six faces set, see [`face_bit`].
+pub const ALL_FACES: u8 = 0b11_1111;
+
+/// Bit of `face` in a face mask, e.g. `ALL_FACES & !face_bit(3)` for every
+/// face but the bottom one. Matches the `KTXcubemapIncomplete` bit order.
+pub const fn face_bit(face: u32) -> u8 {
+    1 << face
+}
+
+/// Direction through the point `(u, v)` of `face`, with `u` and `v` in `[-1, 1]`.
+///
+/// `v` increases downwards, matching the row order texels are stored in.
+/// Follows the Vulkan / OpenGL cubemap face selection table.
+pub fn face_uv_to_direction(face: u32, u: f32, v: f32) -> Vec3 {
+    let dir = match face {
+        0 => Vec3::new(1.0, -v, -u),
+        1 => Vec3::new(-1.0, -v, u),
+        2 => Vec3::new(u, 1.0, v),
+        3 => Vec3::new(u, -1.0, -v),
+        4 => Vec3::new(u, -v, 1.0),
+        5 => Vec3::new(-u, -v, -1.0),
+        _ => panic!("Cubemap face {face} requested, but only {FACE_COUNT} exist."),
+    };
+    dir.normalize()
+}
+
+/// Face and `(u, v)` in `[-1, 1]` that `dir` points at, the inverse of
+/// [`face_uv_to_direction`].
+pub fn direction_to_face_uv(dir: Vec3) -> (u32, f32, f32) {
+    let a = dir.abs();
+    if a.x >= a.y && a.x >= a.z {
+        if dir.x > 0.0 {
+            (0, -dir.z / a.x, -dir.y / a.x)
+        } else {
+            (1, dir.z / a.x, -dir.y / a.x)
+        }
+    } else if a.y >= a.z {
+        if dir.y > 0.0 {
+            (2, dir.x / a.y, dir.z / a.y)
+        } else {
+            (3, dir.x / a.y, -dir.z / a.y)
+        }
+    } else if dir.z > 0.0 {
+        (4, dir.x / a.z, -dir.y / a.z)
+    } else {
+        (5, -dir.x / a.z, -dir.y / a.z)
+    }
+}
+
+/// Direction through the center of texel `(x, y)` on a face of `face_size` texels.
+pub fn texel_direction(face: u32, x: u32, y: u32, face_size: u32) -> Vec3 {
+    let u = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+    let v = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+    face_uv_to_direction(face, u, v)
+}
+
+/// Bilinear lookup in the face `dir` points at. Taps past the face edges are
+/// read from the adjacent faces, see [`texel_across_edges`], so there are no
+/// seams along the edges. `faces` holds the texels of one mip level of each
+/// face, `face_size` wide.
+pub fn sample_bilinear<F: AsRef<[[f32; 4]]>>(faces: &[F], face_size: u32, dir: Vec3) -> [f32; 4] {
+    let (face, u, v) = direction_to_face_uv(dir);
+    let x = (u * 0.5 + 0.5) * face_size as f32 - 0.5;
+    let y = (v * 0.5 + 0.5) * face_size as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i32, y0 as i32);
+
+    let at = |x: i32, y: i32| texel_across_edges(faces, face_size, face, x, y);
+    let lerp = |a: [f32; 4], b: [f32; 4], t: f32| -> [f32; 4] {
+        std::array::from_fn(|c| a[c] + (b[c] - a[c]) * t)
+    };
+    let top = lerp(at(x0, y0), at(x0 + 1, y0), fx);
+    let bottom = lerp(at(x0, y0 + 1), at(x0 + 1, y0 + 1), fx);
+    lerp(top, bottom, fy)
+}
+
+/// Texel `(x, y)` of `face`, where coordinates one past the edges continue
+/// into the adjacent face: the texel of that face nearest to where the grid of
+/// `face` would place it. Past a corner, where three faces meet, one of the two
+/// neighbours is read.
+pub fn texel_across_edges<F: AsRef<[[f32; 4]]>>(
+    faces: &[F],
+    face_size: u32,
+    face: u32,
+    x: i32,
+    y: i32,
+) -> [f32; 4] {
+    let size = face_size as i32;
+    if (0..size).contains(&x) && (0..size).contains(&y) {
+        return faces[face as usize].as_ref()[(y * size + x) as usize];
+    }
+
+    let u = 2.0 * (x as f32 + 0.5) / face_size as f32 - 1.0;
+    let v = 2.0 * (y as f32 + 0.5) / face_size as f32 - 1.0;
+    let (neighbor, u, v) = direction_to_face_uv(face_uv_to_direction(face, u, v));
+    let to_texel = |c: f32| (((c * 0.5 + 0.5) * face_size as f32) as u32).min(face_size - 1);
+    faces[neighbor as usize].as_ref()[(to_texel(v) * face_size + to_texel(u)) as usize]
+}
+
+/// Packs linear RGBA texels into `Rgba16Float` bytes.
+pub fn rgba_f32_to_rgba16f_bytes(texels: &[[f32; 4]]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(texels.len() * 8);
+    for texel in texels {
+        for c in texel {
+            bytes.extend_from_slice(&half::f16::from_f32(*c).to_le_bytes());
+        }
+    }
+    bytes
+}
+
+/// Solid angle subtended by texel `(x, y)` on a face of `face_size` texels.
+pub fn texel_solid_angle(x: u32, y: u32, face_size: u32) -> f32 {
+    // Integral of the projected area element from the face center, see
+    // http://www.rorydriscoll.com/2012/01/15/cubemap-texel-solid-angle/
+    fn area_element(x: f32, y: f32) -> f32 {
+        (x * y).atan2((x * x + y * y + 1.0).sqrt())
+    }
+
+    let texel = 2.0 / face_size as f32;
+    let u0 = x as f32 * texel - 1.0;
+    let v0 = y as f32 * texel - 1.0;
+    let (u1, v1) = (u0 + texel, v0 + texel);
+    area_element(u1, v1) - area_element(u0, v1) - area_element(u1, v0) + area_element(u0, v0)
+}
+
+/// Unpacks `Rgba16Float` bytes into linear RGBA texels.
+pub fn rgba16f_bytes_to_rgba_f32(bytes: &[u8]) -> Vec<[f32; 4]> {
+    bytes
+        .chunks_exact(8)
+        .map(|texel| {
+            std::array::from_fn(|c| {
+                half::f16::from_le_bytes([texel[c * 2], texel[c * 2 + 1]]).to_f32()
+            })
+        })
+        .collect()
+}
+
+/// Creates an `Rgba16Float` cubemap image from face-major texel data
+/// (all mips of face 0, then all mips of face 1, ...).
+pub fn new_cubemap_image(face_size: u32, mip_level_count: u32, data: Vec<u8>) -> Image {
+    Image {
+        data,
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size: Extent3d {
+                width: face_size,
+                height: face_size,
+                depth_or_array_layers: FACE_COUNT,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba16Float,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        sampler: ImageSampler::Default,
+        texture_view_descriptor: Some(TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::Cube),
+            ..Default::default()
+        }),
+        asset_usage: RenderAssetUsages::default(),
+    }
+}
+
+/// Creates a single-mip `Rgba16Float` cubemap by evaluating `f` for the
+/// direction through each texel center.
+pub fn cubemap_from_fn(face_size: u32, f: impl FnMut(Vec3) -> [f32; 4]) -> Image {
+    CubemapData::from_fn(face_size, f).to_image()
+}
+
+/// Stacks cubemaps of the same size, format and mip count into a cube array,
+/// one layer per input in order.
+pub fn stack_cubemap_layers(layers: &[&Image]) -> Image {
+    let first = layers.first().expect("No cubemap layers to stack");
+    let descriptor = &first.texture_descriptor;
+    for layer in layers {
+        let other = &layer.texture_descriptor;
+        if other.format != descriptor.format
+            || other.size != descriptor.size
+            || other.mip_level_count != descriptor.mip_level_count
+        {
+            panic!("Stacked cubemaps need matching formats, sizes and mip level counts");
+        }
+    }
+
+    let mut stacked = (*first).clone();
+    stacked.data = layers
+        .iter()
+        .flat_map(|layer| layer.data.iter().copied())
+        .collect();
+    stacked.texture_descriptor.size.depth_or_array_layers = FACE_COUNT * layers.len() as u32;
+    stacked.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::CubeArray),
+        ..Default::default()
+    });
+    stacked
+}
+
+/// What [`square_faces`] does with cubemaps whose faces aren't square.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NonSquareFaces {
+    /// Refuse them with an error.
+    #[default]
+    Error,
+    /// Cut the longer side of each face down to the shorter one, keeping the center.
+    Crop,
+    /// Extend the shorter side of each face to the longer one, repeating the
+    /// edge texels.
+    Pad,
+}
+
+/// Makes the faces of an uncompressed cubemap or cube array square, as the
+/// mip chain and face sampling assume. Square inputs are returned as they are.
+///
+/// Only the top level of non-square inputs is kept, as the sizes of their mip
+/// levels don't match a square chain. Regenerate it, e.g. with
+/// [`crate::mips::InputMips::Generate`].
+pub fn square_faces(image: &Image, handling: NonSquareFaces) -> std::io::Result<Image> {
+    let descriptor = &image.texture_descriptor;
+    let (width, height) = (descriptor.size.width, descriptor.size.height);
+    if width == height {
+        return Ok(image.clone());
+    }
+    if image.is_compressed() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Squaring the faces of compressed images is not supported",
+        ));
+    }
+
+    let size = match handling {
+        NonSquareFaces::Error => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Cubemap faces are {width}×{height} texels, but need to be square"),
+            ))
+        }
+        NonSquareFaces::Crop => width.min(height),
+        NonSquareFaces::Pad => width.max(height),
+    };
+    // Texel of the input each texel of the output is copied from.
+    let offset = |extent: u32| (extent as i64 - size as i64) / 2;
+    let (offset_x, offset_y) = (offset(width), offset(height));
+
+    let block_size = descriptor.format.block_copy_size(None).unwrap() as usize;
+    let mut data = Vec::with_capacity(
+        size as usize * size as usize * block_size * descriptor.size.depth_or_array_layers as usize,
+    );
+    for face in 0..descriptor.size.depth_or_array_layers {
+        let (byte_range, _, _) = mip_level_byte_range(image, 0, face);
+        let face_bytes = &image.data[byte_range];
+        for y in 0..size as i64 {
+            let source_y = (y + offset_y).clamp(0, height as i64 - 1) as usize;
+            for x in 0..size as i64 {
+                let source_x = (x + offset_x).clamp(0, width as i64 - 1) as usize;
+                let start = (source_y * width as usize + source_x) * block_size;
+                data.extend_from_slice(&face_bytes[start..start + block_size]);
+            }
+        }
+    }
+
+    let mut squared = image.clone();
+    squared.data = data;
+    squared.texture_descriptor.size.width = size;
+    squared.texture_descriptor.size.height = size;
+    squared.texture_descriptor.mip_level_count = 1;
+    Ok(squared)
+}
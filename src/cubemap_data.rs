@@ -0,0 +1,533 @@
+//! Cubemaps held as float texels while they are converted and filtered.
+
+use std::ops::Range;
+
+use bevy::{
+    math::Vec3,
+    prelude::Image,
+    render::render_resource::{TextureViewDescriptor, TextureViewDimension},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    color::{decode_texels, read_texels},
+    cubemap::{
+        direction_to_face_uv, new_cubemap_image, rgba_f32_to_rgba16f_bytes, sample_bilinear,
+        texel_across_edges, texel_direction, texel_solid_angle, FACE_COUNT,
+    },
+    mip_level_byte_range,
+    source::{mip_size, EnvmapSource},
+};
+
+/// Linear RGBA texels of every mip level of every face of a cubemap or cube
+/// array.
+///
+/// Converters and filters work on this instead of slicing the bytes of an
+/// [`Image`], converting at the edges with [`CubemapData::from_image`] and
+/// [`CubemapData::to_image`]. Faces are numbered across layers, face `f` of
+/// layer `l` being `l * 6 + f`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CubemapData {
+    face_size: u32,
+    mip_level_count: u32,
+    layer_count: u32,
+    /// Texels of each mip level of each face, at `face * mip_level_count + mip_level`.
+    levels: Vec<Vec<[f32; 4]>>,
+}
+
+/// How [`CubemapData::sample_with`] filters, mirroring a GPU sampler.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CubemapSampler {
+    /// Filter within a mip level.
+    pub filter: TexelFilter,
+    /// Filter between the two mip levels around `lod`.
+    pub mip_filter: TexelFilter,
+    /// Mip level to read, fractional levels blending two with
+    /// [`TexelFilter::Linear`]. Clamped to the existing levels.
+    pub lod: f32,
+    /// Cube array layer to read.
+    pub layer: u32,
+}
+
+impl CubemapSampler {
+    pub fn with_filter(mut self, filter: TexelFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_mip_filter(mut self, mip_filter: TexelFilter) -> Self {
+        self.mip_filter = mip_filter;
+        self
+    }
+
+    pub fn with_lod(mut self, lod: f32) -> Self {
+        self.lod = lod;
+        self
+    }
+
+    pub fn with_layer(mut self, layer: u32) -> Self {
+        self.layer = layer;
+        self
+    }
+}
+
+/// Filter of a [`CubemapSampler`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TexelFilter {
+    /// The nearest texel, or the nearest mip level.
+    Nearest,
+    /// Bilinear within a level, blending across face edges like seamless
+    /// cubemap filtering, or linear between levels.
+    #[default]
+    Linear,
+}
+
+/// A texel visited by [`CubemapData::texels`].
+#[derive(Clone, Copy, Debug)]
+pub struct Texel<'a> {
+    /// Face across layers, see [`CubemapData`].
+    pub face: u32,
+    pub x: u32,
+    pub y: u32,
+    /// Normalized direction through the texel center.
+    pub direction: Vec3,
+    /// Solid angle the texel covers, in steradians.
+    pub solid_angle: f32,
+    pub texel: &'a [f32; 4],
+}
+
+/// A texel visited by [`CubemapData::texels_mut`].
+#[derive(Debug)]
+pub struct TexelMut<'a> {
+    /// Face across layers, see [`CubemapData`].
+    pub face: u32,
+    pub x: u32,
+    pub y: u32,
+    /// Normalized direction through the texel center.
+    pub direction: Vec3,
+    /// Solid angle the texel covers, in steradians.
+    pub solid_angle: f32,
+    pub texel: &'a mut [f32; 4],
+}
+
+impl CubemapData {
+    /// A black cubemap array with `layer_count` layers.
+    pub fn new(face_size: u32, mip_level_count: u32, layer_count: u32) -> Self {
+        let face_size = face_size.max(1);
+        let levels = (0..layer_count * FACE_COUNT)
+            .flat_map(|_| {
+                (0..mip_level_count).map(|mip_level| {
+                    vec![[0.0; 4]; (mip_size(face_size, mip_level) as usize).pow(2)]
+                })
+            })
+            .collect();
+        Self {
+            face_size,
+            mip_level_count,
+            layer_count,
+            levels,
+        }
+    }
+
+    /// A single-mip cubemap with `f` evaluated for the direction through each
+    /// texel center.
+    pub fn from_fn(face_size: u32, mut f: impl FnMut(Vec3) -> [f32; 4]) -> Self {
+        let mut data = Self::new(face_size, 1, 1);
+        for (face, _, texels) in data.iter_mut() {
+            for (i, texel) in texels.iter_mut().enumerate() {
+                let (x, y) = (i as u32 % face_size, i as u32 / face_size);
+                *texel = f(texel_direction(face, x, y, face_size));
+            }
+        }
+        data
+    }
+
+    /// Copies every level of a single-layer source.
+    pub fn from_source(source: &dyn EnvmapSource) -> Self {
+        let mut data = Self::new(source.face_size(), source.mip_level_count(), 1);
+        for (face, mip_level, texels) in data.iter_mut() {
+            *texels = source.face_texels(face, mip_level);
+        }
+        data
+    }
+
+    /// Decodes a cubemap or cube array in any format [`read_texels`] reads,
+    /// decompressing BC6H and ASTC HDR. The color channels are taken as
+    /// stored. Panics on non-square faces and other formats, which
+    /// [`crate::color::linearize`] reports as errors instead.
+    pub fn from_image(image: &Image) -> Self {
+        let descriptor = &image.texture_descriptor;
+        if descriptor.size.width != descriptor.size.height {
+            panic!(
+                "Cubemap faces need to be square, got {}×{}, see cubemap::square_faces",
+                descriptor.size.width, descriptor.size.height
+            );
+        }
+        let layer_count = (descriptor.size.depth_or_array_layers / FACE_COUNT).max(1);
+        let mut data = Self::new(
+            descriptor.size.width,
+            descriptor.mip_level_count,
+            layer_count,
+        );
+        let unreadable = |error| panic!("{error}");
+        if image.is_compressed() {
+            // Decompressed texels come in the order of the levels.
+            let mut decompressed = read_texels(image).unwrap_or_else(unreadable).into_iter();
+            for (_, _, texels) in data.iter_mut() {
+                let texel_count = texels.len();
+                *texels = decompressed.by_ref().take(texel_count).collect();
+            }
+            return data;
+        }
+        for (face, mip_level, texels) in data.iter_mut() {
+            let (byte_range, _, _) = mip_level_byte_range(image, mip_level, face);
+            *texels = decode_texels(descriptor.format, &image.data[byte_range])
+                .unwrap_or_else(unreadable);
+        }
+        data
+    }
+
+    /// `Rgba16Float` cubemap, or cube array for more than one layer.
+    pub fn to_image(&self) -> Image {
+        let bytes = self
+            .levels
+            .iter()
+            .flat_map(|texels| rgba_f32_to_rgba16f_bytes(texels))
+            .collect();
+        let mut image = new_cubemap_image(self.face_size, self.mip_level_count, bytes);
+        if self.layer_count > 1 {
+            image.texture_descriptor.size.depth_or_array_layers = self.face_count();
+            image.texture_view_descriptor = Some(TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::CubeArray),
+                ..Default::default()
+            });
+        }
+        image
+    }
+
+    /// Width and height of the faces of the top mip level.
+    pub fn face_size(&self) -> u32 {
+        self.face_size
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.layer_count
+    }
+
+    /// Faces of all layers.
+    pub fn face_count(&self) -> u32 {
+        self.layer_count * FACE_COUNT
+    }
+
+    /// Face size of `mip_level`.
+    pub fn mip_size(&self, mip_level: u32) -> u32 {
+        mip_size(self.face_size, mip_level)
+    }
+
+    fn index(&self, face: u32, mip_level: u32) -> usize {
+        if face >= self.face_count() || mip_level >= self.mip_level_count {
+            panic!(
+                "Face {face} mip level {mip_level} requested, but only {} faces with {} mip levels exist.",
+                self.face_count(),
+                self.mip_level_count
+            );
+        }
+        (face * self.mip_level_count + mip_level) as usize
+    }
+
+    /// Texels of one face of a mip level in row-major order.
+    pub fn face(&self, face: u32, mip_level: u32) -> &[[f32; 4]] {
+        &self.levels[self.index(face, mip_level)]
+    }
+
+    pub fn face_mut(&mut self, face: u32, mip_level: u32) -> &mut [[f32; 4]] {
+        let index = self.index(face, mip_level);
+        &mut self.levels[index]
+    }
+
+    /// The six faces of `layer` at `mip_level`, as [`crate::cubemap::sample_bilinear`] takes them.
+    pub fn cube(&self, layer: u32, mip_level: u32) -> Vec<&[[f32; 4]]> {
+        (0..FACE_COUNT)
+            .map(|face| self.face(layer * FACE_COUNT + face, mip_level))
+            .collect()
+    }
+
+    pub fn texel(&self, face: u32, mip_level: u32, x: u32, y: u32) -> [f32; 4] {
+        let size = self.mip_size(mip_level);
+        self.face(face, mip_level)[(y * size + x) as usize]
+    }
+
+    pub fn set_texel(&mut self, face: u32, mip_level: u32, x: u32, y: u32, texel: [f32; 4]) {
+        let size = self.mip_size(mip_level);
+        self.face_mut(face, mip_level)[(y * size + x) as usize] = texel;
+    }
+
+    /// Every face's mip levels as `(face, mip_level, texels)`, in storage order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32, &[[f32; 4]])> {
+        let mip_level_count = self.mip_level_count;
+        self.levels.iter().enumerate().map(move |(i, texels)| {
+            let i = i as u32;
+            (i / mip_level_count, i % mip_level_count, texels.as_slice())
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (u32, u32, &mut Vec<[f32; 4]>)> {
+        let mip_level_count = self.mip_level_count;
+        self.levels.iter_mut().enumerate().map(move |(i, texels)| {
+            let i = i as u32;
+            (i / mip_level_count, i % mip_level_count, texels)
+        })
+    }
+
+    /// Color the top level of the first layer has in direction `dir`, filtered
+    /// bilinearly. See [`CubemapData::sample_with`].
+    pub fn sample(&self, dir: Vec3) -> Vec3 {
+        self.sample_with(dir, &CubemapSampler::default())
+    }
+
+    /// Color in direction `dir` as read with `sampler`, e.g. for CPU-side
+    /// lookups in tools and light baking, or to check what the GPU samples.
+    pub fn sample_with(&self, dir: Vec3, sampler: &CubemapSampler) -> Vec3 {
+        let lod = sampler.lod.clamp(0.0, (self.mip_level_count - 1) as f32);
+        let sample_level = |mip_level: u32| {
+            let faces = self.cube(sampler.layer, mip_level);
+            let size = self.mip_size(mip_level);
+            let [r, g, b, _] = match sampler.filter {
+                TexelFilter::Nearest => {
+                    let (face, u, v) = direction_to_face_uv(dir);
+                    let texel = |c: f32| (((c * 0.5 + 0.5) * size as f32) as u32).min(size - 1);
+                    faces[face as usize][(texel(v) * size + texel(u)) as usize]
+                }
+                TexelFilter::Linear => sample_bilinear(&faces, size, dir),
+            };
+            Vec3::new(r, g, b)
+        };
+        match sampler.mip_filter {
+            TexelFilter::Nearest => sample_level(lod.round() as u32),
+            TexelFilter::Linear => {
+                let lower = lod.floor() as u32;
+                let upper = (lower + 1).min(self.mip_level_count - 1);
+                let color = sample_level(lower);
+                if upper == lower {
+                    return color;
+                }
+                color.lerp(sample_level(upper), lod - lower as f32)
+            }
+        }
+    }
+
+    /// Every texel of `mip_level` with its position, direction and solid angle,
+    /// face by face in storage order. See [`CubemapData::texels_mut`].
+    pub fn texels(&self, mip_level: u32) -> impl Iterator<Item = Texel<'_>> {
+        let size = self.mip_size(mip_level);
+        (0..self.face_count()).flat_map(move |face| {
+            self.face(face, mip_level)
+                .iter()
+                .enumerate()
+                .map(move |(i, texel)| {
+                    let (x, y) = (i as u32 % size, i as u32 / size);
+                    Texel {
+                        face,
+                        x,
+                        y,
+                        direction: texel_direction(face % FACE_COUNT, x, y, size),
+                        solid_angle: texel_solid_angle(x, y, size),
+                        texel,
+                    }
+                })
+        })
+    }
+
+    /// Every texel of `mip_level` for reading and writing, with its position,
+    /// direction and solid angle, so custom analysis and filters don't have to
+    /// redo the cubemap direction math. Face by face in storage order.
+    pub fn texels_mut(&mut self, mip_level: u32) -> impl Iterator<Item = TexelMut<'_>> {
+        let size = self.mip_size(mip_level);
+        let mip_level_count = self.mip_level_count;
+        // Validates `mip_level`.
+        self.index(0, mip_level);
+        self.levels
+            .iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| *i as u32 % mip_level_count == mip_level)
+            .flat_map(move |(i, texels)| {
+                let face = i as u32 / mip_level_count;
+                texels.iter_mut().enumerate().map(move |(i, texel)| {
+                    let (x, y) = (i as u32 % size, i as u32 / size);
+                    TexelMut {
+                        face,
+                        x,
+                        y,
+                        direction: texel_direction(face % FACE_COUNT, x, y, size),
+                        solid_angle: texel_solid_angle(x, y, size),
+                        texel,
+                    }
+                })
+            })
+    }
+
+    /// Applies `f` to every texel.
+    pub fn map_texels(&mut self, mut f: impl FnMut([f32; 4]) -> [f32; 4]) {
+        for texels in &mut self.levels {
+            for texel in texels {
+                *texel = f(*texel);
+            }
+        }
+    }
+
+    /// Sets every texel to `f(face, mip_level, direction)`, with the direction
+    /// through the texel center. Rows are evaluated in parallel, see
+    /// [`crate::threads`].
+    pub fn fill(&mut self, f: impl Fn(u32, u32, Vec3) -> [f32; 4] + Sync) {
+        let face_size = self.face_size;
+        let mip_level_count = self.mip_level_count;
+        self.levels
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(i, texels)| {
+                let (face, mip_level) = (i as u32 / mip_level_count, i as u32 % mip_level_count);
+                let size = mip_size(face_size, mip_level);
+                texels
+                    .par_chunks_mut(size as usize)
+                    .enumerate()
+                    .for_each(|(y, row)| {
+                        for (x, texel) in (0..size).zip(row) {
+                            let dir = texel_direction(face % FACE_COUNT, x, y as u32, size);
+                            *texel = f(face, mip_level, dir);
+                        }
+                    });
+            });
+    }
+
+    /// Averages the texels on either side of every face edge of `mip_level`,
+    /// and the three texels meeting at every corner, so that the faces agree
+    /// along their seams. Small levels need this on hardware without seamless
+    /// cubemap filtering, where lookups never blend across faces.
+    pub fn average_seams(&mut self, mip_level: u32) {
+        let size = self.mip_size(mip_level);
+        if size < 2 {
+            return;
+        }
+        let edge = |i: u32| match i {
+            0 => -1,
+            i if i == size - 1 => 1,
+            _ => 0,
+        };
+
+        for layer in 0..self.layer_count {
+            let original = self
+                .cube(layer, mip_level)
+                .into_iter()
+                .map(<[_]>::to_vec)
+                .collect::<Vec<_>>();
+            for face in 0..FACE_COUNT {
+                for y in 0..size {
+                    for x in 0..size {
+                        let (dx, dy) = (edge(x), edge(y));
+                        if dx == 0 && dy == 0 {
+                            continue;
+                        }
+                        let mut sum = original[face as usize][(y * size + x) as usize];
+                        let mut count = 1.0;
+                        // Step one texel over each edge the texel touches.
+                        for (dx, dy) in [(dx, 0), (0, dy)] {
+                            if dx == 0 && dy == 0 {
+                                continue;
+                            }
+                            let (x, y) = (x as i32 + dx, y as i32 + dy);
+                            let texel = texel_across_edges(&original, size, face, x, y);
+                            for c in 0..4 {
+                                sum[c] += texel[c];
+                            }
+                            count += 1.0;
+                        }
+                        let face = layer * FACE_COUNT + face;
+                        self.set_texel(face, mip_level, x, y, sum.map(|c| c / count));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Copy keeping only the mip levels in `range`, the first becoming the top level.
+    pub fn mip_levels(&self, range: Range<u32>) -> Self {
+        if range.is_empty() || range.end > self.mip_level_count {
+            panic!(
+                "Mip levels {range:?} requested, but only {} exist.",
+                self.mip_level_count
+            );
+        }
+        let levels = (0..self.face_count())
+            .flat_map(|face| range.clone().map(move |mip_level| (face, mip_level)))
+            .map(|(face, mip_level)| self.face(face, mip_level).to_vec())
+            .collect();
+        Self {
+            face_size: self.mip_size(range.start),
+            mip_level_count: range.len() as u32,
+            layer_count: self.layer_count,
+            levels,
+        }
+    }
+
+    /// Copy of a single layer.
+    pub fn layer(&self, layer: u32) -> Self {
+        if layer >= self.layer_count {
+            panic!(
+                "Layer {layer} requested, but only {} exist.",
+                self.layer_count
+            );
+        }
+        let per_layer = (FACE_COUNT * self.mip_level_count) as usize;
+        let start = layer as usize * per_layer;
+        Self {
+            face_size: self.face_size,
+            mip_level_count: self.mip_level_count,
+            layer_count: 1,
+            levels: self.levels[start..start + per_layer].to_vec(),
+        }
+    }
+
+    /// Stacks single- or multi-layer cubemaps of the same size and mip count
+    /// into one cube array.
+    pub fn stack(layers: &[&CubemapData]) -> Self {
+        let first = layers.first().expect("No cubemap layers to stack");
+        if layers.iter().any(|layer| {
+            layer.face_size != first.face_size || layer.mip_level_count != first.mip_level_count
+        }) {
+            panic!("Stacked cubemaps need matching sizes and mip level counts");
+        }
+        Self {
+            face_size: first.face_size,
+            mip_level_count: first.mip_level_count,
+            layer_count: layers.iter().map(|layer| layer.layer_count).sum(),
+            levels: layers
+                .iter()
+                .flat_map(|layer| layer.levels.iter().cloned())
+                .collect(),
+        }
+    }
+}
+
+/// The first layer of the cubemap.
+impl EnvmapSource for CubemapData {
+    fn face_size(&self) -> u32 {
+        self.face_size
+    }
+
+    fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+
+    fn face_texels(&self, face: u32, mip_level: u32) -> Vec<[f32; 4]> {
+        self.face(face, mip_level).to_vec()
+    }
+
+    fn texel(&self, face: u32, mip_level: u32, x: u32, y: u32) -> [f32; 4] {
+        CubemapData::texel(self, face, mip_level, x, y)
+    }
+}
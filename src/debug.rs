@@ -0,0 +1,115 @@
+use bevy::{prelude::Image, render::render_resource::TextureFormat};
+
+use crate::{cubemap::FACE_COUNT, cubemap_data::CubemapData};
+
+/// Face names in storage order, burned into each face by [`label_faces`].
+pub const FACE_LABELS: [&str; 6] = ["+X", "-X", "+Y", "-Y", "+Z", "-Z"];
+
+/// Tint multiplied into each face by [`label_faces`]. Opposite faces use
+/// complementary colors.
+pub const FACE_TINTS: [[f32; 3]; 6] = [
+    [1.0, 0.4, 0.4],
+    [0.4, 1.0, 1.0],
+    [0.4, 1.0, 0.4],
+    [1.0, 0.4, 1.0],
+    [0.4, 0.4, 1.0],
+    [1.0, 1.0, 0.4],
+];
+
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+/// Returns a copy of a `Rgba16Float` cubemap with every face tinted and its
+/// name (`+X`, `-X`, ...) written in the center, on every layer and every mip
+/// level large enough to fit it. Useful for diagnosing face orientation and
+/// ordering bugs.
+pub fn label_faces(image: &Image) -> Image {
+    if image.texture_descriptor.format != TextureFormat::Rgba16Float {
+        panic!("Face labels only supported for Rgba16Float images");
+    }
+
+    let mut data = CubemapData::from_image(image);
+    for layer_face in 0..data.face_count() {
+        let face = (layer_face % FACE_COUNT) as usize;
+        for mip_level in 0..data.mip_level_count() {
+            let size = data.mip_size(mip_level) as usize;
+            let texels = data.face_mut(layer_face, mip_level);
+            for texel in texels.iter_mut() {
+                for (c, tint) in FACE_TINTS[face].iter().enumerate() {
+                    texel[c] *= tint;
+                }
+            }
+
+            draw_label(texels, size, size, FACE_LABELS[face]);
+        }
+    }
+    data.to_image()
+}
+
+/// Draws `text` centered on a black box, scaled to roughly half the face width.
+fn draw_label(texels: &mut [[f32; 4]], width: usize, height: usize, text: &str) {
+    // One cell of spacing between glyphs and a one cell border around the text.
+    let cells_x = text.len() * (GLYPH_WIDTH + 1) + 1;
+    let cells_y = GLYPH_HEIGHT + 2;
+    let scale = (width / (cells_x * 2)).min(height / (cells_y * 2));
+    if scale == 0 {
+        return;
+    }
+
+    let origin_x = (width - cells_x * scale) / 2;
+    let origin_y = (height - cells_y * scale) / 2;
+
+    for cell_y in 0..cells_y {
+        for cell_x in 0..cells_x {
+            let lit = is_text_cell(text, cell_x, cell_y);
+            let value = if lit { 1.0 } else { 0.0 };
+            for y in 0..scale {
+                for x in 0..scale {
+                    let px = origin_x + cell_x * scale + x;
+                    let py = origin_y + cell_y * scale + y;
+                    let texel = &mut texels[py * width + px];
+                    texel[..3].fill(value);
+                }
+            }
+        }
+    }
+}
+
+fn is_text_cell(text: &str, cell_x: usize, cell_y: usize) -> bool {
+    if cell_x == 0 || cell_y == 0 || cell_y > GLYPH_HEIGHT {
+        return false;
+    }
+    let glyph_index = (cell_x - 1) / (GLYPH_WIDTH + 1);
+    let glyph_x = (cell_x - 1) % (GLYPH_WIDTH + 1);
+    if glyph_x == GLYPH_WIDTH {
+        return false;
+    }
+    let Some(c) = text.chars().nth(glyph_index) else {
+        return false;
+    };
+    let row = glyph(c)[cell_y - 1];
+    row & (1 << (GLYPH_WIDTH - 1 - glyph_x)) != 0
+}
+
+/// 5×7 bitmap for the characters used in face labels, one byte per row with
+/// the leftmost pixel in bit 4.
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c {
+        '+' => [
+            0b00000, 0b00100, 0b00100, 0b11111, 0b00100, 0b00100, 0b00000,
+        ],
+        '-' => [
+            0b00000, 0b00000, 0b00000, 0b11111, 0b00000, 0b00000, 0b00000,
+        ],
+        'X' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b01010, 0b10001, 0b10001,
+        ],
+        'Y' => [
+            0b10001, 0b10001, 0b01010, 0b00100, 0b00100, 0b00100, 0b00100,
+        ],
+        'Z' => [
+            0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b10000, 0b11111,
+        ],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
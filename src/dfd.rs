@@ -0,0 +1,561 @@
+//! KTX 2.0 Data-Format Descriptors (Khronos Data Format specification) for the
+//! formats this crate writes.
+
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+};
+
+use crate::color::ColorPrimaries;
+
+// word2: colourModel | colourPrimaries | transferFunction | flags
+const COLOR_MODEL_RGBSDA: u32 = 1; // KHR_DF_MODEL_RGBSDA
+const COLOR_MODEL_BC6H: u32 = 131; // KHR_DF_MODEL_BC6H
+const COLOR_PRIMARIES_BT709: u32 = 1; // Recommended default
+const TRANSFER_LINEAR: u32 = 1; // KHR_DF_TRANSFER_LINEAR
+const TRANSFER_SRGB: u32 = 2; // KHR_DF_TRANSFER_SRGB
+const FLAGS_STRAIGHT_ALPHA: u32 = 0; // no premultiplied alpha
+
+// Qualifier bits (see ChannelTypeQualifiers in ktx2 crate)
+const QUAL_NONE: u32 = 0;
+const QUAL_LINEAR: u32 = 1 << 0; // LINEAR flag, for alpha in sRGB formats
+const QUAL_EXPONENT: u32 = 1 << 1; // EXPONENT flag
+const QUAL_SIGNED: u32 = 1 << 2; // SIGNED flag
+const QUAL_FLOAT: u32 = 1 << 3; // FLOAT flag
+
+// Channel-type codes (KDF §A.3): 0=R,1=G,2=B,15=A
+const CH_R: u32 = 0;
+const CH_G: u32 = 1;
+const CH_B: u32 = 2;
+const CH_A: u32 = 15;
+
+/// Builds a KTX 2.0 Data-Format Descriptor for `VK_FORMAT_E5B9G9R9_UFLOAT_PACK32`.
+///
+/// The descriptor follows the sample layout shown in the specification and uses
+/// one BASIC descriptor block (vendor 0, type 0, version 2).  Six samples are
+/// written so that the validator sees the expected RGB mantissas and their
+/// shared exponent.
+///
+/// Every texel occupies a single 32-bit word, therefore `bytesPlane0` is `4`.
+/// The function returns the descriptor as a little-endian byte vector ready to
+/// be written to the file.
+pub fn create_rgb9e5_dfd() -> Vec<u8> {
+    // For a packed 32-bit texel bytesPlane0 = 4, the rest 0.
+    let mut dfd = basic_block_header(6, 4);
+
+    // For each colour channel we write: mantissa sample followed by its exponent sample.
+
+    // RED mantissa & exponent
+    push_sample(&mut dfd, 0, 9, CH_R, QUAL_NONE, 0, 8448); // R mantissa (bits 0-8)
+    push_sample(&mut dfd, 27, 5, CH_R, QUAL_EXPONENT, 15, 31); // R exponent (bits 27-31)
+
+    // GREEN mantissa & exponent
+    push_sample(&mut dfd, 9, 9, CH_G, QUAL_NONE, 0, 8448); // G mantissa (bits 9-17)
+    push_sample(&mut dfd, 27, 5, CH_G, QUAL_EXPONENT, 15, 31); // G exponent (shared bits)
+
+    // BLUE mantissa & exponent
+    push_sample(&mut dfd, 18, 9, CH_B, QUAL_NONE, 0, 8448); // B mantissa (bits 18-26)
+    push_sample(&mut dfd, 27, 5, CH_B, QUAL_EXPONENT, 15, 31); // B exponent (shared bits)
+
+    patch_total_size(&mut dfd);
+    dfd
+}
+
+/// Builds a Data-Format Descriptor for `VK_FORMAT_R8G8B8A8_UNORM` with a
+/// linear transfer function, used for the custom HDR packings (LogLuv, RGBM, ...)
+/// whose bytes must not be decoded as sRGB.
+pub fn create_rgba8_dfd() -> Vec<u8> {
+    let mut dfd = basic_block_header(4, 4);
+
+    for (i, channel) in [CH_R, CH_G, CH_B, CH_A].into_iter().enumerate() {
+        push_sample(&mut dfd, i as u32 * 8, 8, channel, QUAL_NONE, 0, 255);
+    }
+
+    patch_total_size(&mut dfd);
+    dfd
+}
+
+/// Builds a Data-Format Descriptor for `VK_FORMAT_R8G8B8A8_SRGB`. The color
+/// channels use the sRGB transfer function, alpha is marked as linear.
+pub fn create_rgba8_srgb_dfd() -> Vec<u8> {
+    let mut dfd = basic_block_header(4, 4);
+    // totalSize, word0 and word1 precede word2, whose third byte holds the transfer function.
+    dfd[14] = TRANSFER_SRGB as u8;
+
+    for (i, channel) in [CH_R, CH_G, CH_B, CH_A].into_iter().enumerate() {
+        let qualifiers = if channel == CH_A {
+            QUAL_LINEAR
+        } else {
+            QUAL_NONE
+        };
+        push_sample(&mut dfd, i as u32 * 8, 8, channel, qualifiers, 0, 255);
+    }
+
+    patch_total_size(&mut dfd);
+    dfd
+}
+
+/// Builds a Data-Format Descriptor for `VK_FORMAT_R16G16B16A16_SFLOAT`.
+///
+/// Float samples store the bit patterns of -1.0 and 1.0 (as 32-bit floats) as
+/// their lower and upper bounds, per the specification.
+pub fn create_rgba16f_dfd() -> Vec<u8> {
+    let mut dfd = basic_block_header(4, 8);
+
+    let lower = (-1.0f32).to_bits();
+    let upper = 1.0f32.to_bits();
+    for (i, channel) in [CH_R, CH_G, CH_B, CH_A].into_iter().enumerate() {
+        push_sample(
+            &mut dfd,
+            i as u32 * 16,
+            16,
+            channel,
+            QUAL_FLOAT | QUAL_SIGNED,
+            lower,
+            upper,
+        );
+    }
+
+    patch_total_size(&mut dfd);
+    dfd
+}
+
+/// Builds a Data-Format Descriptor for `VK_FORMAT_B10G11R11_UFLOAT_PACK32`,
+/// with unsigned float samples bounded by 0.0 and 1.0.
+pub fn create_b10g11r11_dfd() -> Vec<u8> {
+    let mut dfd = basic_block_header(3, 4);
+
+    let upper = 1.0f32.to_bits();
+    push_sample(&mut dfd, 0, 11, CH_R, QUAL_FLOAT, 0, upper);
+    push_sample(&mut dfd, 11, 11, CH_G, QUAL_FLOAT, 0, upper);
+    push_sample(&mut dfd, 22, 10, CH_B, QUAL_FLOAT, 0, upper);
+
+    patch_total_size(&mut dfd);
+    dfd
+}
+
+/// Builds a Data-Format Descriptor for `VK_FORMAT_BC6H_UFLOAT_BLOCK`: a 4×4
+/// texel block of 16 bytes described by a single 128-bit color sample.
+pub fn create_bc6h_dfd() -> Vec<u8> {
+    let mut dfd = basic_block_header(1, 16);
+    // colorModel is the first byte of word2, texelBlockDimensions all of word3.
+    dfd[12] = COLOR_MODEL_BC6H as u8;
+    dfd[16..20].copy_from_slice(&[3, 3, 0, 0]);
+
+    // KHR_DF_CHANNEL_BC6H_COLOR is 0.
+    push_sample(&mut dfd, 0, 128, 0, QUAL_FLOAT, 0, 1.0f32.to_bits());
+
+    patch_total_size(&mut dfd);
+    dfd
+}
+
+/// Overwrites the colorPrimaries field of a descriptor built by this module.
+pub fn set_color_primaries(dfd: &mut [u8], primaries: ColorPrimaries) {
+    // totalSize, word0 and word1 precede word2, whose second byte holds the primaries.
+    dfd[13] = primaries.dfd_value();
+}
+
+/// A decoded BASIC descriptor block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicDescriptor {
+    pub vendor_id: u32,
+    pub descriptor_type: u32,
+    pub version_number: u16,
+    /// `KHR_DF_MODEL_*`.
+    pub color_model: u8,
+    /// `KHR_DF_PRIMARIES_*`.
+    pub color_primaries: u8,
+    /// `KHR_DF_TRANSFER_*`.
+    pub transfer_function: u8,
+    pub flags: u8,
+    /// Texel block width, height, depth and fourth dimension, minus one.
+    pub texel_block_dimensions: [u8; 4],
+    pub bytes_planes: [u8; 8],
+    pub samples: Vec<Sample>,
+}
+
+/// One sample of a BASIC descriptor block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sample {
+    pub bit_offset: u16,
+    /// Number of bits, not the stored `bitLength - 1`.
+    pub bit_length: u8,
+    pub channel_type: u8,
+    /// `KHR_DF_SAMPLE_DATATYPE_*` bits: 1 linear, 2 exponent, 4 signed, 8 float.
+    pub qualifiers: u8,
+    pub sample_position: [u8; 4],
+    pub lower: u32,
+    pub upper: u32,
+}
+
+/// Decodes a Data-Format Descriptor, including its leading totalSize word, as
+/// stored in a KTX2 file. Only the first descriptor block is decoded and it has
+/// to be a BASIC block.
+pub fn parse_dfd(dfd: &[u8]) -> std::io::Result<BasicDescriptor> {
+    let invalid = |message: &str| Error::new(ErrorKind::InvalidData, message.to_string());
+    let word = |offset: usize| -> std::io::Result<u32> {
+        dfd.get(offset..offset + 4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or_else(|| invalid("Data-Format Descriptor is truncated"))
+    };
+
+    let total_size = word(0)? as usize;
+    if total_size > dfd.len() {
+        return Err(invalid(
+            "Data-Format Descriptor is shorter than its totalSize",
+        ));
+    }
+
+    let word0 = word(4)?;
+    let vendor_id = word0 & 0x1ffff;
+    let descriptor_type = word0 >> 17;
+    if vendor_id != 0 || descriptor_type != 0 {
+        return Err(invalid("First descriptor block is not a BASIC block"));
+    }
+
+    let word1 = word(8)?;
+    let block_size = (word1 >> 16) as usize;
+    if block_size < 24 || (block_size - 24) % 16 != 0 || 4 + block_size > total_size {
+        return Err(invalid("Invalid BASIC descriptor block size"));
+    }
+
+    let word2 = word(12)?.to_le_bytes();
+    let mut bytes_planes = [0; 8];
+    bytes_planes[..4].copy_from_slice(&word(20)?.to_le_bytes());
+    bytes_planes[4..].copy_from_slice(&word(24)?.to_le_bytes());
+
+    let samples = (0..(block_size - 24) / 16)
+        .map(|i| {
+            let offset = 28 + i * 16;
+            let first = word(offset)?;
+            Ok(Sample {
+                bit_offset: first as u16,
+                bit_length: ((first >> 16) & 0xff) as u8 + 1,
+                channel_type: ((first >> 24) & 0xf) as u8,
+                qualifiers: (first >> 28) as u8,
+                sample_position: word(offset + 4)?.to_le_bytes(),
+                lower: word(offset + 8)?,
+                upper: word(offset + 12)?,
+            })
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    Ok(BasicDescriptor {
+        vendor_id,
+        descriptor_type,
+        version_number: word1 as u16,
+        color_model: word2[0],
+        color_primaries: word2[1],
+        transfer_function: word2[2],
+        flags: word2[3],
+        texel_block_dimensions: word(16)?.to_le_bytes(),
+        bytes_planes,
+        samples,
+    })
+}
+
+impl fmt::Display for BasicDescriptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let color_model = match self.color_model as u32 {
+            0 => "UNSPECIFIED",
+            COLOR_MODEL_RGBSDA => "RGBSDA",
+            COLOR_MODEL_BC6H => "BC6H",
+            _ => "other",
+        };
+        let color_primaries = match self.color_primaries {
+            0 => "UNSPECIFIED",
+            1 => "BT709",
+            2 => "BT601_EBU",
+            3 => "BT601_SMPTE",
+            4 => "BT2020",
+            5 => "CIEXYZ",
+            6 => "ACES",
+            7 => "ACESCC",
+            8 => "NTSC1953",
+            9 => "PAL525",
+            10 => "DISPLAYP3",
+            11 => "ADOBERGB",
+            _ => "other",
+        };
+        let transfer_function = match self.transfer_function {
+            0 => "UNSPECIFIED",
+            1 => "LINEAR",
+            2 => "SRGB",
+            _ => "other",
+        };
+        let [w, h, d, _] = self.texel_block_dimensions;
+
+        writeln!(f, "version: {}", self.version_number)?;
+        writeln!(f, "colorModel: {color_model} ({})", self.color_model)?;
+        writeln!(
+            f,
+            "colorPrimaries: {color_primaries} ({})",
+            self.color_primaries
+        )?;
+        writeln!(
+            f,
+            "transferFunction: {transfer_function} ({})",
+            self.transfer_function
+        )?;
+        writeln!(
+            f,
+            "flags: {}",
+            if self.flags & 1 != 0 {
+                "PREMULTIPLIED"
+            } else {
+                "STRAIGHT"
+            }
+        )?;
+        writeln!(f, "texelBlockDimensions: {}×{}×{}", w + 1, h + 1, d + 1)?;
+        writeln!(f, "bytesPlane: {:?}", self.bytes_planes)?;
+        for sample in &self.samples {
+            writeln!(f, "sample: {sample}")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Sample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let channel = match self.channel_type as u32 {
+            CH_R => "R",
+            CH_G => "G",
+            CH_B => "B",
+            CH_A => "A",
+            13 => "STENCIL",
+            14 => "DEPTH",
+            _ => "?",
+        };
+        let mut qualifiers = Vec::new();
+        for (bit, name) in [
+            (1, "LINEAR"),
+            (QUAL_EXPONENT, "EXPONENT"),
+            (QUAL_SIGNED, "SIGNED"),
+            (QUAL_FLOAT, "FLOAT"),
+        ] {
+            if self.qualifiers as u32 & bit != 0 {
+                qualifiers.push(name);
+            }
+        }
+
+        write!(
+            f,
+            "{channel} bits {}..{}",
+            self.bit_offset,
+            self.bit_offset as u32 + self.bit_length as u32
+        )?;
+        if !qualifiers.is_empty() {
+            write!(f, " [{}]", qualifiers.join(", "))?;
+        }
+        if self.qualifiers as u32 & QUAL_FLOAT != 0 {
+            write!(
+                f,
+                " range {}..{}",
+                f32::from_bits(self.lower),
+                f32::from_bits(self.upper)
+            )
+        } else {
+            write!(f, " range {}..{}", self.lower, self.upper)
+        }
+    }
+}
+
+// Helper to push a 32-bit little-endian word
+fn push(word: u32, out: &mut Vec<u8>) {
+    out.extend_from_slice(&word.to_le_bytes());
+}
+
+/// Writes the totalSize placeholder and the header of a single BASIC
+/// descriptor block (vendor 0, type 0, version 2) with an uncompressed 1×1×1
+/// texel block.
+fn basic_block_header(num_samples: u32, bytes_plane0: u32) -> Vec<u8> {
+    let mut dfd: Vec<u8> = Vec::with_capacity(28 + 16 * num_samples as usize);
+    dfd.extend_from_slice(&0u32.to_le_bytes()); // will be overwritten later
+
+    // Data-format-descriptor header (2 × u32)
+    // word0: descriptorType (lower 15 b) | vendorId (upper 17 b) – both 0 → 0
+    push(0, &mut dfd);
+
+    // The BASIC block length in bytes = 24 (header) + 16 × numSamples.
+    let basic_block_byte_length: u32 = 24 + 16 * num_samples;
+    const VERSION_NUMBER: u32 = 2;
+    // word1: versionNumber (low 16 b) | descriptorBlockSize (high 16 b)
+    let word1 = (basic_block_byte_length << 16) | VERSION_NUMBER;
+    push(word1, &mut dfd);
+
+    let word2 = COLOR_MODEL_RGBSDA
+        | (COLOR_PRIMARIES_BT709 << 8)
+        | (TRANSFER_LINEAR << 16)
+        | (FLAGS_STRAIGHT_ALPHA << 24);
+    push(word2, &mut dfd);
+
+    // word3: texelBlockDimensions – for a 1×1×1 block we store each dimension − 1 = 0
+    push(0, &mut dfd);
+
+    // word4 & word5: bytesPlane0-3 / bytesPlane4-7 (8 × u8)
+    push(bytes_plane0, &mut dfd); // bytesPlane0, others 0
+    push(0, &mut dfd); // bytesPlane4-7 = 0
+
+    dfd
+}
+
+fn push_sample(
+    out: &mut Vec<u8>,
+    bit_offset: u32,
+    bit_length_bits: u32,
+    channel_type: u32,
+    qualifiers: u32,
+    lower: u32,
+    upper: u32,
+) {
+    let first_word =
+        bit_offset | ((bit_length_bits - 1) << 16) | (channel_type << 24) | (qualifiers << 28);
+    push(first_word, out);
+    push(0, out); // samplePosition – not used → 0
+    push(lower, out);
+    push(upper, out);
+}
+
+fn patch_total_size(dfd: &mut [u8]) {
+    let total_size = dfd.len() as u32;
+    dfd[0..4].copy_from_slice(&total_size.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::{
+        B10g11r11Encoder, Bc6hEncoder, LogLuv32Encoder, Rgb9e5Encoder, Rgba16FloatEncoder,
+        Rgba8Encoder, RgbdEncoder, RgbmEncoder, TexelEncoder,
+    };
+
+    /// Color model, transfer function, bytesPlane0, block dimensions and the
+    /// (channel, bit offset, bit length) of every sample an encoder's
+    /// descriptor should have.
+    struct Expected {
+        color_model: u32,
+        transfer_function: u32,
+        bytes_plane0: u8,
+        block_dimensions: [u8; 4],
+        samples: &'static [(u32, u16, u8)],
+    }
+
+    const RGBA8: &[(u32, u16, u8)] = &[(CH_R, 0, 8), (CH_G, 8, 8), (CH_B, 16, 8), (CH_A, 24, 8)];
+
+    fn expected(encoder: &dyn TexelEncoder) -> Expected {
+        let rgbsda = |transfer_function, bytes_plane0, samples| Expected {
+            color_model: COLOR_MODEL_RGBSDA,
+            transfer_function,
+            bytes_plane0,
+            block_dimensions: [0; 4],
+            samples,
+        };
+        match encoder.ktx2_format() {
+            ktx2::Format::E5B9G9R9_UFLOAT_PACK32 => rgbsda(
+                TRANSFER_LINEAR,
+                4,
+                &[
+                    (CH_R, 0, 9),
+                    (CH_R, 27, 5),
+                    (CH_G, 9, 9),
+                    (CH_G, 27, 5),
+                    (CH_B, 18, 9),
+                    (CH_B, 27, 5),
+                ],
+            ),
+            ktx2::Format::R16G16B16A16_SFLOAT => rgbsda(
+                TRANSFER_LINEAR,
+                8,
+                &[
+                    (CH_R, 0, 16),
+                    (CH_G, 16, 16),
+                    (CH_B, 32, 16),
+                    (CH_A, 48, 16),
+                ],
+            ),
+            ktx2::Format::B10G11R11_UFLOAT_PACK32 => rgbsda(
+                TRANSFER_LINEAR,
+                4,
+                &[(CH_R, 0, 11), (CH_G, 11, 11), (CH_B, 22, 10)],
+            ),
+            ktx2::Format::R8G8B8A8_UNORM => rgbsda(TRANSFER_LINEAR, 4, RGBA8),
+            ktx2::Format::R8G8B8A8_SRGB => rgbsda(TRANSFER_SRGB, 4, RGBA8),
+            ktx2::Format::BC6H_UFLOAT_BLOCK => Expected {
+                color_model: COLOR_MODEL_BC6H,
+                transfer_function: TRANSFER_LINEAR,
+                bytes_plane0: 16,
+                block_dimensions: [3, 3, 0, 0],
+                samples: &[(0, 0, 128)],
+            },
+            format => panic!("No expected descriptor for {format:?}"),
+        }
+    }
+
+    #[test]
+    fn encoder_descriptors_round_trip() {
+        let encoders: [&dyn TexelEncoder; 8] = [
+            &Rgb9e5Encoder,
+            &Rgba16FloatEncoder,
+            &B10g11r11Encoder,
+            &Bc6hEncoder,
+            &LogLuv32Encoder,
+            &RgbmEncoder { range: 6.0 },
+            &RgbdEncoder { range: 6.0 },
+            &Rgba8Encoder { range: 1.0 },
+        ];
+        for encoder in encoders {
+            let dfd = encoder.dfd();
+            let parsed = parse_dfd(&dfd).unwrap();
+            let expected = expected(encoder);
+            let format = encoder.ktx2_format();
+
+            assert_eq!(
+                u32::from_le_bytes(dfd[..4].try_into().unwrap()) as usize,
+                dfd.len(),
+                "{format:?}"
+            );
+            assert_eq!((parsed.vendor_id, parsed.descriptor_type), (0, 0));
+            assert_eq!(parsed.version_number, 2, "{format:?}");
+            assert_eq!(
+                parsed.color_model as u32, expected.color_model,
+                "{format:?}"
+            );
+            assert_eq!(
+                parsed.color_primaries as u32, COLOR_PRIMARIES_BT709,
+                "{format:?}"
+            );
+            assert_eq!(
+                parsed.transfer_function as u32, expected.transfer_function,
+                "{format:?}"
+            );
+            assert_eq!(parsed.bytes_planes[0], expected.bytes_plane0, "{format:?}");
+            assert_eq!(parsed.bytes_planes[1..], [0; 7], "{format:?}");
+            assert_eq!(
+                parsed.texel_block_dimensions, expected.block_dimensions,
+                "{format:?}"
+            );
+
+            let samples = parsed
+                .samples
+                .iter()
+                .map(|sample| {
+                    (
+                        sample.channel_type as u32,
+                        sample.bit_offset,
+                        sample.bit_length,
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(samples, expected.samples, "{format:?}");
+        }
+    }
+
+    #[test]
+    fn set_color_primaries_round_trips() {
+        let mut dfd = create_rgba16f_dfd();
+        set_color_primaries(&mut dfd, ColorPrimaries::Rec2020);
+        let parsed = parse_dfd(&dfd).unwrap();
+        assert_eq!(parsed.color_primaries, ColorPrimaries::Rec2020.dfd_value());
+        assert_eq!(parsed.transfer_function as u32, TRANSFER_LINEAR);
+    }
+}
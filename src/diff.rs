@@ -0,0 +1,144 @@
+//! Comparison of two environment maps, as aggregate numbers and as per-face
+//! heatmaps showing where they differ.
+//!
+//! Errors are measured in log2 luminance, i.e. in stops, so a 10% error on the
+//! sun disc and in the shadows weighs the same.
+
+use std::path::Path;
+
+use crate::{
+    adjust::luminance,
+    cubemap::{texel_solid_angle, FACE_COUNT, FACE_NAMES},
+    output::{write_bytes_atomically, OverwritePolicy},
+    source::EnvmapSource,
+    thumbnail::rgb8_png,
+};
+
+/// Luminance below which texels count as black, so that noise in the darkest
+/// texels doesn't dominate the log error.
+const BLACK_LUMINANCE: f32 = 1.0e-4;
+
+/// File name stems of the heatmaps written by [`write_heatmaps`], in face order.
+pub const FACE_FILE_STEMS: [&str; 6] = FACE_NAMES;
+
+/// Per-texel error of one face.
+#[derive(Clone, Debug)]
+pub struct FaceDiff {
+    pub face_size: u32,
+    /// Absolute log2 luminance difference of each texel, row-major.
+    pub errors: Vec<f32>,
+}
+
+impl FaceDiff {
+    /// Error averaged over the solid angle of the face.
+    pub fn mean_error(&self) -> f32 {
+        let (mut sum, mut weight_sum) = (0.0, 0.0);
+        for (i, error) in self.errors.iter().enumerate() {
+            let (x, y) = (i as u32 % self.face_size, i as u32 / self.face_size);
+            let weight = texel_solid_angle(x, y, self.face_size);
+            sum += error * weight;
+            weight_sum += weight;
+        }
+        sum / weight_sum
+    }
+
+    pub fn max_error(&self) -> f32 {
+        self.errors.iter().copied().fold(0.0, f32::max)
+    }
+
+    /// Heatmap PNG of the errors, from black for none over red to white for
+    /// `max_error` stops and more.
+    pub fn heatmap_png(&self, max_error: f32) -> std::io::Result<Vec<u8>> {
+        let pixels = self
+            .errors
+            .iter()
+            .flat_map(|error| heat_color(error / max_error))
+            .collect::<Vec<_>>();
+        rgb8_png(&pixels, self.face_size, self.face_size)
+    }
+}
+
+/// Difference of two cubemaps at one mip level.
+#[derive(Clone, Debug)]
+pub struct CubemapDiff {
+    pub faces: Vec<FaceDiff>,
+}
+
+impl CubemapDiff {
+    /// Error averaged over the whole sphere.
+    pub fn mean_error(&self) -> f32 {
+        self.faces.iter().map(FaceDiff::mean_error).sum::<f32>() / self.faces.len() as f32
+    }
+
+    pub fn max_error(&self) -> f32 {
+        self.faces
+            .iter()
+            .map(FaceDiff::max_error)
+            .fold(0.0, f32::max)
+    }
+}
+
+/// Compares `mip_level` of `test` against `reference`. Fails with
+/// `ErrorKind::InvalidInput` unless both have the same face size and the mip level.
+pub fn diff_cubemaps(
+    reference: &dyn EnvmapSource,
+    test: &dyn EnvmapSource,
+    mip_level: u32,
+) -> std::io::Result<CubemapDiff> {
+    if reference.face_size() != test.face_size() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Inputs differ in size: {} and {} texel faces",
+                reference.face_size(),
+                test.face_size()
+            ),
+        ));
+    }
+    let mip_level_count = reference.mip_level_count().min(test.mip_level_count());
+    if mip_level >= mip_level_count {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Mip level {mip_level} requested, but the inputs have {mip_level_count}"),
+        ));
+    }
+    let face_size = (reference.face_size() >> mip_level).max(1);
+    let log_luminance = |texel: [f32; 4]| {
+        luminance([texel[0], texel[1], texel[2]])
+            .max(BLACK_LUMINANCE)
+            .log2()
+    };
+
+    let faces = (0..FACE_COUNT)
+        .map(|face| FaceDiff {
+            face_size,
+            errors: reference
+                .face_texels(face, mip_level)
+                .into_iter()
+                .zip(test.face_texels(face, mip_level))
+                .map(|(a, b)| (log_luminance(a) - log_luminance(b)).abs())
+                .collect(),
+        })
+        .collect();
+    Ok(CubemapDiff { faces })
+}
+
+/// Writes a heatmap per face to `<dir>/<stem>.png`, see [`FaceDiff::heatmap_png`].
+pub fn write_heatmaps(diff: &CubemapDiff, dir: &Path, max_error: f32) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    for (face, stem) in diff.faces.iter().zip(FACE_FILE_STEMS) {
+        write_bytes_atomically(
+            &dir.join(format!("{stem}.png")),
+            OverwritePolicy::Overwrite,
+            &face.heatmap_png(max_error)?,
+        )?;
+    }
+    Ok(())
+}
+
+/// Black, red, yellow, white ramp over `t` in `[0, 1]`.
+fn heat_color(t: f32) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0) * 3.0;
+    let ramp = |start: f32| ((t - start).clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+    [ramp(0.0), ramp(1.0), ramp(2.0)]
+}
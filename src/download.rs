@@ -0,0 +1,41 @@
+//! HTTP(S) inputs, downloaded into a cache directory before conversion so bake
+//! jobs can read HDRIs straight from a web server or bucket.
+//!
+//! Downloading needs the `download` feature.
+
+use std::path::{Path, PathBuf};
+
+use crate::provenance::content_hash;
+
+/// Whether `input` is an HTTP(S) URL rather than a file path.
+pub fn is_url(input: &str) -> bool {
+    input.starts_with("http://") || input.starts_with("https://")
+}
+
+/// Path `url` is cached at in `cache_dir`. The file name ends in the name of
+/// the downloaded file, keeping its extension for picking the loader. The
+/// prefix is [`content_hash`] of the URL, which is stable between builds.
+pub fn cache_path(url: &str, cache_dir: &Path) -> PathBuf {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let name = path.rsplit('/').next().unwrap_or_default();
+    cache_dir.join(format!("{:016x}_{name}", content_hash(url.as_bytes())))
+}
+
+/// Downloads `url` into `cache_dir` unless it's already there, and returns the
+/// path of the cached file. Interrupted downloads never leave a partial file
+/// in the cache.
+#[cfg(feature = "download")]
+pub fn fetch(url: &str, cache_dir: &Path) -> std::io::Result<PathBuf> {
+    use crate::output::{write_atomically, OverwritePolicy};
+
+    let path = cache_path(url, cache_dir);
+    std::fs::create_dir_all(cache_dir)?;
+    write_atomically(&path, OverwritePolicy::Skip, |file| {
+        let response = ureq::get(url)
+            .call()
+            .map_err(|e| std::io::Error::other(format!("Downloading {url} failed: {e}")))?;
+        std::io::copy(&mut response.into_reader(), file)?;
+        Ok(())
+    })?;
+    Ok(path)
+}
@@ -0,0 +1,272 @@
+use std::fmt::Debug;
+
+use crate::{
+    b10g11r11::float3_to_b10g11r11,
+    bc6h::compress_bc6h,
+    color::TransferFunction,
+    dfd::{
+        create_b10g11r11_dfd, create_bc6h_dfd, create_rgb9e5_dfd, create_rgba16f_dfd,
+        create_rgba8_dfd, create_rgba8_srgb_dfd,
+    },
+    logluv::float3_to_logluv32,
+    metadata,
+    rgb9e5::float3_to_rgb9e5,
+    rgbm::{float3_to_rgbd, float3_to_rgbm},
+};
+
+/// Turns linear RGBA texels into the bytes of one KTX2 pixel format.
+///
+/// Implement this to add an output format, the writer only talks to this trait.
+pub trait TexelEncoder: Debug + Send + Sync {
+    fn ktx2_format(&self) -> ktx2::Format;
+
+    /// Value of the `typeSize` header field: the size of the data type the
+    /// format is made of, 1 for block-compressed formats.
+    fn type_size(&self) -> u32;
+
+    /// Data-Format Descriptor of the format, with Rec.709 primaries.
+    fn dfd(&self) -> Vec<u8>;
+
+    /// Key/value metadata needed to decode the texels.
+    fn key_values(&self) -> Vec<(String, Vec<u8>)> {
+        Vec::new()
+    }
+
+    /// Appends the encoding of one face of one mip level, `width`×`height`
+    /// texels in row-major order, to `out`.
+    fn encode(&self, texels: &[[f32; 4]], width: u32, height: u32, out: &mut Vec<u8>);
+}
+
+/// Shared-exponent `E5B9G9R9_UFLOAT_PACK32`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rgb9e5Encoder;
+
+impl TexelEncoder for Rgb9e5Encoder {
+    fn ktx2_format(&self) -> ktx2::Format {
+        ktx2::Format::E5B9G9R9_UFLOAT_PACK32
+    }
+
+    fn type_size(&self) -> u32 {
+        4
+    }
+
+    fn dfd(&self) -> Vec<u8> {
+        create_rgb9e5_dfd()
+    }
+
+    fn encode(&self, texels: &[[f32; 4]], _width: u32, _height: u32, out: &mut Vec<u8>) {
+        for texel in texels {
+            out.extend_from_slice(&float3_to_rgb9e5(&texel[..3]).to_le_bytes());
+        }
+    }
+}
+
+/// `R16G16B16A16_SFLOAT`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rgba16FloatEncoder;
+
+impl TexelEncoder for Rgba16FloatEncoder {
+    fn ktx2_format(&self) -> ktx2::Format {
+        ktx2::Format::R16G16B16A16_SFLOAT
+    }
+
+    fn type_size(&self) -> u32 {
+        2
+    }
+
+    fn dfd(&self) -> Vec<u8> {
+        create_rgba16f_dfd()
+    }
+
+    fn encode(&self, texels: &[[f32; 4]], _width: u32, _height: u32, out: &mut Vec<u8>) {
+        for c in texels.iter().flatten() {
+            out.extend_from_slice(&half::f16::from_f32(*c).to_le_bytes());
+        }
+    }
+}
+
+/// `B10G11R11_UFLOAT_PACK32`, no shared exponent but one bit less of mantissa.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct B10g11r11Encoder;
+
+impl TexelEncoder for B10g11r11Encoder {
+    fn ktx2_format(&self) -> ktx2::Format {
+        ktx2::Format::B10G11R11_UFLOAT_PACK32
+    }
+
+    fn type_size(&self) -> u32 {
+        4
+    }
+
+    fn dfd(&self) -> Vec<u8> {
+        create_b10g11r11_dfd()
+    }
+
+    fn encode(&self, texels: &[[f32; 4]], _width: u32, _height: u32, out: &mut Vec<u8>) {
+        for texel in texels {
+            out.extend_from_slice(&float3_to_b10g11r11(&texel[..3]).to_le_bytes());
+        }
+    }
+}
+
+/// Block-compressed `BC6H_UFLOAT_BLOCK`, 1 byte per texel.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Bc6hEncoder;
+
+impl TexelEncoder for Bc6hEncoder {
+    fn ktx2_format(&self) -> ktx2::Format {
+        ktx2::Format::BC6H_UFLOAT_BLOCK
+    }
+
+    fn type_size(&self) -> u32 {
+        1
+    }
+
+    fn dfd(&self) -> Vec<u8> {
+        create_bc6h_dfd()
+    }
+
+    fn encode(&self, texels: &[[f32; 4]], width: u32, height: u32, out: &mut Vec<u8>) {
+        out.extend_from_slice(&compress_bc6h(texels, width, height));
+    }
+}
+
+/// LogLuv32 packed into `R8G8B8A8_UNORM`, tagged in the key/value metadata.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogLuv32Encoder;
+
+impl TexelEncoder for LogLuv32Encoder {
+    fn ktx2_format(&self) -> ktx2::Format {
+        ktx2::Format::R8G8B8A8_UNORM
+    }
+
+    fn type_size(&self) -> u32 {
+        1
+    }
+
+    fn dfd(&self) -> Vec<u8> {
+        create_rgba8_dfd()
+    }
+
+    fn key_values(&self) -> Vec<(String, Vec<u8>)> {
+        vec![(
+            metadata::ENCODING_KEY.to_string(),
+            metadata::string_value("LogLuv32"),
+        )]
+    }
+
+    fn encode(&self, texels: &[[f32; 4]], _width: u32, _height: u32, out: &mut Vec<u8>) {
+        for texel in texels {
+            out.extend_from_slice(&float3_to_logluv32(&texel[..3]));
+        }
+    }
+}
+
+/// RGBM packed into `R8G8B8A8_UNORM`, with `range` stored in the key/value metadata.
+#[derive(Clone, Copy, Debug)]
+pub struct RgbmEncoder {
+    pub range: f32,
+}
+
+impl TexelEncoder for RgbmEncoder {
+    fn ktx2_format(&self) -> ktx2::Format {
+        ktx2::Format::R8G8B8A8_UNORM
+    }
+
+    fn type_size(&self) -> u32 {
+        1
+    }
+
+    fn dfd(&self) -> Vec<u8> {
+        create_rgba8_dfd()
+    }
+
+    fn key_values(&self) -> Vec<(String, Vec<u8>)> {
+        range_key_values("RGBM", self.range)
+    }
+
+    fn encode(&self, texels: &[[f32; 4]], _width: u32, _height: u32, out: &mut Vec<u8>) {
+        for texel in texels {
+            out.extend_from_slice(&float3_to_rgbm(&texel[..3], self.range));
+        }
+    }
+}
+
+/// RGBD packed into `R8G8B8A8_UNORM`, with `range` stored in the key/value metadata.
+#[derive(Clone, Copy, Debug)]
+pub struct RgbdEncoder {
+    pub range: f32,
+}
+
+impl TexelEncoder for RgbdEncoder {
+    fn ktx2_format(&self) -> ktx2::Format {
+        ktx2::Format::R8G8B8A8_UNORM
+    }
+
+    fn type_size(&self) -> u32 {
+        1
+    }
+
+    fn dfd(&self) -> Vec<u8> {
+        create_rgba8_dfd()
+    }
+
+    fn key_values(&self) -> Vec<(String, Vec<u8>)> {
+        range_key_values("RGBD", self.range)
+    }
+
+    fn encode(&self, texels: &[[f32; 4]], _width: u32, _height: u32, out: &mut Vec<u8>) {
+        for texel in texels {
+            out.extend_from_slice(&float3_to_rgbd(&texel[..3], self.range));
+        }
+    }
+}
+
+/// Default of [`Rgba8Encoder::range`], for LDR environments.
+pub const DEFAULT_RGBA8_RANGE: f32 = 1.0;
+
+/// Color divided by `range` in `R8G8B8A8_SRGB`, keeping alpha, with `range`
+/// stored in the key/value metadata. Colors above `range` are clipped.
+#[derive(Clone, Copy, Debug)]
+pub struct Rgba8Encoder {
+    pub range: f32,
+}
+
+impl TexelEncoder for Rgba8Encoder {
+    fn ktx2_format(&self) -> ktx2::Format {
+        ktx2::Format::R8G8B8A8_SRGB
+    }
+
+    fn type_size(&self) -> u32 {
+        1
+    }
+
+    fn dfd(&self) -> Vec<u8> {
+        create_rgba8_srgb_dfd()
+    }
+
+    fn key_values(&self) -> Vec<(String, Vec<u8>)> {
+        range_key_values("RGBA8", self.range)
+    }
+
+    fn encode(&self, texels: &[[f32; 4]], _width: u32, _height: u32, out: &mut Vec<u8>) {
+        let unorm8 = |v: f32| (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8;
+        for [r, g, b, a] in texels {
+            let srgb = |c: f32| unorm8(TransferFunction::Srgb.from_linear(c / self.range));
+            out.extend_from_slice(&[srgb(*r), srgb(*g), srgb(*b), unorm8(*a)]);
+        }
+    }
+}
+
+fn range_key_values(encoding: &str, range: f32) -> Vec<(String, Vec<u8>)> {
+    vec![
+        (
+            metadata::ENCODING_KEY.to_string(),
+            metadata::string_value(encoding),
+        ),
+        (
+            metadata::RANGE_KEY.to_string(),
+            metadata::string_value(&range.to_string()),
+        ),
+    ]
+}
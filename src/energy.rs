@@ -0,0 +1,114 @@
+//! Energy conservation analysis of prefiltered environment maps.
+//!
+//! Prefiltering only redistributes radiance over the sphere, so every mip
+//! level should integrate to the same total radiance as the source. Drift away
+//! from it shows up as environment lighting that darkens or brightens with
+//! roughness.
+
+use std::fmt;
+
+use bevy::math::Vec3;
+
+use crate::{
+    adjust::luminance,
+    cubemap::{texel_solid_angle, FACE_COUNT},
+    source::EnvmapSource,
+};
+
+/// Radiance of `mip_level` integrated over the sphere, per color channel.
+pub fn total_radiance(source: &dyn EnvmapSource, mip_level: u32) -> Vec3 {
+    let size = (source.face_size() >> mip_level).max(1);
+    let mut total = Vec3::ZERO;
+    for face in 0..FACE_COUNT {
+        for (i, [r, g, b, _]) in source.face_texels(face, mip_level).into_iter().enumerate() {
+            let (x, y) = (i as u32 % size, i as u32 / size);
+            total += Vec3::new(r, g, b) * texel_solid_angle(x, y, size);
+        }
+    }
+    total
+}
+
+/// Total radiance of one mip level compared to the source.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LevelEnergy {
+    pub mip_level: u32,
+    pub radiance: Vec3,
+    /// Relative luminance change against the source, e.g. `-0.08` for 8% lost.
+    pub drift: f32,
+}
+
+/// Energy of the source and of every level of a prefiltered map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnergyReport {
+    pub source: Vec3,
+    pub levels: Vec<LevelEnergy>,
+}
+
+impl EnergyReport {
+    /// Largest absolute drift over all levels.
+    pub fn max_drift(&self) -> f32 {
+        self.levels
+            .iter()
+            .map(|level| level.drift.abs())
+            .fold(0.0, f32::max)
+    }
+}
+
+/// Compares the total radiance of every mip level of `prefiltered` against the
+/// top level of `source`.
+pub fn energy_report(source: &dyn EnvmapSource, prefiltered: &dyn EnvmapSource) -> EnergyReport {
+    let source_radiance = total_radiance(source, 0);
+    let source_luminance = luminance(source_radiance.to_array());
+    let levels = (0..prefiltered.mip_level_count())
+        .map(|mip_level| {
+            let radiance = total_radiance(prefiltered, mip_level);
+            LevelEnergy {
+                mip_level,
+                radiance,
+                drift: luminance(radiance.to_array()) / source_luminance - 1.0,
+            }
+        })
+        .collect();
+    EnergyReport {
+        source: source_radiance,
+        levels,
+    }
+}
+
+impl fmt::Display for EnergyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [r, g, b] = self.source.to_array();
+        writeln!(f, "source: {r:.4} {g:.4} {b:.4}")?;
+        for level in &self.levels {
+            let [r, g, b] = level.radiance.to_array();
+            writeln!(
+                f,
+                "  {}: {r:.4} {g:.4} {b:.4} ({:+.2}%)",
+                level.mip_level,
+                level.drift * 100.0
+            )?;
+        }
+        write!(f, "max drift: {:.2}%", self.max_drift() * 100.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generate::solid_color_cubemap,
+        prefilter::{prefilter_specular, PrefilterSettings},
+    };
+
+    #[test]
+    fn uniform_environment_keeps_its_radiance() {
+        let source = solid_color_cubemap(16, [1.0, 0.5, 0.25, 1.0]);
+        let prefiltered = prefilter_specular(&source, &PrefilterSettings::default());
+        let report = energy_report(&source, &prefiltered);
+        assert_eq!(report.levels.len(), 5);
+        for level in &report.levels {
+            let error = (level.radiance - report.source).abs().max_element();
+            assert!(error <= report.source.max_element() * 0.01, "{report}");
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Equirectangular (latitude-longitude) panoramas, the layout most HDRIs are
+//! shared in, and their conversion to cubemaps.
+
+use std::f32::consts::PI;
+
+use bevy::{
+    math::{Vec3, Vec4},
+    prelude::Image,
+};
+
+use crate::{color::read_texels, cubemap_data::CubemapData};
+
+/// How [`Equirect::to_two_to_one`] treats panoramas that aren't exactly 2:1.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EquirectAspect {
+    /// Take the longer side as spanning its full angle and fill the missing
+    /// part of the shorter one with the average of the adjacent edge. Phone
+    /// panoramas are usually 360° wide but cover less than 180° vertically.
+    #[default]
+    Letterbox,
+    /// Cut the longer side down to 2:1, keeping the center.
+    Crop,
+    /// Scale to 2:1, distorting the environment.
+    Stretch,
+}
+
+/// Linear RGBA panorama, azimuth along the rows and elevation down the columns.
+/// The center looks towards -Z, as Bevy's cameras do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Equirect {
+    pub width: u32,
+    pub height: u32,
+    /// Row-major texels.
+    pub texels: Vec<[f32; 4]>,
+}
+
+impl Equirect {
+    /// Reads the top level of a 2D image in any format [`read_texels`] reads,
+    /// taking the color channels as stored.
+    pub fn from_image(image: &Image) -> std::io::Result<Self> {
+        let size = image.texture_descriptor.size;
+        let texel_count = (size.width * size.height) as usize;
+        let mut texels = read_texels(image)?;
+        texels.truncate(texel_count);
+        Ok(Self {
+            width: size.width,
+            height: size.height,
+            texels,
+        })
+    }
+
+    /// Whether the panorama is 2:1, spanning 360° by 180°.
+    pub fn is_two_to_one(&self) -> bool {
+        self.width == 2 * self.height
+    }
+
+    /// The panorama made 2:1 as chosen by `aspect`. [`EquirectAspect::Stretch`]
+    /// only changes how it's sampled, so it's returned as it is.
+    pub fn to_two_to_one(&self, aspect: EquirectAspect) -> Self {
+        if self.is_two_to_one() || aspect == EquirectAspect::Stretch {
+            return self.clone();
+        }
+        let wide = self.width > 2 * self.height;
+        match aspect {
+            EquirectAspect::Letterbox if wide => self.letterbox(self.width, self.width / 2),
+            EquirectAspect::Letterbox => self.letterbox(self.height * 2, self.height),
+            EquirectAspect::Crop => {
+                let (width, height) = if wide {
+                    (self.height * 2, self.height)
+                } else {
+                    (self.width, self.width / 2)
+                };
+                let (x0, y0) = ((self.width - width) / 2, (self.height - height) / 2);
+                Self {
+                    width,
+                    height,
+                    texels: (0..height)
+                        .flat_map(|y| (0..width).map(move |x| (x, y)))
+                        .map(|(x, y)| self.texel(x0 + x, y0 + y))
+                        .collect(),
+                }
+            }
+            EquirectAspect::Stretch => unreachable!(),
+        }
+    }
+
+    fn letterbox(&self, width: u32, height: u32) -> Self {
+        let average = |texels: &mut dyn Iterator<Item = [f32; 4]>| {
+            let (sum, count) = texels.fold((Vec4::ZERO, 0.0), |(sum, count), texel| {
+                (sum + Vec4::from_array(texel), count + 1.0)
+            });
+            (sum / count).to_array()
+        };
+        let (x0, y0) = ((width - self.width) / 2, (height - self.height) / 2);
+        // Fill of the texels before and after the panorama.
+        let (before, after) = if width > self.width {
+            (
+                average(&mut (0..self.height).map(|y| self.texel(0, y))),
+                average(&mut (0..self.height).map(|y| self.texel(self.width - 1, y))),
+            )
+        } else {
+            (
+                average(&mut (0..self.width).map(|x| self.texel(x, 0))),
+                average(&mut (0..self.width).map(|x| self.texel(x, self.height - 1))),
+            )
+        };
+
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let texel = if x < x0 || y < y0 {
+                    before
+                } else if x >= x0 + self.width || y >= y0 + self.height {
+                    after
+                } else {
+                    self.texel(x - x0, y - y0)
+                };
+                texels.push(texel);
+            }
+        }
+        Self {
+            width,
+            height,
+            texels,
+        }
+    }
+
+    fn texel(&self, x: u32, y: u32) -> [f32; 4] {
+        self.texels[(y * self.width + x) as usize]
+    }
+
+    /// Bilinear lookup in direction `dir`, wrapping around horizontally.
+    pub fn sample(&self, dir: Vec3) -> [f32; 4] {
+        let dir = dir.normalize();
+        let azimuth = dir.x.atan2(-dir.z);
+        let polar = dir.y.clamp(-1.0, 1.0).acos();
+        let x = (azimuth + PI) / (2.0 * PI) * self.width as f32 - 0.5;
+        let y = (polar / PI * self.height as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+
+        let (x0, y0) = (x.floor(), y.floor() as u32);
+        let (fx, fy) = (x - x0, y - y0 as f32);
+        let wrap = |x: f32| (x.rem_euclid(self.width as f32) as u32).min(self.width - 1);
+        let (x0, x1) = (wrap(x0), wrap(x0 + 1.0));
+        let y1 = (y0 + 1).min(self.height - 1);
+
+        let at = |x: u32, y: u32| Vec4::from_array(self.texel(x, y));
+        let top = at(x0, y0).lerp(at(x1, y0), fx);
+        let bottom = at(x0, y1).lerp(at(x1, y1), fx);
+        top.lerp(bottom, fy).to_array()
+    }
+
+    /// Resamples the panorama into a single-mip `Rgba16Float` cubemap.
+    pub fn to_cubemap(&self, face_size: u32) -> Image {
+        let mut data = CubemapData::new(face_size, 1, 1);
+        data.fill(|_, _, dir| self.sample(dir));
+        data.to_image()
+    }
+
+    /// Face size that keeps about the resolution of the panorama at the equator.
+    pub fn default_face_size(&self) -> u32 {
+        (self.width / 4).max(1)
+    }
+}
@@ -0,0 +1,153 @@
+//! Dual-fisheye images from 360° cameras, two back-to-back circular fisheyes
+//! side by side, stitched into cubemaps without the vendor's stitching app.
+
+use bevy::{
+    math::{Vec2, Vec3, Vec4},
+    prelude::Image,
+};
+
+use crate::{color::read_texels, cubemap_data::CubemapData};
+
+/// Placement and projection of one lens in the image. Lenses are taken as
+/// equidistant, the distance from the center growing linearly with the angle
+/// from the optical axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FisheyeLens {
+    /// Center of the image circle, in fractions of the image width and height.
+    pub center: Vec2,
+    /// Radius of the image circle, in fractions of the image height.
+    pub radius: f32,
+    /// Field of view across the image circle in degrees, usually a bit over 180.
+    pub fov: f32,
+    /// Clockwise rotation of the lens image in degrees.
+    pub roll: f32,
+}
+
+/// Default of [`FisheyeLens::fov`], about what consumer 360° cameras cover.
+pub const DEFAULT_FISHEYE_FOV: f32 = 190.0;
+
+/// Default of [`DualFisheye::blend_angle`].
+pub const DEFAULT_FISHEYE_BLEND: f32 = 10.0;
+
+/// Both lenses of a dual-fisheye image. The front lens looks towards -Z, the
+/// direction the center of an equirectangular panorama faces, the back lens
+/// towards +Z.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DualFisheye {
+    pub front: FisheyeLens,
+    pub back: FisheyeLens,
+    /// Degrees around the seam between the lenses over which they are blended.
+    pub blend_angle: f32,
+}
+
+impl Default for DualFisheye {
+    /// Ricoh Theta-style layout: a 2:1 image with the front lens filling the
+    /// left half and the back lens the right half.
+    fn default() -> Self {
+        let lens = |x| FisheyeLens {
+            center: Vec2::new(x, 0.5),
+            radius: 0.5,
+            fov: DEFAULT_FISHEYE_FOV,
+            roll: 0.0,
+        };
+        Self {
+            front: lens(0.25),
+            back: lens(0.75),
+            blend_angle: DEFAULT_FISHEYE_BLEND,
+        }
+    }
+}
+
+impl DualFisheye {
+    pub fn with_fov(mut self, degrees: f32) -> Self {
+        self.front.fov = degrees;
+        self.back.fov = degrees;
+        self
+    }
+
+    pub fn with_radius(mut self, radius: f32) -> Self {
+        self.front.radius = radius;
+        self.back.radius = radius;
+        self
+    }
+
+    pub fn with_blend_angle(mut self, degrees: f32) -> Self {
+        self.blend_angle = degrees;
+        self
+    }
+}
+
+/// Texels of the whole dual-fisheye image, sampled bilinearly.
+struct FisheyeImage {
+    width: u32,
+    height: u32,
+    texels: Vec<[f32; 4]>,
+}
+
+impl FisheyeImage {
+    fn sample(&self, x: f32, y: f32) -> Vec4 {
+        let x = (x - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let y = (y - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+        let at = |x: u32, y: u32| Vec4::from_array(self.texels[(y * self.width + x) as usize]);
+        let top = at(x0, y0).lerp(at(x1, y0), fx);
+        let bottom = at(x0, y1).lerp(at(x1, y1), fx);
+        top.lerp(bottom, fy)
+    }
+
+    /// Texel `lens` sees in direction `local`, given in the frame of the lens
+    /// with +Z along its optical axis, or `None` outside its field of view.
+    fn sample_lens(&self, lens: &FisheyeLens, local: Vec3) -> Option<Vec4> {
+        let theta = local.z.clamp(-1.0, 1.0).acos();
+        let half_fov = lens.fov.to_radians() / 2.0;
+        if theta > half_fov {
+            return None;
+        }
+        let phi = local.y.atan2(local.x) - lens.roll.to_radians();
+        let r = theta / half_fov * lens.radius * self.height as f32;
+        let x = lens.center.x * self.width as f32 + r * phi.cos();
+        let y = lens.center.y * self.height as f32 - r * phi.sin();
+        Some(self.sample(x, y))
+    }
+}
+
+/// Stitches the top level of a dual-fisheye image in any format
+/// [`read_texels`] reads into a single-mip `Rgba16Float` cubemap. The color
+/// channels are taken as stored.
+pub fn dual_fisheye_to_cubemap(
+    image: &Image,
+    lenses: &DualFisheye,
+    face_size: u32,
+) -> std::io::Result<Image> {
+    let size = image.texture_descriptor.size;
+    let mut texels = read_texels(image)?;
+    texels.truncate((size.width * size.height) as usize);
+    let fisheye = FisheyeImage {
+        width: size.width,
+        height: size.height,
+        texels,
+    };
+    let blend = lenses.blend_angle.to_radians().max(1e-4);
+
+    let mut data = CubemapData::new(face_size, 1, 1);
+    data.fill(|_, _, dir| {
+        // The front lens looks along -Z with +X to its right, the back lens
+        // along +Z with -X to its right.
+        let front = fisheye.sample_lens(&lenses.front, Vec3::new(dir.x, dir.y, -dir.z));
+        let back = fisheye.sample_lens(&lenses.back, Vec3::new(-dir.x, dir.y, dir.z));
+        let color = match (front, back) {
+            (Some(front), Some(back)) => {
+                // Angle past the seam plane, positive towards the front lens.
+                let elevation = (-dir.z).clamp(-1.0, 1.0).asin();
+                let t = (elevation / blend + 0.5).clamp(0.0, 1.0);
+                back.lerp(front, t)
+            }
+            (Some(color), None) | (None, Some(color)) => color,
+            (None, None) => Vec4::ZERO,
+        };
+        color.to_array()
+    });
+    Ok(data.to_image())
+}
@@ -0,0 +1,37 @@
+//! Procedural cubemaps with analytically known content, for validating
+//! conversions and downstream loaders.
+
+use bevy::{math::Vec3, prelude::Image};
+
+use crate::cubemap::cubemap_from_fn;
+
+/// Cubemap where every texel is `color`.
+pub fn solid_color_cubemap(face_size: u32, color: [f32; 4]) -> Image {
+    cubemap_from_fn(face_size, |_| color)
+}
+
+/// Cubemap blending from `bottom` straight down (-Y) to `top` straight up (+Y),
+/// linear in the y component of the direction.
+pub fn gradient_cubemap(face_size: u32, top: [f32; 4], bottom: [f32; 4]) -> Image {
+    cubemap_from_fn(face_size, |dir| {
+        let t = dir.y * 0.5 + 0.5;
+        std::array::from_fn(|i| bottom[i] + (top[i] - bottom[i]) * t)
+    })
+}
+
+/// Cubemap where every texel stores the direction through its center, see
+/// [`encode_direction`].
+pub fn direction_cubemap(face_size: u32) -> Image {
+    cubemap_from_fn(face_size, encode_direction)
+}
+
+/// Maps a unit direction into `[0, 1]` so it survives unsigned formats like RGB9E5.
+pub fn encode_direction(dir: Vec3) -> [f32; 4] {
+    let c = dir * 0.5 + 0.5;
+    [c.x, c.y, c.z, 1.0]
+}
+
+/// Inverse of [`encode_direction`].
+pub fn decode_direction(texel: [f32; 4]) -> Vec3 {
+    (Vec3::new(texel[0], texel[1], texel[2]) * 2.0 - 1.0).normalize()
+}
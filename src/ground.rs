@@ -0,0 +1,274 @@
+//! Fixes for the lower hemisphere of captured environments, where the ground
+//! is often unusable: a tripod, the photographer, or a stitching seam.
+
+use std::f32::consts::PI;
+
+use bevy::{
+    math::{Quat, Vec3, Vec4},
+    prelude::Image,
+};
+
+use crate::{
+    cubemap::{sample_bilinear, texel_direction},
+    cubemap_data::CubemapData,
+    source::EnvmapSource,
+};
+
+/// What replaces the environment below the horizon.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GroundFill {
+    /// Linear RGB.
+    Solid([f32; 3]),
+    /// Linear RGB blended from `horizon` at the horizon to `nadir` straight down.
+    Gradient { horizon: [f32; 3], nadir: [f32; 3] },
+}
+
+impl GroundFill {
+    /// Color at `t`, 0 at the horizon and 1 at the nadir.
+    fn color(&self, t: f32) -> Vec3 {
+        match *self {
+            GroundFill::Solid(color) => Vec3::from_array(color),
+            GroundFill::Gradient { horizon, nadir } => {
+                Vec3::from_array(horizon).lerp(Vec3::from_array(nadir), t)
+            }
+        }
+    }
+}
+
+/// Replacement of everything below a horizon, see [`replace_ground`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroundReplacement {
+    pub fill: GroundFill,
+    /// Elevation of the horizon in degrees, negative to keep some of the
+    /// captured ground. Clamped to -90..=90.
+    pub horizon_angle: f32,
+    /// Degrees above the horizon over which the environment fades into the
+    /// fill, 0 for a hard edge.
+    pub blend_angle: f32,
+}
+
+impl GroundReplacement {
+    pub fn new(fill: GroundFill) -> Self {
+        Self {
+            fill,
+            horizon_angle: 0.0,
+            blend_angle: 0.0,
+        }
+    }
+
+    pub fn with_horizon_angle(mut self, degrees: f32) -> Self {
+        self.horizon_angle = degrees;
+        self
+    }
+
+    pub fn with_blend_angle(mut self, degrees: f32) -> Self {
+        self.blend_angle = degrees;
+        self
+    }
+}
+
+/// Replaces the environment below `replacement.horizon_angle` with its fill,
+/// on every mip level. Alpha is kept. Returns a `Rgba16Float` cubemap.
+pub fn replace_ground(source: &dyn EnvmapSource, replacement: &GroundReplacement) -> Image {
+    let mut data = CubemapData::from_source(source);
+    let face_size = data.face_size();
+    let horizon = replacement.horizon_angle.clamp(-90.0, 90.0);
+    // Degrees from the horizon down to the nadir, spanned by the fill gradient.
+    let ground_depth = (horizon + 90.0).max(f32::EPSILON);
+    for (face, mip_level, texels) in data.iter_mut() {
+        let size = (face_size >> mip_level).max(1);
+        for (i, texel) in texels.iter_mut().enumerate() {
+            let (x, y) = (i as u32 % size, i as u32 / size);
+            let dir = texel_direction(face, x, y, size);
+            let elevation = dir.y.clamp(-1.0, 1.0).asin().to_degrees();
+            let ground = if elevation <= horizon {
+                1.0
+            } else if elevation < horizon + replacement.blend_angle {
+                1.0 - (elevation - horizon) / replacement.blend_angle
+            } else {
+                continue;
+            };
+
+            let t = ((horizon - elevation) / ground_depth).clamp(0.0, 1.0);
+            let fill = replacement.fill.color(t);
+            let color = Vec3::new(texel[0], texel[1], texel[2]).lerp(fill, ground);
+            *texel = [color.x, color.y, color.z, texel[3]];
+        }
+    }
+    data.to_image()
+}
+
+/// Reprojection of the lower hemisphere onto a flat ground, as seen from a
+/// different point than the capture, see [`project_ground`].
+///
+/// An environment captured 1.7 m above the floor shows the floor from 1.7 m up
+/// in every direction, which looks wrong for a camera close to the floor.
+/// Treating the lower hemisphere as a ground plane and looking at it from the
+/// actual camera position fixes the scale of the ground.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GroundProjection {
+    /// Height of the capture above the ground.
+    pub capture_height: f32,
+    /// Position of the camera relative to the ground point below the capture,
+    /// in the same unit. `y` is the camera height.
+    pub camera_position: Vec3,
+}
+
+impl GroundProjection {
+    /// Camera straight above or below the capture at `camera_height`.
+    pub fn new(capture_height: f32, camera_height: f32) -> Self {
+        Self {
+            capture_height,
+            camera_position: Vec3::new(0.0, camera_height, 0.0),
+        }
+    }
+
+    /// Direction from the capture towards what the camera sees in direction
+    /// `dir`. Directions above the horizon are taken as infinitely far away and
+    /// stay the same.
+    pub fn source_direction(&self, dir: Vec3) -> Vec3 {
+        if dir.y >= 0.0 {
+            return dir;
+        }
+        let hit = self.camera_position + dir * (self.camera_position.y.max(0.0) / -dir.y);
+        (hit - Vec3::new(0.0, self.capture_height, 0.0)).normalize()
+    }
+}
+
+/// Reprojects the lower hemisphere of every mip level as described by
+/// `projection`. Returns a `Rgba16Float` cubemap.
+pub fn project_ground(source: &dyn EnvmapSource, projection: &GroundProjection) -> Image {
+    let input = CubemapData::from_source(source);
+    let levels = (0..input.mip_level_count())
+        .map(|mip_level| input.cube(0, mip_level))
+        .collect::<Vec<_>>();
+
+    let mut projected = CubemapData::new(input.face_size(), input.mip_level_count(), 1);
+    projected.fill(|_, mip_level, dir| {
+        sample_bilinear(
+            &levels[mip_level as usize],
+            input.mip_size(mip_level),
+            projection.source_direction(dir),
+        )
+    });
+    projected.to_image()
+}
+
+/// How [`patch_nadir`] fills the region around the nadir.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NadirFill {
+    /// Reflect the surroundings into the region across its edge.
+    #[default]
+    Mirror,
+    /// Blend from the colors along the edge to their average at the nadir,
+    /// a smooth patch without any texture.
+    Blur,
+    /// Copy the neighboring region of the same size, keeping the texture of
+    /// the ground but leaving a visible edge.
+    Clone,
+}
+
+/// Patch over the tripod or the hole most captured panoramas have straight
+/// down, see [`patch_nadir`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NadirPatch {
+    pub fill: NadirFill,
+    /// Angular radius of the patched region around the nadir in degrees.
+    pub radius: f32,
+}
+
+/// Default of [`NadirPatch::radius`], enough for a tripod seen from a
+/// panoramic head.
+pub const DEFAULT_NADIR_RADIUS: f32 = 20.0;
+
+impl NadirPatch {
+    pub fn new(fill: NadirFill) -> Self {
+        Self {
+            fill,
+            radius: DEFAULT_NADIR_RADIUS,
+        }
+    }
+
+    pub fn with_radius(mut self, degrees: f32) -> Self {
+        self.radius = degrees;
+        self
+    }
+}
+
+/// Azimuth bins the edge colors of [`NadirFill::Blur`] are averaged in.
+const BLUR_BINS: usize = 32;
+
+/// Direction at `polar` radians from the nadir, in the azimuth of `dir`.
+fn from_nadir(dir: Vec3, polar: f32) -> Vec3 {
+    let horizontal = Vec3::new(dir.x, 0.0, dir.z).normalize_or_zero();
+    let horizontal = if horizontal == Vec3::ZERO {
+        Vec3::X
+    } else {
+        horizontal
+    };
+    horizontal * polar.sin() - Vec3::Y * polar.cos()
+}
+
+fn azimuth_bin(dir: Vec3) -> f32 {
+    let azimuth = dir.z.atan2(dir.x).rem_euclid(2.0 * PI);
+    azimuth / (2.0 * PI) * BLUR_BINS as f32
+}
+
+/// Fills the region within `patch.radius` of the nadir on every mip level.
+/// Returns a `Rgba16Float` cubemap.
+pub fn patch_nadir(source: &dyn EnvmapSource, patch: &NadirPatch) -> Image {
+    let input = CubemapData::from_source(source);
+    let levels = (0..input.mip_level_count())
+        .map(|mip_level| input.cube(0, mip_level))
+        .collect::<Vec<_>>();
+    let radius = patch.radius.to_radians().clamp(0.0, PI / 2.0);
+    let sample = |mip_level: u32, dir: Vec3| {
+        sample_bilinear(&levels[mip_level as usize], input.mip_size(mip_level), dir)
+    };
+
+    // Average color of each azimuth bin in a ring just outside the region,
+    // and of the whole ring, per mip level.
+    let ring = if patch.fill == NadirFill::Blur {
+        (0..input.mip_level_count())
+            .map(|mip_level| {
+                let bins = (0..BLUR_BINS)
+                    .map(|bin| {
+                        let azimuth = (bin as f32 + 0.5) / BLUR_BINS as f32 * 2.0 * PI;
+                        let dir = Vec3::new(azimuth.cos(), 0.0, azimuth.sin());
+                        (1..=4)
+                            .map(|i| {
+                                let polar = radius * (1.0 + 0.05 * i as f32);
+                                Vec4::from_array(sample(mip_level, from_nadir(dir, polar)))
+                            })
+                            .sum::<Vec4>()
+                            / 4.0
+                    })
+                    .collect::<Vec<_>>();
+                let center = bins.iter().copied().sum::<Vec4>() / BLUR_BINS as f32;
+                (bins, center)
+            })
+            .collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+
+    let mut patched = CubemapData::new(input.face_size(), input.mip_level_count(), 1);
+    patched.fill(|_, mip_level, dir| {
+        let polar = (-dir.y).clamp(-1.0, 1.0).acos();
+        if polar >= radius {
+            return sample(mip_level, dir);
+        }
+        match patch.fill {
+            NadirFill::Mirror => sample(mip_level, from_nadir(dir, 2.0 * radius - polar)),
+            NadirFill::Clone => sample(mip_level, Quat::from_rotation_x(2.0 * radius) * dir),
+            NadirFill::Blur => {
+                let (bins, center) = &ring[mip_level as usize];
+                let bin = azimuth_bin(dir) - 0.5;
+                let i = bin.floor().rem_euclid(BLUR_BINS as f32) as usize;
+                let edge = bins[i].lerp(bins[(i + 1) % BLUR_BINS], bin - bin.floor());
+                center.lerp(edge, polar / radius).to_array()
+            }
+        }
+    });
+    patched.to_image()
+}
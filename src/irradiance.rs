@@ -0,0 +1,106 @@
+use bevy::{math::Vec3, prelude::Image};
+
+use crate::{
+    accumulate::{Accumulation, WeightedSum},
+    cubemap::{texel_direction, texel_solid_angle, FACE_COUNT},
+    cubemap_data::CubemapData,
+    source::EnvmapSource,
+};
+
+/// Radiance projected onto the first three bands of real spherical harmonics,
+/// which is all the diffuse irradiance depends on (Ramamoorthi and Hanrahan,
+/// "An Efficient Representation for Irradiance Environment Maps").
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SphericalHarmonics9 {
+    pub coefficients: [Vec3; 9],
+}
+
+impl SphericalHarmonics9 {
+    /// Projects the top mip level of a linear cubemap. The sums are kept in
+    /// f64, which costs little next to reading the texels.
+    pub fn project(source: &dyn EnvmapSource) -> Self {
+        let size = source.face_size();
+        let mut sums = [WeightedSum::new(Accumulation::F64); 9];
+        for face in 0..FACE_COUNT {
+            let texels = source.face_texels(face, 0);
+            for (i, [r, g, b, _]) in texels.into_iter().enumerate() {
+                let (x, y) = (i as u32 % size, i as u32 / size);
+                let weight = texel_solid_angle(x, y, size);
+                let basis = sh_basis(texel_direction(face, x, y, size));
+                for (sum, basis) in sums.iter_mut().zip(basis) {
+                    sum.add(Vec3::new(r, g, b), basis * weight);
+                }
+            }
+        }
+        Self {
+            coefficients: sums.map(|sum| sum.total()),
+        }
+    }
+
+    /// Irradiance around `normal` divided by π, i.e. the cosine weighted
+    /// average radiance. This is what Bevy expects in a diffuse environment map.
+    pub fn irradiance(&self, normal: Vec3) -> Vec3 {
+        // Clamped cosine convolution of each band, divided by π.
+        const BAND_FACTORS: [f32; 9] = [
+            1.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            2.0 / 3.0,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+            0.25,
+        ];
+        let basis = sh_basis(normal);
+        let mut irradiance = Vec3::ZERO;
+        for i in 0..9 {
+            irradiance += self.coefficients[i] * (basis[i] * BAND_FACTORS[i]);
+        }
+        irradiance.max(Vec3::ZERO)
+    }
+
+    /// The coefficients as JSON, `{"coefficients": [[r, g, b], ...]}` in the
+    /// band order of [`Self::coefficients`]: l = 0, then l = 1 for y, z and x,
+    /// then l = 2 for xy, yz, 3z² - 1, xz and x² - y².
+    pub fn to_json(&self) -> String {
+        let rows = self
+            .coefficients
+            .iter()
+            .map(|c| format!("    [{}, {}, {}]", c.x, c.y, c.z))
+            .collect::<Vec<_>>()
+            .join(",\n");
+        format!("{{\n  \"coefficients\": [\n{rows}\n  ]\n}}\n")
+    }
+}
+
+fn sh_basis(dir: Vec3) -> [f32; 9] {
+    let Vec3 { x, y, z } = dir;
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3.0 * z * z - 1.0),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+/// Diffuse irradiance map of a linear cubemap, evaluated at `face_size` with
+/// `mip_level_count` levels.
+pub fn irradiance_cubemap(
+    source: &dyn EnvmapSource,
+    face_size: u32,
+    mip_level_count: u32,
+) -> Image {
+    let sh = SphericalHarmonics9::project(source);
+    let mut data = CubemapData::new(face_size, mip_level_count, 1);
+    data.fill(|_, _, dir| {
+        let e = sh.irradiance(dir);
+        [e.x, e.y, e.z, 1.0]
+    });
+    data.to_image()
+}
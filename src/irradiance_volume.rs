@@ -0,0 +1,338 @@
+//! Irradiance volumes: ambient lighting sampled on a 3D grid, in the 3D texture
+//! layout of Bevy's `IrradianceVolume` light probes.
+//!
+//! Every voxel stores an [`AmbientCube`], the irradiance arriving from the six
+//! axis directions. The texture is `x`×`2y`×`3z` voxels: the depth slices hold
+//! the X, Y and Z axes one after another, and within each slice the top half
+//! holds the positive and the bottom half the negative direction.
+//!
+//! Volumes are baked from the scene with [`IrradianceVolumeBakePlugin`], which
+//! captures a small cubemap at every voxel center, or from any other lighting
+//! by filling the cubes directly and calling [`write_irradiance_volume`].
+
+use std::path::{Path, PathBuf};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+    tasks::AsyncComputeTaskPool,
+};
+
+use crate::{
+    capture::{CubemapCapture, CubemapCapturePlugin, CubemapCaptured},
+    cubemap::rgba_f32_to_rgba16f_bytes,
+    dfd::set_color_primaries,
+    irradiance::SphericalHarmonics9,
+    ktx2_writer::{Header, KTX2Writer, WriterLevel},
+    output::write_atomically,
+    pipeline::EncodeSettings,
+    source::EnvmapSource,
+};
+
+/// Directions of the ambient cube entries, +X, -X, +Y, -Y, +Z, -Z.
+pub const AMBIENT_CUBE_DIRECTIONS: [Vec3; 6] = [
+    Vec3::X,
+    Vec3::NEG_X,
+    Vec3::Y,
+    Vec3::NEG_Y,
+    Vec3::Z,
+    Vec3::NEG_Z,
+];
+
+/// Captures running at the same time while baking a volume.
+const MAX_CAPTURES_IN_FLIGHT: usize = 8;
+
+/// Irradiance divided by π around each of [`AMBIENT_CUBE_DIRECTIONS`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AmbientCube(pub [Vec3; 6]);
+
+impl AmbientCube {
+    pub fn from_spherical_harmonics(sh: &SphericalHarmonics9) -> Self {
+        Self(AMBIENT_CUBE_DIRECTIONS.map(|dir| sh.irradiance(dir)))
+    }
+
+    /// Ambient cube of the top mip level of a linear cubemap.
+    pub fn from_cubemap(source: &dyn EnvmapSource) -> Self {
+        Self::from_spherical_harmonics(&SphericalHarmonics9::project(source))
+    }
+}
+
+/// Number of voxels of a volume with `resolution` voxels along each axis.
+pub fn voxel_count(resolution: UVec3) -> usize {
+    (resolution.x * resolution.y * resolution.z) as usize
+}
+
+/// Voxel at `index` of the x-major, then y, then z order cubes are passed in.
+pub fn voxel_at(resolution: UVec3, index: usize) -> UVec3 {
+    let index = index as u32;
+    UVec3::new(
+        index % resolution.x,
+        index / resolution.x % resolution.y,
+        index / (resolution.x * resolution.y),
+    )
+}
+
+/// Center of `voxel` in the unit cube around the origin that the volume's
+/// transform maps onto the world.
+pub fn voxel_center(resolution: UVec3, voxel: UVec3) -> Vec3 {
+    Vec3::new(
+        (voxel.x as f32 + 0.5) / resolution.x as f32 - 0.5,
+        (voxel.y as f32 + 0.5) / resolution.y as f32 - 0.5,
+        (voxel.z as f32 + 0.5) / resolution.z as f32 - 0.5,
+    )
+}
+
+/// Texels of the volume texture in row-major order, slice by slice.
+fn volume_texels(resolution: UVec3, cubes: &[AmbientCube]) -> std::io::Result<Vec<[f32; 4]>> {
+    if cubes.len() != voxel_count(resolution) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Irradiance volume of {resolution:?} voxels needs {} ambient cubes, got {}",
+                voxel_count(resolution),
+                cubes.len()
+            ),
+        ));
+    }
+    let mut texels = Vec::with_capacity(cubes.len() * 6);
+    for axis in 0..3 {
+        for z in 0..resolution.z {
+            for sign in 0..2 {
+                for y in 0..resolution.y {
+                    for x in 0..resolution.x {
+                        let index = ((z * resolution.y + y) * resolution.x + x) as usize;
+                        let e = cubes[index].0[axis * 2 + sign];
+                        texels.push([e.x, e.y, e.z, 1.0]);
+                    }
+                }
+            }
+        }
+    }
+    Ok(texels)
+}
+
+/// `Rgba16Float` 3D texture of `cubes`, ready for Bevy's `IrradianceVolume`.
+pub fn irradiance_volume_image(resolution: UVec3, cubes: &[AmbientCube]) -> std::io::Result<Image> {
+    Ok(Image::new(
+        Extent3d {
+            width: resolution.x,
+            height: resolution.y * 2,
+            depth_or_array_layers: resolution.z * 3,
+        },
+        TextureDimension::D3,
+        rgba_f32_to_rgba16f_bytes(&volume_texels(resolution, cubes)?),
+        TextureFormat::Rgba16Float,
+        RenderAssetUsages::default(),
+    ))
+}
+
+/// Writes `cubes` as a 3D KTX2 texture with the format, primaries,
+/// supercompression and metadata of `settings`. The image stages of `settings`
+/// don't apply to volumes.
+pub fn write_irradiance_volume(
+    resolution: UVec3,
+    cubes: &[AmbientCube],
+    output_path: &Path,
+    settings: &EncodeSettings,
+) -> std::io::Result<()> {
+    let texels = volume_texels(resolution, cubes)?;
+    let (width, height) = (resolution.x, resolution.y * 2);
+
+    let encoder = &settings.encoder;
+    let mut level_bytes = Vec::new();
+    for slice in texels.chunks((width * height) as usize) {
+        encoder.encode(slice, width, height, &mut level_bytes);
+    }
+
+    let mut dfd_bytes = encoder.dfd();
+    set_color_primaries(&mut dfd_bytes, settings.primaries);
+    let mut key_values = encoder.key_values();
+    key_values.extend(settings.metadata.iter().cloned());
+
+    let writer = KTX2Writer {
+        header: Header {
+            format: Some(encoder.ktx2_format()),
+            type_size: encoder.type_size(),
+            pixel_width: width,
+            pixel_height: height,
+            pixel_depth: resolution.z * 3,
+            layer_count: 0,
+            face_count: 1,
+            supercompression_scheme: settings.supercompression.scheme(),
+        },
+        dfd_bytes: &dfd_bytes,
+        sgd_bytes: &[],
+        key_values,
+        levels_descending: vec![WriterLevel {
+            uncompressed_length: level_bytes.len(),
+            bytes: settings
+                .supercompression
+                .compress_level(&level_bytes, settings.min_compressed_level_size)?,
+        }],
+    };
+
+    write_atomically(output_path, settings.overwrite, |file| writer.write(file))?;
+    Ok(())
+}
+
+/// Bakes [`BakeIrradianceVolume`] requests, adding [`CubemapCapturePlugin`] if needed.
+pub struct IrradianceVolumeBakePlugin;
+
+impl Plugin for IrradianceVolumeBakePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<CubemapCapturePlugin>() {
+            app.add_plugins(CubemapCapturePlugin);
+        }
+        app.add_event::<BakeIrradianceVolume>()
+            .init_resource::<VolumeBakes>()
+            .add_systems(Update, (start_volume_bakes, advance_volume_bakes));
+    }
+}
+
+/// Requests an irradiance volume captured from the scene.
+#[derive(Event, Clone, Debug)]
+pub struct BakeIrradianceVolume {
+    /// Entity whose `GlobalTransform` maps the unit cube around the origin
+    /// onto the volume, as for Bevy's irradiance volume light probes.
+    pub volume: Entity,
+    /// Voxels along each axis.
+    pub resolution: UVec3,
+    /// Face size of the cubemap captured at each voxel.
+    pub face_size: u32,
+    /// Entities hidden while capturing, e.g. the player.
+    pub exclude: Vec<Entity>,
+    pub output_path: PathBuf,
+    /// Format, primaries, supercompression and metadata of the output.
+    pub settings: EncodeSettings,
+}
+
+impl Default for BakeIrradianceVolume {
+    fn default() -> Self {
+        Self {
+            volume: Entity::PLACEHOLDER,
+            resolution: UVec3::new(8, 4, 8),
+            face_size: 32,
+            exclude: Vec::new(),
+            output_path: PathBuf::from("irradiance_volume.ktx2"),
+            settings: EncodeSettings::default(),
+        }
+    }
+}
+
+/// A running volume bake, its finished voxels and the captures in flight.
+struct VolumeBake {
+    request: BakeIrradianceVolume,
+    transform: GlobalTransform,
+    cubes: Vec<AmbientCube>,
+    next_voxel: usize,
+    captures: Vec<(Entity, usize)>,
+}
+
+#[derive(Resource, Default)]
+struct VolumeBakes(Vec<VolumeBake>);
+
+/// Spawns captures for the next voxels until [`MAX_CAPTURES_IN_FLIGHT`] run.
+fn capture_next_voxels(commands: &mut Commands, bake: &mut VolumeBake) {
+    let resolution = bake.request.resolution;
+    while bake.captures.len() < MAX_CAPTURES_IN_FLIGHT && bake.next_voxel < bake.cubes.len() {
+        let local = voxel_center(resolution, voxel_at(resolution, bake.next_voxel));
+        let position = bake.transform.transform_point(local);
+        let entity = commands
+            .spawn((
+                GlobalTransform::from_translation(position),
+                CubemapCapture {
+                    face_size: bake.request.face_size,
+                    exclude: bake.request.exclude.clone(),
+                },
+            ))
+            .id();
+        bake.captures.push((entity, bake.next_voxel));
+        bake.next_voxel += 1;
+    }
+}
+
+fn start_volume_bakes(
+    mut commands: Commands,
+    mut requests: EventReader<BakeIrradianceVolume>,
+    mut bakes: ResMut<VolumeBakes>,
+    volumes: Query<&GlobalTransform>,
+) {
+    for request in requests.read() {
+        let Ok(transform) = volumes.get(request.volume) else {
+            error!(
+                "Irradiance volume {:?} has no GlobalTransform",
+                request.volume
+            );
+            continue;
+        };
+        if voxel_count(request.resolution) == 0 {
+            error!(
+                "Irradiance volume resolution {:?} is empty",
+                request.resolution
+            );
+            continue;
+        }
+        let mut bake = VolumeBake {
+            request: request.clone(),
+            transform: *transform,
+            cubes: vec![AmbientCube::default(); voxel_count(request.resolution)],
+            next_voxel: 0,
+            captures: Vec::new(),
+        };
+        capture_next_voxels(&mut commands, &mut bake);
+        bakes.0.push(bake);
+    }
+}
+
+fn advance_volume_bakes(
+    mut commands: Commands,
+    mut captured: EventReader<CubemapCaptured>,
+    mut bakes: ResMut<VolumeBakes>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for event in captured.read() {
+        let Some((index, capture)) = bakes.0.iter().enumerate().find_map(|(index, bake)| {
+            let capture = bake
+                .captures
+                .iter()
+                .position(|(entity, _)| *entity == event.entity)?;
+            Some((index, capture))
+        }) else {
+            continue;
+        };
+        commands.entity(event.entity).despawn();
+
+        let bake = &mut bakes.0[index];
+        let (_, voxel) = bake.captures.remove(capture);
+        if let Some(image) = images.remove(&event.image) {
+            bake.cubes[voxel] = AmbientCube::from_cubemap(&image);
+        }
+        capture_next_voxels(&mut commands, bake);
+        if !bake.captures.is_empty() {
+            continue;
+        }
+
+        let bake = bakes.0.remove(index);
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let request = bake.request;
+                let result = write_irradiance_volume(
+                    request.resolution,
+                    &bake.cubes,
+                    &request.output_path,
+                    &request.settings,
+                );
+                match result {
+                    Ok(()) => info!("Baked irradiance volume {}", request.output_path.display()),
+                    Err(e) => error!(
+                        "Failed to bake irradiance volume {}: {e}",
+                        request.output_path.display()
+                    ),
+                }
+            })
+            .detach();
+    }
+}
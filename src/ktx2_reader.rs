@@ -0,0 +1,648 @@
+use std::{
+    fmt,
+    io::{Error, ErrorKind, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use bevy::{
+    prelude::Image,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+            TextureViewDescriptor, TextureViewDimension,
+        },
+        texture::ImageSampler,
+    },
+};
+
+use crate::{
+    color::TransferFunction,
+    cubemap::rgba_f32_to_rgba16f_bytes,
+    dfd::{parse_dfd, BasicDescriptor},
+    ktx2_writer::{Header, KTX2Writer, WriterLevel},
+    logluv::logluv32_to_float3,
+    metadata::{
+        f32_list_value, parse_f32_list, string_value, CONTENT_HASH_KEY, ENCODING_KEY,
+        MIP_ROUGHNESS_KEY, ORIENTATION_KEY, PROVENANCE_KEY, RANGE_KEY, THUMBNAIL_KEY,
+        ZSTD_DICTIONARY_KEY,
+    },
+    orientation::{reorient, Orientation},
+    output::{write_atomically, OverwritePolicy},
+    pipeline::DEFAULT_MIN_COMPRESSED_LEVEL_SIZE,
+    provenance::Provenance,
+    rgbm::{rgbd_to_float3, rgbm_to_float3},
+    supercompression::{decompress, Supercompression, ZstdDictionary},
+};
+
+/// An existing KTX2 file, split into the parts `KTX2Writer` writes.
+pub struct KTX2File {
+    pub header: ktx2::Header,
+    pub dfd_bytes: Vec<u8>,
+    /// Supercompression global data of the scheme, empty for zstd.
+    pub sgd_bytes: Vec<u8>,
+    /// Key/value metadata in file order.
+    pub key_values: Vec<(String, Vec<u8>)>,
+    /// Level data as stored, still supercompressed, starting at the base level.
+    pub levels: Vec<ReaderLevel>,
+}
+
+pub struct ReaderLevel {
+    pub uncompressed_length: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl KTX2File {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(bytes: &[u8]) -> std::io::Result<Self> {
+        let reader = ktx2::Reader::new(bytes)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Invalid KTX2 file: {e}")))?;
+        let header = reader.header();
+
+        let dfd_start = header.index.dfd_byte_offset as usize;
+        let dfd_bytes = bytes
+            .get(dfd_start..dfd_start + header.index.dfd_byte_length as usize)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "Data-Format Descriptor is truncated",
+                )
+            })?
+            .to_vec();
+
+        let sgd_start = header.index.sgd_byte_offset as usize;
+        let sgd_bytes = bytes
+            .get(sgd_start..sgd_start + header.index.sgd_byte_length as usize)
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "Supercompression global data is truncated",
+                )
+            })?
+            .to_vec();
+
+        let key_values = reader
+            .key_value_data()
+            .map(|(key, value)| (key.to_string(), value.to_vec()))
+            .collect();
+
+        let levels = reader
+            .levels()
+            .map(|level| ReaderLevel {
+                uncompressed_length: level.uncompressed_byte_length as usize,
+                bytes: level.data.to_vec(),
+            })
+            .collect();
+
+        Ok(Self {
+            header,
+            dfd_bytes,
+            sgd_bytes,
+            key_values,
+            levels,
+        })
+    }
+
+    pub fn dfd(&self) -> std::io::Result<BasicDescriptor> {
+        parse_dfd(&self.dfd_bytes)
+    }
+
+    /// Levels with their supercompression undone, starting at the base level.
+    pub fn decompressed_levels(&self) -> std::io::Result<Vec<Vec<u8>>> {
+        self.decompressed_levels_with(None)
+    }
+
+    /// [`KTX2File::decompressed_levels`] of files that may be compressed with
+    /// a zstd dictionary, which has to be the one recorded in the metadata.
+    pub fn decompressed_levels_with(
+        &self,
+        dictionary: Option<&ZstdDictionary>,
+    ) -> std::io::Result<Vec<Vec<u8>>> {
+        if self.header.supercompression_scheme == Some(ZstdDictionary::scheme()) {
+            let Some(id) = self.dictionary_id() else {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "Levels are compressed with a zstd dictionary, but its id is missing",
+                ));
+            };
+            let dictionary = dictionary.filter(|dictionary| dictionary.id() == id);
+            let Some(dictionary) = dictionary else {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Levels need zstd dictionary {id} to be decompressed"),
+                ));
+            };
+            return self
+                .levels
+                .iter()
+                .map(|level| dictionary.decompress(&level.bytes, level.uncompressed_length))
+                .collect();
+        }
+
+        self.levels
+            .iter()
+            .map(|level| {
+                decompress(
+                    self.header.supercompression_scheme,
+                    &level.bytes,
+                    level.uncompressed_length,
+                )
+            })
+            .collect()
+    }
+
+    /// Id of the zstd dictionary the levels are compressed with, if any.
+    pub fn dictionary_id(&self) -> Option<u32> {
+        self.key_values
+            .iter()
+            .find(|(key, _)| key == ZSTD_DICTIONARY_KEY)
+            .and_then(|(_, value)| {
+                std::str::from_utf8(value)
+                    .ok()?
+                    .trim_end_matches('\0')
+                    .parse()
+                    .ok()
+            })
+    }
+
+    /// Compresses every level with zstd and a shared `dictionary` at `level`,
+    /// recording the dictionary id in the metadata. The file gets the vendor
+    /// scheme [`crate::supercompression::ZSTD_DICTIONARY_SCHEME`].
+    pub fn compress_with_dictionary(
+        &mut self,
+        dictionary: &ZstdDictionary,
+        level: i32,
+    ) -> std::io::Result<()> {
+        let levels = self.decompressed_levels_with(Some(dictionary))?;
+        self.levels = levels
+            .into_iter()
+            .map(|bytes| {
+                Ok(ReaderLevel {
+                    uncompressed_length: bytes.len(),
+                    bytes: dictionary.compress(&bytes, level)?,
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        self.header.supercompression_scheme = Some(ZstdDictionary::scheme());
+        self.sgd_bytes.clear();
+        self.key_values
+            .retain(|(key, _)| key != ZSTD_DICTIONARY_KEY);
+        self.key_values.push((
+            ZSTD_DICTIONARY_KEY.to_string(),
+            string_value(&dictionary.id().to_string()),
+        ));
+        Ok(())
+    }
+
+    /// Decodes RGBA8 texels packed with `encoding` to `Rgba16Float` bytes.
+    fn unpack(&self, encoding: &str, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        let range = || {
+            self.range().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{encoding} files need a range in the metadata"),
+                )
+            })
+        };
+        let texels = data.chunks_exact(4).map(|t| [t[0], t[1], t[2], t[3]]);
+        let decoded: Vec<[f32; 4]> = match encoding {
+            "LogLuv32" => texels
+                .map(|t| {
+                    let [r, g, b] = logluv32_to_float3(t);
+                    [r, g, b, 1.0]
+                })
+                .collect(),
+            "RGBM" => {
+                let range = range()?;
+                texels
+                    .map(|t| {
+                        let [r, g, b] = rgbm_to_float3(t, range);
+                        [r, g, b, 1.0]
+                    })
+                    .collect()
+            }
+            "RGBD" => {
+                let range = range()?;
+                texels
+                    .map(|t| {
+                        let [r, g, b] = rgbd_to_float3(t, range);
+                        [r, g, b, 1.0]
+                    })
+                    .collect()
+            }
+            "RGBA8" => {
+                let range = range()?;
+                let srgb = |v: u8| TransferFunction::Srgb.to_linear(v as f32 / 255.0) * range;
+                texels
+                    .map(|[r, g, b, a]| [srgb(r), srgb(g), srgb(b), a as f32 / 255.0])
+                    .collect()
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Decoding {encoding} files is not supported"),
+                ))
+            }
+        };
+        Ok(rgba_f32_to_rgba16f_bytes(&decoded))
+    }
+
+    /// PNG preview embedded in the metadata, if any.
+    pub fn thumbnail(&self) -> Option<&[u8]> {
+        self.key_values
+            .iter()
+            .find(|(key, _)| key == THUMBNAIL_KEY)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Provenance recorded by the tool that baked the file, if any.
+    pub fn provenance(&self) -> Option<Provenance> {
+        self.key_values
+            .iter()
+            .find(|(key, _)| key == PROVENANCE_KEY)
+            .and_then(|(_, value)| Provenance::parse(value))
+    }
+
+    /// Content hash recorded when the file was written, if any.
+    pub fn content_hash(&self) -> Option<&str> {
+        self.key_values
+            .iter()
+            .find(|(key, _)| key == CONTENT_HASH_KEY)
+            .and_then(|(_, value)| std::str::from_utf8(value).ok())
+            .map(|hash| hash.trim_end_matches('\0'))
+    }
+
+    /// [`Self::content_hash`] of the file at `path`, reading only its header
+    /// and key/value data rather than loading the whole file.
+    pub fn read_content_hash(path: &Path) -> std::io::Result<Option<String>> {
+        let mut file = std::fs::File::open(path)?;
+        let mut header = [0; ktx2::Header::LENGTH];
+        file.read_exact(&mut header)?;
+        let u32_at = |offset: usize| {
+            u32::from_le_bytes(header[offset..offset + 4].try_into().unwrap()) as usize
+        };
+        let (kvd_offset, kvd_length) = (u32_at(56), u32_at(60));
+        let mut kvd = vec![0; kvd_length];
+        file.seek(SeekFrom::Start(kvd_offset as u64))?;
+        file.read_exact(&mut kvd)?;
+
+        let mut offset = 0;
+        while let Some(length) = kvd.get(offset..offset + 4) {
+            let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+            let Some(entry) = kvd.get(offset + 4..offset + 4 + length) else {
+                break;
+            };
+            if let Some(value) = entry
+                .strip_prefix(CONTENT_HASH_KEY.as_bytes())
+                .and_then(|rest| rest.strip_prefix(b"\0"))
+            {
+                let hash = String::from_utf8_lossy(value);
+                return Ok(Some(hash.trim_end_matches('\0').to_string()));
+            }
+            offset = (offset + 4 + length).next_multiple_of(4);
+        }
+        Ok(None)
+    }
+
+    /// Perceptual roughness each mip level was prefiltered for, if recorded.
+    pub fn mip_roughness(&self) -> Option<Vec<f32>> {
+        self.key_values
+            .iter()
+            .find(|(key, _)| key == MIP_ROUGHNESS_KEY)
+            .and_then(|(_, value)| parse_f32_list(value))
+    }
+
+    /// Keeps the recorded roughness of the levels left after dropping `skipped`
+    /// levels from the top of the chain and any from its end.
+    fn retain_mip_roughness(&mut self, skipped: usize) {
+        let Some(roughness) = self.mip_roughness() else {
+            return;
+        };
+        let kept = roughness
+            .iter()
+            .skip(skipped)
+            .take(self.levels.len())
+            .copied()
+            .collect::<Vec<_>>();
+        for (key, value) in &mut self.key_values {
+            if key == MIP_ROUGHNESS_KEY {
+                *value = f32_list_value(&kept);
+            }
+        }
+    }
+
+    /// Orientation recorded in `KTXorientation`, the default right-down layout
+    /// when missing or unknown.
+    pub fn orientation(&self) -> Orientation {
+        self.key_values
+            .iter()
+            .find(|(key, _)| key == ORIENTATION_KEY)
+            .and_then(|(_, value)| Orientation::parse(value))
+            .unwrap_or_default()
+    }
+
+    /// HDR packing of an RGBA8 file, see [`ENCODING_KEY`].
+    pub fn encoding(&self) -> Option<&str> {
+        self.key_values
+            .iter()
+            .find(|(key, _)| key == ENCODING_KEY)
+            .and_then(|(_, value)| Some(std::str::from_utf8(value).ok()?.trim_end_matches('\0')))
+    }
+
+    /// Largest value of an RGBM, RGBD or RGBA8 file, see [`RANGE_KEY`].
+    pub fn range(&self) -> Option<f32> {
+        self.key_values
+            .iter()
+            .find(|(key, _)| key == RANGE_KEY)
+            .and_then(|(_, value)| {
+                std::str::from_utf8(value)
+                    .ok()?
+                    .trim_end_matches('\0')
+                    .parse()
+                    .ok()
+            })
+    }
+
+    /// Reconstructs the texture as an [`Image`] with the layout Bevy expects:
+    /// all mip levels of each face or layer together, and texels reoriented to
+    /// right-down according to `KTXorientation`. Files with an
+    /// [`KTX2File::encoding`] are unpacked to linear `Rgba16Float`.
+    pub fn to_image(&self) -> std::io::Result<Image> {
+        let header = &self.header;
+        let format = header
+            .format
+            .and_then(|format| texture_format(format.value()))
+            .ok_or_else(|| {
+                Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Loading {:?} files is not supported", header.format),
+                )
+            })?;
+
+        let dfd = self.dfd()?;
+        let orientation = self.orientation();
+        let block_size = dfd.bytes_planes[0] as usize;
+        if orientation != Orientation::RightDown && dfd.texel_block_dimensions[..2] != [0, 0] {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "Reorienting block-compressed textures is not supported",
+            ));
+        }
+
+        let levels = self.decompressed_levels()?;
+        let layers = header.layer_count.max(1) * header.face_count;
+        let mut data = Vec::new();
+        for layer in 0..layers as usize {
+            for (mip_level, level) in levels.iter().enumerate() {
+                let layer_size = level.len() / layers as usize;
+                let texels = &level[layer * layer_size..(layer + 1) * layer_size];
+                if orientation == Orientation::RightDown {
+                    data.extend_from_slice(texels);
+                    continue;
+                }
+                let texels = texels.chunks_exact(block_size).collect::<Vec<_>>();
+                let width = (header.pixel_width >> mip_level).max(1);
+                let height = (header.pixel_height >> mip_level).max(1);
+                for texel in reorient(&texels, width, height, orientation, Orientation::RightDown) {
+                    data.extend_from_slice(texel);
+                }
+            }
+        }
+
+        let (format, data) = match self.encoding() {
+            Some(encoding) => (TextureFormat::Rgba16Float, self.unpack(encoding, &data)?),
+            None => (format, data),
+        };
+
+        let view_dimension = match (header.face_count, header.layer_count) {
+            (6, 0 | 1) => Some(TextureViewDimension::Cube),
+            (6, _) => Some(TextureViewDimension::CubeArray),
+            (_, 0 | 1) => None,
+            _ => Some(TextureViewDimension::D2Array),
+        };
+        Ok(Image {
+            data,
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width: header.pixel_width,
+                    height: header.pixel_height.max(1),
+                    depth_or_array_layers: layers,
+                },
+                mip_level_count: levels.len() as u32,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            sampler: ImageSampler::Default,
+            texture_view_descriptor: view_dimension.map(|dimension| TextureViewDescriptor {
+                dimension: Some(dimension),
+                ..Default::default()
+            }),
+            asset_usage: RenderAssetUsages::default(),
+        })
+    }
+
+    /// Replaces the supercompression of every level, leaving the texel data untouched.
+    pub fn resupercompress(&mut self, supercompression: Supercompression) -> std::io::Result<()> {
+        let levels = self.decompressed_levels()?;
+        self.levels = levels
+            .into_iter()
+            .map(|bytes| {
+                Ok(ReaderLevel {
+                    uncompressed_length: bytes.len(),
+                    bytes: supercompression
+                        .compress_level(&bytes, DEFAULT_MIN_COMPRESSED_LEVEL_SIZE)?,
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        self.header.supercompression_scheme = supercompression.scheme();
+        // The global data belongs to the previous scheme.
+        self.sgd_bytes.clear();
+        self.key_values
+            .retain(|(key, _)| key != ZSTD_DICTIONARY_KEY);
+        Ok(())
+    }
+
+    /// Removes the `count` largest mip levels, always keeping the smallest one.
+    /// The remaining levels are stored as-is, so no re-encoding happens.
+    pub fn drop_largest_mips(&mut self, count: u32) {
+        let count = (count as usize).min(self.levels.len().saturating_sub(1));
+        if count == 0 {
+            return;
+        }
+        self.levels.drain(..count);
+        let header = &mut self.header;
+        header.pixel_width = (header.pixel_width >> count).max(1);
+        if header.pixel_height > 0 {
+            header.pixel_height = (header.pixel_height >> count).max(1);
+        }
+        if header.pixel_depth > 0 {
+            header.pixel_depth = (header.pixel_depth >> count).max(1);
+        }
+        header.level_count = self.levels.len() as u32;
+        self.retain_mip_roughness(count);
+    }
+
+    /// Keeps only the mip levels whose largest dimension lies within
+    /// `min_size..=max_size`. At least one level is always kept.
+    pub fn limit_mip_sizes(&mut self, max_size: Option<u32>, min_size: Option<u32>) {
+        fn level_size(header: &ktx2::Header, level: usize) -> u32 {
+            (header.pixel_width.max(header.pixel_height) >> level).max(1)
+        }
+
+        if let Some(max_size) = max_size {
+            let too_large = (0..self.levels.len())
+                .take_while(|level| level_size(&self.header, *level) > max_size)
+                .count();
+            self.drop_largest_mips(too_large as u32);
+        }
+
+        if let Some(min_size) = min_size {
+            let keep = (0..self.levels.len())
+                .take_while(|level| level_size(&self.header, *level) >= min_size)
+                .count()
+                .max(1);
+            self.levels.truncate(keep);
+            self.header.level_count = self.levels.len() as u32;
+            self.retain_mip_roughness(0);
+        }
+    }
+
+    /// Writes the file back out, updating its content hash if it has one.
+    pub fn write<T: std::io::Write>(&self, writer: &mut T) -> std::io::Result<()> {
+        let mut file = KTX2Writer {
+            header: Header {
+                format: self.header.format,
+                type_size: self.header.type_size,
+                pixel_width: self.header.pixel_width,
+                pixel_height: self.header.pixel_height,
+                pixel_depth: self.header.pixel_depth,
+                layer_count: self.header.layer_count,
+                face_count: self.header.face_count,
+                supercompression_scheme: self.header.supercompression_scheme,
+            },
+            dfd_bytes: &self.dfd_bytes,
+            sgd_bytes: &self.sgd_bytes,
+            key_values: self.key_values.clone(),
+            levels_descending: self
+                .levels
+                .iter()
+                .map(|level| WriterLevel {
+                    uncompressed_length: level.uncompressed_length,
+                    bytes: level.bytes.clone(),
+                })
+                .collect(),
+        };
+        if self.content_hash().is_some() {
+            let (bytes, _) = file.serialize_with_content_hash()?;
+            return writer.write_all(&bytes);
+        }
+        file.write(writer)
+    }
+
+    /// Writes the file to `path` atomically, replacing any existing file.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        write_atomically(path, OverwritePolicy::Overwrite, |file| self.write(file))?;
+        Ok(())
+    }
+}
+
+/// Trains a zstd dictionary of at most `max_size` bytes on the levels of
+/// `files`, using every face or layer of every level as a sample.
+pub fn train_dictionary(files: &[KTX2File], max_size: usize) -> std::io::Result<ZstdDictionary> {
+    let mut samples = Vec::new();
+    for file in files {
+        let layers = (file.header.layer_count.max(1) * file.header.face_count) as usize;
+        for level in file.decompressed_levels()? {
+            let layer_size = level.len() / layers;
+            if layer_size == 0 {
+                continue;
+            }
+            samples.extend(level.chunks(layer_size).map(<[u8]>::to_vec));
+        }
+    }
+    ZstdDictionary::train(&samples, max_size)
+}
+
+/// wgpu format of a `VkFormat`, for the formats [`KTX2File::to_image`] supports.
+fn texture_format(vk_format: u32) -> Option<TextureFormat> {
+    Some(match vk_format {
+        37 => TextureFormat::Rgba8Unorm,
+        43 => TextureFormat::Rgba8UnormSrgb,
+        97 => TextureFormat::Rgba16Float,
+        109 => TextureFormat::Rgba32Float,
+        122 => TextureFormat::Rg11b10Float,
+        123 => TextureFormat::Rgb9e5Ufloat,
+        143 => TextureFormat::Bc6hRgbUfloat,
+        144 => TextureFormat::Bc6hRgbFloat,
+        _ => return None,
+    })
+}
+
+/// Human readable summary of the header, levels, descriptor and metadata.
+impl fmt::Display for KTX2File {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let header = &self.header;
+        match header.format {
+            Some(format) => writeln!(f, "format: {format:?}")?,
+            None => writeln!(f, "format: UNDEFINED")?,
+        }
+        writeln!(f, "typeSize: {}", header.type_size)?;
+        writeln!(
+            f,
+            "pixelSize: {}×{}×{}",
+            header.pixel_width, header.pixel_height, header.pixel_depth
+        )?;
+        writeln!(f, "layerCount: {}", header.layer_count)?;
+        writeln!(f, "faceCount: {}", header.face_count)?;
+        writeln!(f, "levelCount: {}", header.level_count)?;
+        match header.supercompression_scheme {
+            Some(scheme) => writeln!(f, "supercompressionScheme: {scheme:?}")?,
+            None => writeln!(f, "supercompressionScheme: NONE")?,
+        }
+
+        if !self.sgd_bytes.is_empty() {
+            writeln!(
+                f,
+                "supercompressionGlobalData: {} bytes",
+                self.sgd_bytes.len()
+            )?;
+        }
+
+        writeln!(f, "\nlevels:")?;
+        for (i, level) in self.levels.iter().enumerate() {
+            writeln!(
+                f,
+                "  {i}: {} bytes, {} uncompressed",
+                level.bytes.len(),
+                level.uncompressed_length
+            )?;
+        }
+
+        writeln!(f, "\ndataFormatDescriptor:")?;
+        match self.dfd() {
+            Ok(dfd) => {
+                for line in dfd.to_string().lines() {
+                    writeln!(f, "  {line}")?;
+                }
+            }
+            Err(e) => writeln!(f, "  {e}")?,
+        }
+
+        writeln!(f, "\nkeyValueData:")?;
+        for (key, value) in &self.key_values {
+            let text = value.strip_suffix(&[0]).unwrap_or(value);
+            match std::str::from_utf8(text) {
+                Ok(text) => writeln!(f, "  {key}: {}", text.replace('\n', "\n    "))?,
+                Err(_) => writeln!(f, "  {key}: {} bytes", value.len())?,
+            }
+        }
+        Ok(())
+    }
+}
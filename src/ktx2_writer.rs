@@ -1,6 +1,16 @@
+use crate::{
+    metadata::{string_value, CONTENT_HASH_KEY},
+    provenance::content_hash,
+};
+
 pub struct KTX2Writer<'a> {
     pub header: Header,
     pub dfd_bytes: &'a [u8],
+    /// Supercompression global data, e.g. the codebooks of BasisLZ. Empty for
+    /// schemes without it, such as zstd.
+    pub sgd_bytes: &'a [u8],
+    /// Key/value metadata, written sorted by key as the specification requires.
+    pub key_values: Vec<(String, Vec<u8>)>,
     pub levels_descending: Vec<WriterLevel>,
 }
 
@@ -8,6 +18,20 @@ impl<'a> KTX2Writer<'a> {
     pub fn write<T: std::io::Write>(&self, writer: &mut T) -> std::io::Result<()> {
         let dfd_offset =
             ktx2::Header::LENGTH + self.levels_descending.len() * ktx2::LevelIndex::LENGTH;
+        let kvd_offset = dfd_offset + self.dfd_bytes.len();
+        let kvd_bytes = self.key_value_data();
+        let kvd_end = kvd_offset + kvd_bytes.len();
+        // The global data is 8-byte aligned, and levels follow it.
+        let sgd_offset = if self.sgd_bytes.is_empty() {
+            0
+        } else {
+            kvd_end.next_multiple_of(8)
+        };
+        let data_start = if self.sgd_bytes.is_empty() {
+            kvd_end
+        } else {
+            sgd_offset + self.sgd_bytes.len()
+        };
 
         writer.write_all(
             &ktx2::Header {
@@ -22,23 +46,29 @@ impl<'a> KTX2Writer<'a> {
                 level_count: self.levels_descending.len() as u32,
                 index: ktx2::Index {
                     dfd_byte_length: self.dfd_bytes.len() as u32,
-                    kvd_byte_length: 0,
-                    sgd_byte_length: 0,
+                    kvd_byte_length: kvd_bytes.len() as u32,
+                    sgd_byte_length: self.sgd_bytes.len() as u64,
                     dfd_byte_offset: dfd_offset as u32,
-                    kvd_byte_offset: 0,
-                    sgd_byte_offset: 0,
+                    kvd_byte_offset: if kvd_bytes.is_empty() {
+                        0
+                    } else {
+                        kvd_offset as u32
+                    },
+                    sgd_byte_offset: sgd_offset as u64,
                 },
             }
             .as_bytes()[..],
         )?;
 
-        let mut offset = dfd_offset + self.dfd_bytes.len();
+        let alignment = self.level_alignment();
+        let mut offset = data_start;
 
         let mut levels = self
             .levels_descending
             .iter()
             .rev()
             .map(|level| {
+                offset = offset.next_multiple_of(alignment);
                 let index = ktx2::LevelIndex {
                     byte_offset: offset as u64,
                     byte_length: level.bytes.len() as u64,
@@ -58,13 +88,90 @@ impl<'a> KTX2Writer<'a> {
         }
 
         writer.write_all(self.dfd_bytes)?;
+        writer.write_all(&kvd_bytes)?;
+        if !self.sgd_bytes.is_empty() {
+            writer.write_all(&vec![0; sgd_offset - kvd_end])?;
+            writer.write_all(self.sgd_bytes)?;
+        }
 
+        let mut offset = data_start;
         for level in self.levels_descending.iter().rev() {
+            // Mip padding
+            let padding = offset.next_multiple_of(alignment) - offset;
+            writer.write_all(&vec![0; padding])?;
             writer.write_all(&level.bytes)?;
+            offset += padding + level.bytes.len();
         }
 
         Ok(())
     }
+
+    /// Serializes the file with a [`CONTENT_HASH_KEY`] entry, replacing any
+    /// existing one, and returns its bytes and the hash. The hash is of the
+    /// file with zeros as the digits, so it is filled in after serializing.
+    pub fn serialize_with_content_hash(&mut self) -> std::io::Result<(Vec<u8>, String)> {
+        const PLACEHOLDER: &str = "0000000000000000";
+        self.key_values.retain(|(key, _)| key != CONTENT_HASH_KEY);
+        self.key_values
+            .push((CONTENT_HASH_KEY.to_string(), string_value(PLACEHOLDER)));
+        let mut bytes = Vec::new();
+        self.write(&mut bytes)?;
+
+        let hash = format!("{:016x}", content_hash(&bytes));
+        // Keys are unique, so the entry is the only match in the key/value data.
+        let entry = [CONTENT_HASH_KEY.as_bytes(), b"\0", PLACEHOLDER.as_bytes()].concat();
+        let kvd_offset = ktx2::Header::LENGTH
+            + self.levels_descending.len() * ktx2::LevelIndex::LENGTH
+            + self.dfd_bytes.len();
+        let position = bytes[kvd_offset..]
+            .windows(entry.len())
+            .position(|window| window == entry)
+            .unwrap();
+        let digits = kvd_offset + position + CONTENT_HASH_KEY.len() + 1;
+        bytes[digits..digits + hash.len()].copy_from_slice(hash.as_bytes());
+        self.key_values.last_mut().unwrap().1 = string_value(&hash);
+        Ok((bytes, hash))
+    }
+
+    /// Alignment of each level's data: the least common multiple of the texel
+    /// block size and 4 without supercompression, none with it.
+    fn level_alignment(&self) -> usize {
+        if self.header.supercompression_scheme.is_some() {
+            return 1;
+        }
+        let block_size = crate::dfd::parse_dfd(self.dfd_bytes)
+            .map_or(1, |dfd| dfd.bytes_planes[0] as usize)
+            .max(1);
+        lcm(block_size, 4)
+    }
+
+    fn key_value_data(&self) -> Vec<u8> {
+        let mut entries = self.key_values.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+        let mut kvd = Vec::new();
+        for (key, value) in entries {
+            let key_and_value_byte_length = key.len() + 1 + value.len();
+            kvd.extend_from_slice(&(key_and_value_byte_length as u32).to_le_bytes());
+            kvd.extend_from_slice(key.as_bytes());
+            kvd.push(0);
+            kvd.extend_from_slice(value);
+            // Every entry is padded to a multiple of 4 bytes.
+            kvd.resize(kvd.len().next_multiple_of(4), 0);
+        }
+        kvd
+    }
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+    fn gcd(a: usize, b: usize) -> usize {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    a / gcd(a, b) * b
 }
 
 pub struct WriterLevel {
@@ -0,0 +1,106 @@
+//! Byte order of the levels of an image with several layers.
+//!
+//! Bevy images, KTX2 loading and [`crate::readback::readback_texture`] store
+//! all mip levels of a face together. Data copied level by level from the GPU,
+//! or uploaded with wgpu's `TextureDataOrder::MipMajor`, stores all faces of a
+//! mip level together instead. Both have the same size, so which one some
+//! bytes use can't be detected and has to be given.
+
+use bevy::{prelude::Image, render::render_resource::TextureDescriptor};
+
+use crate::source::mip_size;
+
+/// Order of the faces and mip levels in `Image::data`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DataLayout {
+    /// Every mip level of face 0, then of face 1 and so on, the layout of
+    /// KTX2-loaded Bevy images and the one the rest of the crate expects.
+    #[default]
+    FaceMajor,
+    /// Every face of mip level 0, then of mip level 1 and so on.
+    MipMajor,
+}
+
+/// Bytes of one face of `mip_level`, counting whole blocks for compressed
+/// formats.
+fn face_level_bytes(descriptor: &TextureDescriptor, mip_level: u32) -> usize {
+    let format = descriptor.format;
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format
+        .block_copy_size(None)
+        .unwrap_or_else(|| panic!("Reordering {format:?} images is not supported"));
+    let blocks_x = mip_size(descriptor.size.width, mip_level).div_ceil(block_width);
+    let blocks_y = mip_size(descriptor.size.height, mip_level).div_ceil(block_height);
+    blocks_x as usize * blocks_y as usize * block_size as usize
+}
+
+/// Copies `image`, stored in `from`, into the `to` layout. Images with a single
+/// layer or mip level are the same in both.
+pub fn reorder_layout(image: &Image, from: DataLayout, to: DataLayout) -> Image {
+    let descriptor = &image.texture_descriptor;
+    let layer_count = descriptor.size.depth_or_array_layers as usize;
+    let mip_level_count = descriptor.mip_level_count;
+    if from == to || layer_count == 1 || mip_level_count == 1 {
+        return image.clone();
+    }
+
+    let level_bytes = (0..mip_level_count)
+        .map(|mip_level| face_level_bytes(descriptor, mip_level))
+        .collect::<Vec<_>>();
+    let face_bytes = level_bytes.iter().sum::<usize>();
+    if image.data.len() != face_bytes * layer_count {
+        panic!(
+            "Image has {} bytes, but its layers and mip levels need {}",
+            image.data.len(),
+            face_bytes * layer_count
+        );
+    }
+
+    // Bytes before each level within a face.
+    let level_starts = level_bytes
+        .iter()
+        .scan(0, |start, bytes| {
+            let level_start = *start;
+            *start += bytes;
+            Some(level_start)
+        })
+        .collect::<Vec<_>>();
+    let source_offset = |mip_level: usize, layer: usize| match from {
+        DataLayout::FaceMajor => layer * face_bytes + level_starts[mip_level],
+        DataLayout::MipMajor => {
+            level_starts[mip_level] * layer_count + layer * level_bytes[mip_level]
+        }
+    };
+
+    let mut data = Vec::with_capacity(image.data.len());
+    let mut copy = |mip_level: usize, layer: usize| {
+        let start = source_offset(mip_level, layer);
+        data.extend_from_slice(&image.data[start..start + level_bytes[mip_level]]);
+    };
+    match to {
+        DataLayout::FaceMajor => {
+            for layer in 0..layer_count {
+                for mip_level in 0..level_bytes.len() {
+                    copy(mip_level, layer);
+                }
+            }
+        }
+        DataLayout::MipMajor => {
+            for mip_level in 0..level_bytes.len() {
+                for layer in 0..layer_count {
+                    copy(mip_level, layer);
+                }
+            }
+        }
+    }
+
+    let mut reordered = image.clone();
+    reordered.data = data;
+    reordered
+}
+
+/// Copies `image`, stored in `layout`, into the face-major layout the rest of
+/// the crate expects.
+pub fn to_face_major(image: &Image, layout: DataLayout) -> Image {
+    reorder_layout(image, layout, DataLayout::FaceMajor)
+}
@@ -1,222 +1,415 @@
-use std::path::Path;
+use std::{borrow::Cow, ops::Range, path::Path, sync::Arc};
 
 use bevy::{
     prelude::Image,
-    render::{render_asset::RenderAssetUsages, render_resource::Extent3d},
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureFormat},
+    },
 };
-use ktx2::SupercompressionScheme;
+use rayon::prelude::*;
+
+use cubemap::{face_bit, ALL_FACES, FACE_COUNT, FACE_NAMES};
+use cubemap_data::CubemapData;
+use dfd::set_color_primaries;
+use encoder::{
+    B10g11r11Encoder, Bc6hEncoder, LogLuv32Encoder, Rgb9e5Encoder, Rgba16FloatEncoder,
+    Rgba8Encoder, RgbdEncoder, RgbmEncoder, TexelEncoder,
+};
+use ktx2_reader::KTX2File;
 use ktx2_writer::{Header, KTX2Writer, WriterLevel};
-use rgb9e5::float3_to_rgb9e5;
-
+use orientation::{reorient, Orientation};
+use output::{should_write, write_bytes_atomically, OverwritePolicy};
+use pipeline::EncodeSettings;
+
+pub mod accumulate;
+pub mod adjust;
+#[cfg(feature = "astc")]
+pub mod astc;
+pub mod b10g11r11;
+pub mod bc6h;
+pub mod blend;
+pub mod capture;
+pub mod color;
+pub mod conformance;
+pub mod cubemap;
+pub mod cubemap_data;
+pub mod debug;
+pub mod dfd;
+pub mod diff;
+pub mod download;
+pub mod encoder;
+pub mod energy;
+pub mod equirect;
+pub mod fisheye;
+pub mod generate;
+pub mod ground;
+pub mod irradiance;
+pub mod irradiance_volume;
+pub mod ktx2_reader;
 pub mod ktx2_writer;
+pub mod layout;
+pub mod logluv;
+pub mod lut;
+pub mod memory;
+pub mod merge;
+pub mod metadata;
+pub mod mips;
+#[cfg(feature = "tokio")]
+pub mod nonblocking;
+pub mod orientation;
+pub mod output;
+pub mod pipeline;
+pub mod prefilter;
+pub mod prelude;
+pub mod probes;
+pub mod processor;
+pub mod progress;
+pub mod provenance;
+pub mod readback;
 pub mod rgb9e5;
+pub mod rgbm;
+pub mod sky;
+pub mod source;
+pub mod supercompression;
+pub mod threads;
+pub mod thumbnail;
+pub mod time_of_day;
+pub mod transform;
+pub mod validate;
+#[cfg(feature = "video")]
+pub mod video;
+
+/// Views little-endian f16 bytes, e.g. `Rgba16Float` image data, as f16 values
+/// without copying. Fails on an odd length, if `bytes` isn't 2-byte aligned,
+/// which loaders don't guarantee, or on big-endian targets; copy with
+/// [`f16_vec_from_byte_slice`] then.
+pub fn to_vec_f16_from_byte_slice(bytes: &[u8]) -> std::io::Result<&[half::f16]> {
+    if cfg!(target_endian = "big") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Little-endian f16 bytes can't be viewed in place on big-endian targets",
+        ));
+    }
+    bytemuck::try_cast_slice(bytes).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "Can't view {} bytes at {:p} as f16 values: {e}",
+                bytes.len(),
+                bytes.as_ptr()
+            ),
+        )
+    })
+}
+
+/// Copies little-endian f16 bytes into f16 values, whatever their alignment
+/// and the byte order of the target. Fails on an odd length.
+pub fn f16_vec_from_byte_slice(bytes: &[u8]) -> std::io::Result<Vec<half::f16>> {
+    if bytes.len() % 2 != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} bytes aren't a whole number of f16 values", bytes.len()),
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(2)
+        .map(|b| half::f16::from_le_bytes([b[0], b[1]]))
+        .collect())
+}
 
-pub fn to_vec_f16_from_byte_slice(vecs: &[u8]) -> &[half::f16] {
-    unsafe { std::slice::from_raw_parts(vecs.as_ptr() as *const _, vecs.len() / 2) }
+/// u32 values as little-endian bytes, as KTX2 and GPU uploads expect. Borrows
+/// on little-endian targets and byte swaps into a copy on big-endian ones.
+pub fn u32_to_bytes(values: &[u32]) -> Cow<'_, [u8]> {
+    if cfg!(target_endian = "little") {
+        Cow::Borrowed(bytemuck::cast_slice(values))
+    } else {
+        Cow::Owned(values.iter().flat_map(|v| v.to_le_bytes()).collect())
+    }
 }
 
-pub fn u32_to_bytes(vecs: &[u32]) -> &[u8] {
-    unsafe { std::slice::from_raw_parts(vecs.as_ptr() as *const _, vecs.len() * 4) }
+/// Pixel encoding of the levels written to a KTX2 file.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub enum OutputFormat {
+    /// Shared-exponent `E5B9G9R9_UFLOAT_PACK32`.
+    #[default]
+    Rgb9e5,
+    /// LogLuv32 packed into `R8G8B8A8_UNORM`, tagged in the key/value metadata.
+    /// Must be decoded in the shader and can't be filtered by the sampler.
+    LogLuv32,
+    /// RGBM packed into `R8G8B8A8_UNORM`, with `range` stored in the key/value metadata.
+    Rgbm { range: f32 },
+    /// RGBD packed into `R8G8B8A8_UNORM`, with `range` stored in the key/value metadata.
+    Rgbd { range: f32 },
+    /// `R16G16B16A16_SFLOAT`, copying `Rgba16Float` input without any loss,
+    /// alpha included.
+    Rgba16Float,
+    /// Color divided by `range` in `R8G8B8A8_SRGB`, with `range` stored in the
+    /// key/value metadata. Unlike the other 8-bit formats it keeps alpha, e.g.
+    /// for cubemaps whose alpha holds sky occlusion or blend masks.
+    Rgba8 { range: f32 },
+    /// `B10G11R11_UFLOAT_PACK32`.
+    B10g11r11,
+    /// Block-compressed `BC6H_UFLOAT_BLOCK`, a quarter of the size of RGB9E5.
+    Bc6h,
 }
 
-pub fn write_ktx2(image: &Image, output_path: &Path) {
-    if image.is_compressed() {
-        panic!("Only uncompressed images supported");
-    }
-
-    let mut mips = Vec::new();
-    for mip_level in 0..image.texture_descriptor.mip_level_count {
-        let mut rgb9e5 = Vec::new();
-        for face in 0..6 {
-            let mip_data = extract_mip_level(image, mip_level, face);
-            let f16data = to_vec_f16_from_byte_slice(&mip_data.data);
-
-            for v in f16data.chunks(4) {
-                rgb9e5.push(float3_to_rgb9e5(&[
-                    v[0].to_f32(),
-                    v[1].to_f32(),
-                    v[2].to_f32(),
-                ]));
-            }
+impl OutputFormat {
+    /// Encoder writing this format.
+    pub fn encoder(self) -> Arc<dyn TexelEncoder> {
+        match self {
+            OutputFormat::Rgb9e5 => Arc::new(Rgb9e5Encoder),
+            OutputFormat::LogLuv32 => Arc::new(LogLuv32Encoder),
+            OutputFormat::Rgbm { range } => Arc::new(RgbmEncoder { range }),
+            OutputFormat::Rgbd { range } => Arc::new(RgbdEncoder { range }),
+            OutputFormat::Rgba16Float => Arc::new(Rgba16FloatEncoder),
+            OutputFormat::Rgba8 { range } => Arc::new(Rgba8Encoder { range }),
+            OutputFormat::B10g11r11 => Arc::new(B10g11r11Encoder),
+            OutputFormat::Bc6h => Arc::new(Bc6hEncoder),
         }
+    }
+}
 
-        let rgb9e5_bytes = u32_to_bytes(&rgb9e5).to_vec();
-        mips.push(WriterLevel {
-            uncompressed_length: rgb9e5_bytes.len(),
-            bytes: zstd::bulk::compress(&rgb9e5_bytes, 0).unwrap(),
-        });
+/// Writes `image` to `output_path` with the format, primaries, supercompression
+/// and layer names of `settings`. The image stages of `settings` are not applied
+/// here, see [`pipeline::process`] and [`pipeline::encode`].
+///
+/// The file records a hash of its content under
+/// [`metadata::CONTENT_HASH_KEY`]. An existing file with the same hash is kept
+/// as is, so unchanged bakes don't touch it. Existing files are otherwise
+/// handled by `settings.overwrite`, checked before encoding.
+pub fn write_ktx2(
+    image: &Image,
+    output_path: &Path,
+    settings: &EncodeSettings,
+) -> std::io::Result<()> {
+    if image.texture_descriptor.format != TextureFormat::Rgba16Float {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Only Rgba16Float images supported, got {:?}",
+                image.texture_descriptor.format
+            ),
+        ));
     }
 
-    // Create DFD for RGB9E5 format
-    let dfd_bytes = create_rgb9e5_dfd();
+    let size = image.texture_descriptor.size;
+    if size.width != size.height {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Cubemap faces must be square, got {}x{}",
+                size.width, size.height
+            ),
+        ));
+    }
+
+    let array_layers = size.depth_or_array_layers;
+    if array_layers == 0 || array_layers % 6 != 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Cubemap layer count must be a multiple of 6, got {array_layers}"),
+        ));
+    }
+    let cube_layers = array_layers / 6;
+
+    let present_faces = settings.cubemap_faces & ALL_FACES;
+    if present_faces == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "No cubemap faces selected",
+        ));
+    }
+
+    if !should_write(output_path, settings.overwrite)? {
+        return Ok(());
+    }
+
+    let encoder = &settings.encoder;
+    let data = CubemapData::from_image(image);
+    // Levels and their faces are encoded and compressed in parallel.
+    let mips = settings.threads.install(|| {
+        (0..data.mip_level_count())
+            .into_par_iter()
+            .map(|mip_level| {
+                let size = data.mip_size(mip_level);
+                // KTX2 stores every face of layer 0, then every face of layer 1, ...
+                let level_bytes = (0..data.face_count())
+                    .into_par_iter()
+                    .filter(|face| present_faces & face_bit(face % FACE_COUNT) != 0)
+                    .map(|face| {
+                        let texels = reorient(
+                            data.face(face, mip_level),
+                            size,
+                            size,
+                            Orientation::RightDown,
+                            settings.orientation,
+                        );
+                        let mut bytes = Vec::new();
+                        encoder.encode(&texels, size, size, &mut bytes);
+                        bytes
+                    })
+                    .collect::<Vec<_>>()
+                    .concat();
+
+                Ok(WriterLevel {
+                    uncompressed_length: level_bytes.len(),
+                    bytes: settings
+                        .supercompression
+                        .compress_level(&level_bytes, settings.min_compressed_level_size)?,
+                })
+            })
+            .collect::<std::io::Result<Vec<_>>>()
+    })?;
+
+    let mut dfd_bytes = encoder.dfd();
+    set_color_primaries(&mut dfd_bytes, settings.primaries);
+
+    let mut key_values = encoder.key_values();
+    if !settings.layer_names.is_empty() {
+        key_values.push((
+            metadata::LAYERS_KEY.to_string(),
+            metadata::string_value(&settings.layer_names.join(",")),
+        ));
+    }
+    if let Some(prefilter) = &settings.prefilter {
+        // Levels dropped after prefiltering were the smallest, the rest kept
+        // the roughness of their position in the full chain.
+        let mut roughness = prefilter.level_roughness(image.texture_descriptor.size.width);
+        roughness.truncate(image.texture_descriptor.mip_level_count as usize);
+        key_values.push((
+            metadata::MIP_ROUGHNESS_KEY.to_string(),
+            metadata::f32_list_value(&roughness),
+        ));
+    }
+    key_values.push((
+        metadata::ORIENTATION_KEY.to_string(),
+        metadata::string_value(settings.orientation.as_str()),
+    ));
+    if let Some(width) = settings.thumbnail_width {
+        key_values.push((
+            metadata::THUMBNAIL_KEY.to_string(),
+            thumbnail::render_thumbnail(image, width)?,
+        ));
+    }
+    key_values.extend(settings.metadata.iter().cloned());
+
+    if present_faces != ALL_FACES {
+        key_values.push((
+            metadata::CUBEMAP_INCOMPLETE_KEY.to_string(),
+            vec![present_faces],
+        ));
+    }
+    if settings.face_array {
+        let faces = (0..FACE_COUNT)
+            .filter(|face| present_faces & face_bit(*face) != 0)
+            .map(|face| FACE_NAMES[face as usize])
+            .collect::<Vec<_>>()
+            .join(",");
+        key_values.push((
+            metadata::FACE_LAYERS_KEY.to_string(),
+            metadata::string_value(&vec![faces; cube_layers as usize].join(",")),
+        ));
+    }
+
+    // Incomplete cubemaps and face arrays store their faces as array layers.
+    let (layer_count, face_count) = if present_faces == ALL_FACES && !settings.face_array {
+        // Must be 0 for non-array cube maps according to KTX2 spec
+        (if cube_layers > 1 { cube_layers } else { 0 }, 6)
+    } else {
+        (present_faces.count_ones() * cube_layers, 1)
+    };
 
     // https://github.khronos.org/KTX-Specification/
-    let writer = KTX2Writer {
+    let mut writer = KTX2Writer {
         header: Header {
-            format: Some(ktx2::Format::E5B9G9R9_UFLOAT_PACK32),
-            type_size: 4,
+            format: Some(encoder.ktx2_format()),
+            type_size: encoder.type_size(),
             pixel_width: image.texture_descriptor.size.width,
             pixel_height: image.texture_descriptor.size.height,
             pixel_depth: 0, // Must be 0 for cube maps according to KTX2 spec
-            layer_count: 0, // Must be 0 for non-array cube maps according to KTX2 spec
-            face_count: 6,
-            supercompression_scheme: Some(SupercompressionScheme::Zstandard),
+            layer_count,
+            face_count,
+            supercompression_scheme: settings.supercompression.scheme(),
         },
         dfd_bytes: &dfd_bytes,
+        sgd_bytes: &[],
+        key_values,
         levels_descending: mips,
     };
 
-    writer
-        .write(&mut std::fs::File::create(output_path).unwrap())
-        .unwrap();
-}
+    // Leave identical files alone, so their modification time doesn't change.
+    let (bytes, hash) = writer.serialize_with_content_hash()?;
+    if settings.overwrite == OverwritePolicy::Overwrite
+        && KTX2File::read_content_hash(output_path)
+            .is_ok_and(|existing| existing.as_deref() == Some(hash.as_str()))
+    {
+        return Ok(());
+    }
 
-/// Builds a KTX 2.0 Data-Format Descriptor for `VK_FORMAT_E5B9G9R9_UFLOAT_PACK32`.
-///
-/// The descriptor follows the sample layout shown in the specification and uses
-/// one BASIC descriptor block (vendor 0, type 0, version 2).  Six samples are
-/// written so that the validator sees the expected RGB mantissas and their
-/// shared exponent.
-///
-/// Every texel occupies a single 32-bit word, therefore `bytesPlane0` is `4`.
-/// The function returns the descriptor as a little-endian byte vector ready to
-/// be written to the file.
-fn create_rgb9e5_dfd() -> Vec<u8> {
-    // Helper to push a 32-bit little-endian word
-    fn push(word: u32, out: &mut Vec<u8>) {
-        out.extend_from_slice(&word.to_le_bytes());
-    }
-
-    let mut dfd: Vec<u8> = Vec::with_capacity(132);
-    dfd.extend_from_slice(&0u32.to_le_bytes()); // will be overwritten later
-
-    // Data-format-descriptor header (2 × u32)
-    // word0: descriptorType (lower 15 b) | vendorId (upper 17 b) – both 0 → 0
-    push(0, &mut dfd);
-
-    // The BASIC block length in bytes = 24 (header) + 16 × numSamples.
-    const NUM_SAMPLES: usize = 6;
-    const BASIC_BLOCK_BYTE_LENGTH: u32 = 24 + 16 * NUM_SAMPLES as u32;
-    const VERSION_NUMBER: u32 = 2;
-    // word1: versionNumber (low 16 b) | descriptorBlockSize (high 16 b)
-    let word1 = (BASIC_BLOCK_BYTE_LENGTH << 16) | VERSION_NUMBER;
-    push(word1, &mut dfd);
-
-    // word2: colourModel | colourPrimaries | transferFunction | flags
-    const COLOR_MODEL_RGBSDA: u32 = 1; // KHR_DF_MODEL_RGBSDA
-    const COLOR_PRIMARIES_BT709: u32 = 1; // Recommended default
-    const TRANSFER_LINEAR: u32 = 1; // KHR_DF_TRANSFER_LINEAR
-    const FLAGS_STRAIGHT_ALPHA: u32 = 0; // no premultiplied alpha
-    let word2 = COLOR_MODEL_RGBSDA
-        | (COLOR_PRIMARIES_BT709 << 8)
-        | (TRANSFER_LINEAR << 16)
-        | (FLAGS_STRAIGHT_ALPHA << 24);
-    push(word2, &mut dfd);
-
-    // word3: texelBlockDimensions – for a 1×1×1 block we store each dimension − 1 = 0
-    push(0, &mut dfd);
-
-    // word4 & word5: bytesPlane0-3 / bytesPlane4-7 (8 × u8)
-    // For a packed 32-bit texel bytesPlane0 = 4, the rest 0.
-    push(4, &mut dfd); // bytesPlane0 = 4, others 0
-    push(0, &mut dfd); // bytesPlane4-7 = 0
-
-    fn push_sample(
-        out: &mut Vec<u8>,
-        bit_offset: u32,
-        bit_length_bits: u32,
-        channel_type: u32,
-        qualifiers: u32,
-        lower: u32,
-        upper: u32,
-    ) {
-        let first_word =
-            bit_offset | ((bit_length_bits - 1) << 16) | (channel_type << 24) | (qualifiers << 28);
-        push(first_word, out);
-        push(0, out); // samplePosition – not used → 0
-        push(lower, out);
-        push(upper, out);
-    }
-
-    // Qualifier bits (see ChannelTypeQualifiers in ktx2 crate)
-    const QUAL_NONE: u32 = 0;
-    const QUAL_EXPONENT: u32 = 1 << 1; // EXPONENT flag
-
-    // Channel-type codes (KDF §A.3): 0=R,1=G,2=B,3=A/SharedExponent
-    const CH_R: u32 = 0;
-    const CH_G: u32 = 1;
-    const CH_B: u32 = 2;
-    const CH_EXPONENT: u32 = 3; // shared exponent stored in alpha slot
-
-    // For each colour channel we write: mantissa sample followed by its exponent sample.
-
-    // RED mantissa & exponent
-    push_sample(&mut dfd, 0, 9, CH_R, QUAL_NONE, 0, 8448); // R mantissa (bits 0-8)
-    push_sample(&mut dfd, 27, 5, CH_R, QUAL_EXPONENT, 15, 31); // R exponent (bits 27-31)
-
-    // GREEN mantissa & exponent
-    push_sample(&mut dfd, 9, 9, CH_G, QUAL_NONE, 0, 8448); // G mantissa (bits 9-17)
-    push_sample(&mut dfd, 27, 5, CH_G, QUAL_EXPONENT, 15, 31); // G exponent (shared bits)
-
-    // BLUE mantissa & exponent
-    push_sample(&mut dfd, 18, 9, CH_B, QUAL_NONE, 0, 8448); // B mantissa (bits 18-26)
-    push_sample(&mut dfd, 27, 5, CH_B, QUAL_EXPONENT, 15, 31); // B exponent (shared bits)
-
-    // Patch totalSize ------------------------------------------------------------------
-    let total_size = dfd.len() as u32;
-    dfd[0..4].copy_from_slice(&total_size.to_le_bytes());
-
-    dfd
+    write_bytes_atomically(output_path, settings.overwrite, &bytes)?;
+    Ok(())
 }
 
 /// Extract a specific individual mip level as a new image.
+#[deprecated(note = "use `CubemapData::from_image(image).face(face, mip_level)` instead")]
 pub fn extract_mip_level(image: &Image, mip_level: u32, face: u32) -> Image {
     let descriptor = &image.texture_descriptor;
 
-    if descriptor.mip_level_count < mip_level {
+    if mip_level >= descriptor.mip_level_count {
         panic!(
             "Mip level {mip_level} requested, but only {} are avaliable.",
             descriptor.mip_level_count
         );
     }
 
-    let block_size = descriptor.format.block_copy_size(None).unwrap() as usize;
-
-    let mut byte_offset = 0usize;
-
-    for _ in 0..face {
-        let mut width = descriptor.size.width as usize;
-        let mut height = descriptor.size.height as usize;
-        for _ in 0..descriptor.mip_level_count {
-            byte_offset += width * block_size * height;
-            width /= 2;
-            height /= 2;
-        }
-    }
-
-    let mut width = descriptor.size.width as usize;
-    let mut height = descriptor.size.height as usize;
-
-    for _ in 0..mip_level {
-        byte_offset += width * block_size * height;
-        width /= 2;
-        height /= 2;
-    }
+    let (byte_range, width, height) = mip_level_byte_range(image, mip_level, face);
 
     let mut new_descriptor = descriptor.clone();
 
     new_descriptor.mip_level_count = 1;
     new_descriptor.size = Extent3d {
-        width: width as u32,
-        height: height as u32,
+        width,
+        height,
         depth_or_array_layers: 1,
     };
 
     Image {
-        data: image.data[byte_offset..byte_offset + (width * block_size * height)].to_vec(),
+        data: image.data[byte_range].to_vec(),
         texture_descriptor: new_descriptor,
         sampler: image.sampler.clone(),
         texture_view_descriptor: image.texture_view_descriptor.clone(),
         asset_usage: RenderAssetUsages::default(),
     }
 }
+
+/// Byte range of a mip level of a face within `image.data`, together with the
+/// width and height of that level. Levels follow the usual `max(1, floor(size / 2))`
+/// rule, so non-power-of-two and rectangular images are sliced correctly.
+pub fn mip_level_byte_range(image: &Image, mip_level: u32, face: u32) -> (Range<usize>, u32, u32) {
+    let descriptor = &image.texture_descriptor;
+    let block_size = descriptor.format.block_copy_size(None).unwrap() as usize;
+    let level_size = |mip_level: u32| {
+        (
+            source::mip_size(descriptor.size.width, mip_level),
+            source::mip_size(descriptor.size.height, mip_level),
+        )
+    };
+    let level_bytes = |mip_level: u32| {
+        let (width, height) = level_size(mip_level);
+        width as usize * height as usize * block_size
+    };
+
+    let face_bytes = (0..descriptor.mip_level_count)
+        .map(level_bytes)
+        .sum::<usize>();
+    let byte_offset = face as usize * face_bytes + (0..mip_level).map(level_bytes).sum::<usize>();
+    let (width, height) = level_size(mip_level);
+
+    (
+        byte_offset..byte_offset + level_bytes(mip_level),
+        width,
+        height,
+    )
+}
@@ -0,0 +1,71 @@
+// LogLuv32 as described by Greg Ward, "The LogLuv Encoding for Full Gamut,
+// High Dynamic Range Images", packed into four bytes for RGBA8 textures:
+// R = u', G = v', B = high byte of log2 luminance, A = low byte.
+// http://www.anyhere.com/gward/papers/jgtpap1.pdf
+
+const LOG_LUMINANCE_SCALE: f32 = 256.0;
+const LOG_LUMINANCE_BIAS: f32 = 64.0;
+const UV_SCALE: f32 = 410.0;
+
+#[inline]
+fn linear_rgb_to_xyz(rgb: &[f32]) -> [f32; 3] {
+    [
+        0.4124 * rgb[0] + 0.3576 * rgb[1] + 0.1805 * rgb[2],
+        0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2],
+        0.0193 * rgb[0] + 0.1192 * rgb[1] + 0.9505 * rgb[2],
+    ]
+}
+
+#[inline]
+fn xyz_to_linear_rgb(xyz: [f32; 3]) -> [f32; 3] {
+    [
+        3.2406 * xyz[0] - 1.5372 * xyz[1] - 0.4986 * xyz[2],
+        -0.9689 * xyz[0] + 1.8758 * xyz[1] + 0.0415 * xyz[2],
+        0.0557 * xyz[0] - 0.2040 * xyz[1] + 1.0570 * xyz[2],
+    ]
+}
+
+/// Encodes linear Rec.709 RGB as LogLuv32. Luminances from 2^-64 up to the
+/// largest finite f32, about 2^128, round-trip; zero and negative luminance
+/// encode as black.
+#[inline]
+pub fn float3_to_logluv32(rgb: &[f32]) -> [u8; 4] {
+    let rgb = [rgb[0].max(0.0), rgb[1].max(0.0), rgb[2].max(0.0)];
+    let [x, y, z] = linear_rgb_to_xyz(&rgb);
+    if y <= 0.0 {
+        return [0; 4];
+    }
+
+    let log_luminance = ((y.log2() + LOG_LUMINANCE_BIAS) * LOG_LUMINANCE_SCALE)
+        .floor()
+        .clamp(1.0, u16::MAX as f32) as u16;
+
+    let denom = x + 15.0 * y + 3.0 * z;
+    let u = (UV_SCALE * 4.0 * x / denom).floor().clamp(0.0, 255.0) as u8;
+    let v = (UV_SCALE * 9.0 * y / denom).floor().clamp(0.0, 255.0) as u8;
+
+    let [high, low] = log_luminance.to_be_bytes();
+    [u, v, high, low]
+}
+
+#[inline]
+pub fn logluv32_to_float3(v: [u8; 4]) -> [f32; 3] {
+    let log_luminance = u16::from_be_bytes([v[2], v[3]]);
+    if log_luminance == 0 {
+        return [0.0; 3];
+    }
+
+    let y = ((log_luminance as f32 + 0.5) / LOG_LUMINANCE_SCALE - LOG_LUMINANCE_BIAS).exp2();
+    let u = (v[0] as f32 + 0.5) / UV_SCALE;
+    let v = (v[1] as f32 + 0.5) / UV_SCALE;
+
+    // CIE 1976 u'v' back to xy chromaticity
+    let denom = 6.0 * u - 16.0 * v + 12.0;
+    let cx = 9.0 * u / denom;
+    let cy = 4.0 * v / denom;
+
+    let x = cx / cy * y;
+    let z = (1.0 - cx - cy) / cy * y;
+    let rgb = xyz_to_linear_rgb([x, y, z]);
+    [rgb[0].max(0.0), rgb[1].max(0.0), rgb[2].max(0.0)]
+}
@@ -0,0 +1,213 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use bevy::{prelude::Image, render::render_resource::TextureFormat};
+
+use crate::cubemap::{rgba16f_bytes_to_rgba_f32, rgba_f32_to_rgba16f_bytes};
+
+/// A 3D color lookup table in the Resolve / Adobe `.cube` format, optionally
+/// preceded by a 1D shaper as written by Resolve.
+#[derive(Clone, Debug)]
+pub struct Lut3d {
+    pub size: u32,
+    /// From `DOMAIN_MIN` or `LUT_3D_INPUT_RANGE`.
+    pub domain_min: [f32; 3],
+    /// From `DOMAIN_MAX` or `LUT_3D_INPUT_RANGE`.
+    pub domain_max: [f32; 3],
+    /// `size³` entries with red changing fastest, then green, then blue.
+    pub table: Vec<[f32; 3]>,
+    /// Per-channel curve applied before the table, from `LUT_1D_SIZE`.
+    pub shaper: Option<Lut1d>,
+}
+
+/// Per-channel curves of a `.cube` file, sampled at evenly spaced inputs.
+#[derive(Clone, Debug)]
+pub struct Lut1d {
+    /// From `LUT_1D_INPUT_RANGE`.
+    pub domain_min: f32,
+    /// From `LUT_1D_INPUT_RANGE`.
+    pub domain_max: f32,
+    pub table: Vec<[f32; 3]>,
+}
+
+impl Lut1d {
+    /// Looks up each channel of `rgb` with linear interpolation, clamping
+    /// values outside the domain.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let max_index = (self.table.len() - 1) as f32;
+        std::array::from_fn(|c| {
+            let t = (rgb[c] - self.domain_min) / (self.domain_max - self.domain_min);
+            let coord = t.clamp(0.0, 1.0) * max_index;
+            let lower = (coord.floor() as usize).min(self.table.len() - 2);
+            let fract = coord - lower as f32;
+            self.table[lower][c] + (self.table[lower + 1][c] - self.table[lower][c]) * fract
+        })
+    }
+}
+
+impl Lut3d {
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> std::io::Result<Self> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidData, message);
+        let parse_triplet = |values: &[&str], line: &str| -> std::io::Result<[f32; 3]> {
+            let parsed = values
+                .iter()
+                .map(|v| v.parse::<f32>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| invalid(format!("Invalid numbers in .cube line `{line}`")))?;
+            parsed
+                .try_into()
+                .map_err(|_| invalid(format!("Expected three values in .cube line `{line}`")))
+        };
+
+        let parse_range = |values: &[&str], line: &str| -> std::io::Result<(f32, f32)> {
+            match values {
+                [min, max] => min
+                    .parse::<f32>()
+                    .ok()
+                    .zip(max.parse::<f32>().ok())
+                    .filter(|(min, max)| min < max)
+                    .ok_or_else(|| invalid(format!("Invalid input range `{line}`"))),
+                _ => Err(invalid(format!(
+                    "Expected two values in .cube line `{line}`"
+                ))),
+            }
+        };
+
+        let mut size = None;
+        let mut shaper_size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut shaper_range = (0.0, 1.0);
+        let mut table = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let keyword = tokens.next().unwrap();
+            let values = tokens.collect::<Vec<_>>();
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => {
+                    size = Some(
+                        values
+                            .first()
+                            .and_then(|v| v.parse::<u32>().ok())
+                            .filter(|size| *size >= 2)
+                            .ok_or_else(|| invalid(format!("Invalid LUT_3D_SIZE `{line}`")))?,
+                    );
+                }
+                "DOMAIN_MIN" => domain_min = parse_triplet(&values, line)?,
+                "DOMAIN_MAX" => domain_max = parse_triplet(&values, line)?,
+                "LUT_3D_INPUT_RANGE" => {
+                    let (min, max) = parse_range(&values, line)?;
+                    domain_min = [min; 3];
+                    domain_max = [max; 3];
+                }
+                "LUT_1D_SIZE" => {
+                    shaper_size = Some(
+                        values
+                            .first()
+                            .and_then(|v| v.parse::<u32>().ok())
+                            .filter(|size| *size >= 2)
+                            .ok_or_else(|| invalid(format!("Invalid LUT_1D_SIZE `{line}`")))?,
+                    );
+                }
+                "LUT_1D_INPUT_RANGE" => shaper_range = parse_range(&values, line)?,
+                _ => {
+                    let mut entry = vec![keyword];
+                    entry.extend(values);
+                    table.push(parse_triplet(&entry, line)?);
+                }
+            }
+        }
+
+        let Some(size) = size else {
+            return Err(invalid(match shaper_size {
+                Some(_) => "1D-only .cube LUTs are not supported".into(),
+                None => "Missing LUT_3D_SIZE".into(),
+            }));
+        };
+        // Resolve lists the shaper entries before the 3D ones.
+        let shaper_len = shaper_size.unwrap_or(0) as usize;
+        if table.len() != shaper_len + (size * size * size) as usize {
+            return Err(invalid(format!(
+                "Expected {} LUT entries, found {}",
+                shaper_len + (size * size * size) as usize,
+                table.len()
+            )));
+        }
+        let shaper = shaper_size.map(|_| Lut1d {
+            domain_min: shaper_range.0,
+            domain_max: shaper_range.1,
+            table: table.drain(..shaper_len).collect(),
+        });
+
+        Ok(Self {
+            size,
+            domain_min,
+            domain_max,
+            table,
+            shaper,
+        })
+    }
+
+    /// Looks up `rgb` with trilinear interpolation, after the shaper if any.
+    /// Values outside the domain are clamped to it, so HDR content needs a LUT
+    /// with a matching domain.
+    pub fn apply(&self, rgb: [f32; 3]) -> [f32; 3] {
+        let rgb = match &self.shaper {
+            Some(shaper) => shaper.apply(rgb),
+            None => rgb,
+        };
+        let max_index = (self.size - 1) as f32;
+        let coords: [f32; 3] = std::array::from_fn(|c| {
+            let t = (rgb[c] - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c]);
+            t.clamp(0.0, 1.0) * max_index
+        });
+        let lower = coords.map(|c| (c.floor() as u32).min(self.size - 2));
+        let fract: [f32; 3] = std::array::from_fn(|c| coords[c] - lower[c] as f32);
+
+        let at =
+            |r: u32, g: u32, b: u32| self.table[((b * self.size + g) * self.size + r) as usize];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| -> [f32; 3] {
+            std::array::from_fn(|c| a[c] + (b[c] - a[c]) * t)
+        };
+
+        let [r, g, b] = lower;
+        let c00 = lerp(at(r, g, b), at(r + 1, g, b), fract[0]);
+        let c10 = lerp(at(r, g + 1, b), at(r + 1, g + 1, b), fract[0]);
+        let c01 = lerp(at(r, g, b + 1), at(r + 1, g, b + 1), fract[0]);
+        let c11 = lerp(at(r, g + 1, b + 1), at(r + 1, g + 1, b + 1), fract[0]);
+        let c0 = lerp(c00, c10, fract[1]);
+        let c1 = lerp(c01, c11, fract[1]);
+        lerp(c0, c1, fract[2])
+    }
+}
+
+/// Applies `lut` to the linear color channels of a `Rgba16Float` image.
+pub fn apply_lut(image: &Image, lut: &Lut3d) -> Image {
+    if image.texture_descriptor.format != TextureFormat::Rgba16Float {
+        panic!("Applying a LUT only supported for Rgba16Float images");
+    }
+
+    let texels = rgba16f_bytes_to_rgba_f32(&image.data)
+        .into_iter()
+        .map(|[r, g, b, a]| {
+            let [r, g, b] = lut.apply([r, g, b]);
+            [r, g, b, a]
+        })
+        .collect::<Vec<_>>();
+
+    let mut graded = image.clone();
+    graded.data = rgba_f32_to_rgba16f_bytes(&texels);
+    graded
+}
@@ -1,18 +1,72 @@
-use std::{path::PathBuf, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 
 use bevy::{
     app::{AppExit, ScheduleRunnerPlugin},
     log::{Level, LogPlugin},
     prelude::*,
+    render::render_resource::TextureFormat,
+};
+#[cfg(feature = "download")]
+use bevy_mod_environment_map_tools::download::fetch;
+#[cfg(feature = "video")]
+use bevy_mod_environment_map_tools::video::{decode_frames, encode_frames, VideoFrames};
+use bevy_mod_environment_map_tools::{
+    accumulate::Accumulation,
+    adjust::LuminanceMeasure,
+    color::{convert_primaries_to_rec709, linearize, ColorPrimaries, TransferFunction},
+    conformance::{check_prefilter, test_environment, DEFAULT_CONFORMANCE_FACE_SIZE},
+    cubemap::{face_bit, square_faces, stack_cubemap_layers, NonSquareFaces, ALL_FACES},
+    debug::label_faces,
+    diff::{diff_cubemaps, write_heatmaps, FACE_FILE_STEMS},
+    download::is_url,
+    encoder::DEFAULT_RGBA8_RANGE,
+    energy::energy_report,
+    equirect::{Equirect, EquirectAspect},
+    fisheye::{dual_fisheye_to_cubemap, DualFisheye, DEFAULT_FISHEYE_BLEND, DEFAULT_FISHEYE_FOV},
+    ground::{
+        GroundFill, GroundProjection, GroundReplacement, NadirFill, NadirPatch,
+        DEFAULT_NADIR_RADIUS,
+    },
+    irradiance::{irradiance_cubemap, SphericalHarmonics9},
+    ktx2_reader::{train_dictionary, KTX2File},
+    layout::{to_face_major, DataLayout},
+    lut::Lut3d,
+    memory::MemoryBudget,
+    merge::{merge_exposures, Bracket},
+    mips::{InputMips, MipFilter, DEFAULT_GAUSSIAN_WIDTH, DEFAULT_TRIANGLE_WIDTH},
+    output::{should_write, write_bytes_atomically, OverwritePolicy},
+    pipeline::{process, process_variants, EncodeSettings, DEFAULT_MIN_COMPRESSED_LEVEL_SIZE},
+    prefilter::{
+        AdaptiveSampling, PrefilterQuality, PrefilterSettings, RoughnessMapping, SampleSequence,
+    },
+    processor::{DEFAULT_DIFFUSE_FACE_SIZE, DEFAULT_SPECULAR_FACE_SIZE},
+    progress::CancellationToken,
+    provenance::Provenance,
+    rgbm::{DEFAULT_RGBD_RANGE, DEFAULT_RGBM_RANGE},
+    supercompression::{Supercompression, ZstdDictionary},
+    threads::set_global_thread_count,
+    transform::horizon_correction,
+    validate::{is_valid, validate_file},
+    write_ktx2, OutputFormat,
 };
-use bevy_mod_environment_map_tools::write_ktx2;
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 
 /// Encode Rgba16Float images as rgb9e5 in ktx2 files.
-#[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[derive(Parser, Debug, Resource)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input file paths
     #[arg(short, long, value_delimiter = ',')]
     inputs: Vec<PathBuf>,
@@ -20,10 +74,1155 @@ struct Args {
     /// Output file paths
     #[arg(short, long, value_delimiter = ',')]
     outputs: Vec<PathBuf>,
+
+    /// Directory inputs given as HTTP(S) URLs are downloaded to and reused from [default: bevy_mod_environment_map_tools in the temporary directory]
+    #[arg(long)]
+    download_cache: Option<PathBuf>,
+
+    /// Pixel encoding of the output files
+    #[arg(short, long, value_enum, default_value_t = Format::Rgb9e5)]
+    format: Format,
+
+    /// Largest representable value for the rgbm, rgbd and rgba8 formats [default: 6 for rgbm, 255 for rgbd, 1 for rgba8]
+    #[arg(long)]
+    range: Option<f32>,
+
+    /// Transfer function of the input color channels: linear, srgb or a gamma exponent such as 2.2 [default: from the input format]
+    #[arg(long)]
+    input_transfer: Option<TransferFunction>,
+
+    /// Merge all inputs into one HDR source, taking them as bracketed exposures at these EVs, e.g. -2,0,2
+    #[arg(long, value_delimiter = ',', allow_negative_numbers = true)]
+    bracket_evs: Vec<f32>,
+
+    /// Color primaries of the input
+    #[arg(long, value_enum, default_value_t = Primaries::Rec709)]
+    input_primaries: Primaries,
+
+    /// Keep the input primaries and record them in the output instead of converting to Rec.709
+    #[arg(long)]
+    keep_primaries: bool,
+
+    /// Scale the input so that this luminance statistic hits --normalize-target
+    #[arg(long, value_enum)]
+    normalize: Option<Measure>,
+
+    /// Percentile of the sphere used by --normalize percentile
+    #[arg(long, default_value_t = 90.0)]
+    normalize_percentile: f32,
+
+    /// Luminance the --normalize statistic is scaled to
+    #[arg(long, default_value_t = 1.0)]
+    normalize_target: f32,
+
+    /// Multiply the input by this intensity
+    #[arg(long, default_value_t = 1.0)]
+    intensity: f32,
+
+    /// Gain applied to the input, as r,g,b or one value for all channels
+    #[arg(long, value_parser = parse_gain, default_value = "1,1,1")]
+    gain: [f32; 3],
+
+    /// Exposure adjustment in stops
+    #[arg(long, default_value_t = 0.0)]
+    exposure: f32,
+
+    /// Rotate the environment around the vertical axis by this many degrees
+    #[arg(long, default_value_t = 0.0)]
+    rotation: f32,
+
+    /// Level the horizon of an input shot with the camera looking up by this many degrees
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    pitch: f32,
+
+    /// Level the horizon of an input shot with the camera rolled clockwise by this many degrees
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    roll: f32,
+
+    /// Fill the tripod hole around the nadir, one method for all inputs or one per input
+    #[arg(long, value_enum, value_delimiter = ',')]
+    nadir_patch: Vec<Nadir>,
+
+    /// Angular radius of the region --nadir-patch fills, in degrees
+    #[arg(long, default_value_t = DEFAULT_NADIR_RADIUS)]
+    nadir_radius: f32,
+
+    /// Height the input was captured at, enables reprojecting its lower hemisphere onto the ground as seen from --camera-height
+    #[arg(long)]
+    capture_height: Option<f32>,
+
+    /// Camera height above the ground for --capture-height, in the same unit
+    #[arg(long)]
+    camera_height: Option<f32>,
+
+    /// Replace the environment below the horizon with this color, as r,g,b
+    #[arg(long, value_delimiter = ',')]
+    ground_color: Vec<f32>,
+
+    /// Fade --ground-color into this color straight down, as r,g,b
+    #[arg(long, value_delimiter = ',')]
+    ground_nadir_color: Vec<f32>,
+
+    /// Elevation of the horizon for --ground-color in degrees, negative to keep some of the captured ground
+    #[arg(long, default_value_t = 0.0)]
+    horizon_angle: f32,
+
+    /// Degrees above the horizon over which the environment fades into --ground-color
+    #[arg(long, default_value_t = 0.0)]
+    horizon_blend: f32,
+
+    /// Apply a .cube 3D LUT to the linear input as a grading stage
+    #[arg(long)]
+    lut: Option<PathBuf>,
+
+    /// Downsample inputs whose faces are larger than this many texels
+    #[arg(long)]
+    max_face_size: Option<u32>,
+
+    /// Prefilter the input for specular image based lighting
+    #[arg(long)]
+    prefilter: bool,
+
+    /// Prefilter preset setting samples, lod bias, accumulation and seam fixing, overridden by the options below
+    #[arg(long, value_enum, default_value_t = Quality::Standard)]
+    prefilter_quality: Quality,
+
+    /// GGX samples per texel when prefiltering [default: 64 for draft, 1024 for standard, 4096 for high]
+    #[arg(long)]
+    prefilter_samples: Option<u32>,
+
+    /// Also sample bright parts of the environment when prefiltering, removing speckles from small suns at twice the cost
+    #[arg(long)]
+    sample_lights: bool,
+
+    /// Points the GGX samples of each texel are placed at when prefiltering
+    #[arg(long, value_enum, default_value_t = Sequence::Random)]
+    sample_sequence: Sequence,
+
+    /// Prefilter adaptively, sampling each texel until its relative standard error is below this, e.g. 0.01
+    #[arg(long)]
+    adaptive_tolerance: Option<f32>,
+
+    /// Most samples an adaptively prefiltered texel takes [default: 4 times --prefilter-samples]
+    #[arg(long, requires = "adaptive_tolerance")]
+    max_prefilter_samples: Option<u32>,
+
+    /// Added to the source mip level prefilter samples read, higher is blurrier but less noisy [default: from the preset]
+    #[arg(long)]
+    prefilter_lod_bias: Option<f32>,
+
+    /// Average texels along the face edges of prefiltered levels [default: from the preset]
+    #[arg(long)]
+    fix_seams: Option<bool>,
+
+    /// How mip levels map to roughness when prefiltering
+    #[arg(long, value_enum, default_value_t = Mapping::Linear)]
+    roughness_mapping: Mapping,
+
+    /// Explicit perceptual roughness of each mip level when prefiltering, overrides --roughness-mapping
+    #[arg(long, value_delimiter = ',')]
+    roughness_levels: Vec<f32>,
+
+    /// Sum prefilter samples in f64, avoiding drift with many samples over bright suns [default: from the preset]
+    #[arg(long)]
+    precise_accumulation: Option<bool>,
+
+    /// Write one output per face size, e.g. 1024,512,256, named like output_1024.ktx2, sharing the work before resizing
+    #[arg(long, value_delimiter = ',')]
+    variants: Vec<u32>,
+
+    /// Keep at most this many mip levels
+    #[arg(long)]
+    max_mip_levels: Option<u32>,
+
+    /// Drop mip levels whose faces are smaller than this many texels
+    #[arg(long)]
+    min_mip_size: Option<u32>,
+
+    /// Supercompression scheme of the outputs
+    #[arg(long, value_enum, default_value_t = Scheme::Zstd)]
+    supercompression: Scheme,
+
+    /// Zstandard compression level, 0 selects the zstd default
+    #[arg(long, default_value_t = 0)]
+    zstd_level: i32,
+
+    /// Store mip levels smaller than this many bytes without zstd compression
+    #[arg(long, default_value_t = DEFAULT_MIN_COMPRESSED_LEVEL_SIZE)]
+    min_compressed_level_size: usize,
+
+    /// How equirectangular inputs that aren't 2:1 are made 2:1
+    #[arg(long, value_enum, default_value_t = Aspect::Letterbox)]
+    equirect_aspect: Aspect,
+
+    /// Face size of the cubemaps equirectangular and dual-fisheye inputs are converted to [default: a quarter of their width]
+    #[arg(long)]
+    equirect_face_size: Option<u32>,
+
+    /// Take 2D inputs as dual-fisheye images from 360° cameras, front lens on the left, instead of equirectangular panoramas
+    #[arg(long)]
+    dual_fisheye: bool,
+
+    /// Field of view of each --dual-fisheye lens in degrees
+    #[arg(long, default_value_t = DEFAULT_FISHEYE_FOV)]
+    fisheye_fov: f32,
+
+    /// Radius of each --dual-fisheye image circle, as a fraction of the image height
+    #[arg(long, default_value_t = 0.5)]
+    fisheye_radius: f32,
+
+    /// Degrees around the seam over which the --dual-fisheye lenses are blended
+    #[arg(long, default_value_t = DEFAULT_FISHEYE_BLEND)]
+    fisheye_blend: f32,
+
+    /// Byte order of the input's faces and mip levels, mip-major for raw GPU copies made level by level
+    #[arg(long, value_enum, default_value_t = Layout::FaceMajor)]
+    input_layout: Layout,
+
+    /// Refuse inputs with non-square faces, or crop or pad them to squares, dropping their mips
+    #[arg(long, value_enum, default_value_t = NonSquare::Error)]
+    non_square_faces: NonSquare,
+
+    /// Keep the mip chain of the input, or regenerate it from the top level with --mip-filter
+    #[arg(long, value_enum, default_value_t = MipHandling::Reuse)]
+    input_mips: MipHandling,
+
+    /// Filter regenerated or generated mip levels are downsampled with
+    #[arg(long, value_enum, default_value_t = Filter::Box)]
+    mip_filter: Filter,
+
+    /// Width of --mip-filter triangle or gaussian in texels of the smaller level [default: 2 for triangle, 3 for gaussian]
+    #[arg(long)]
+    mip_filter_width: Option<f32>,
+
+    /// Add the diffuse irradiance of the input as a second cube array layer, named in the metadata
+    #[arg(long)]
+    merge_irradiance: bool,
+
+    /// Also write the diffuse irradiance of each input to these paths, unprefiltered
+    #[arg(long, value_delimiter = ',')]
+    diffuse_outputs: Vec<PathBuf>,
+
+    /// Face size of the --diffuse-outputs cubemaps
+    #[arg(long, default_value_t = DEFAULT_DIFFUSE_FACE_SIZE)]
+    diffuse_face_size: u32,
+
+    /// Also write the spherical harmonics of each input's radiance to these paths as JSON
+    #[arg(long, value_delimiter = ',')]
+    sh_outputs: Vec<PathBuf>,
+
+    /// Leave these faces out, writing an incomplete cubemap, e.g. ny for sky-only captures
+    #[arg(long, value_enum, value_delimiter = ',')]
+    omit_faces: Vec<Face>,
+
+    /// Write the faces as a 2D array texture instead of a cubemap, naming the face of each layer in the metadata
+    #[arg(long)]
+    face_array: bool,
+
+    /// Tint each face and burn its name into it, for debugging orientation
+    #[arg(long)]
+    label_faces: bool,
+
+    /// Embed a tone-mapped PNG preview this many pixels wide in the metadata
+    #[arg(long)]
+    thumbnail: Option<u32>,
+
+    /// Record the bake time in the provenance metadata, making outputs differ between runs
+    #[arg(long)]
+    provenance_timestamp: bool,
+
+    /// What to do with outputs that already exist
+    #[arg(long, value_enum, default_value_t = Existing::Overwrite)]
+    existing: Existing,
+
+    /// Memory in MiB concurrent processing may use, inputs are also loaded one at a time [default: unlimited]
+    #[arg(long)]
+    memory_budget: Option<usize>,
+
+    /// Number of worker threads for prefiltering, resampling and encoding [default: one per core]
+    #[arg(long, global = true)]
+    threads: Option<usize>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print the header, levels, Data-Format Descriptor and metadata of ktx2 files
+    Info {
+        /// ktx2 file paths
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Rewrite a ktx2 file with a different supercompression, keeping the texel data
+    Recompress {
+        /// Input ktx2 file path
+        input: PathBuf,
+        /// Output ktx2 file path
+        output: PathBuf,
+        /// Supercompression scheme of the output
+        #[arg(long, value_enum, default_value_t = Scheme::Zstd)]
+        supercompression: Scheme,
+        /// Zstandard compression level, 0 selects the zstd default
+        #[arg(long, default_value_t = 0)]
+        zstd_level: i32,
+        /// Compress with this zstd dictionary, see train-dictionary
+        #[arg(long)]
+        dictionary: Option<PathBuf>,
+    },
+    /// Train a zstd dictionary on many small ktx2 files, e.g. all probes of a scene
+    TrainDictionary {
+        /// ktx2 file paths
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Output dictionary path
+        #[arg(short, long)]
+        output: PathBuf,
+        /// Largest dictionary size in bytes
+        #[arg(long, default_value_t = 112_640)]
+        max_size: usize,
+    },
+    /// Remove mip levels from a ktx2 file without re-encoding it, e.g. for low-spec presets
+    StripMips {
+        /// Input ktx2 file path
+        input: PathBuf,
+        /// Output ktx2 file path
+        output: PathBuf,
+        /// Remove this many of the largest mip levels
+        #[arg(long, default_value_t = 0)]
+        drop_largest: u32,
+        /// Remove mip levels larger than this many texels
+        #[arg(long)]
+        max_size: Option<u32>,
+        /// Remove mip levels smaller than this many texels
+        #[arg(long)]
+        min_size: Option<u32>,
+    },
+    /// Check ktx2 files against the constraints of the KTX2 specification
+    Validate {
+        /// ktx2 file paths
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Compare two ktx2 cubemaps in log2 luminance, optionally writing per-face heatmaps
+    Diff {
+        /// Reference ktx2 file path
+        reference: PathBuf,
+        /// ktx2 file path compared against the reference
+        test: PathBuf,
+        /// Mip level to compare
+        #[arg(long, default_value_t = 0)]
+        mip_level: u32,
+        /// Directory to write px.png, nx.png, ... heatmaps to
+        #[arg(long)]
+        heatmaps: Option<PathBuf>,
+        /// Error in stops shown as white in the heatmaps
+        #[arg(long, default_value_t = 1.0)]
+        max_error: f32,
+    },
+    /// Report how much the total radiance of each mip level drifts from the source
+    Energy {
+        /// Prefiltered ktx2 file path
+        prefiltered: PathBuf,
+        /// Unfiltered source ktx2 file path [default: the top level of the prefiltered file]
+        #[arg(long)]
+        source: Option<PathBuf>,
+    },
+    /// Compare the prefilter against a brute-force reference convolution at low resolution
+    Conformance {
+        /// Source ktx2 file path [default: a procedural sky with a sun]
+        #[arg(long)]
+        input: Option<PathBuf>,
+        /// Face size to compare at
+        #[arg(long, default_value_t = DEFAULT_CONFORMANCE_FACE_SIZE)]
+        face_size: u32,
+        /// Prefilter preset to check
+        #[arg(long, value_enum, default_value_t = Quality::Standard)]
+        quality: Quality,
+        /// GGX samples per texel [default: from the preset]
+        #[arg(long)]
+        samples: Option<u32>,
+        /// Fail if the mean error of any mip level exceeds this many stops
+        #[arg(long)]
+        tolerance: Option<f32>,
+        /// Sum prefilter samples in f64 [default: from the preset]
+        #[arg(long)]
+        precise_accumulation: Option<bool>,
+    },
+    /// Save the PNG preview embedded with --thumbnail
+    ExtractThumbnail {
+        /// Input ktx2 file path
+        input: PathBuf,
+        /// Output png file path
+        output: PathBuf,
+    },
+    /// Convert with toktx's command line, for build scripts switching over from toktx
+    Toktx(ToktxArgs),
+    /// Bake one HDRI into the diffuse and specular maps of a Bevy EnvironmentMapLight
+    Bake(BakeArgs),
+    /// Convert frames of an equirectangular video into an animated cube array, one layer per frame
+    #[cfg(feature = "video")]
+    Video {
+        /// Input video file path
+        input: PathBuf,
+        /// Output ktx2 file path
+        output: PathBuf,
+        /// Keep every nth frame
+        #[arg(long, default_value_t = 1)]
+        step: u32,
+        /// Stop after this many kept frames
+        #[arg(long)]
+        max_frames: Option<u32>,
+        /// Face size of the cubemaps [default: a quarter of the video width]
+        #[arg(long)]
+        face_size: Option<u32>,
+        /// Transfer function of the frames: linear, srgb or a gamma exponent such as 2.2
+        #[arg(long, default_value = "srgb")]
+        transfer: TransferFunction,
+        /// Pixel encoding of the output file
+        #[arg(short, long, value_enum, default_value_t = Format::Rgb9e5)]
+        format: Format,
+        /// Prefilter each frame for specular image based lighting
+        #[arg(long)]
+        prefilter: bool,
+    },
+}
+
+/// The toktx options that apply to HDR cubemaps, translated into the options
+/// of the main command.
+#[derive(clap::Args, Debug)]
+struct ToktxArgs {
+    /// Ignored, outputs are always KTX2
+    #[arg(long)]
+    t2: bool,
+
+    /// Ignored, outputs are always cubemaps. Six face images aren't supported, convert a cubemap instead
+    #[arg(long)]
+    cubemap: bool,
+
+    /// Generate a mip chain for inputs without one
+    #[arg(long)]
+    genmipmap: bool,
+
+    /// Keep at most this many mip levels
+    #[arg(long)]
+    levels: Option<u32>,
+
+    /// Supercompress with zstd, at level 3 unless given as --zcmp=<level>
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "3")]
+    zcmp: Option<i32>,
+
+    /// Transfer function of the input, linear or srgb
+    #[arg(long = "assign_oetf", value_parser = ["linear", "srgb"])]
+    assign_oetf: Option<String>,
+
+    /// Pixel encoding of the output, not a toktx option. toktx keeps the input format, which is rgba16f here
+    #[arg(long, value_enum, default_value_t = Format::Rgba16Float)]
+    format: Format,
+
+    /// Output file path
+    outfile: PathBuf,
+
+    /// Input file path
+    #[arg(required = true)]
+    infiles: Vec<PathBuf>,
+}
+
+impl ToktxArgs {
+    /// Command line of the main command doing the same conversion.
+    fn to_args(&self) -> Vec<OsString> {
+        if self.infiles.len() > 1 {
+            panic!("Building cubemaps from six face images isn't supported, convert a cubemap");
+        }
+        let mut args: Vec<OsString> = vec![
+            env!("CARGO_PKG_NAME").into(),
+            "--inputs".into(),
+            self.infiles[0].clone().into(),
+            "--outputs".into(),
+            self.outfile.clone().into(),
+            "--format".into(),
+            self.format.to_possible_value().unwrap().get_name().into(),
+        ];
+        match self.zcmp {
+            Some(level) => args.extend(["--zstd-level".into(), level.to_string().into()]),
+            None => args.extend(["--supercompression".into(), "none".into()]),
+        }
+        if self.genmipmap {
+            args.extend(["--input-mips".into(), "generate".into()]);
+        }
+        if let Some(levels) = self.levels {
+            args.extend(["--max-mip-levels".into(), levels.to_string().into()]);
+        }
+        if let Some(oetf) = &self.assign_oetf {
+            args.extend(["--input-transfer".into(), oetf.into()]);
+        }
+        args
+    }
+}
+
+/// Writes the files of an `EnvironmentMapLight` for one HDRI, translated into
+/// the options of the main command.
+#[derive(clap::Args, Debug)]
+struct BakeArgs {
+    /// Input HDRI, an equirectangular panorama or a cubemap
+    input: PathBuf,
+
+    /// Directory the files are written to, created if missing
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
+
+    /// Start of the output file names [default: the input file stem]
+    #[arg(long)]
+    name: Option<String>,
+
+    /// Face size of the specular map
+    #[arg(long, default_value_t = DEFAULT_SPECULAR_FACE_SIZE)]
+    face_size: u32,
+
+    /// Face size of the diffuse map
+    #[arg(long, default_value_t = DEFAULT_DIFFUSE_FACE_SIZE)]
+    diffuse_face_size: u32,
+
+    /// Pixel encoding of both maps
+    #[arg(short, long, value_enum, default_value_t = Format::Rgb9e5)]
+    format: Format,
+
+    /// Prefilter preset of the specular map
+    #[arg(long, value_enum, default_value_t = Quality::Standard)]
+    quality: Quality,
+
+    /// Also write the spherical harmonics of the environment as <name>_sh.json
+    #[arg(long)]
+    sh: bool,
+}
+
+impl BakeArgs {
+    /// Command line of the main command writing `<name>_specular.ktx2`,
+    /// `<name>_diffuse.ktx2` and optionally `<name>_sh.json`.
+    fn to_args(&self) -> Vec<OsString> {
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => self
+                .input
+                .file_stem()
+                .unwrap_or_else(|| panic!("{} has no file name", self.input.display()))
+                .to_string_lossy()
+                .into_owned(),
+        };
+        let output = |suffix: &str| self.output_dir.join(format!("{name}_{suffix}"));
+        let mut args: Vec<OsString> = vec![
+            env!("CARGO_PKG_NAME").into(),
+            "--inputs".into(),
+            self.input.clone().into(),
+            "--outputs".into(),
+            output("specular.ktx2").into(),
+            "--diffuse-outputs".into(),
+            output("diffuse.ktx2").into(),
+            "--diffuse-face-size".into(),
+            self.diffuse_face_size.to_string().into(),
+            "--format".into(),
+            self.format.to_possible_value().unwrap().get_name().into(),
+            "--prefilter".into(),
+            "--prefilter-quality".into(),
+            self.quality.to_possible_value().unwrap().get_name().into(),
+            // Equirectangular inputs are resampled at the face size, cubemaps
+            // downsampled to it.
+            "--equirect-face-size".into(),
+            self.face_size.to_string().into(),
+            "--max-face-size".into(),
+            self.face_size.to_string().into(),
+        ];
+        if self.sh {
+            args.extend(["--sh-outputs".into(), output("sh.json").into()]);
+        }
+        args
+    }
+}
+
+/// Cubemap faces, p and n standing for the positive and negative axis.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Face {
+    Px,
+    Nx,
+    Py,
+    Ny,
+    Pz,
+    Nz,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Scheme {
+    None,
+    Zstd,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    /// Shared-exponent RGB9E5
+    Rgb9e5,
+    /// LogLuv32 packed into RGBA8, for hardware without shared-exponent formats
+    #[value(name = "logluv32")]
+    LogLuv32,
+    /// RGBM packed into RGBA8, for LDR-only mobile targets
+    Rgbm,
+    /// RGBD packed into RGBA8, for LDR-only mobile targets
+    Rgbd,
+    /// Lossless copy of Rgba16Float input, keeping alpha
+    #[value(name = "rgba16f")]
+    Rgba16Float,
+    /// sRGB RGBA8 scaled by the range, keeping alpha
+    #[value(name = "rgba8")]
+    Rgba8,
+    /// Packed 11/11/10-bit floats without shared exponent
+    #[value(name = "b10g11r11")]
+    B10g11r11,
+    /// Block-compressed BC6H, for desktop GPUs
+    #[value(name = "bc6h")]
+    Bc6h,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Primaries {
+    Rec709,
+    Rec2020,
+    #[value(name = "acescg")]
+    AcesCg,
+    DisplayP3,
+}
+
+impl From<Primaries> for ColorPrimaries {
+    fn from(primaries: Primaries) -> Self {
+        match primaries {
+            Primaries::Rec709 => ColorPrimaries::Rec709,
+            Primaries::Rec2020 => ColorPrimaries::Rec2020,
+            Primaries::AcesCg => ColorPrimaries::AcesCg,
+            Primaries::DisplayP3 => ColorPrimaries::DisplayP3,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Measure {
+    /// Brightest texel
+    Peak,
+    /// Solid angle weighted geometric mean
+    LogAverage,
+    /// Auto exposure from a luminance percentile, see --normalize-percentile
+    Percentile,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MipHandling {
+    /// Re-encode the mips of the input as they are
+    Reuse,
+    /// Discard the mips of the input and filter new ones from the top level with --mip-filter
+    Regenerate,
+    /// Filter mips from the top level of inputs without any with --mip-filter
+    Generate,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Aspect {
+    /// Take the longer side as a full turn and fill the rest with the average of the adjacent edge
+    Letterbox,
+    /// Cut the longer side down, keeping the center
+    Crop,
+    /// Scale to 2:1, distorting the environment
+    Stretch,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Layout {
+    /// All mip levels of a face together, like KTX2 files and Bevy images
+    FaceMajor,
+    /// All faces of a mip level together
+    MipMajor,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum NonSquare {
+    /// Fail on non-square faces
+    Error,
+    /// Crop the longer side to the shorter one, keeping the center
+    Crop,
+    /// Pad the shorter side to the longer one by repeating the edges
+    Pad,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Existing {
+    /// Replace existing outputs
+    Overwrite,
+    /// Leave existing outputs untouched
+    Skip,
+    /// Fail on existing outputs
+    Error,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+enum Nadir {
+    /// Keep the nadir as captured
+    None,
+    /// Reflect the surroundings into the region
+    Mirror,
+    /// Blend smoothly from the colors around the region
+    Blur,
+    /// Copy the neighboring region
+    Clone,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Filter {
+    /// Average of the 2×2 texels covered, the sharpest
+    Box,
+    /// Tent weights, smoother
+    Triangle,
+    /// Gaussian weights, the smoothest
+    Gaussian,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Quality {
+    Draft,
+    Standard,
+    High,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Mapping {
+    /// Perceptual roughness linear in the mip level, as sampled by Bevy
+    Linear,
+    /// Squared perceptual roughness linear in the mip level
+    PerceptualSquared,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Sequence {
+    /// Independent pseudo-random points
+    Random,
+    /// The Hammersley set, even at exactly --prefilter-samples
+    Hammersley,
+    /// The Sobol sequence, even at any sample count
+    Sobol,
+    /// The Sobol sequence shifted per texel in a blue noise pattern, for the least visible noise at low sample counts
+    BlueNoise,
+}
+
+impl Quality {
+    fn preset(self) -> PrefilterQuality {
+        match self {
+            Quality::Draft => PrefilterQuality::Draft,
+            Quality::Standard => PrefilterQuality::Standard,
+            Quality::High => PrefilterQuality::High,
+        }
+    }
+}
+
+impl Args {
+    fn prefilter_settings(&self) -> PrefilterSettings {
+        let roughness_mapping = if !self.roughness_levels.is_empty() {
+            RoughnessMapping::Custom(self.roughness_levels.clone())
+        } else {
+            match self.roughness_mapping {
+                Mapping::Linear => RoughnessMapping::Linear,
+                Mapping::PerceptualSquared => RoughnessMapping::PerceptualSquared,
+            }
+        };
+        let mut settings = PrefilterSettings::preset(self.prefilter_quality.preset());
+        settings.roughness_mapping = roughness_mapping;
+        if let Some(sample_count) = self.prefilter_samples {
+            settings.sample_count = sample_count;
+        }
+        if let Some(lod_bias) = self.prefilter_lod_bias {
+            settings.lod_bias = lod_bias;
+        }
+        if let Some(fix_seams) = self.fix_seams {
+            settings.fix_seams = fix_seams;
+        }
+        if let Some(precise_accumulation) = self.precise_accumulation {
+            settings.accumulation = accumulation(precise_accumulation);
+        }
+        settings.sample_lights = self.sample_lights;
+        settings.sequence = match self.sample_sequence {
+            Sequence::Random => SampleSequence::Random,
+            Sequence::Hammersley => SampleSequence::Hammersley,
+            Sequence::Sobol => SampleSequence::Sobol,
+            Sequence::BlueNoise => SampleSequence::BlueNoise,
+        };
+        if let Some(tolerance) = self.adaptive_tolerance {
+            settings.adaptive = Some(AdaptiveSampling {
+                tolerance,
+                max_sample_count: self
+                    .max_prefilter_samples
+                    .unwrap_or(settings.sample_count * 4),
+                ..Default::default()
+            });
+        }
+        settings
+    }
+
+    fn encode_settings(
+        &self,
+        primaries: ColorPrimaries,
+        memory_budget: Option<MemoryBudget>,
+    ) -> EncodeSettings {
+        EncodeSettings::default()
+            .with_format(self.output_format())
+            .with_primaries(primaries)
+            .with_supercompression(match self.supercompression {
+                Scheme::None => Supercompression::None,
+                Scheme::Zstd => Supercompression::Zstandard {
+                    level: self.zstd_level,
+                },
+            })
+            .with_normalize(
+                self.luminance_measure()
+                    .map(|measure| (measure, self.normalize_target)),
+            )
+            .with_intensity(self.intensity)
+            .with_gain(self.gain)
+            .with_exposure(self.exposure)
+            .with_rotation(
+                Quat::from_rotation_y(self.rotation.to_radians())
+                    * horizon_correction(self.pitch.to_radians(), self.roll.to_radians()),
+            )
+            .with_ground_projection(
+                self.capture_height
+                    .zip(self.camera_height)
+                    .map(|(capture, camera)| GroundProjection::new(capture, camera)),
+            )
+            .with_ground_replacement(self.ground_replacement())
+            .with_max_face_size(self.max_face_size)
+            .with_prefilter(self.prefilter.then(|| self.prefilter_settings()))
+            .with_mip_limits(self.max_mip_levels, self.min_mip_size)
+            .with_min_compressed_level_size(self.min_compressed_level_size)
+            .with_input_mips(match self.input_mips {
+                MipHandling::Reuse => InputMips::Reuse,
+                MipHandling::Regenerate => InputMips::Regenerate,
+                MipHandling::Generate => InputMips::Generate,
+            })
+            .with_mip_filter(match self.mip_filter {
+                Filter::Box => MipFilter::Box,
+                Filter::Triangle => MipFilter::Triangle {
+                    width: self.mip_filter_width.unwrap_or(DEFAULT_TRIANGLE_WIDTH),
+                },
+                Filter::Gaussian => MipFilter::Gaussian {
+                    width: self.mip_filter_width.unwrap_or(DEFAULT_GAUSSIAN_WIDTH),
+                },
+            })
+            .with_cubemap_faces(
+                self.omit_faces
+                    .iter()
+                    .fold(ALL_FACES, |faces, face| faces & !face_bit(*face as u32)),
+            )
+            .with_face_array(self.face_array)
+            .with_thumbnail(self.thumbnail)
+            .with_memory_budget(memory_budget)
+            .with_overwrite(self.overwrite_policy())
+    }
+
+    fn overwrite_policy(&self) -> OverwritePolicy {
+        match self.existing {
+            Existing::Overwrite => OverwritePolicy::Overwrite,
+            Existing::Skip => OverwritePolicy::Skip,
+            Existing::Error => OverwritePolicy::Error,
+        }
+    }
+
+    /// Path of `input`, downloading it first if it's a URL.
+    fn local_input(&self, input: &Path) -> PathBuf {
+        let Some(url) = input.to_str().filter(|input| is_url(input)) else {
+            return input.to_path_buf();
+        };
+        #[cfg(feature = "download")]
+        {
+            let cache_dir = self
+                .download_cache
+                .clone()
+                .unwrap_or_else(|| std::env::temp_dir().join(env!("CARGO_PKG_NAME")));
+            fetch(url, &cache_dir).unwrap()
+        }
+        #[cfg(not(feature = "download"))]
+        panic!("{url} is a URL, which needs the download feature");
+    }
+
+    /// Statistic `--normalize` scales by.
+    fn luminance_measure(&self) -> Option<LuminanceMeasure> {
+        self.normalize.map(|measure| match measure {
+            Measure::Peak => LuminanceMeasure::Peak,
+            Measure::LogAverage => LuminanceMeasure::LogAverage,
+            Measure::Percentile => LuminanceMeasure::Percentile(self.normalize_percentile),
+        })
+    }
+
+    /// Nadir patch of the input at `index`.
+    fn nadir_patch(&self, index: usize) -> Option<NadirPatch> {
+        let nadir = match self.nadir_patch.as_slice() {
+            [] => Nadir::None,
+            [nadir] => *nadir,
+            per_input => per_input[index],
+        };
+        let fill = match nadir {
+            Nadir::None => return None,
+            Nadir::Mirror => NadirFill::Mirror,
+            Nadir::Blur => NadirFill::Blur,
+            Nadir::Clone => NadirFill::Clone,
+        };
+        Some(NadirPatch::new(fill).with_radius(self.nadir_radius))
+    }
+
+    fn ground_replacement(&self) -> Option<GroundReplacement> {
+        let rgb = |values: &[f32]| [values[0], values[1], values[2]];
+        let fill = match (
+            self.ground_color.is_empty(),
+            self.ground_nadir_color.is_empty(),
+        ) {
+            (true, _) => return None,
+            (false, true) => GroundFill::Solid(rgb(&self.ground_color)),
+            (false, false) => GroundFill::Gradient {
+                horizon: rgb(&self.ground_color),
+                nadir: rgb(&self.ground_nadir_color),
+            },
+        };
+        Some(
+            GroundReplacement::new(fill)
+                .with_horizon_angle(self.horizon_angle)
+                .with_blend_angle(self.horizon_blend),
+        )
+    }
+
+    fn output_format(&self) -> OutputFormat {
+        self.format.output_format(self.range)
+    }
+}
+
+impl Format {
+    fn output_format(self, range: Option<f32>) -> OutputFormat {
+        match self {
+            Format::Rgb9e5 => OutputFormat::Rgb9e5,
+            Format::LogLuv32 => OutputFormat::LogLuv32,
+            Format::Rgbm => OutputFormat::Rgbm {
+                range: range.unwrap_or(DEFAULT_RGBM_RANGE),
+            },
+            Format::Rgbd => OutputFormat::Rgbd {
+                range: range.unwrap_or(DEFAULT_RGBD_RANGE),
+            },
+            Format::Rgba16Float => OutputFormat::Rgba16Float,
+            Format::Rgba8 => OutputFormat::Rgba8 {
+                range: range.unwrap_or(DEFAULT_RGBA8_RANGE),
+            },
+            Format::B10g11r11 => OutputFormat::B10g11r11,
+            Format::Bc6h => OutputFormat::Bc6h,
+        }
+    }
 }
 
 fn main() {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    let translated = match &args.command {
+        Some(Command::Toktx(toktx)) => Some(toktx.to_args()),
+        Some(Command::Bake(bake)) => {
+            std::fs::create_dir_all(&bake.output_dir).unwrap();
+            Some(bake.to_args())
+        }
+        _ => None,
+    };
+    if let Some(translated) = translated {
+        let threads = args.threads;
+        args = Args::parse_from(translated);
+        args.threads = threads;
+    }
+    if let Some(threads) = args.threads {
+        set_global_thread_count(threads).unwrap();
+    }
+
+    match &args.command {
+        Some(Command::Info { files }) => {
+            for file in files {
+                println!("{}\n{}", file.display(), KTX2File::load(file).unwrap());
+            }
+            return;
+        }
+        Some(Command::Recompress {
+            input,
+            output,
+            supercompression,
+            zstd_level,
+            dictionary,
+        }) => {
+            let supercompression = match supercompression {
+                Scheme::None => Supercompression::None,
+                Scheme::Zstd => Supercompression::Zstandard { level: *zstd_level },
+            };
+            let mut file = KTX2File::load(input).unwrap();
+            match dictionary {
+                Some(dictionary) => {
+                    if supercompression == Supercompression::None {
+                        panic!("--dictionary needs zstd supercompression");
+                    }
+                    let dictionary = ZstdDictionary::load(dictionary).unwrap();
+                    file.compress_with_dictionary(&dictionary, *zstd_level)
+                        .unwrap();
+                }
+                None => file.resupercompress(supercompression).unwrap(),
+            }
+            file.save(output).unwrap();
+            return;
+        }
+        Some(Command::TrainDictionary {
+            files,
+            output,
+            max_size,
+        }) => {
+            let files = files
+                .iter()
+                .map(|file| KTX2File::load(file).unwrap())
+                .collect::<Vec<_>>();
+            let dictionary = train_dictionary(&files, *max_size).unwrap();
+            dictionary.save(output).unwrap();
+            println!(
+                "Trained a {} byte dictionary with id {}",
+                dictionary.bytes.len(),
+                dictionary.id()
+            );
+            return;
+        }
+        Some(Command::StripMips {
+            input,
+            output,
+            drop_largest,
+            max_size,
+            min_size,
+        }) => {
+            let mut file = KTX2File::load(input).unwrap();
+            file.drop_largest_mips(*drop_largest);
+            file.limit_mip_sizes(*max_size, *min_size);
+            file.save(output).unwrap();
+            return;
+        }
+        Some(Command::Diff {
+            reference,
+            test,
+            mip_level,
+            heatmaps,
+            max_error,
+        }) => {
+            let reference = KTX2File::load(reference).unwrap().to_image().unwrap();
+            let test = KTX2File::load(test).unwrap().to_image().unwrap();
+            let diff = diff_cubemaps(&reference, &test, *mip_level).unwrap_or_else(|e| {
+                Args::command()
+                    .error(clap::error::ErrorKind::ValueValidation, e)
+                    .exit()
+            });
+            for (face, stem) in diff.faces.iter().zip(FACE_FILE_STEMS) {
+                println!(
+                    "{stem}: mean {:.4}, max {:.4} stops",
+                    face.mean_error(),
+                    face.max_error()
+                );
+            }
+            println!(
+                "all: mean {:.4}, max {:.4} stops",
+                diff.mean_error(),
+                diff.max_error()
+            );
+            if let Some(dir) = heatmaps {
+                write_heatmaps(&diff, dir, *max_error).unwrap();
+            }
+            return;
+        }
+        Some(Command::Energy {
+            prefiltered,
+            source,
+        }) => {
+            let prefiltered = KTX2File::load(prefiltered).unwrap().to_image().unwrap();
+            let source = source
+                .as_ref()
+                .map(|source| KTX2File::load(source).unwrap().to_image().unwrap());
+            println!(
+                "{}",
+                energy_report(source.as_ref().unwrap_or(&prefiltered), &prefiltered)
+            );
+            return;
+        }
+        Some(Command::Conformance {
+            input,
+            face_size,
+            quality,
+            samples,
+            tolerance,
+            precise_accumulation,
+        }) => {
+            let source = match input {
+                Some(input) => KTX2File::load(input).unwrap().to_image().unwrap(),
+                None => test_environment(*face_size),
+            };
+            let mut settings = PrefilterSettings::preset(quality.preset());
+            if let Some(samples) = samples {
+                settings.sample_count = *samples;
+            }
+            if let Some(precise_accumulation) = precise_accumulation {
+                settings.accumulation = accumulation(*precise_accumulation);
+            }
+            let report = check_prefilter(&source, *face_size, &settings);
+            println!("{report}");
+            if tolerance.is_some_and(|tolerance| report.max_mean_error() > tolerance) {
+                std::process::exit(1);
+            }
+            return;
+        }
+        #[cfg(feature = "video")]
+        Some(Command::Video {
+            input,
+            output,
+            step,
+            max_frames,
+            face_size,
+            transfer,
+            format,
+            prefilter,
+        }) => {
+            let frames = VideoFrames {
+                step: *step,
+                max_frames: *max_frames,
+                transfer: *transfer,
+            };
+            let frames = decode_frames(input, &frames).unwrap();
+            let Some((_, first)) = frames.first() else {
+                panic!("{} has no frames", input.display());
+            };
+            let face_size = face_size.unwrap_or(first.texture_descriptor.size.width / 4);
+            let settings = EncodeSettings::default()
+                .with_format(format.output_format(None))
+                .with_prefilter(prefilter.then(PrefilterSettings::default));
+            encode_frames(&frames, face_size, output, &settings).unwrap();
+            return;
+        }
+        Some(Command::ExtractThumbnail { input, output }) => {
+            let file = KTX2File::load(input).unwrap();
+            let Some(thumbnail) = file.thumbnail() else {
+                panic!("{} has no thumbnail", input.display());
+            };
+            std::fs::write(output, thumbnail).unwrap();
+            return;
+        }
+        Some(Command::Validate { files }) => {
+            let mut all_valid = true;
+            for file in files {
+                let issues = validate_file(file).unwrap();
+                if issues.is_empty() {
+                    println!("{}: valid", file.display());
+                }
+                for issue in &issues {
+                    println!("{}: {issue}", file.display());
+                }
+                all_valid &= is_valid(&issues);
+            }
+            if !all_valid {
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => {}
+    }
 
     if args.inputs.is_empty() {
         panic!("No input paths provided");
@@ -33,10 +1232,55 @@ fn main() {
         panic!("No output paths provided");
     }
 
-    if args.inputs.len() != args.outputs.len() {
+    if !args.bracket_evs.is_empty() {
+        if args.bracket_evs.len() != args.inputs.len() {
+            panic!("--bracket-evs needs one value per input");
+        }
+        if args.outputs.len() != 1 {
+            panic!("Merged brackets need exactly one output path");
+        }
+    } else if args.inputs.len() != args.outputs.len() {
         panic!("Input and output path lengths don't match");
     }
 
+    for (paths, option) in [
+        (&args.diffuse_outputs, "--diffuse-outputs"),
+        (&args.sh_outputs, "--sh-outputs"),
+    ] {
+        if !paths.is_empty() && paths.len() != args.outputs.len() {
+            panic!("{option} needs one path per output path");
+        }
+    }
+
+    if ![0, 3].contains(&args.ground_color.len())
+        || ![0, 3].contains(&args.ground_nadir_color.len())
+    {
+        panic!("Ground colors need exactly three values");
+    }
+
+    if args.nadir_patch.len() > 1 && args.nadir_patch.len() != args.inputs.len() {
+        panic!("--nadir-patch needs one method, or one per input");
+    }
+
+    if args.capture_height.is_some() != args.camera_height.is_some() {
+        panic!("--capture-height and --camera-height need to be given together");
+    }
+
+    if !args.ground_nadir_color.is_empty() && args.ground_color.is_empty() {
+        panic!("--ground-nadir-color needs --ground-color");
+    }
+
+    // Read once, failing before any input is converted.
+    let lut = args.lut.as_ref().map(|path| {
+        Arc::new(Lut3d::load(path).unwrap_or_else(|error| panic!("{}: {error}", path.display())))
+    });
+
+    args.inputs = args
+        .inputs
+        .iter()
+        .map(|input| args.local_input(input))
+        .collect();
+
     let mut app = App::new();
     // TODO don't be ridiculous
     app.add_plugins(
@@ -48,7 +1292,7 @@ fn main() {
             .add(AssetPlugin::default())
             .add(ImagePlugin::default()),
     )
-    .add_systems(Update, convert);
+    .add_systems(Update, (load_inputs, convert).chain());
 
     // Use bevy's logging for debug builds.
     #[cfg(debug_assertions)]
@@ -60,39 +1304,213 @@ fn main() {
         });
     }
 
-    for (input, output) in args.inputs.iter().zip(args.outputs.iter()) {
-        let asset_server = app.world.resource_mut::<AssetServer>();
-        // using canonicalize to avoid being relative to the asset folder
-        let image_h = asset_server.load(std::fs::canonicalize(input).unwrap());
-        app.world.spawn(ImageToConvert {
-            image_h,
-            output_path: PathBuf::from(output),
-        });
-    }
+    let queue = if args.bracket_evs.is_empty() {
+        args.inputs
+            .iter()
+            .zip(&args.outputs)
+            .enumerate()
+            .map(|(index, (input, output))| QueuedInput {
+                index,
+                inputs: vec![input.clone()],
+                bracket_evs: Vec::new(),
+                output_path: output.clone(),
+            })
+            .collect()
+    } else {
+        VecDeque::from([QueuedInput {
+            index: 0,
+            inputs: args.inputs.clone(),
+            bracket_evs: args.bracket_evs.clone(),
+            output_path: args.outputs[0].clone(),
+        }])
+    };
+    app.insert_resource(InputQueue(queue));
+    app.insert_resource(GradingLut(lut));
+    let memory_budget = args
+        .memory_budget
+        .map(|mib| MemoryBudget::new(mib * 1024 * 1024));
+    app.insert_resource(SharedMemoryBudget(memory_budget));
 
+    app.insert_resource(args);
     app.run();
 }
 
-#[derive(Component)]
-struct Converted;
+/// `output` with `_<face_size>` appended to the file stem, for `--variants`.
+fn variant_path(output: &Path, face_size: u32) -> PathBuf {
+    let mut file_name = output.file_stem().unwrap_or_default().to_owned();
+    file_name.push(format!("_{face_size}"));
+    if let Some(extension) = output.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    output.with_file_name(file_name)
+}
+
+/// Parses `--gain`, three comma separated values or one for all channels.
+fn parse_gain(value: &str) -> Result<[f32; 3], String> {
+    let values = value
+        .split(',')
+        .map(|v| v.trim().parse::<f32>().map_err(|e| format!("`{v}`: {e}")))
+        .collect::<Result<Vec<_>, _>>()?;
+    match values[..] {
+        [gain] => Ok([gain; 3]),
+        [r, g, b] => Ok([r, g, b]),
+        _ => Err(format!("expected 1 or 3 values, got {}", values.len())),
+    }
+}
+
+/// Accumulation selected by `--precise-accumulation`.
+fn accumulation(precise: bool) -> Accumulation {
+    if precise {
+        Accumulation::F64
+    } else {
+        Accumulation::F32
+    }
+}
+
+/// Settings recorded in the provenance: the resolved encode settings and the
+/// stages run before them, leaving out paths and options that don't change
+/// the output.
+fn provenance_settings(args: &Args, settings: &EncodeSettings) -> String {
+    let lut = args
+        .lut
+        .as_ref()
+        .and_then(|lut| lut.file_name())
+        .map(|name| name.to_string_lossy().into_owned());
+    [
+        format!("encoder={:?}", settings.encoder),
+        format!("primaries={:?}", settings.primaries),
+        format!("supercompression={:?}", settings.supercompression),
+        format!("input_transfer={:?}", args.input_transfer),
+        format!("input_primaries={:?}", args.input_primaries),
+        format!("normalize={:?}", settings.normalize),
+        format!("intensity={}", settings.intensity),
+        format!("gain={:?}", settings.gain),
+        format!("lut={lut:?}"),
+        format!("exposure={}", settings.exposure),
+        format!("rotation={:?}", settings.rotation),
+        format!("nadir_patch={:?}", settings.nadir_patch),
+        format!("ground_projection={:?}", settings.ground_projection),
+        format!("ground_replacement={:?}", settings.ground_replacement),
+        format!("max_face_size={:?}", settings.max_face_size),
+        format!("prefilter={:?}", settings.prefilter),
+        format!("max_mip_levels={:?}", settings.max_mip_levels),
+        format!("min_mip_size={:?}", settings.min_mip_size),
+        format!("input_mips={:?}", settings.input_mips),
+        format!("mip_filter={:?}", settings.mip_filter),
+        format!("cubemap_faces={:#08b}", settings.cubemap_faces),
+        format!("face_array={}", settings.face_array),
+    ]
+    .join("; ")
+}
+
+/// The `--memory-budget` shared by every conversion of the run.
+#[derive(Resource)]
+struct SharedMemoryBudget(Option<MemoryBudget>);
+
+/// The `--lut` grading all inputs.
+#[derive(Resource)]
+struct GradingLut(Option<Arc<Lut3d>>);
+
+/// Inputs whose loading hasn't started yet.
+#[derive(Resource)]
+struct InputQueue(VecDeque<QueuedInput>);
+
+struct QueuedInput {
+    index: usize,
+    /// One input, or every exposure with `--bracket-evs`.
+    inputs: Vec<PathBuf>,
+    bracket_evs: Vec<f32>,
+    output_path: PathBuf,
+}
+
+/// Starts loading queued inputs, all at once, or with `--memory-budget` one
+/// at a time so that only the input being converted is held in memory.
+fn load_inputs(
+    mut commands: Commands,
+    mut queue: ResMut<InputQueue>,
+    asset_server: Res<AssetServer>,
+    loading: Query<(), With<ImageToConvert>>,
+    args: Res<Args>,
+) {
+    if args.memory_budget.is_some() && !loading.is_empty() {
+        return;
+    }
+    while let Some(queued) = queue.0.pop_front() {
+        // using canonicalize to avoid being relative to the asset folder
+        let handles = queued
+            .inputs
+            .iter()
+            .map(|input| asset_server.load(std::fs::canonicalize(input).unwrap()))
+            .collect::<Vec<Handle<Image>>>();
+        commands.spawn(ImageToConvert {
+            image_h: handles[0].clone(),
+            index: queued.index,
+            brackets: handles.into_iter().zip(queued.bracket_evs).collect(),
+            input_path: queued.inputs[0].clone(),
+            output_path: queued.output_path,
+        });
+        if args.memory_budget.is_some() {
+            break;
+        }
+    }
+}
 
 #[derive(Component)]
 struct ImageToConvert {
     image_h: Handle<Image>,
+    /// Position in `--inputs`, for options given per input.
+    index: usize,
+    /// Exposures merged into the input with `--bracket-evs`, including `image_h`.
+    brackets: Vec<(Handle<Image>, f32)>,
+    input_path: PathBuf,
     output_path: PathBuf,
 }
 
 fn convert(
     mut commands: Commands,
-    query: Query<(Entity, &ImageToConvert), Without<Converted>>,
-    images: ResMut<Assets<Image>>,
+    query: Query<(Entity, &ImageToConvert)>,
+    images: Res<Assets<Image>>,
+    queue: Res<InputQueue>,
+    args: Res<Args>,
+    lut: Res<GradingLut>,
+    memory_budget: Res<SharedMemoryBudget>,
     mut app_exit_events: EventWriter<AppExit>,
 ) {
-    if query.is_empty() {
+    if query.is_empty() && queue.0.is_empty() {
         app_exit_events.send(AppExit);
     }
     for (entity, conv) in &query {
         if let Some(image) = images.get(&conv.image_h) {
+            let Some(brackets) = conv
+                .brackets
+                .iter()
+                .map(|(handle, ev)| images.get(handle).map(|image| Bracket { image, ev: *ev }))
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+            // Existing outputs are handled before doing any of the work.
+            let mut output_paths = if args.variants.is_empty() {
+                vec![conv.output_path.clone()]
+            } else {
+                args.variants
+                    .iter()
+                    .map(|face_size| variant_path(&conv.output_path, *face_size))
+                    .collect()
+            };
+            output_paths.extend(args.diffuse_outputs.get(conv.index).cloned());
+            output_paths.extend(args.sh_outputs.get(conv.index).cloned());
+            let pending = output_paths
+                .iter()
+                .map(|path| should_write(path, args.overwrite_policy()))
+                .collect::<std::io::Result<Vec<_>>>()
+                .unwrap();
+            if !pending.contains(&true) {
+                println!("Skipping {}, it already exists", conv.output_path.display());
+                commands.entity(entity).despawn();
+                continue;
+            }
             println!(
                 "Converting {}, {:?}, mip_level_count: {} format:{:?}",
                 &conv.output_path.display(),
@@ -100,8 +1518,150 @@ fn convert(
                 image.texture_descriptor.mip_level_count,
                 image.texture_descriptor.format,
             );
-            write_ktx2(image, &conv.output_path);
-            commands.entity(entity).insert(Converted);
+            let mut image = Cow::Borrowed(image);
+            if !brackets.is_empty() {
+                image = Cow::Owned(merge_exposures(&brackets, args.input_transfer).unwrap());
+            } else if args.input_transfer.is_some()
+                || image.texture_descriptor.format != TextureFormat::Rgba16Float
+            {
+                image = Cow::Owned(linearize(&image, args.input_transfer).unwrap());
+            }
+            if args.input_layout == Layout::MipMajor {
+                image = Cow::Owned(to_face_major(&image, DataLayout::MipMajor));
+            }
+            let size = image.texture_descriptor.size;
+            if size.depth_or_array_layers == 1 && args.dual_fisheye {
+                let lenses = DualFisheye::default()
+                    .with_fov(args.fisheye_fov)
+                    .with_radius(args.fisheye_radius)
+                    .with_blend_angle(args.fisheye_blend);
+                let face_size = args.equirect_face_size.unwrap_or(size.width / 4).max(1);
+                image = Cow::Owned(dual_fisheye_to_cubemap(&image, &lenses, face_size).unwrap());
+            } else if size.depth_or_array_layers == 1 {
+                let equirect = Equirect::from_image(&image).unwrap();
+                if !equirect.is_two_to_one() {
+                    eprintln!(
+                        "Warning: {} is {}×{} instead of 2:1, using --equirect-aspect {}",
+                        conv.input_path.display(),
+                        size.width,
+                        size.height,
+                        args.equirect_aspect.to_possible_value().unwrap().get_name(),
+                    );
+                }
+                let equirect = equirect.to_two_to_one(match args.equirect_aspect {
+                    Aspect::Letterbox => EquirectAspect::Letterbox,
+                    Aspect::Crop => EquirectAspect::Crop,
+                    Aspect::Stretch => EquirectAspect::Stretch,
+                });
+                let face_size = args
+                    .equirect_face_size
+                    .unwrap_or_else(|| equirect.default_face_size());
+                image = Cow::Owned(equirect.to_cubemap(face_size));
+            } else if size.width != size.height {
+                let handling = match args.non_square_faces {
+                    NonSquare::Error => NonSquareFaces::Error,
+                    NonSquare::Crop => NonSquareFaces::Crop,
+                    NonSquare::Pad => NonSquareFaces::Pad,
+                };
+                image = Cow::Owned(square_faces(&image, handling).unwrap_or_else(|error| {
+                    panic!(
+                        "{}: {error}, use --non-square-faces crop or pad",
+                        conv.input_path.display()
+                    )
+                }));
+                let face_size = image.texture_descriptor.size.width;
+                eprintln!(
+                    "Warning: {} has {}×{} faces, {} to {face_size}×{face_size} and dropped its mips",
+                    conv.input_path.display(),
+                    size.width,
+                    size.height,
+                    if handling == NonSquareFaces::Crop {
+                        "cropped"
+                    } else {
+                        "padded"
+                    },
+                );
+            }
+            let input_primaries = args.input_primaries.into();
+            let output_primaries = if args.keep_primaries {
+                input_primaries
+            } else {
+                image = Cow::Owned(convert_primaries_to_rec709(&image, input_primaries));
+                ColorPrimaries::Rec709
+            };
+            let settings = args
+                .encode_settings(output_primaries, memory_budget.0.clone())
+                .with_lut(lut.0.clone())
+                .with_nadir_patch(args.nadir_patch(conv.index));
+            let provenance = Provenance::new(provenance_settings(&args, &settings));
+            let mut provenance = provenance
+                .clone()
+                .with_source_file(&conv.input_path)
+                .unwrap_or_else(|error| {
+                    eprintln!(
+                        "Warning: {}: {error}, leaving the source out of the provenance",
+                        conv.input_path.display()
+                    );
+                    provenance
+                });
+            if args.provenance_timestamp {
+                provenance = provenance.with_timestamp();
+            }
+            let settings = settings.with_provenance(&provenance);
+            let progress = |stage: &str, fraction: f32| {
+                print!("\r{stage}: {:.0}%", fraction * 100.0);
+                std::io::stdout().flush().ok();
+                if fraction >= 1.0 {
+                    println!();
+                }
+            };
+            let cancel = CancellationToken::new();
+            let outputs = if args.variants.is_empty() {
+                let image = process(&image, &settings, &progress, &cancel).unwrap();
+                vec![(image, conv.output_path.clone())]
+            } else {
+                process_variants(&image, &settings, &args.variants, &progress, &cancel)
+                    .unwrap()
+                    .into_iter()
+                    .zip(&args.variants)
+                    .map(|(image, face_size)| (image, variant_path(&conv.output_path, *face_size)))
+                    .collect()
+            };
+            // Irradiance is integrated from the input before prefiltering,
+            // with the other image stages like rotation applied.
+            let diffuse_output = args.diffuse_outputs.get(conv.index);
+            let sh_output = args.sh_outputs.get(conv.index);
+            let unfiltered_settings = settings.clone().with_prefilter(None);
+            let unfiltered =
+                (diffuse_output.is_some() || sh_output.is_some() || args.merge_irradiance)
+                    .then(|| process(&image, &unfiltered_settings, &(), &cancel).unwrap());
+            if let (Some(path), Some(unfiltered)) = (diffuse_output, &unfiltered) {
+                let diffuse = irradiance_cubemap(unfiltered, args.diffuse_face_size, 1);
+                write_ktx2(&diffuse, path, &unfiltered_settings).unwrap();
+            }
+            if let (Some(path), Some(unfiltered)) = (sh_output, &unfiltered) {
+                let sh = SphericalHarmonics9::project(unfiltered);
+                write_bytes_atomically(path, settings.overwrite, sh.to_json().as_bytes()).unwrap();
+            }
+            for (mut image, output_path) in outputs {
+                let mut settings = settings.clone();
+                if let Some(unfiltered) = unfiltered.as_ref().filter(|_| args.merge_irradiance) {
+                    let descriptor = &image.texture_descriptor;
+                    let diffuse = irradiance_cubemap(
+                        unfiltered,
+                        descriptor.size.width,
+                        descriptor.mip_level_count,
+                    );
+                    image = stack_cubemap_layers(&[&image, &diffuse]);
+                    settings = settings.with_layer_names(&["specular", "diffuse"]);
+                }
+                if args.label_faces {
+                    image = label_faces(&image);
+                }
+                write_ktx2(&image, &output_path, &settings).unwrap();
+            }
+            // Dropping the handles frees the decoded input.
+            commands.entity(entity).despawn();
         }
     }
 }
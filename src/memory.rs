@@ -0,0 +1,85 @@
+//! Limiting how much memory concurrent conversions use.
+//!
+//! A batch of large inputs processed in parallel holds several float copies of
+//! each at once. A [`MemoryBudget`] shared by the jobs makes each one reserve
+//! its estimated peak before starting, and wait while the others use it up.
+
+use std::sync::{Arc, Condvar, Mutex};
+
+use bevy::prelude::Image;
+
+#[derive(Debug)]
+struct Usage {
+    limit: usize,
+    used: usize,
+}
+
+/// Bytes shared by concurrent jobs. Clones share the same budget.
+#[derive(Clone, Debug)]
+pub struct MemoryBudget(Arc<(Mutex<Usage>, Condvar)>);
+
+impl MemoryBudget {
+    pub fn new(bytes: usize) -> Self {
+        Self(Arc::new((
+            Mutex::new(Usage {
+                limit: bytes,
+                used: 0,
+            }),
+            Condvar::new(),
+        )))
+    }
+
+    /// Size of the budget in bytes.
+    pub fn limit(&self) -> usize {
+        self.0 .0.lock().unwrap().limit
+    }
+
+    /// Blocks until `bytes` fit into the budget and reserves them until the
+    /// returned reservation is dropped. A job larger than the whole budget
+    /// runs once nothing else holds a reservation, instead of never.
+    pub fn reserve(&self, bytes: usize) -> MemoryReservation {
+        let (usage, released) = &*self.0;
+        let mut usage = usage.lock().unwrap();
+        while usage.used > 0 && usage.used + bytes > usage.limit {
+            usage = released.wait(usage).unwrap();
+        }
+        usage.used += bytes;
+        MemoryReservation {
+            budget: self.clone(),
+            bytes,
+        }
+    }
+}
+
+/// Same budget.
+impl PartialEq for MemoryBudget {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Bytes reserved in a [`MemoryBudget`], released on drop.
+#[derive(Debug)]
+pub struct MemoryReservation {
+    budget: MemoryBudget,
+    bytes: usize,
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        let (usage, released) = &*self.budget.0;
+        usage.lock().unwrap().used -= self.bytes;
+        released.notify_all();
+    }
+}
+
+/// Rough peak memory of [`crate::pipeline::process`] for `image`:
+/// the input plus about three f32 RGBA copies of every level.
+pub fn estimated_peak_bytes(image: &Image) -> usize {
+    let descriptor = &image.texture_descriptor;
+    let block_size = descriptor.format.block_copy_size(None).unwrap_or(8).max(1) as usize;
+    // Compressed formats store a block of texels in each block_size bytes.
+    let (block_width, block_height) = descriptor.format.block_dimensions();
+    let texel_count = image.data.len() / block_size * (block_width * block_height) as usize;
+    image.data.len() + texel_count * 16 * 3
+}
@@ -0,0 +1,94 @@
+//! Merging bracketed LDR exposures into one HDR source, so probes shot as a
+//! series of camera JPEGs can be converted without a separate HDR tool.
+
+use bevy::{prelude::Image, render::render_resource::TextureFormat};
+
+use crate::{
+    color::{read_texels, TransferFunction},
+    cubemap::rgba_f32_to_rgba16f_bytes,
+};
+
+/// One exposure of a bracketed series.
+#[derive(Clone, Copy, Debug)]
+pub struct Bracket<'a> {
+    pub image: &'a Image,
+    /// Exposure relative to the one the result is scaled to, in stops. An
+    /// image shot at `+2` collected four times the light.
+    pub ev: f32,
+}
+
+/// How much a stored value in `[0, 1]` is trusted: fully in the middle, not at
+/// all at black, where noise dominates, or at white, where it clips.
+fn weight(value: f32) -> f32 {
+    let centered = 2.0 * value.clamp(0.0, 1.0) - 1.0;
+    1.0 - centered.powi(8)
+}
+
+/// Merges images of the same scene, size and layout, shot at different
+/// exposures, into a linear `Rgba16Float` image of radiance at EV 0.
+///
+/// Each texel is the average of the exposures' linear values scaled by
+/// `2^-ev`, weighted by how far their stored value is from clipping. Texels
+/// clipped or black in every exposure take the darkest or brightest one.
+/// `transfer` decodes the stored values, by default from the format. Alpha is
+/// taken from the first image. Fails on formats [`read_texels`] can't read.
+pub fn merge_exposures(
+    brackets: &[Bracket],
+    transfer: Option<TransferFunction>,
+) -> std::io::Result<Image> {
+    let first = brackets.first().expect("No exposures to merge").image;
+    let descriptor = &first.texture_descriptor;
+    for bracket in brackets {
+        let other = &bracket.image.texture_descriptor;
+        if other.size != descriptor.size || other.mip_level_count != descriptor.mip_level_count {
+            panic!("Merged exposures need matching sizes and mip level counts");
+        }
+    }
+
+    let mut brackets = brackets.to_vec();
+    brackets.sort_by(|a, b| a.ev.total_cmp(&b.ev));
+    let exposures = brackets
+        .iter()
+        .map(|bracket| {
+            let transfer = transfer.unwrap_or_else(|| {
+                TransferFunction::from_format(bracket.image.texture_descriptor.format)
+            });
+            Ok((read_texels(bracket.image)?, transfer, (-bracket.ev).exp2()))
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let alpha = read_texels(first)?;
+
+    let texels = (0..alpha.len())
+        .map(|i| {
+            let mut sum = [0.0; 3];
+            let mut weight_sum = 0.0;
+            for (texels, transfer, scale) in &exposures {
+                let stored = texels[i];
+                let w = weight(stored[0].max(stored[1]).max(stored[2]));
+                for c in 0..3 {
+                    sum[c] += w * transfer.to_linear(stored[c]) * scale;
+                }
+                weight_sum += w;
+            }
+            let [r, g, b] = if weight_sum > 0.0 {
+                sum.map(|c| c / weight_sum)
+            } else {
+                // Clipped everywhere: the darkest exposure is the best bound,
+                // black everywhere: the brightest one.
+                let darkest = &exposures[0];
+                let (texels, transfer, scale) = if darkest.0[i][..3].iter().any(|c| *c > 0.5) {
+                    darkest
+                } else {
+                    exposures.last().unwrap()
+                };
+                std::array::from_fn(|c| transfer.to_linear(texels[i][c]) * scale)
+            };
+            [r, g, b, alpha[i][3]]
+        })
+        .collect::<Vec<_>>();
+
+    let mut merged = first.clone();
+    merged.data = rgba_f32_to_rgba16f_bytes(&texels);
+    merged.texture_descriptor.format = TextureFormat::Rgba16Float;
+    Ok(merged)
+}
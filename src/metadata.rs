@@ -0,0 +1,85 @@
+//! Custom key/value metadata written into KTX2 files.
+//!
+//! Keys starting with `KTX` or `ktx` are reserved by the specification, so the
+//! custom keys of this crate share a crate-specific prefix.
+
+/// Names the HDR packing of an RGBA8 file, e.g. `LogLuv32`, `RGBM` or `RGBD`.
+pub const ENCODING_KEY: &str = "bevy_mod_environment_map_tools.encoding";
+
+/// Largest representable value of RGBM / RGBD encoded files, as a decimal string.
+pub const RANGE_KEY: &str = "bevy_mod_environment_map_tools.range";
+
+/// Comma separated names of the cube array layers, e.g. `specular,diffuse`.
+pub const LAYERS_KEY: &str = "bevy_mod_environment_map_tools.layers";
+
+/// Marks a cubemap with fewer than six faces. The value is a single byte whose
+/// bits 0 to 5 tell which of the faces +X, -X, +Y, -Y, +Z, -Z are present.
+/// The faces are stored as array layers with a faceCount of 1.
+pub const CUBEMAP_INCOMPLETE_KEY: &str = "KTXcubemapIncomplete";
+
+/// Comma separated face of each array layer of a cubemap written as a 2D
+/// array, e.g. `px,nx,py,ny,pz,nz`, listing every layer of cube arrays. See
+/// [`crate::pipeline::EncodeSettings::face_array`].
+pub const FACE_LAYERS_KEY: &str = "bevy_mod_environment_map_tools.face_layers";
+
+/// Comma separated perceptual roughness each mip level of a prefiltered
+/// specular chain was filtered for, starting at the top level, e.g.
+/// `0,0.25,0.5,0.75,1`. See [`crate::prefilter::RoughnessMapping`].
+pub const MIP_ROUGHNESS_KEY: &str = "bevy_mod_environment_map_tools.mip_roughness";
+
+/// Parses a comma separated list of numbers like the value of
+/// [`MIP_ROUGHNESS_KEY`].
+pub fn parse_f32_list(value: &[u8]) -> Option<Vec<f32>> {
+    let text = std::str::from_utf8(value).ok()?.trim_end_matches('\0');
+    text.split(',')
+        .map(|number| number.trim().parse().ok())
+        .collect()
+}
+
+/// Encodes numbers as a comma separated string value, see [`parse_f32_list`].
+pub fn f32_list_value(values: &[f32]) -> Vec<u8> {
+    let text = values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+    string_value(&text)
+}
+
+/// Direction texel coordinates increase in, e.g. `rd` for right and down, see
+/// [`crate::orientation::Orientation`].
+pub const ORIENTATION_KEY: &str = "KTXorientation";
+
+/// Id of the [`crate::supercompression::ZstdDictionary`] the levels are
+/// compressed with, as a decimal string.
+pub const ZSTD_DICTIONARY_KEY: &str = "bevy_mod_environment_map_tools.zstd_dictionary";
+
+/// Source file, source hash, tool version, settings and optionally the bake
+/// time of a file, see [`crate::provenance::Provenance`].
+pub const PROVENANCE_KEY: &str = "bevy_mod_environment_map_tools.provenance";
+
+/// Tone-mapped equirectangular PNG preview of the first layer, see
+/// [`crate::thumbnail`]. Unlike the other values it is binary.
+pub const THUMBNAIL_KEY: &str = "bevy_mod_environment_map_tools.thumbnail";
+
+/// Hash of the file with zeros in place of this value, as 16 hex digits of
+/// [`crate::provenance::content_hash`]. Bakes skip rewriting files whose hash
+/// matches what they would write, see [`crate::write_ktx2`].
+pub const CONTENT_HASH_KEY: &str = "bevy_mod_environment_map_tools.content_hash";
+
+/// Encodes a string value as NUL-terminated UTF-8, as the KTX2 specification
+/// recommends for text values.
+pub fn string_value(value: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(value.len() + 1);
+    bytes.extend_from_slice(value.as_bytes());
+    bytes.push(0);
+    bytes
+}
+
+/// Comma separated hour of day of each cube array layer of a time-of-day
+/// sequence, e.g. `6,9,12,15,18`.
+pub const TIME_OF_DAY_KEY: &str = "bevy_mod_environment_map_tools.time_of_day";
+
+/// Comma separated time in seconds of each cube array layer of an animated
+/// environment decoded from video, see `video::encode_frames`.
+pub const FRAME_TIMES_KEY: &str = "bevy_mod_environment_map_tools.frame_times";
@@ -0,0 +1,354 @@
+use bevy::{prelude::Image, render::render_resource::TextureFormat};
+
+use crate::{
+    cubemap::{texel_across_edges, FACE_COUNT},
+    cubemap_data::CubemapData,
+    source::mip_size,
+};
+
+/// Halves a face with a 2×2 box filter. Odd trailing rows and columns are
+/// folded into the last output texel.
+pub fn downsample_face(texels: &[[f32; 4]], width: u32, height: u32) -> (Vec<[f32; 4]>, u32, u32) {
+    let new_width = (width / 2).max(1);
+    let new_height = (height / 2).max(1);
+    let mut out = Vec::with_capacity((new_width * new_height) as usize);
+
+    for y in 0..new_height {
+        let y0 = y * 2;
+        let y1 = if y == new_height - 1 { height } else { y0 + 2 };
+        for x in 0..new_width {
+            let x0 = x * 2;
+            let x1 = if x == new_width - 1 { width } else { x0 + 2 };
+
+            let mut sum = [0.0; 4];
+            let mut count = 0.0;
+            for sy in y0..y1.max(y0 + 1) {
+                for sx in x0..x1.max(x0 + 1) {
+                    let texel = texels[(sy.min(height - 1) * width + sx.min(width - 1)) as usize];
+                    for c in 0..4 {
+                        sum[c] += texel[c];
+                    }
+                    count += 1.0;
+                }
+            }
+            out.push(sum.map(|c| c / count));
+        }
+    }
+
+    (out, new_width, new_height)
+}
+
+/// Filter that generated mip levels are downsampled with.
+///
+/// The box filter keeps sharp skyboxes crisp but aliases, wider filters give
+/// smoother chains, e.g. for radiance that gets blurred further anyway. Widths
+/// are in texels of the smaller level.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub enum MipFilter {
+    /// Averages the 2×2 texels each output texel covers.
+    #[default]
+    Box,
+    /// Weights falling off linearly to zero at `width / 2` from the center.
+    Triangle { width: f32 },
+    /// Gaussian weights with a standard deviation of `width / 6`, cut off at
+    /// `width / 2` from the center.
+    Gaussian { width: f32 },
+}
+
+/// Default width of [`MipFilter::Triangle`], the classic tent over 4×4 texels.
+pub const DEFAULT_TRIANGLE_WIDTH: f32 = 2.0;
+
+/// Default width of [`MipFilter::Gaussian`].
+pub const DEFAULT_GAUSSIAN_WIDTH: f32 = 3.0;
+
+impl MipFilter {
+    /// Weight at distance `t` from the center, `None` outside the filter.
+    fn weight(self, t: f32) -> Option<f32> {
+        let (width, weight) = match self {
+            MipFilter::Box => (1.0, 1.0),
+            MipFilter::Triangle { width } => (width, 1.0 - t.abs() / (width / 2.0)),
+            MipFilter::Gaussian { width } => {
+                let sigma = width / 6.0;
+                (width, (-t * t / (2.0 * sigma * sigma)).exp())
+            }
+        };
+        (t.abs() < width.max(1e-3) / 2.0).then_some(weight.max(0.0))
+    }
+
+    /// Normalized weights of the source texels of each of the `to` output
+    /// texels along an axis of `from` texels. Taps may lie past the edges.
+    fn taps(self, from: u32, to: u32) -> Vec<Vec<(i64, f32)>> {
+        let scale = from as f32 / to as f32;
+        let radius = match self {
+            MipFilter::Box => 0.5,
+            MipFilter::Triangle { width } | MipFilter::Gaussian { width } => width / 2.0,
+        } * scale;
+        (0..to)
+            .map(|x| {
+                let center = (x as f32 + 0.5) * scale;
+                let first = (center - radius).floor() as i64;
+                let last = (center + radius).ceil() as i64;
+                let mut taps = (first..=last)
+                    .filter_map(|s| {
+                        let weight = self.weight((s as f32 + 0.5 - center) / scale)?;
+                        Some((s, weight))
+                    })
+                    .collect::<Vec<_>>();
+                let total = taps.iter().map(|(_, weight)| weight).sum::<f32>();
+                if total > 0.0 {
+                    taps.iter_mut().for_each(|(_, weight)| *weight /= total);
+                } else {
+                    taps = vec![((center as i64).min(from as i64 - 1), 1.0)];
+                }
+                taps
+            })
+            .collect()
+    }
+}
+
+/// Halves a face with `filter`, applied separably along the rows and then
+/// the columns, clamping taps past the edges. [`MipFilter::Box`] is the same
+/// as [`downsample_face`].
+pub fn downsample_face_with(
+    texels: &[[f32; 4]],
+    width: u32,
+    height: u32,
+    filter: MipFilter,
+) -> (Vec<[f32; 4]>, u32, u32) {
+    if filter == MipFilter::Box {
+        return downsample_face(texels, width, height);
+    }
+    let new_width = (width / 2).max(1);
+    let new_height = (height / 2).max(1);
+    let weighted_sum = |taps: &[(i64, f32)], from: u32, texel: &dyn Fn(u32) -> [f32; 4]| {
+        taps.iter().fold([0.0; 4], |mut sum, (s, weight)| {
+            let texel = texel((*s).clamp(0, from as i64 - 1) as u32);
+            for c in 0..4 {
+                sum[c] += texel[c] * weight;
+            }
+            sum
+        })
+    };
+
+    let mut rows = Vec::with_capacity((new_width * height) as usize);
+    let column_taps = filter.taps(width, new_width);
+    for y in 0..height {
+        for taps in &column_taps {
+            rows.push(weighted_sum(taps, width, &|x| {
+                texels[(y * width + x) as usize]
+            }));
+        }
+    }
+
+    let mut out = Vec::with_capacity((new_width * new_height) as usize);
+    for taps in &filter.taps(height, new_height) {
+        for x in 0..new_width {
+            out.push(weighted_sum(taps, height, &|y| {
+                rows[(y * new_width + x) as usize]
+            }));
+        }
+    }
+    (out, new_width, new_height)
+}
+
+/// Halves the six faces of a cube with `filter` like [`downsample_face_with`],
+/// except that taps past a face edge read the adjacent face instead of being
+/// clamped, which would open seams between the faces.
+pub fn downsample_cube_with<F: AsRef<[[f32; 4]]>>(
+    faces: &[F],
+    face_size: u32,
+    filter: MipFilter,
+) -> (Vec<Vec<[f32; 4]>>, u32) {
+    let new_size = (face_size / 2).max(1);
+    if filter == MipFilter::Box {
+        // The 2×2 box doesn't reach past the edges.
+        let faces = faces
+            .iter()
+            .map(|texels| downsample_face(texels.as_ref(), face_size, face_size).0)
+            .collect();
+        return (faces, new_size);
+    }
+
+    let taps = filter.taps(face_size, new_size);
+    let faces = (0..FACE_COUNT)
+        .map(|face| {
+            let mut out = Vec::with_capacity((new_size * new_size) as usize);
+            for y_taps in &taps {
+                for x_taps in &taps {
+                    let mut sum = [0.0; 4];
+                    for &(y, y_weight) in y_taps {
+                        for &(x, x_weight) in x_taps {
+                            let texel =
+                                texel_across_edges(faces, face_size, face, x as i32, y as i32);
+                            for c in 0..4 {
+                                sum[c] += texel[c] * x_weight * y_weight;
+                            }
+                        }
+                    }
+                    out.push(sum);
+                }
+            }
+            out
+        })
+        .collect();
+    (faces, new_size)
+}
+
+/// Downsamples a `Rgba16Float` cubemap until its faces are at most
+/// `max_face_size` texels wide.
+///
+/// When the image already has a mip chain the levels that fit are kept as is,
+/// otherwise the smallest existing level is box filtered down to size.
+pub fn limit_face_size(image: &Image, max_face_size: u32) -> Image {
+    if image.texture_descriptor.format != TextureFormat::Rgba16Float {
+        panic!("Resizing only supported for Rgba16Float images");
+    }
+
+    let mip_level_count = image.texture_descriptor.mip_level_count;
+    let mut skipped_levels = 0;
+    let mut face_size = image.texture_descriptor.size.width;
+    while face_size > max_face_size.max(1) {
+        face_size = mip_size(face_size, 1);
+        skipped_levels += 1;
+    }
+
+    if skipped_levels == 0 {
+        return image.clone();
+    }
+
+    let data = CubemapData::from_image(image);
+    if skipped_levels < mip_level_count {
+        return data.mip_levels(skipped_levels..mip_level_count).to_image();
+    }
+
+    let mut limited = CubemapData::new(face_size, 1, data.layer_count());
+    for (face, _, out) in limited.iter_mut() {
+        let mut texels = data.face(face, mip_level_count - 1).to_vec();
+        let mut width = data.mip_size(mip_level_count - 1);
+        let mut height = width;
+        while width > face_size {
+            (texels, width, height) = downsample_face(&texels, width, height);
+        }
+        *out = texels;
+    }
+    limited.to_image()
+}
+
+/// What to do with a mip chain the input already has.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum InputMips {
+    /// Trust the existing levels and re-encode them as they are.
+    #[default]
+    Reuse,
+    /// Discard the existing levels and filter a full chain from the top level
+    /// with [`EncodeSettings::mip_filter`](crate::pipeline::EncodeSettings::mip_filter),
+    /// e.g. when DCC tools produced poorly filtered mips.
+    Regenerate,
+    /// Keep an existing chain, and filter a full chain from the top level of
+    /// inputs that only have one level, like [`InputMips::Regenerate`].
+    Generate,
+}
+
+/// Replaces the mip chain of a `Rgba16Float` cubemap with a full chain down to
+/// 1×1, box filtered from the top level.
+pub fn regenerate_mips(image: &Image) -> Image {
+    regenerate_mips_with(image, MipFilter::Box)
+}
+
+/// [`regenerate_mips`] downsampling each level from the one above with `filter`,
+/// see [`downsample_cube_with`].
+pub fn regenerate_mips_with(image: &Image, filter: MipFilter) -> Image {
+    if image.texture_descriptor.format != TextureFormat::Rgba16Float {
+        panic!("Generating mips only supported for Rgba16Float images");
+    }
+
+    let source = CubemapData::from_image(image);
+    let face_size = source.face_size();
+    let mip_level_count = face_size.ilog2() + 1;
+    let mut data = CubemapData::new(face_size, mip_level_count, source.layer_count());
+    for layer in 0..source.layer_count() {
+        let mut size = face_size;
+        let mut faces = source
+            .cube(layer, 0)
+            .into_iter()
+            .map(<[_]>::to_vec)
+            .collect::<Vec<_>>();
+        for mip_level in 1..mip_level_count {
+            (faces, size) = downsample_cube_with(&faces, size, filter);
+            for (face, texels) in (0..).zip(&faces) {
+                data.face_mut(layer * FACE_COUNT + face, mip_level)
+                    .copy_from_slice(texels);
+            }
+        }
+        for face in layer * FACE_COUNT..(layer + 1) * FACE_COUNT {
+            data.face_mut(face, 0).copy_from_slice(source.face(face, 0));
+        }
+    }
+    data.to_image()
+}
+
+/// Drops the smallest mip levels of a cubemap, keeping at most
+/// `max_mip_levels` levels and no level with faces smaller than `min_mip_size`.
+/// The top level is always kept.
+pub fn limit_mips(image: &Image, max_mip_levels: Option<u32>, min_mip_size: Option<u32>) -> Image {
+    let mip_level_count = image.texture_descriptor.mip_level_count;
+    let mut kept_levels = 1;
+    while kept_levels < mip_level_count {
+        let size = mip_size(image.texture_descriptor.size.width, kept_levels);
+        if max_mip_levels.is_some_and(|max| kept_levels >= max)
+            || min_mip_size.is_some_and(|min| size < min)
+        {
+            break;
+        }
+        kept_levels += 1;
+    }
+
+    if kept_levels == mip_level_count {
+        return image.clone();
+    }
+
+    CubemapData::from_image(image)
+        .mip_levels(0..kept_levels)
+        .to_image()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate::{gradient_cubemap, solid_color_cubemap};
+
+    #[test]
+    fn limit_mips_keeps_every_layer() {
+        let layers = [
+            regenerate_mips(&solid_color_cubemap(16, [1.0, 0.5, 0.25, 1.0])),
+            regenerate_mips(&gradient_cubemap(
+                16,
+                [2.0, 1.5, 1.0, 1.0],
+                [0.1, 0.2, 0.3, 1.0],
+            )),
+        ]
+        .map(|layer| CubemapData::from_image(&layer));
+        let array = CubemapData::stack(&[&layers[0], &layers[1]]).to_image();
+
+        let limited = limit_mips(&array, Some(2), None);
+        let descriptor = &limited.texture_descriptor;
+        assert_eq!(descriptor.mip_level_count, 2);
+        assert_eq!(descriptor.size.depth_or_array_layers, 2 * FACE_COUNT);
+        assert_eq!(limited.data.len(), 2 * 6 * (16 * 16 + 8 * 8) * 8);
+
+        let limited = CubemapData::from_image(&limited);
+        for (layer, expected) in layers.iter().enumerate() {
+            for face in 0..FACE_COUNT {
+                let face_index = layer as u32 * FACE_COUNT + face;
+                for mip_level in 0..2 {
+                    assert_eq!(
+                        limited.face(face_index, mip_level),
+                        expected.face(face, mip_level)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Async variants of the encoding APIs for use inside a tokio runtime.
+//!
+//! The work itself is CPU bound, so every function moves it to tokio's blocking
+//! thread pool and only awaits the result. Panics inside the blocking functions
+//! are returned as errors instead of tearing down the caller.
+
+use std::{io::Error, path::PathBuf, sync::Arc};
+
+use bevy::prelude::Image;
+use tokio::task::{spawn_blocking, JoinError, JoinSet};
+
+use crate::{
+    ktx2_reader::KTX2File,
+    output::should_write,
+    pipeline::{process, EncodeSettings},
+    prefilter::{prefilter_specular_with_progress, PrefilterSettings},
+    progress::{CancellationToken, ProgressSink},
+    supercompression::Supercompression,
+};
+
+fn join_error(e: JoinError) -> Error {
+    Error::other(format!("Conversion task failed: {e}"))
+}
+
+/// Async [`crate::write_ktx2`].
+pub async fn write_ktx2(
+    image: Image,
+    output_path: PathBuf,
+    settings: EncodeSettings,
+) -> std::io::Result<()> {
+    spawn_blocking(move || crate::write_ktx2(&image, &output_path, &settings))
+        .await
+        .map_err(join_error)?
+}
+
+/// Async [`crate::pipeline::encode`], reporting the progress of the image stages.
+pub async fn encode(
+    image: Image,
+    output_path: PathBuf,
+    settings: EncodeSettings,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
+) -> std::io::Result<()> {
+    spawn_blocking(move || {
+        if !should_write(&output_path, settings.overwrite)? {
+            return Ok(());
+        }
+        let image = process(&image, &settings, progress.as_ref(), &cancel)?;
+        crate::write_ktx2(&image, &output_path, &settings)
+    })
+    .await
+    .map_err(join_error)?
+}
+
+/// Encodes every `(image, output_path)` pair concurrently, returning the first
+/// error after all jobs have finished. `progress` receives the fraction of
+/// finished files. Once `cancel` is cancelled, running jobs stop at their next
+/// checkpoint and the batch fails with `ErrorKind::Interrupted`. A
+/// [`crate::memory::MemoryBudget`] in `settings` limits how many large images
+/// are processed at once.
+pub async fn encode_batch(
+    jobs: Vec<(Image, PathBuf)>,
+    settings: EncodeSettings,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
+) -> std::io::Result<()> {
+    let job_count = jobs.len();
+    let mut tasks = JoinSet::new();
+    for (image, output_path) in jobs {
+        let settings = settings.clone();
+        let cancel = cancel.clone();
+        tasks.spawn_blocking(move || {
+            if !should_write(&output_path, settings.overwrite)? {
+                return Ok(());
+            }
+            let image = process(&image, &settings, &(), &cancel)?;
+            crate::write_ktx2(&image, &output_path, &settings)
+        });
+    }
+
+    let mut result = Ok(());
+    let mut finished = 0;
+    while let Some(joined) = tasks.join_next().await {
+        result = result.and(joined.map_err(join_error).and_then(|written| written));
+        finished += 1;
+        progress.progress("encode", finished as f32 / job_count as f32);
+    }
+    result
+}
+
+/// Async [`prefilter_specular_with_progress`].
+pub async fn prefilter_specular(
+    image: Image,
+    settings: PrefilterSettings,
+    progress: Arc<dyn ProgressSink>,
+    cancel: CancellationToken,
+) -> std::io::Result<Image> {
+    spawn_blocking(move || {
+        prefilter_specular_with_progress(&image, &settings, progress.as_ref(), &cancel)
+    })
+    .await
+    .map_err(join_error)?
+    .map_err(Error::from)
+}
+
+/// Reads and parses a KTX2 file without blocking the runtime.
+pub async fn load_ktx2(path: PathBuf) -> std::io::Result<KTX2File> {
+    let bytes = tokio::fs::read(path).await?;
+    spawn_blocking(move || KTX2File::parse(&bytes))
+        .await
+        .map_err(join_error)?
+}
+
+/// Async [`KTX2File::resupercompress`] from `input` to `output`.
+pub async fn resupercompress_file(
+    input: PathBuf,
+    output: PathBuf,
+    supercompression: Supercompression,
+) -> std::io::Result<()> {
+    let mut file = load_ktx2(input).await?;
+    spawn_blocking(move || {
+        file.resupercompress(supercompression)?;
+        file.save(&output)
+    })
+    .await
+    .map_err(join_error)?
+}
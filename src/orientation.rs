@@ -0,0 +1,67 @@
+//! The `KTXorientation` convention, the directions texel x and y coordinates
+//! increase in.
+
+/// Direction of increasing texel coordinates, as recorded in `KTXorientation`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Orientation {
+    /// x to the right and y down, the layout of Bevy images and cubemap faces.
+    #[default]
+    RightDown,
+    RightUp,
+    LeftDown,
+    LeftUp,
+}
+
+impl Orientation {
+    /// The `KTXorientation` value without its NUL terminator, e.g. `rd`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Orientation::RightDown => "rd",
+            Orientation::RightUp => "ru",
+            Orientation::LeftDown => "ld",
+            Orientation::LeftUp => "lu",
+        }
+    }
+
+    /// Parses a `KTXorientation` value. The z direction of 3D textures is
+    /// ignored, as is a trailing NUL.
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        match value.get(..2)? {
+            b"rd" => Some(Orientation::RightDown),
+            b"ru" => Some(Orientation::RightUp),
+            b"ld" => Some(Orientation::LeftDown),
+            b"lu" => Some(Orientation::LeftUp),
+            _ => None,
+        }
+    }
+
+    fn points_left(self) -> bool {
+        matches!(self, Orientation::LeftDown | Orientation::LeftUp)
+    }
+
+    fn points_up(self) -> bool {
+        matches!(self, Orientation::RightUp | Orientation::LeftUp)
+    }
+}
+
+/// Reorders the row-major texels of a `width`×`height` image stored with
+/// orientation `from` into orientation `to`.
+pub fn reorient<T: Copy>(
+    texels: &[T],
+    width: u32,
+    height: u32,
+    from: Orientation,
+    to: Orientation,
+) -> Vec<T> {
+    let flip_x = from.points_left() != to.points_left();
+    let flip_y = from.points_up() != to.points_up();
+    let mut reoriented = Vec::with_capacity(texels.len());
+    for y in 0..height {
+        let source_y = if flip_y { height - 1 - y } else { y };
+        for x in 0..width {
+            let source_x = if flip_x { width - 1 - x } else { x };
+            reoriented.push(texels[(source_y * width + source_x) as usize]);
+        }
+    }
+    reoriented
+}
@@ -0,0 +1,110 @@
+//! Crash-safe output files.
+//!
+//! Outputs are written to a temporary file next to the destination and renamed
+//! over it once complete, so a crash or Ctrl-C mid-bake leaves at most a stray
+//! `.tmp` file behind, never a truncated KTX2 the asset server tries to load.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufWriter, Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// What to do when an output file already exists.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OverwritePolicy {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+    /// Keep the existing file and don't write anything.
+    Skip,
+    /// Fail with `ErrorKind::AlreadyExists`.
+    Error,
+}
+
+fn already_exists(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::AlreadyExists,
+        format!("{} already exists", path.display()),
+    )
+}
+
+/// Whether `path` should be written under `policy`: `false` if it exists and
+/// is skipped, an error if it exists and that is an error. Checked before
+/// doing the work of producing an output, [`write_atomically`] checks again.
+pub fn should_write(path: &Path, policy: OverwritePolicy) -> std::io::Result<bool> {
+    match policy {
+        OverwritePolicy::Overwrite => Ok(true),
+        _ if !path.exists() => Ok(true),
+        OverwritePolicy::Skip => Ok(false),
+        OverwritePolicy::Error => Err(already_exists(path)),
+    }
+}
+
+/// Temporary file `path` is written to before being renamed into place,
+/// unique to this call so concurrent writes of the same path don't share it.
+fn temporary_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let file_name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{file_name}.{}.{count}.tmp", std::process::id()))
+}
+
+/// Writes `path` through `write` atomically, see the module documentation.
+/// Returns `false` if the file existed and `policy` skipped it.
+///
+/// Unless overwriting, `path` is claimed with an empty file created with
+/// `create_new` before writing, so of several writers racing for it only one
+/// succeeds. The empty file is replaced when the write completes and removed
+/// if it fails.
+pub fn write_atomically(
+    path: &Path,
+    policy: OverwritePolicy,
+    write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+) -> std::io::Result<bool> {
+    let claimed = policy != OverwritePolicy::Overwrite;
+    if claimed {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return match policy {
+                    OverwritePolicy::Skip => Ok(false),
+                    _ => Err(already_exists(path)),
+                };
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let temporary = temporary_path(path);
+    let result = File::create(&temporary).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()
+    });
+    match result.and_then(|()| std::fs::rename(&temporary, path)) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            std::fs::remove_file(&temporary).ok();
+            if claimed {
+                std::fs::remove_file(path).ok();
+            }
+            Err(e)
+        }
+    }
+}
+
+/// [`write_atomically`] for data that is already in memory.
+pub fn write_bytes_atomically(
+    path: &Path,
+    policy: OverwritePolicy,
+    bytes: &[u8],
+) -> std::io::Result<bool> {
+    write_atomically(path, policy, |writer| writer.write_all(bytes))
+}
@@ -0,0 +1,494 @@
+use std::sync::Arc;
+
+use bevy::{math::Quat, prelude::Image};
+
+use crate::{
+    adjust::{apply_gain, normalize_luminance, LuminanceMeasure},
+    color::ColorPrimaries,
+    cubemap::{ALL_FACES, FACE_COUNT},
+    cubemap_data::CubemapData,
+    encoder::TexelEncoder,
+    ground::{
+        patch_nadir, project_ground, replace_ground, GroundProjection, GroundReplacement,
+        NadirPatch,
+    },
+    lut::{apply_lut, Lut3d},
+    memory::{estimated_peak_bytes, MemoryBudget, MemoryReservation},
+    metadata,
+    mips::{limit_face_size, limit_mips, regenerate_mips_with, InputMips, MipFilter},
+    orientation::Orientation,
+    output::{should_write, OverwritePolicy},
+    prefilter::{prefilter_specular_with_progress, PrefilterSettings},
+    progress::{CancellationToken, Cancelled, ProgressSink},
+    provenance::Provenance,
+    source::{check_readable, EnvmapSource},
+    supercompression::Supercompression,
+    threads::Threads,
+    transform::rotate_cubemap,
+    OutputFormat,
+};
+
+/// Default of [`EncodeSettings::min_compressed_level_size`], below which zstd
+/// rarely saves anything.
+pub const DEFAULT_MIN_COMPRESSED_LEVEL_SIZE: usize = 256;
+
+/// Everything that controls how a linear `Rgba16Float` cubemap is turned into
+/// a KTX2 file. Start from `EncodeSettings::default()` and chain the `with_*`
+/// methods to change what's needed.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct EncodeSettings {
+    pub encoder: Arc<dyn TexelEncoder>,
+    /// Primaries recorded in the Data-Format Descriptor. They must describe the
+    /// image data, no conversion happens.
+    pub primaries: ColorPrimaries,
+    pub supercompression: Supercompression,
+    /// Scale the image so its luminance, measured like this, equals the
+    /// target, see [`normalize_luminance`]. Cube arrays are measured on their
+    /// first layer and scaled as a whole.
+    pub normalize: Option<(LuminanceMeasure, f32)>,
+    /// Factor the color channels are multiplied by, see [`apply_gain`].
+    pub intensity: f32,
+    /// Per-channel factor the color channels are multiplied by, after `intensity`.
+    pub gain: [f32; 3],
+    /// Color grading applied after the gain, see [`apply_lut`].
+    pub lut: Option<Arc<Lut3d>>,
+    /// Exposure adjustment in stops, the image is scaled by `2^exposure`.
+    pub exposure: f32,
+    /// Rotation applied to the environment, see [`rotate_cubemap`].
+    pub rotation: Quat,
+    /// Fill the tripod hole around the nadir, see [`patch_nadir`].
+    pub nadir_patch: Option<NadirPatch>,
+    /// Reproject the lower hemisphere onto a ground plane, see [`project_ground`].
+    pub ground_projection: Option<GroundProjection>,
+    /// Replace the lower hemisphere before prefiltering, see [`replace_ground`].
+    pub ground_replacement: Option<GroundReplacement>,
+    /// Downsample inputs whose faces are larger than this.
+    pub max_face_size: Option<u32>,
+    /// Prefilter for specular image based lighting with these settings.
+    pub prefilter: Option<PrefilterSettings>,
+    pub max_mip_levels: Option<u32>,
+    pub min_mip_size: Option<u32>,
+    /// Names of the cube array layers, recorded in the key/value metadata.
+    pub layer_names: Vec<String>,
+    /// Additional key/value metadata, see [`crate::metadata`].
+    pub metadata: Vec<(String, Vec<u8>)>,
+    /// Mask of the faces to write, see [`crate::cubemap::face_bit`]. Leaving faces out writes
+    /// an incomplete cubemap, e.g. for sky-only captures without the bottom face.
+    pub cubemap_faces: u8,
+    /// Write the faces as the layers of a 2D array texture instead of a cubemap,
+    /// for render paths that can't sample cubemaps or sample the faces themselves.
+    /// The face of each layer is recorded in [`metadata::FACE_LAYERS_KEY`].
+    pub face_array: bool,
+    /// Texel orientation of the output, recorded in `KTXorientation`.
+    pub orientation: Orientation,
+    /// What to do when the output file already exists.
+    pub overwrite: OverwritePolicy,
+    /// Whether a mip chain of the input is kept or regenerated from its top level.
+    /// Prefiltering always ignores existing mips.
+    pub input_mips: InputMips,
+    /// Filter regenerated or generated mip levels are downsampled with.
+    pub mip_filter: MipFilter,
+    /// Levels smaller than this many bytes are stored without compression even
+    /// when supercompressing, as compressing a few bytes only grows them.
+    pub min_compressed_level_size: usize,
+    /// Width of the PNG preview embedded in the metadata, none if `None`.
+    pub thumbnail_width: Option<u32>,
+    /// Threads the image stages and the encoder run on.
+    pub threads: Threads,
+    /// Budget [`process`] reserves the estimated memory of its image stages in,
+    /// waiting while concurrent jobs sharing it use too much.
+    pub memory_budget: Option<MemoryBudget>,
+}
+
+impl Default for EncodeSettings {
+    fn default() -> Self {
+        Self {
+            encoder: OutputFormat::default().encoder(),
+            primaries: ColorPrimaries::default(),
+            supercompression: Supercompression::default(),
+            normalize: None,
+            intensity: 1.0,
+            gain: [1.0; 3],
+            lut: None,
+            exposure: 0.0,
+            rotation: Quat::IDENTITY,
+            nadir_patch: None,
+            ground_projection: None,
+            ground_replacement: None,
+            max_face_size: None,
+            prefilter: None,
+            max_mip_levels: None,
+            min_mip_size: None,
+            layer_names: Vec::new(),
+            metadata: Vec::new(),
+            cubemap_faces: ALL_FACES,
+            face_array: false,
+            orientation: Orientation::default(),
+            overwrite: OverwritePolicy::default(),
+            input_mips: InputMips::default(),
+            mip_filter: MipFilter::default(),
+            min_compressed_level_size: DEFAULT_MIN_COMPRESSED_LEVEL_SIZE,
+            thumbnail_width: None,
+            threads: Threads::default(),
+            memory_budget: None,
+        }
+    }
+}
+
+impl EncodeSettings {
+    pub fn with_format(mut self, format: OutputFormat) -> Self {
+        self.encoder = format.encoder();
+        self
+    }
+
+    /// Writes a custom format, see [`TexelEncoder`].
+    pub fn with_encoder(mut self, encoder: impl TexelEncoder + 'static) -> Self {
+        self.encoder = Arc::new(encoder);
+        self
+    }
+
+    pub fn with_primaries(mut self, primaries: ColorPrimaries) -> Self {
+        self.primaries = primaries;
+        self
+    }
+
+    pub fn with_supercompression(mut self, supercompression: Supercompression) -> Self {
+        self.supercompression = supercompression;
+        self
+    }
+
+    pub fn with_normalize(mut self, normalize: Option<(LuminanceMeasure, f32)>) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_gain(mut self, gain: [f32; 3]) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn with_lut(mut self, lut: Option<Arc<Lut3d>>) -> Self {
+        self.lut = lut;
+        self
+    }
+
+    pub fn with_exposure(mut self, exposure: f32) -> Self {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn with_rotation(mut self, rotation: Quat) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_nadir_patch(mut self, patch: Option<NadirPatch>) -> Self {
+        self.nadir_patch = patch;
+        self
+    }
+
+    pub fn with_ground_projection(mut self, projection: Option<GroundProjection>) -> Self {
+        self.ground_projection = projection;
+        self
+    }
+
+    pub fn with_ground_replacement(mut self, replacement: Option<GroundReplacement>) -> Self {
+        self.ground_replacement = replacement;
+        self
+    }
+
+    pub fn with_max_face_size(mut self, max_face_size: Option<u32>) -> Self {
+        self.max_face_size = max_face_size;
+        self
+    }
+
+    pub fn with_prefilter(mut self, prefilter: Option<PrefilterSettings>) -> Self {
+        self.prefilter = prefilter;
+        self
+    }
+
+    pub fn with_mip_limits(
+        mut self,
+        max_mip_levels: Option<u32>,
+        min_mip_size: Option<u32>,
+    ) -> Self {
+        self.max_mip_levels = max_mip_levels;
+        self.min_mip_size = min_mip_size;
+        self
+    }
+
+    pub fn with_layer_names(mut self, layer_names: &[&str]) -> Self {
+        self.layer_names = layer_names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn with_cubemap_faces(mut self, cubemap_faces: u8) -> Self {
+        self.cubemap_faces = cubemap_faces;
+        self
+    }
+
+    pub fn with_face_array(mut self, face_array: bool) -> Self {
+        self.face_array = face_array;
+        self
+    }
+
+    pub fn with_orientation(mut self, orientation: Orientation) -> Self {
+        self.orientation = orientation;
+        self
+    }
+
+    pub fn with_input_mips(mut self, input_mips: InputMips) -> Self {
+        self.input_mips = input_mips;
+        self
+    }
+
+    pub fn with_mip_filter(mut self, mip_filter: MipFilter) -> Self {
+        self.mip_filter = mip_filter;
+        self
+    }
+
+    pub fn with_min_compressed_level_size(mut self, size: usize) -> Self {
+        self.min_compressed_level_size = size;
+        self
+    }
+
+    pub fn with_thumbnail(mut self, width: Option<u32>) -> Self {
+        self.thumbnail_width = width;
+        self
+    }
+
+    pub fn with_threads(mut self, threads: Threads) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn with_memory_budget(mut self, budget: Option<MemoryBudget>) -> Self {
+        self.memory_budget = budget;
+        self
+    }
+
+    pub fn with_overwrite(mut self, overwrite: OverwritePolicy) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Adds a key/value pair to the metadata, replacing any value stored under `key`.
+    pub fn with_metadata(mut self, key: &str, value: Vec<u8>) -> Self {
+        self.metadata.retain(|(existing, _)| existing != key);
+        self.metadata.push((key.to_string(), value));
+        self
+    }
+
+    /// Records where the file came from and how it was baked.
+    pub fn with_provenance(self, provenance: &Provenance) -> Self {
+        self.with_metadata(metadata::PROVENANCE_KEY, provenance.to_value())
+    }
+}
+
+/// Runs the image stages of `settings` on every layer of a linear
+/// `Rgba16Float` cubemap or cube array: luminance normalization, intensity
+/// and gain, LUT grading, mip regeneration, exposure, rotation, nadir
+/// patching, ground projection, ground replacement, face size limit,
+/// prefiltering and mip limits, in that order.
+/// Writing the result with [`crate::write_ktx2`] applies the remaining settings.
+/// Fails on formats the stages can't read, see [`check_readable`], and once
+/// `cancel` is cancelled.
+pub fn process(
+    image: &Image,
+    settings: &EncodeSettings,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> std::io::Result<Image> {
+    check_readable(image.texture_descriptor.format)?;
+    let _reservation = reserve_memory(image, settings);
+    Ok(settings
+        .threads
+        .install(|| process_stages(image, settings, progress, cancel))?)
+}
+
+fn reserve_memory(image: &Image, settings: &EncodeSettings) -> Option<MemoryReservation> {
+    let budget = settings.memory_budget.as_ref()?;
+    Some(budget.reserve(estimated_peak_bytes(image)))
+}
+
+fn process_stages(
+    image: &Image,
+    settings: &EncodeSettings,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> Result<Image, Cancelled> {
+    let image = prepare(image, settings, cancel)?;
+    resize_and_filter(image, settings, progress, cancel)
+}
+
+/// The stages that don't depend on the output size.
+fn prepare(
+    image: &Image,
+    settings: &EncodeSettings,
+    cancel: &CancellationToken,
+) -> Result<Image, Cancelled> {
+    let mut image = image.clone();
+    if let Some((measure, target)) = settings.normalize {
+        image = normalize_luminance(&image, measure, target);
+    }
+    if settings.intensity != 1.0 || settings.gain != [1.0; 3] {
+        image = apply_gain(&image, settings.intensity, settings.gain);
+    }
+    if let Some(lut) = &settings.lut {
+        image = apply_lut(&image, lut);
+    }
+    let regenerate = match settings.input_mips {
+        InputMips::Reuse => false,
+        InputMips::Regenerate => image.texture_descriptor.mip_level_count > 1,
+        InputMips::Generate => image.texture_descriptor.mip_level_count == 1,
+    };
+    if regenerate {
+        image = regenerate_mips_with(&image, settings.mip_filter);
+    }
+    if settings.exposure != 0.0 {
+        image = apply_gain(&image, settings.exposure.exp2(), [1.0; 3]);
+    }
+    if settings.rotation != Quat::IDENTITY {
+        cancel.check()?;
+        image = per_layer(&image, |source| {
+            Ok(rotate_cubemap(source, settings.rotation))
+        })?;
+    }
+    if let Some(patch) = &settings.nadir_patch {
+        image = per_layer(&image, |source| Ok(patch_nadir(source, patch)))?;
+    }
+    if let Some(projection) = &settings.ground_projection {
+        image = per_layer(&image, |source| Ok(project_ground(source, projection)))?;
+    }
+    if let Some(replacement) = &settings.ground_replacement {
+        image = per_layer(&image, |source| Ok(replace_ground(source, replacement)))?;
+    }
+    Ok(image)
+}
+
+/// Runs a stage reading a single cubemap, like every [`EnvmapSource`], on each
+/// layer of a cube array and stacks the results.
+fn per_layer(
+    image: &Image,
+    mut stage: impl FnMut(&dyn EnvmapSource) -> Result<Image, Cancelled>,
+) -> Result<Image, Cancelled> {
+    if image.texture_descriptor.size.depth_or_array_layers <= FACE_COUNT {
+        return stage(image);
+    }
+    let data = CubemapData::from_image(image);
+    let layers = (0..data.layer_count())
+        .map(|layer| stage(&data.layer(layer)).map(|image| CubemapData::from_image(&image)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(CubemapData::stack(&layers.iter().collect::<Vec<_>>()).to_image())
+}
+
+fn resize_and_filter(
+    mut image: Image,
+    settings: &EncodeSettings,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> Result<Image, Cancelled> {
+    if let Some(max_face_size) = settings.max_face_size {
+        image = limit_face_size(&image, max_face_size);
+    }
+    if let Some(prefilter) = &settings.prefilter {
+        image = per_layer(&image, |source| {
+            prefilter_specular_with_progress(source, prefilter, progress, cancel)
+        })?;
+    }
+    if settings.max_mip_levels.is_some() || settings.min_mip_size.is_some() {
+        image = limit_mips(&image, settings.max_mip_levels, settings.min_mip_size);
+    }
+    Ok(image)
+}
+
+/// [`process`]es `image` once per entry of `face_sizes`, each used as
+/// `max_face_size`, e.g. for the quality tiers of different platforms.
+///
+/// The stages before the face size limit, like rotation and ground fixes, run
+/// only once. Prefiltering runs per variant, as the roughness each level is
+/// filtered for depends on the length of the chain.
+pub fn process_variants(
+    image: &Image,
+    settings: &EncodeSettings,
+    face_sizes: &[u32],
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> std::io::Result<Vec<Image>> {
+    check_readable(image.texture_descriptor.format)?;
+    let _reservation = reserve_memory(image, settings);
+    let variants = settings.threads.install(|| {
+        let prepared = prepare(image, settings, cancel)?;
+        face_sizes
+            .iter()
+            .map(|face_size| {
+                let settings = settings.clone().with_max_face_size(Some(*face_size));
+                resize_and_filter(prepared.clone(), &settings, progress, cancel)
+            })
+            .collect::<Result<Vec<_>, _>>()
+    })?;
+    Ok(variants)
+}
+
+/// [`process`]es `image` and writes it to `output_path`. Nothing is processed
+/// if `settings.overwrite` keeps an existing file.
+pub fn encode(
+    image: &Image,
+    output_path: &std::path::Path,
+    settings: &EncodeSettings,
+) -> std::io::Result<()> {
+    if !should_write(output_path, settings.overwrite)? {
+        return Ok(());
+    }
+    let image = process(image, settings, &(), &CancellationToken::new())?;
+    crate::write_ktx2(&image, output_path, settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{math::Vec3, render::render_resource::TextureFormat};
+
+    use super::*;
+    use crate::{generate::solid_color_cubemap, ground::GroundFill};
+
+    #[test]
+    fn process_keeps_every_layer() {
+        let colors = [[1.0, 0.5, 0.25, 1.0], [0.25, 0.5, 1.0, 1.0]];
+        let layers = colors.map(|color| CubemapData::from_image(&solid_color_cubemap(8, color)));
+        let array = CubemapData::stack(&[&layers[0], &layers[1]]).to_image();
+
+        let settings = EncodeSettings::default()
+            .with_rotation(Quat::from_rotation_y(1.0))
+            .with_ground_replacement(Some(GroundReplacement::new(GroundFill::Solid([
+                0.5, 0.5, 0.5,
+            ]))));
+        let processed = process(&array, &settings, &(), &CancellationToken::new()).unwrap();
+        let processed = CubemapData::from_image(&processed);
+        assert_eq!(processed.layer_count(), 2);
+        for (layer, &[r, g, b, _]) in colors.iter().enumerate() {
+            // Straight up stays above the replaced ground.
+            let top = processed.layer(layer as u32).sample(Vec3::Y);
+            assert!((top - Vec3::new(r, g, b)).abs().max_element() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn process_rejects_unreadable_formats() {
+        let mut image = solid_color_cubemap(8, [1.0; 4]);
+        image.texture_descriptor.format = TextureFormat::Bc6hRgbUfloat;
+        let error = process(
+            &image,
+            &EncodeSettings::default(),
+            &(),
+            &CancellationToken::new(),
+        )
+        .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::Unsupported);
+    }
+}
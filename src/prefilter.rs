@@ -0,0 +1,646 @@
+use std::{f32::consts::PI, ops::Range};
+
+use bevy::{math::Vec3, prelude::Image};
+use rayon::prelude::*;
+
+use crate::{
+    accumulate::{Accumulation, WeightedSum},
+    adjust::luminance,
+    cubemap::{
+        direction_to_face_uv, face_uv_to_direction, sample_bilinear, texel_direction,
+        texel_solid_angle, FACE_COUNT,
+    },
+    cubemap_data::CubemapData,
+    mips::downsample_face,
+    progress::{CancellationToken, Cancelled, ProgressSink},
+    source::{mip_size, EnvmapSource},
+};
+
+/// How the mip levels of a prefiltered specular chain map to roughness.
+///
+/// Has to match how the shader picks the mip level to sample for a given
+/// perceptual roughness, or materials end up blurrier or sharper than intended.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[non_exhaustive]
+pub enum RoughnessMapping {
+    /// Perceptual roughness grows linearly with the mip level, from 0 at the
+    /// top level to 1 at the last. This is what Bevy's PBR shader samples with.
+    #[default]
+    Linear,
+    /// The squared perceptual roughness (the GGX alpha) grows linearly with the
+    /// mip level, spending more levels on glossy reflections.
+    PerceptualSquared,
+    /// Explicit perceptual roughness of each mip level, starting at the top
+    /// level. Levels past the end of the list reuse its last value.
+    Custom(Vec<f32>),
+}
+
+impl RoughnessMapping {
+    /// Perceptual roughness the given mip level is filtered for.
+    pub fn roughness(&self, mip_level: u32, mip_level_count: u32) -> f32 {
+        let t = if mip_level_count > 1 {
+            mip_level as f32 / (mip_level_count - 1) as f32
+        } else {
+            0.0
+        };
+        match self {
+            RoughnessMapping::Linear => t,
+            RoughnessMapping::PerceptualSquared => t.sqrt(),
+            RoughnessMapping::Custom(levels) => levels
+                .get(mip_level as usize)
+                .or(levels.last())
+                .copied()
+                .unwrap_or(t),
+        }
+    }
+}
+
+/// Sampling each texel in batches until its estimate converged, instead of
+/// with a fixed sample count. Texels seeing small bright lights get up to
+/// `max_sample_count` samples, texels of mostly uniform skies stop after two
+/// batches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Samples per batch. The spread between the batches estimates the error.
+    pub batch_size: u32,
+    /// Samples after which a texel stops even if it didn't converge.
+    pub max_sample_count: u32,
+    /// Standard error relative to the luminance of a texel below which it
+    /// counts as converged.
+    pub tolerance: f32,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            batch_size: 32,
+            max_sample_count: 4096,
+            tolerance: 0.01,
+        }
+    }
+}
+
+/// Points the GGX samples of a texel are placed at.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleSequence {
+    /// Independent pseudo-random points.
+    #[default]
+    Random,
+    /// The Hammersley set for `sample_count` points, shifted by a random
+    /// offset per texel. Covers the lobe evenly, but only at exactly
+    /// `sample_count` samples.
+    Hammersley,
+    /// The first two dimensions of the Sobol sequence, shifted by a random
+    /// offset per texel. Even at any sample count, so it suits adaptive
+    /// sampling.
+    Sobol,
+    /// The Sobol sequence shifted by offsets that differ little between
+    /// neighbouring texels in a blue noise pattern, so the remaining error is
+    /// high frequency grain instead of blotches. Best for low sample counts.
+    BlueNoise,
+}
+
+impl SampleSequence {
+    /// Offset the points of the texel at `(x, y)` with `seed` are shifted by.
+    fn texel_offset(self, x: u32, y: u32, seed: u32) -> (f32, f32) {
+        match self {
+            SampleSequence::Random => (0.0, 0.0),
+            SampleSequence::Hammersley | SampleSequence::Sobol => random_pair(seed, u32::MAX),
+            SampleSequence::BlueNoise => {
+                // Interleaved gradient noise (Jimenez, "Next Generation Post
+                // Processing in Call of Duty: Advanced Warfare") and the R2
+                // dither (Roberts, "The Unreasonable Effectiveness of
+                // Quasirandom Sequences").
+                let (x, y) = (x as f32, y as f32);
+                let ign = (52.982_918 * (0.067_110_56 * x + 0.005_837_15 * y).fract()).fract();
+                let r2 = (0.754_877_7 * x + 0.569_840_3 * y).fract();
+                (ign, r2)
+            }
+        }
+    }
+
+    /// Point `i` of `count` in `[0, 1)²` for a texel with `seed` and `offset`.
+    fn point(self, i: u32, count: u32, seed: u32, offset: (f32, f32)) -> (f32, f32) {
+        let (u, v) = match self {
+            SampleSequence::Random => return random_pair(seed, i),
+            SampleSequence::Hammersley => (
+                (i % count.max(1)) as f32 / count.max(1) as f32,
+                unit_float(i.reverse_bits()),
+            ),
+            SampleSequence::Sobol | SampleSequence::BlueNoise => {
+                (unit_float(i.reverse_bits()), unit_float(sobol_second(i)))
+            }
+        };
+        ((u + offset.0).fract(), (v + offset.1).fract())
+    }
+}
+
+/// Second dimension of the Sobol sequence, the first being the bit reversal.
+fn sobol_second(mut i: u32) -> u32 {
+    let mut direction = 1 << 31;
+    let mut result = 0;
+    while i != 0 {
+        if i & 1 != 0 {
+            result ^= direction;
+        }
+        i >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+/// Maps 32 random bits to `[0, 1)`.
+fn unit_float(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+/// Starting points for [`PrefilterSettings`], trading bake time for noise.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PrefilterQuality {
+    /// Few samples from blurrier source levels, for quick iteration. Noisy
+    /// highlights show up as blur rather than speckles.
+    Draft,
+    #[default]
+    Standard,
+    /// Many samples from sharper source levels, summed in f64, with seams
+    /// averaged. For shipping bakes.
+    High,
+}
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct PrefilterSettings {
+    /// GGX samples per output texel.
+    pub sample_count: u32,
+    pub roughness_mapping: RoughnessMapping,
+    /// Number of output mip levels, defaults to a full chain down to 1×1.
+    pub mip_level_count: Option<u32>,
+    /// Precision the samples of each texel are summed in.
+    pub accumulation: Accumulation,
+    /// Added to the source mip level each sample reads. Higher values trade
+    /// sharpness for less noise at low sample counts.
+    pub lod_bias: f32,
+    /// Average the texels along face edges of every level, see
+    /// [`CubemapData::average_seams`].
+    pub fix_seams: bool,
+    /// Pair every GGX sample with one drawn in proportion to the luminance of
+    /// the environment and combine them with multiple importance sampling, so
+    /// small bright suns don't leave speckles in glossy levels. Costs twice
+    /// the samples.
+    pub sample_lights: bool,
+    /// Points the samples of each texel are placed at.
+    pub sequence: SampleSequence,
+    /// Take as many samples as each texel needs instead of `sample_count`,
+    /// which then only sets the source mip level samples read.
+    pub adaptive: Option<AdaptiveSampling>,
+}
+
+impl PrefilterSettings {
+    /// Number of mip levels prefiltered for a source with faces of `face_size`.
+    pub fn chain_length(&self, face_size: u32) -> u32 {
+        let full_chain = face_size.max(1).ilog2() + 1;
+        self.mip_level_count
+            .unwrap_or(full_chain)
+            .clamp(1, full_chain)
+    }
+
+    /// Perceptual roughness of each mip level prefiltered for a source with
+    /// faces of `face_size`, starting at the top level.
+    pub fn level_roughness(&self, face_size: u32) -> Vec<f32> {
+        let mip_level_count = self.chain_length(face_size);
+        (0..mip_level_count)
+            .map(|mip_level| self.roughness_mapping.roughness(mip_level, mip_level_count))
+            .collect()
+    }
+
+    /// Settings of a quality preset. Change the fields afterwards to override
+    /// parts of it.
+    pub fn preset(quality: PrefilterQuality) -> Self {
+        let (sample_count, lod_bias, accumulation, fix_seams) = match quality {
+            PrefilterQuality::Draft => (64, 2.0, Accumulation::F32, false),
+            PrefilterQuality::Standard => (1024, 1.0, Accumulation::F32, false),
+            PrefilterQuality::High => (4096, 0.5, Accumulation::F64, true),
+        };
+        Self {
+            sample_count,
+            roughness_mapping: RoughnessMapping::default(),
+            mip_level_count: None,
+            accumulation,
+            lod_bias,
+            fix_seams,
+            sample_lights: false,
+            sequence: SampleSequence::default(),
+            adaptive: None,
+        }
+    }
+}
+
+impl Default for PrefilterSettings {
+    fn default() -> Self {
+        Self::preset(PrefilterQuality::default())
+    }
+}
+
+/// Source cubemap with a box filtered mip chain, used for filtered importance
+/// sampling (Křivánek and Colbert, "Real-time Shading with Filtered Importance
+/// Sampling").
+struct SourceChain {
+    /// Texels indexed by `[mip][face]`.
+    levels: Vec<Vec<Vec<[f32; 4]>>>,
+    face_size: u32,
+}
+
+impl SourceChain {
+    fn new(source: &dyn EnvmapSource) -> Self {
+        let face_size = source.face_size();
+        let mut levels = vec![(0..FACE_COUNT)
+            .map(|face| source.face_texels(face, 0))
+            .collect::<Vec<_>>()];
+
+        let mut size = face_size;
+        while size > 1 {
+            let next = levels
+                .last()
+                .unwrap()
+                .iter()
+                .map(|texels| downsample_face(texels, size, size).0)
+                .collect();
+            levels.push(next);
+            size /= 2;
+        }
+
+        Self { levels, face_size }
+    }
+
+    fn sample(&self, dir: Vec3, lod: f32) -> Vec3 {
+        let lod = lod.clamp(0.0, (self.levels.len() - 1) as f32);
+        let lower = lod.floor() as usize;
+        let upper = (lower + 1).min(self.levels.len() - 1);
+        let a = self.sample_level(dir, lower);
+        if upper == lower {
+            return a;
+        }
+        a.lerp(self.sample_level(dir, upper), lod - lower as f32)
+    }
+
+    fn sample_level(&self, dir: Vec3, mip_level: usize) -> Vec3 {
+        let size = (self.face_size >> mip_level).max(1);
+        let [r, g, b, _] = sample_bilinear(&self.levels[mip_level], size, dir);
+        Vec3::new(r, g, b)
+    }
+
+    /// Alpha of the level `mip_level` at `dir`, not blurred by the lobe since
+    /// it holds masks rather than light.
+    fn alpha(&self, dir: Vec3, mip_level: usize) -> f32 {
+        let mip_level = mip_level.min(self.levels.len() - 1);
+        let size = (self.face_size >> mip_level).max(1);
+        sample_bilinear(&self.levels[mip_level], size, dir)[3]
+    }
+}
+
+/// Face size of the level [`LightDistribution`] is built from. Bright spots
+/// only need to be found, the samples still read the full resolution chain.
+const LIGHT_DISTRIBUTION_SIZE: u32 = 128;
+
+/// Piecewise constant distribution of directions over the texels of a source
+/// level, each texel as likely as its luminance times its solid angle.
+struct LightDistribution {
+    face_size: u32,
+    luminance: Vec<f32>,
+    /// Running sum of luminance times solid angle over all texels, in face order.
+    cdf: Vec<f64>,
+}
+
+impl LightDistribution {
+    /// Distribution of the first level of `source` no larger than
+    /// [`LIGHT_DISTRIBUTION_SIZE`], `None` for black environments.
+    fn new(source: &SourceChain) -> Option<Self> {
+        let mip_level = (0..source.levels.len())
+            .find(|mip_level| {
+                mip_size(source.face_size, *mip_level as u32) <= LIGHT_DISTRIBUTION_SIZE
+            })
+            .unwrap_or(source.levels.len() - 1);
+        let face_size = mip_size(source.face_size, mip_level as u32);
+        let luminance = source.levels[mip_level]
+            .iter()
+            .flatten()
+            .map(|[r, g, b, _]| luminance([*r, *g, *b]).max(0.0))
+            .collect::<Vec<_>>();
+        let mut total = 0.0;
+        let cdf = luminance
+            .iter()
+            .enumerate()
+            .map(|(i, luminance)| {
+                let (_, x, y) = texel_at(i, face_size);
+                total += (*luminance * texel_solid_angle(x, y, face_size)) as f64;
+                total
+            })
+            .collect::<Vec<_>>();
+        (total > 0.0).then_some(Self {
+            face_size,
+            luminance,
+            cdf,
+        })
+    }
+
+    fn total(&self) -> f64 {
+        *self.cdf.last().unwrap()
+    }
+
+    /// Direction for the point `xi` in `[0, 1)²`. The first coordinate picks the
+    /// texel, what's left of it and the second one the position in the texel.
+    fn sample(&self, xi: (f32, f32)) -> Vec3 {
+        let target = xi.0 as f64 * self.total();
+        let index = self
+            .cdf
+            .partition_point(|sum| *sum <= target)
+            .min(self.cdf.len() - 1);
+        let start = if index > 0 { self.cdf[index - 1] } else { 0.0 };
+        let within = ((target - start) / (self.cdf[index] - start).max(f64::MIN_POSITIVE)) as f32;
+
+        let (face, x, y) = texel_at(index, self.face_size);
+        let u = 2.0 * (x as f32 + within.clamp(0.0, 1.0)) / self.face_size as f32 - 1.0;
+        let v = 2.0 * (y as f32 + xi.1) / self.face_size as f32 - 1.0;
+        face_uv_to_direction(face, u, v)
+    }
+
+    /// Density per steradian of [`Self::sample`] returning `dir`.
+    fn pdf(&self, dir: Vec3) -> f32 {
+        let (face, u, v) = direction_to_face_uv(dir);
+        let texel = |coordinate: f32| {
+            (((coordinate + 1.0) / 2.0 * self.face_size as f32) as u32).min(self.face_size - 1)
+        };
+        let index = ((face * self.face_size + texel(v)) * self.face_size + texel(u)) as usize;
+        (self.luminance[index] as f64 / self.total()) as f32
+    }
+}
+
+/// Face and coordinates of texel `index` of all faces of a level, in face order.
+fn texel_at(index: usize, face_size: u32) -> (u32, u32, u32) {
+    let index = index as u32;
+    let in_face = index % (face_size * face_size);
+    (
+        index / (face_size * face_size),
+        in_face % face_size,
+        in_face / face_size,
+    )
+}
+
+/// Prefilters a linear cubemap for specular image based lighting with
+/// the GGX distribution, writing one roughness per mip level as chosen by
+/// `settings.roughness_mapping`. Existing mips of the input are ignored.
+///
+/// Rows of texels are prefiltered in parallel on the current rayon pool, see
+/// [`crate::threads`].
+pub fn prefilter_specular(source: &dyn EnvmapSource, settings: &PrefilterSettings) -> Image {
+    prefilter_specular_with_progress(source, settings, &(), &CancellationToken::new()).unwrap()
+}
+
+/// [`prefilter_specular`] reporting its progress to `progress`, stopping with
+/// `Err(Cancelled)` after the current face once `cancel` is cancelled.
+pub fn prefilter_specular_with_progress(
+    source: &dyn EnvmapSource,
+    settings: &PrefilterSettings,
+    progress: &dyn ProgressSink,
+    cancel: &CancellationToken,
+) -> Result<Image, Cancelled> {
+    let face_size = source.face_size();
+    let mip_level_count = settings.chain_length(face_size);
+
+    let source = SourceChain::new(source);
+    let lights = settings
+        .sample_lights
+        .then(|| LightDistribution::new(&source))
+        .flatten();
+
+    // Progress is measured in texels, every level costs the same per texel.
+    let total_texels = (0..mip_level_count)
+        .map(|mip_level| ((face_size >> mip_level).max(1) as u64).pow(2))
+        .sum::<u64>()
+        * FACE_COUNT as u64;
+    let mut done_texels = 0;
+    progress.progress("prefilter", 0.0);
+
+    let mut output = CubemapData::new(face_size, mip_level_count, 1);
+    for mip_level in 0..mip_level_count {
+        let roughness = settings
+            .roughness_mapping
+            .roughness(mip_level, mip_level_count);
+        let size = output.mip_size(mip_level);
+        for face in 0..FACE_COUNT {
+            cancel.check()?;
+            output
+                .face_mut(face, mip_level)
+                .par_chunks_mut(size as usize)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    let y = y as u32;
+                    for (x, texel) in (0..size).zip(row) {
+                        let n = texel_direction(face, x, y, size);
+                        let seed = (face * size + y) * size + x;
+                        let offset = settings.sequence.texel_offset(x, y, seed);
+                        let c = prefilter_texel(
+                            &source,
+                            n,
+                            roughness,
+                            settings,
+                            seed,
+                            offset,
+                            lights.as_ref(),
+                        );
+                        *texel = [c.x, c.y, c.z, source.alpha(n, mip_level as usize)];
+                    }
+                });
+            done_texels += (size * size) as u64;
+            progress.progress("prefilter", done_texels as f32 / total_texels as f32);
+        }
+        if settings.fix_seams {
+            output.average_seams(mip_level);
+        }
+    }
+    Ok(output.to_image())
+}
+
+fn prefilter_texel(
+    source: &SourceChain,
+    n: Vec3,
+    perceptual_roughness: f32,
+    settings: &PrefilterSettings,
+    seed: u32,
+    offset: (f32, f32),
+    lights: Option<&LightDistribution>,
+) -> Vec3 {
+    if perceptual_roughness <= 0.0 {
+        return source.sample(n, 0.0);
+    }
+
+    let (tangent_x, tangent_y) = tangent_frame(n);
+    let lobe = GgxLobe {
+        source,
+        n,
+        tangent_x,
+        tangent_y,
+        alpha: perceptual_roughness * perceptual_roughness,
+        texel_solid_angle: 4.0 * PI / (6.0 * (source.face_size * source.face_size) as f32),
+        settings,
+        seed,
+        offset,
+        lights,
+    };
+
+    let sum = match &settings.adaptive {
+        None => {
+            let mut sum = WeightedSum::new(settings.accumulation);
+            lobe.add_samples(0..settings.sample_count, &mut sum);
+            sum
+        }
+        Some(adaptive) => lobe.sample_adaptively(adaptive),
+    };
+    sum.average().unwrap_or_else(|| source.sample(n, 0.0))
+}
+
+/// The GGX lobe around one output texel's normal.
+struct GgxLobe<'a> {
+    source: &'a SourceChain,
+    n: Vec3,
+    tangent_x: Vec3,
+    tangent_y: Vec3,
+    alpha: f32,
+    texel_solid_angle: f32,
+    settings: &'a PrefilterSettings,
+    seed: u32,
+    /// Shift of the sample points, see [`SampleSequence::point`].
+    offset: (f32, f32),
+    /// Where to sample the environment in addition to the lobe, if at all.
+    lights: Option<&'a LightDistribution>,
+}
+
+impl GgxLobe<'_> {
+    /// Adds the samples with the indices in `samples` to `sum`.
+    ///
+    /// Every sample is weighted by `n_dot_l`, times its share of the GGX
+    /// density in the combined density when light sampling adds a second
+    /// sample from the environment (the balance heuristic of Veach and Guibas,
+    /// "Optimally Combining Sampling Techniques for Monte Carlo Rendering").
+    fn add_samples(&self, samples: Range<u32>, sum: &mut WeightedSum) {
+        let n = self.n;
+        let light_seed = pcg_hash(self.seed);
+        // Split sum approximation: assume n = v = r.
+        for i in samples {
+            let xi =
+                self.settings
+                    .sequence
+                    .point(i, self.settings.sample_count, self.seed, self.offset);
+            let h = importance_sample_ggx(xi, self.alpha, n, self.tangent_x, self.tangent_y);
+            let l = 2.0 * n.dot(h) * h - n;
+            self.add_sample(l, sum);
+
+            if let Some(lights) = self.lights {
+                self.add_sample(lights.sample(random_pair(light_seed, i)), sum);
+            }
+        }
+    }
+
+    fn add_sample(&self, l: Vec3, sum: &mut WeightedSum) {
+        let n_dot_l = self.n.dot(l);
+        if n_dot_l <= 0.0 {
+            return;
+        }
+
+        // pdf = D * n_dot_h / (4 * v_dot_h), and v_dot_h = n_dot_h here.
+        let n_dot_h = self.n.dot((self.n + l).normalize());
+        let pdf = d_ggx(n_dot_h, self.alpha) / 4.0;
+        let light_pdf = self.lights.map_or(0.0, |lights| lights.pdf(l));
+        if pdf + light_pdf <= 0.0 {
+            return;
+        }
+
+        // The mip level read depends on the nominal sample count only, so
+        // adaptive sampling blurs like the fixed count would.
+        let sample_solid_angle =
+            1.0 / (self.settings.sample_count as f32 * (pdf + light_pdf) + 1e-4);
+        let lod =
+            0.5 * (sample_solid_angle / self.texel_solid_angle).log2() + self.settings.lod_bias;
+
+        sum.add(
+            self.source.sample(l, lod),
+            n_dot_l * pdf / (pdf + light_pdf),
+        );
+    }
+
+    /// Samples in batches until the standard error of the mean, estimated from
+    /// the spread of the batch averages, falls below the tolerance.
+    fn sample_adaptively(&self, adaptive: &AdaptiveSampling) -> WeightedSum {
+        let accumulation = self.settings.accumulation;
+        let batch_size = adaptive.batch_size.max(1);
+        let mut sum = WeightedSum::new(accumulation);
+        // Welford's running mean and variance of the batch luminances.
+        let (mut batches, mut mean, mut m2) = (0u32, 0.0f32, 0.0f32);
+        let mut taken = 0;
+        while taken < adaptive.max_sample_count.max(1) {
+            let end = (taken + batch_size).min(adaptive.max_sample_count.max(1));
+            let mut batch = WeightedSum::new(accumulation);
+            self.add_samples(taken..end, &mut batch);
+            sum.merge(&batch);
+            taken = end;
+
+            let Some(average) = batch.average() else {
+                continue;
+            };
+            let value = luminance(average.to_array());
+            batches += 1;
+            let delta = value - mean;
+            mean += delta / batches as f32;
+            m2 += delta * (value - mean);
+            if batches >= 2 {
+                let standard_error = (m2 / (batches - 1) as f32 / batches as f32).sqrt();
+                if standard_error <= adaptive.tolerance * mean {
+                    break;
+                }
+            }
+        }
+        sum
+    }
+}
+
+pub(crate) fn d_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let f = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * f * f)
+}
+
+fn importance_sample_ggx(
+    xi: (f32, f32),
+    alpha: f32,
+    n: Vec3,
+    tangent_x: Vec3,
+    tangent_y: Vec3,
+) -> Vec3 {
+    let phi = 2.0 * PI * xi.0;
+    let cos_theta = ((1.0 - xi.1) / (1.0 + (alpha * alpha - 1.0) * xi.1)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    (tangent_x * (sin_theta * phi.cos()) + tangent_y * (sin_theta * phi.sin()) + n * cos_theta)
+        .normalize()
+}
+
+fn tangent_frame(n: Vec3) -> (Vec3, Vec3) {
+    let up = if n.z.abs() < 0.999 { Vec3::Z } else { Vec3::X };
+    let tangent_x = up.cross(n).normalize();
+    let tangent_y = n.cross(tangent_x);
+    (tangent_x, tangent_y)
+}
+
+/// PCG hash, see Jarzynski and Olano, "Hash Functions for GPU Rendering".
+fn pcg_hash(input: u32) -> u32 {
+    let state = input.wrapping_mul(747_796_405).wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    (word >> 22) ^ word
+}
+
+/// Pseudo-random point in `[0, 1)²` for sample `i` of the texel with `seed`.
+fn random_pair(seed: u32, i: u32) -> (f32, f32) {
+    let a = pcg_hash(seed ^ pcg_hash(i));
+    let b = pcg_hash(a);
+    (unit_float(a), unit_float(b))
+}
@@ -0,0 +1,23 @@
+//! The stable surface of the crate: [`EnvironmentMapProcessor`] and the types
+//! configuring it. These keep their names and paths across releases, unlike
+//! the modules they're defined in.
+//!
+//! Its enums and settings structs are `#[non_exhaustive]`, so new variants and
+//! fields aren't breaking changes. Match the enums with a wildcard arm, build
+//! [`EncodeSettings`] from `EncodeSettings::default()` with its `with_*`
+//! methods, and [`PrefilterSettings`] from [`PrefilterSettings::preset`],
+//! changing its fields afterwards.
+//!
+//! `use bevy_mod_environment_map_tools::prelude::*;`
+
+pub use crate::{
+    color::{ColorPrimaries, TransferFunction},
+    mips::{InputMips, MipFilter},
+    output::OverwritePolicy,
+    pipeline::EncodeSettings,
+    prefilter::{PrefilterQuality, PrefilterSettings, RoughnessMapping},
+    processor::{EnvironmentMapProcessor, DEFAULT_DIFFUSE_FACE_SIZE, DEFAULT_SPECULAR_FACE_SIZE},
+    progress::{CancellationToken, ProgressSink},
+    supercompression::Supercompression,
+    OutputFormat,
+};
@@ -0,0 +1,169 @@
+//! Bakes every light probe in the world into environment map files.
+//!
+//! Send a [`BakeLightProbes`] event with [`LightProbeBakePlugin`] added. Each
+//! `LightProbe` entity is captured at its transform with
+//! [`crate::capture::CubemapCapture`], then prefiltered and written as
+//! `<name>_specular.ktx2` and `<name>_diffuse.ktx2`, named after the entity's
+//! `Name` or its index, with the index appended to names shared by several
+//! probes. Encoding runs on the async compute task pool.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use bevy::{pbr::LightProbe, prelude::*, tasks::AsyncComputeTaskPool};
+
+use crate::{
+    capture::{CapturedCubemap, CubemapCapture, CubemapCapturePlugin, CubemapCaptured},
+    irradiance::irradiance_cubemap,
+    pipeline::{encode, EncodeSettings},
+    prefilter::PrefilterSettings,
+};
+
+/// Bakes [`BakeLightProbes`] requests, adding [`CubemapCapturePlugin`] if needed.
+pub struct LightProbeBakePlugin;
+
+impl Plugin for LightProbeBakePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<CubemapCapturePlugin>() {
+            app.add_plugins(CubemapCapturePlugin);
+        }
+        app.add_event::<BakeLightProbes>()
+            .init_resource::<ProbeBakes>()
+            .add_systems(Update, (start_probe_bakes, write_probe_bakes));
+    }
+}
+
+/// Requests a bake of every `LightProbe` entity.
+#[derive(Event, Clone, Debug)]
+pub struct BakeLightProbes {
+    /// Directory the KTX2 files are written to, created if missing.
+    pub output_dir: PathBuf,
+    /// Face size of the captured and specular cubemaps.
+    pub face_size: u32,
+    /// Face size of the diffuse irradiance cubemaps.
+    pub diffuse_face_size: u32,
+    /// Encoding of both maps. Only the specular map is prefiltered.
+    pub settings: EncodeSettings,
+    /// Entities hidden while capturing, e.g. the player.
+    pub exclude: Vec<Entity>,
+}
+
+impl Default for BakeLightProbes {
+    fn default() -> Self {
+        Self {
+            output_dir: PathBuf::from("probes"),
+            face_size: 256,
+            diffuse_face_size: 32,
+            settings: EncodeSettings::default().with_prefilter(Some(PrefilterSettings::default())),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+/// Output of a captured probe, waiting for its cubemap.
+struct ProbeBake {
+    specular_path: PathBuf,
+    diffuse_path: PathBuf,
+    diffuse_face_size: u32,
+    settings: EncodeSettings,
+}
+
+#[derive(Resource, Default)]
+struct ProbeBakes(HashMap<Entity, ProbeBake>);
+
+/// File name stem of a probe, its `Name` with anything but letters, digits,
+/// `-` and `_` replaced, or `probe_<index>` for unnamed probes.
+fn probe_file_stem(entity: Entity, name: Option<&Name>) -> String {
+    match name {
+        Some(name) if !name.as_str().is_empty() => name
+            .as_str()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect(),
+        _ => format!("probe_{}", entity.index()),
+    }
+}
+
+fn start_probe_bakes(
+    mut commands: Commands,
+    mut requests: EventReader<BakeLightProbes>,
+    mut bakes: ResMut<ProbeBakes>,
+    probes: Query<(Entity, Option<&Name>), With<LightProbe>>,
+) {
+    for request in requests.read() {
+        if let Err(e) = std::fs::create_dir_all(&request.output_dir) {
+            error!(
+                "Failed to create probe output directory {}: {e}",
+                request.output_dir.display()
+            );
+            continue;
+        }
+
+        let mut stems = HashSet::new();
+        for (entity, name) in &probes {
+            // Sanitizing can map different names to one stem, e.g. `a b` and `a_b`.
+            let mut stem = probe_file_stem(entity, name);
+            while !stems.insert(stem.clone()) {
+                stem = format!("{stem}_{}", entity.index());
+            }
+            bakes.0.insert(
+                entity,
+                ProbeBake {
+                    specular_path: request.output_dir.join(format!("{stem}_specular.ktx2")),
+                    diffuse_path: request.output_dir.join(format!("{stem}_diffuse.ktx2")),
+                    diffuse_face_size: request.diffuse_face_size,
+                    settings: request.settings.clone(),
+                },
+            );
+            commands.entity(entity).insert(CubemapCapture {
+                face_size: request.face_size,
+                exclude: request.exclude.clone(),
+            });
+        }
+    }
+}
+
+fn write_probe_bakes(
+    mut commands: Commands,
+    mut captured: EventReader<CubemapCaptured>,
+    mut bakes: ResMut<ProbeBakes>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for event in captured.read() {
+        let Some(bake) = bakes.0.remove(&event.entity) else {
+            continue;
+        };
+        let Some(image) = images.remove(&event.image) else {
+            continue;
+        };
+        commands.entity(event.entity).remove::<CapturedCubemap>();
+
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let diffuse = irradiance_cubemap(&image, bake.diffuse_face_size, 1);
+                let result = encode(&image, &bake.specular_path, &bake.settings).and_then(|()| {
+                    encode(
+                        &diffuse,
+                        &bake.diffuse_path,
+                        &bake.settings.clone().with_prefilter(None),
+                    )
+                });
+                match result {
+                    Ok(()) => info!("Baked light probe {}", bake.specular_path.display()),
+                    Err(e) => error!(
+                        "Failed to bake light probe {}: {e}",
+                        bake.specular_path.display()
+                    ),
+                }
+            })
+            .detach();
+    }
+}
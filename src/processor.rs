@@ -0,0 +1,171 @@
+//! A small entry point for build tools that stays stable across releases.
+//!
+//! [`EnvironmentMapProcessor`] covers the common path from a source image to
+//! KTX2 files without naming the modules implementing it, which are
+//! reorganized between releases. Import it through [`crate::prelude`], which
+//! also has the types configuring it, and reach into the other modules only
+//! for what it doesn't cover.
+
+use std::{fmt, path::Path, sync::Arc};
+
+use bevy::{prelude::Image, render::render_resource::TextureFormat};
+
+use crate::{
+    color::linearize,
+    equirect::{Equirect, EquirectAspect},
+    irradiance::irradiance_cubemap,
+    ktx2_reader::KTX2File,
+    pipeline::{process, EncodeSettings},
+    progress::{CancellationToken, ProgressSink},
+    write_ktx2,
+};
+
+/// Face size of Bevy's bundled diffuse maps, enough for smooth irradiance.
+pub const DEFAULT_DIFFUSE_FACE_SIZE: u32 = 32;
+/// Face size of Bevy's bundled specular maps.
+pub const DEFAULT_SPECULAR_FACE_SIZE: u32 = 512;
+
+/// Turns source images into encoded environment maps with one set of
+/// [`EncodeSettings`].
+#[derive(Clone, Default)]
+pub struct EnvironmentMapProcessor {
+    settings: EncodeSettings,
+    face_size: Option<u32>,
+    diffuse_face_size: Option<u32>,
+    progress: Option<Arc<dyn ProgressSink>>,
+    cancel: CancellationToken,
+}
+
+impl fmt::Debug for EnvironmentMapProcessor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EnvironmentMapProcessor")
+            .field("settings", &self.settings)
+            .field("face_size", &self.face_size)
+            .field("diffuse_face_size", &self.diffuse_face_size)
+            .field("cancel", &self.cancel)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EnvironmentMapProcessor {
+    /// A processor encoding with `settings`. Build them with the `with_*`
+    /// methods of [`EncodeSettings`], as its fields change between releases.
+    pub fn new(settings: EncodeSettings) -> Self {
+        Self {
+            settings,
+            ..Default::default()
+        }
+    }
+
+    /// Face size equirectangular inputs are resampled to. By default a
+    /// quarter of their width.
+    pub fn with_face_size(mut self, face_size: Option<u32>) -> Self {
+        self.face_size = face_size;
+        self
+    }
+
+    /// Face size of the diffuse maps written by [`Self::bake`]. By default
+    /// [`DEFAULT_DIFFUSE_FACE_SIZE`].
+    pub fn with_diffuse_face_size(mut self, face_size: Option<u32>) -> Self {
+        self.diffuse_face_size = face_size;
+        self
+    }
+
+    /// Reports the progress of the image stages to `progress`.
+    pub fn with_progress(mut self, progress: Arc<dyn ProgressSink>) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Stops processing at the next checkpoint once `cancel` is cancelled,
+    /// failing with `ErrorKind::Interrupted`. Keep a clone to cancel it.
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// The settings the processor encodes with.
+    pub fn settings(&self) -> &EncodeSettings {
+        &self.settings
+    }
+
+    /// Reads a KTX2 file, decompressing it if needed, as a linear cubemap
+    /// ready for [`Self::process`].
+    pub fn load(&self, path: &Path) -> std::io::Result<Image> {
+        self.prepare(&KTX2File::load(path)?.to_image()?)
+    }
+
+    /// Converts `image` into the linear `Rgba16Float` cubemap the other
+    /// methods work on: decodes its format, assuming its default transfer
+    /// function, and resamples 2D images as equirectangular panoramas,
+    /// letterboxing those that aren't 2:1. Fails on formats it can't decode.
+    pub fn prepare(&self, image: &Image) -> std::io::Result<Image> {
+        let mut image = if image.texture_descriptor.format == TextureFormat::Rgba16Float {
+            image.clone()
+        } else {
+            linearize(image, None)?
+        };
+        if image.texture_descriptor.size.depth_or_array_layers == 1 {
+            let equirect = Equirect::from_image(&image)?.to_two_to_one(EquirectAspect::Letterbox);
+            let face_size = self
+                .face_size
+                .unwrap_or_else(|| equirect.default_face_size());
+            image = equirect.to_cubemap(face_size);
+        }
+        Ok(image)
+    }
+
+    /// Prepares `image` and runs the image stages of the settings on it, see
+    /// [`crate::pipeline::process`].
+    pub fn process(&self, image: &Image) -> std::io::Result<Image> {
+        self.process_prepared(&self.prepare(image)?, &self.settings)
+    }
+
+    fn process_prepared(&self, image: &Image, settings: &EncodeSettings) -> std::io::Result<Image> {
+        let progress = self.progress.as_deref().unwrap_or(&());
+        process(image, settings, progress, &self.cancel)
+    }
+
+    /// Writes an already processed cubemap to `path`.
+    pub fn write(&self, image: &Image, path: &Path) -> std::io::Result<()> {
+        write_ktx2(image, path, &self.settings)
+    }
+
+    /// Processes `image` and writes it to `path`.
+    pub fn convert(&self, image: &Image, path: &Path) -> std::io::Result<()> {
+        self.write(&self.process(image)?, path)
+    }
+
+    /// Loads the KTX2 file at `input`, processes it and writes it to `output`.
+    pub fn convert_file(&self, input: &Path, output: &Path) -> std::io::Result<()> {
+        self.convert(&self.load(input)?, output)
+    }
+
+    /// Writes the files of an `EnvironmentMapLight` for `image` to
+    /// `output_dir`: `<name>_specular.ktx2`, prefiltered even if the settings
+    /// don't ask for it, and `<name>_diffuse.ktx2` with the irradiance of the
+    /// image before prefiltering.
+    pub fn bake(&self, image: &Image, output_dir: &Path, name: &str) -> std::io::Result<()> {
+        std::fs::create_dir_all(output_dir)?;
+        let prepared = self.prepare(image)?;
+        let prefilter = self.settings.prefilter.clone().unwrap_or_default();
+        let specular_settings = self.settings.clone().with_prefilter(Some(prefilter));
+        let specular = self.process_prepared(&prepared, &specular_settings)?;
+        write_ktx2(
+            &specular,
+            &output_dir.join(format!("{name}_specular.ktx2")),
+            &specular_settings,
+        )?;
+
+        // The image stages still apply, e.g. a rotation must turn both maps.
+        let diffuse_settings = self.settings.clone().with_prefilter(None);
+        let unfiltered = self.process_prepared(&prepared, &diffuse_settings)?;
+        let diffuse_face_size = self.diffuse_face_size.unwrap_or(DEFAULT_DIFFUSE_FACE_SIZE);
+        let diffuse = irradiance_cubemap(&unfiltered, diffuse_face_size, 1);
+        write_ktx2(
+            &diffuse,
+            &output_dir.join(format!("{name}_diffuse.ktx2")),
+            &diffuse_settings,
+        )
+    }
+}
@@ -0,0 +1,74 @@
+//! Progress reporting and cancellation for long-running operations.
+
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Receives progress updates from long-running operations, e.g. to drive a
+/// progress bar. Called from the thread doing the work.
+pub trait ProgressSink: Send + Sync {
+    /// `fraction` of the operation named `stage` is done, from 0 to 1.
+    fn progress(&self, stage: &str, fraction: f32);
+}
+
+/// Ignores all progress.
+impl ProgressSink for () {
+    fn progress(&self, _stage: &str, _fraction: f32) {}
+}
+
+impl<F: Fn(&str, f32) + Send + Sync> ProgressSink for F {
+    fn progress(&self, stage: &str, fraction: f32) {
+        self(stage, fraction)
+    }
+}
+
+/// Shared flag asking operations to stop at the next opportunity. Clones share
+/// the same flag, so one can be handed to the operation and the other kept to
+/// cancel it.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// `Err(Cancelled)` once cancelled, for use with `?`.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Returned by operations stopped through a [`CancellationToken`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Operation was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl From<Cancelled> for std::io::Error {
+    fn from(cancelled: Cancelled) -> Self {
+        std::io::Error::new(std::io::ErrorKind::Interrupted, cancelled)
+    }
+}
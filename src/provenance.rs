@@ -0,0 +1,100 @@
+//! Provenance metadata tracing a baked file back to its source and settings.
+
+use std::{path::Path, time::SystemTime};
+
+use crate::metadata::string_value;
+
+/// Where a baked file came from, stored under
+/// [`crate::metadata::PROVENANCE_KEY`] as `name=value` lines.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// File name of the source, without its directory.
+    pub source: Option<String>,
+    /// [`content_hash`] of the source file, as 16 hex digits.
+    pub source_hash: Option<String>,
+    /// Name and version of the tool that wrote the file.
+    pub tool: String,
+    /// Settings the file was baked with, e.g. the command line.
+    pub settings: String,
+    /// Bake time in seconds since the Unix epoch. Leave it out for
+    /// byte-identical output across runs.
+    pub timestamp: Option<u64>,
+}
+
+impl Provenance {
+    /// Provenance of a file baked by this crate with `settings`, without
+    /// source or timestamp.
+    pub fn new(settings: impl Into<String>) -> Self {
+        Self {
+            tool: concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).to_string(),
+            settings: settings.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Records the name and content hash of the source file at `path`.
+    pub fn with_source_file(mut self, path: &Path) -> std::io::Result<Self> {
+        let bytes = std::fs::read(path)?;
+        self.source = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        self.source_hash = Some(format!("{:016x}", content_hash(&bytes)));
+        Ok(self)
+    }
+
+    /// Records the current time as the bake time.
+    pub fn with_timestamp(mut self) -> Self {
+        self.timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .ok()
+            .map(|duration| duration.as_secs());
+        self
+    }
+
+    /// The metadata value, NUL-terminated.
+    pub fn to_value(&self) -> Vec<u8> {
+        let mut text = String::new();
+        if let Some(source) = &self.source {
+            text += &format!("source={source}\n");
+        }
+        if let Some(hash) = &self.source_hash {
+            text += &format!("source_hash={hash}\n");
+        }
+        text += &format!("tool={}\n", self.tool);
+        // Settings may not span lines, they would be read back as other fields.
+        text += &format!("settings={}\n", self.settings.replace('\n', " "));
+        if let Some(timestamp) = self.timestamp {
+            text += &format!("timestamp={timestamp}\n");
+        }
+        string_value(text.trim_end())
+    }
+
+    /// Parses a metadata value written by [`Provenance::to_value`]. Unknown
+    /// lines are ignored.
+    pub fn parse(value: &[u8]) -> Option<Self> {
+        let text = std::str::from_utf8(value).ok()?.trim_end_matches('\0');
+        let mut provenance = Provenance::default();
+        for line in text.lines() {
+            let Some((name, value)) = line.split_once('=') else {
+                continue;
+            };
+            match name {
+                "source" => provenance.source = Some(value.to_string()),
+                "source_hash" => provenance.source_hash = Some(value.to_string()),
+                "tool" => provenance.tool = value.to_string(),
+                "settings" => provenance.settings = value.to_string(),
+                "timestamp" => provenance.timestamp = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Some(provenance)
+    }
+}
+
+/// 64-bit FNV-1a hash of `bytes`. Stable across platforms and releases, unlike
+/// the standard library hashers.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
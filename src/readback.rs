@@ -0,0 +1,164 @@
+use std::sync::mpsc;
+
+use bevy::{
+    prelude::Image,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer,
+            ImageCopyTexture, ImageDataLayout, Maintain, MapMode, Origin3d, Texture, TextureAspect,
+            TextureDescriptor, TextureDimension, TextureUsages, TextureViewDescriptor,
+            TextureViewDimension,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        texture::ImageSampler,
+    },
+};
+
+use crate::cubemap::FACE_COUNT;
+
+/// One layer of one mip level in the readback buffer.
+struct LevelCopy {
+    layer: u32,
+    mip_level: u32,
+    width: u32,
+    height: u32,
+    buffer_offset: u64,
+    padded_bytes_per_row: u32,
+}
+
+/// Copies every layer and mip level of a GPU texture, e.g. a cubemap render
+/// target, back into a CPU [`Image`] with the same format, ready for
+/// [`crate::write_ktx2`] or the rest of the pipeline.
+///
+/// wgpu needs buffer rows padded to 256 bytes, the padding is stripped so the
+/// result uses the tightly packed, face-major layout of Bevy images. Blocks
+/// until the GPU finished the copy. The texture needs `TextureUsages::COPY_SRC`
+/// and an uncompressed format.
+pub fn readback_texture(
+    render_device: &RenderDevice,
+    render_queue: &RenderQueue,
+    texture: &Texture,
+) -> Image {
+    let format = texture.format();
+    let size = texture.size();
+    let mip_level_count = texture.mip_level_count();
+    if format.is_compressed() {
+        panic!("Reading back compressed {format:?} textures is not supported");
+    }
+    let block_size = format
+        .block_copy_size(None)
+        .unwrap_or_else(|| panic!("Reading back {format:?} textures is not supported"));
+
+    let mut copies = Vec::new();
+    let mut buffer_size = 0;
+    for layer in 0..size.depth_or_array_layers {
+        for mip_level in 0..mip_level_count {
+            let width = (size.width >> mip_level).max(1);
+            let height = (size.height >> mip_level).max(1);
+            let padded_bytes_per_row =
+                RenderDevice::align_copy_bytes_per_row((width * block_size) as usize) as u32;
+            copies.push(LevelCopy {
+                layer,
+                mip_level,
+                width,
+                height,
+                buffer_offset: buffer_size,
+                padded_bytes_per_row,
+            });
+            buffer_size += padded_bytes_per_row as u64 * height as u64;
+        }
+    }
+
+    let buffer = render_device.create_buffer(&BufferDescriptor {
+        label: Some("envmap_readback_buffer"),
+        size: buffer_size,
+        usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = render_device.create_command_encoder(&CommandEncoderDescriptor {
+        label: Some("envmap_readback"),
+    });
+    for copy in &copies {
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture,
+                mip_level: copy.mip_level,
+                origin: Origin3d {
+                    x: 0,
+                    y: 0,
+                    z: copy.layer,
+                },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: copy.buffer_offset,
+                    bytes_per_row: Some(copy.padded_bytes_per_row),
+                    rows_per_image: Some(copy.height),
+                },
+            },
+            Extent3d {
+                width: copy.width,
+                height: copy.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+    render_queue.submit([encoder.finish()]);
+
+    let slice = buffer.slice(..);
+    let (sender, receiver) = mpsc::channel();
+    render_device.map_buffer(&slice, MapMode::Read, move |result| {
+        sender.send(result).ok();
+    });
+    render_device.poll(Maintain::Wait);
+    receiver
+        .recv()
+        .unwrap()
+        .expect("Failed to map the readback buffer");
+
+    let mut data = Vec::new();
+    {
+        let mapped = slice.get_mapped_range();
+        for copy in &copies {
+            let row_length = (copy.width * block_size) as usize;
+            for row in 0..copy.height as u64 {
+                let start = (copy.buffer_offset + row * copy.padded_bytes_per_row as u64) as usize;
+                data.extend_from_slice(&mapped[start..start + row_length]);
+            }
+        }
+    }
+    buffer.unmap();
+
+    let layers = size.depth_or_array_layers;
+    let view_dimension = if layers == FACE_COUNT {
+        Some(TextureViewDimension::Cube)
+    } else if layers % FACE_COUNT == 0 {
+        Some(TextureViewDimension::CubeArray)
+    } else {
+        None
+    };
+
+    Image {
+        data,
+        texture_descriptor: TextureDescriptor {
+            label: None,
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        sampler: ImageSampler::Default,
+        texture_view_descriptor: view_dimension.map(|dimension| TextureViewDescriptor {
+            dimension: Some(dimension),
+            ..Default::default()
+        }),
+        asset_usage: RenderAssetUsages::default(),
+    }
+}
@@ -0,0 +1,66 @@
+// RGBM and RGBD pack HDR colors into RGBA8 by storing a per-texel multiplier
+// (RGBM) or divisor (RGBD) in alpha. `range` is the largest representable
+// value and has to be known by the decoder, so it's written to the file's
+// key/value metadata.
+
+/// Range commonly used for RGBM encoded environment maps.
+pub const DEFAULT_RGBM_RANGE: f32 = 6.0;
+/// Range commonly used for RGBD encoded environment maps.
+pub const DEFAULT_RGBD_RANGE: f32 = 255.0;
+
+#[inline]
+fn unorm8(v: f32) -> u8 {
+    (v.clamp(0.0, 1.0) * 255.0 + 0.5) as u8
+}
+
+/// Encodes linear RGB as RGBM, decoded with `rgb * a * range`.
+#[inline]
+pub fn float3_to_rgbm(rgb: &[f32], range: f32) -> [u8; 4] {
+    let max_rgb = rgb[0].max(rgb[1]).max(rgb[2]).max(0.0);
+    // Round the multiplier up so the color channels never exceed 1.
+    let m = ((max_rgb / range).clamp(1.0 / 255.0, 1.0) * 255.0).ceil() / 255.0;
+    let scale = 1.0 / (m * range);
+    [
+        unorm8(rgb[0] * scale),
+        unorm8(rgb[1] * scale),
+        unorm8(rgb[2] * scale),
+        unorm8(m),
+    ]
+}
+
+#[inline]
+pub fn rgbm_to_float3(v: [u8; 4], range: f32) -> [f32; 3] {
+    let scale = v[3] as f32 / 255.0 * range / 255.0;
+    [
+        v[0] as f32 * scale,
+        v[1] as f32 * scale,
+        v[2] as f32 * scale,
+    ]
+}
+
+/// Encodes linear RGB as RGBD, decoded with `rgb * (range / 255) / a`.
+///
+/// Compared to RGBM, precision is concentrated in the dark end of the range.
+#[inline]
+pub fn float3_to_rgbd(rgb: &[f32], range: f32) -> [u8; 4] {
+    let max_rgb = rgb[0].max(rgb[1]).max(rgb[2]).max(f32::MIN_POSITIVE);
+    let d = ((range / max_rgb).max(1.0).floor() / 255.0).clamp(1.0 / 255.0, 1.0);
+    let scale = d * 255.0 / range;
+    [
+        unorm8(rgb[0] * scale),
+        unorm8(rgb[1] * scale),
+        unorm8(rgb[2] * scale),
+        unorm8(d),
+    ]
+}
+
+#[inline]
+pub fn rgbd_to_float3(v: [u8; 4], range: f32) -> [f32; 3] {
+    let d = (v[3] as f32 / 255.0).max(1.0 / 255.0);
+    let scale = range / 255.0 / d / 255.0;
+    [
+        v[0] as f32 * scale,
+        v[1] as f32 * scale,
+        v[2] as f32 * scale,
+    ]
+}
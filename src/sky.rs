@@ -0,0 +1,357 @@
+use std::{
+    f32::consts::PI,
+    io::{Error, ErrorKind},
+};
+
+use bevy::{math::Vec3, prelude::Image};
+
+use crate::cubemap::cubemap_from_fn;
+
+/// Parameters for the Nishita-style atmospheric scattering sky model.
+///
+/// Distances are in meters and coefficients in 1/m. The defaults describe an
+/// Earth-like atmosphere using the coefficients from Hillaire, "A Scalable and
+/// Production Ready Sky and Atmosphere Rendering Technique" (2020).
+#[derive(Clone, Debug)]
+pub struct NishitaSky {
+    pub planet_radius: f32,
+    pub atmosphere_radius: f32,
+    /// Height of the viewer above the planet surface.
+    pub observer_height: f32,
+    pub rayleigh_scattering: Vec3,
+    pub rayleigh_scale_height: f32,
+    pub mie_scattering: f32,
+    pub mie_absorption: f32,
+    pub mie_scale_height: f32,
+    /// Henyey-Greenstein asymmetry parameter of the Mie phase function.
+    pub mie_anisotropy: f32,
+    pub ozone_absorption: Vec3,
+    /// Altitude of the center of the ozone layer.
+    pub ozone_center_height: f32,
+    /// Half width of the tent-shaped ozone density profile.
+    pub ozone_half_width: f32,
+    /// Albedo of the planet surface, lighting the atmosphere from below.
+    pub ground_albedo: Vec3,
+    /// Direction towards the sun, y is up.
+    pub sun_direction: Vec3,
+    /// Illuminance of the sun at the top of the atmosphere.
+    pub sun_intensity: f32,
+    /// Angular radius of the sun disc in radians.
+    pub sun_angular_radius: f32,
+    /// Draw the sun disc into the sky. Its radiance is chosen so that the disc
+    /// integrates to `sun_intensity`.
+    pub render_sun_disc: bool,
+    /// Ray marching steps along each view ray, at least 1.
+    pub view_samples: u32,
+    /// Ray marching steps along each ray towards the sun, at least 1.
+    pub light_samples: u32,
+    /// Include the multiple scattering approximation on top of single scattering.
+    pub multiple_scattering: bool,
+}
+
+impl Default for NishitaSky {
+    fn default() -> Self {
+        Self {
+            planet_radius: 6_360e3,
+            atmosphere_radius: 6_460e3,
+            observer_height: 200.0,
+            rayleigh_scattering: Vec3::new(5.802e-6, 13.558e-6, 33.1e-6),
+            rayleigh_scale_height: 8e3,
+            mie_scattering: 3.996e-6,
+            mie_absorption: 4.4e-6,
+            mie_scale_height: 1.2e3,
+            mie_anisotropy: 0.8,
+            ozone_absorption: Vec3::new(0.650e-6, 1.881e-6, 0.085e-6),
+            ozone_center_height: 25e3,
+            ozone_half_width: 15e3,
+            ground_albedo: Vec3::splat(0.3),
+            sun_direction: Vec3::new(0.0, 0.5, -1.0).normalize(),
+            sun_intensity: 20.0,
+            sun_angular_radius: 0.004_65,
+            render_sun_disc: true,
+            view_samples: 32,
+            light_samples: 8,
+            multiple_scattering: true,
+        }
+    }
+}
+
+/// Scattering and extinction coefficients at a point in the atmosphere.
+struct Medium {
+    rayleigh: Vec3,
+    mie: f32,
+    extinction: Vec3,
+}
+
+impl NishitaSky {
+    /// Fails with `ErrorKind::InvalidInput` unless both sample counts are at
+    /// least 1.
+    pub fn validate(&self) -> std::io::Result<()> {
+        if self.view_samples == 0 || self.light_samples == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "The sky needs at least one view and light sample, not {} and {}",
+                    self.view_samples, self.light_samples
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Bakes the sky into an `Rgba16Float` cubemap with `face_size` texels per
+    /// side. Fails on invalid settings, see [`NishitaSky::validate`].
+    pub fn bake(&self, face_size: u32) -> std::io::Result<Image> {
+        self.validate()?;
+        let ms_lut = self
+            .multiple_scattering
+            .then(|| MultipleScatteringLut::new(self));
+        Ok(cubemap_from_fn(face_size, |dir| {
+            let l = self.radiance_with_lut(dir, ms_lut.as_ref());
+            [l.x, l.y, l.z, 1.0]
+        }))
+    }
+
+    /// Sky radiance seen by the observer in direction `dir`.
+    ///
+    /// Prefer [`NishitaSky::bake`] when evaluating many directions, as this
+    /// rebuilds the multiple scattering table on every call.
+    pub fn radiance(&self, dir: Vec3) -> std::io::Result<Vec3> {
+        self.validate()?;
+        let ms_lut = self
+            .multiple_scattering
+            .then(|| MultipleScatteringLut::new(self));
+        Ok(self.radiance_with_lut(dir, ms_lut.as_ref()))
+    }
+
+    fn radiance_with_lut(&self, dir: Vec3, ms_lut: Option<&MultipleScatteringLut>) -> Vec3 {
+        let dir = dir.normalize();
+        let sun_dir = self.sun_direction.normalize();
+        let origin = Vec3::new(0.0, self.planet_radius + self.observer_height, 0.0);
+
+        let Some(t_atmosphere) = ray_sphere_exit(origin, dir, self.atmosphere_radius) else {
+            return Vec3::ZERO;
+        };
+        let t_ground = ray_sphere_entry(origin, dir, self.planet_radius);
+        let t_max = t_ground.unwrap_or(t_atmosphere).min(t_atmosphere);
+
+        let cos_theta = dir.dot(sun_dir);
+        let phase_r = rayleigh_phase(cos_theta);
+        let phase_m = henyey_greenstein_phase(cos_theta, self.mie_anisotropy);
+
+        let dt = t_max / self.view_samples as f32;
+        let mut transmittance = Vec3::ONE;
+        let mut radiance = Vec3::ZERO;
+        for i in 0..self.view_samples {
+            let p = origin + dir * ((i as f32 + 0.5) * dt);
+            let medium = self.medium(p);
+            let step_transmittance = (-medium.extinction * dt).exp();
+
+            let sun_transmittance = self.transmittance_to_sun(p, sun_dir);
+            let scattering = medium.rayleigh + Vec3::splat(medium.mie);
+            let mut in_scattered =
+                (medium.rayleigh * phase_r + Vec3::splat(medium.mie * phase_m)) * sun_transmittance;
+            if let Some(lut) = ms_lut {
+                let up = p.normalize();
+                in_scattered += scattering * lut.sample(self, p.length(), up.dot(sun_dir));
+            }
+
+            // Analytic integration of the in-scattering over the step, see
+            // Hillaire, "Physically Based and Unified Volumetric Rendering in Frostbite".
+            let integral = (in_scattered - in_scattered * step_transmittance) / medium.extinction;
+            radiance += transmittance * integral;
+            transmittance *= step_transmittance;
+        }
+
+        if let Some(t) = t_ground {
+            if t <= t_atmosphere {
+                let p = origin + dir * t;
+                let normal = p.normalize();
+                let irradiance =
+                    normal.dot(sun_dir).max(0.0) * self.transmittance_to_sun(p, sun_dir);
+                radiance += transmittance * irradiance * self.ground_albedo / PI;
+            }
+        } else if self.render_sun_disc && cos_theta >= self.sun_angular_radius.cos() {
+            let solid_angle = 2.0 * PI * (1.0 - self.sun_angular_radius.cos());
+            radiance += transmittance / solid_angle;
+        }
+
+        radiance * self.sun_intensity
+    }
+
+    fn medium(&self, p: Vec3) -> Medium {
+        let height = (p.length() - self.planet_radius).max(0.0);
+        let rayleigh_density = (-height / self.rayleigh_scale_height).exp();
+        let mie_density = (-height / self.mie_scale_height).exp();
+        let ozone_density =
+            (1.0 - (height - self.ozone_center_height).abs() / self.ozone_half_width).max(0.0);
+
+        let rayleigh = self.rayleigh_scattering * rayleigh_density;
+        let mie = self.mie_scattering * mie_density;
+        let extinction = rayleigh
+            + Vec3::splat((self.mie_scattering + self.mie_absorption) * mie_density)
+            + self.ozone_absorption * ozone_density;
+
+        Medium {
+            rayleigh,
+            mie,
+            extinction,
+        }
+    }
+
+    /// Transmittance from `p` to the top of the atmosphere towards `sun_dir`,
+    /// zero if the planet is in the way.
+    fn transmittance_to_sun(&self, p: Vec3, sun_dir: Vec3) -> Vec3 {
+        if ray_sphere_entry(p, sun_dir, self.planet_radius).is_some() {
+            return Vec3::ZERO;
+        }
+        let Some(t_max) = ray_sphere_exit(p, sun_dir, self.atmosphere_radius) else {
+            return Vec3::ONE;
+        };
+        let dt = t_max / self.light_samples as f32;
+        let mut optical_depth = Vec3::ZERO;
+        for i in 0..self.light_samples {
+            let sample = p + sun_dir * ((i as f32 + 0.5) * dt);
+            optical_depth += self.medium(sample).extinction * dt;
+        }
+        (-optical_depth).exp()
+    }
+}
+
+/// Multiple scattering contribution Ψ_ms as a function of altitude and sun
+/// zenith angle, following Hillaire 2020. Light scattered more than once is
+/// approximated as isotropic, which turns the infinite series of scattering
+/// orders into a geometric series `L_2nd / (1 - f_ms)`.
+struct MultipleScatteringLut {
+    values: Vec<Vec3>,
+}
+
+impl MultipleScatteringLut {
+    const SIZE: usize = 32;
+    const DIRECTIONS: u32 = 64;
+    const STEPS: u32 = 20;
+
+    fn new(sky: &NishitaSky) -> Self {
+        let mut values = Vec::with_capacity(Self::SIZE * Self::SIZE);
+        for j in 0..Self::SIZE {
+            let radius = sky.planet_radius
+                + (j as f32 + 0.5) / Self::SIZE as f32
+                    * (sky.atmosphere_radius - sky.planet_radius);
+            for i in 0..Self::SIZE {
+                let cos_sun_zenith = 2.0 * (i as f32 + 0.5) / Self::SIZE as f32 - 1.0;
+                values.push(Self::compute(sky, radius, cos_sun_zenith));
+            }
+        }
+        Self { values }
+    }
+
+    fn compute(sky: &NishitaSky, radius: f32, cos_sun_zenith: f32) -> Vec3 {
+        let origin = Vec3::new(0.0, radius, 0.0);
+        let sin_sun_zenith = (1.0 - cos_sun_zenith * cos_sun_zenith).max(0.0).sqrt();
+        let sun_dir = Vec3::new(0.0, cos_sun_zenith, -sin_sun_zenith);
+        let isotropic_phase = 1.0 / (4.0 * PI);
+
+        let mut second_order = Vec3::ZERO;
+        let mut transfer = Vec3::ZERO;
+        for n in 0..Self::DIRECTIONS {
+            let dir = fibonacci_sphere(n, Self::DIRECTIONS);
+            let Some(t_atmosphere) = ray_sphere_exit(origin, dir, sky.atmosphere_radius) else {
+                continue;
+            };
+            let t_ground = ray_sphere_entry(origin, dir, sky.planet_radius);
+            let t_max = t_ground.unwrap_or(t_atmosphere).min(t_atmosphere);
+            let dt = t_max / Self::STEPS as f32;
+
+            let mut transmittance = Vec3::ONE;
+            for s in 0..Self::STEPS {
+                let p = origin + dir * ((s as f32 + 0.5) * dt);
+                let medium = sky.medium(p);
+                let scattering = medium.rayleigh + Vec3::splat(medium.mie);
+                let step_transmittance = (-medium.extinction * dt).exp();
+                let integral_factor = (Vec3::ONE - step_transmittance) / medium.extinction;
+
+                let sun_transmittance = sky.transmittance_to_sun(p, sun_dir);
+                second_order += transmittance
+                    * scattering
+                    * sun_transmittance
+                    * isotropic_phase
+                    * integral_factor;
+                transfer += transmittance * scattering * integral_factor;
+                transmittance *= step_transmittance;
+            }
+
+            if t_ground.is_some_and(|t| t <= t_atmosphere) {
+                let p = origin + dir * t_max;
+                let normal = p.normalize();
+                let irradiance =
+                    normal.dot(sun_dir).max(0.0) * sky.transmittance_to_sun(p, sun_dir);
+                second_order += transmittance * irradiance * sky.ground_albedo / PI;
+            }
+        }
+
+        // Uniform sphere integral of the isotropic phase function: 4π / N * 1 / 4π.
+        let second_order = second_order / Self::DIRECTIONS as f32;
+        let transfer = transfer / Self::DIRECTIONS as f32;
+        second_order / (Vec3::ONE - transfer)
+    }
+
+    fn sample(&self, sky: &NishitaSky, radius: f32, cos_sun_zenith: f32) -> Vec3 {
+        let size = Self::SIZE as f32;
+        let height_t = (radius - sky.planet_radius) / (sky.atmosphere_radius - sky.planet_radius);
+        let y = (height_t * size - 0.5).clamp(0.0, size - 1.0);
+        let x = ((cos_sun_zenith * 0.5 + 0.5) * size - 0.5).clamp(0.0, size - 1.0);
+
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(Self::SIZE - 1), (y0 + 1).min(Self::SIZE - 1));
+        let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+
+        let at = |x: usize, y: usize| self.values[y * Self::SIZE + x];
+        let top = at(x0, y0).lerp(at(x1, y0), fx);
+        let bottom = at(x0, y1).lerp(at(x1, y1), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+fn rayleigh_phase(cos_theta: f32) -> f32 {
+    3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta)
+}
+
+fn henyey_greenstein_phase(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
+
+/// Evenly distributed point `i` of `n` on the unit sphere.
+fn fibonacci_sphere(i: u32, n: u32) -> Vec3 {
+    let golden_angle = PI * (3.0 - 5f32.sqrt());
+    let y = 1.0 - 2.0 * (i as f32 + 0.5) / n as f32;
+    let r = (1.0 - y * y).sqrt();
+    let phi = golden_angle * i as f32;
+    Vec3::new(r * phi.cos(), y, r * phi.sin())
+}
+
+/// Distance along the ray to where it leaves a sphere of `radius` centered at
+/// the origin, if it does so in front of the ray origin.
+fn ray_sphere_exit(origin: Vec3, dir: Vec3, radius: f32) -> Option<f32> {
+    let b = origin.dot(dir);
+    let c = origin.dot(origin) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = -b + discriminant.sqrt();
+    (t > 0.0).then_some(t)
+}
+
+/// Distance along the ray to where it enters a sphere of `radius` centered at
+/// the origin, if the ray hits the sphere from the outside.
+fn ray_sphere_entry(origin: Vec3, dir: Vec3, radius: f32) -> Option<f32> {
+    let b = origin.dot(dir);
+    let c = origin.dot(origin) - radius * radius;
+    let discriminant = b * b - c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let t = -b - discriminant.sqrt();
+    (t > 0.0).then_some(t)
+}
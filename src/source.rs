@@ -0,0 +1,148 @@
+use bevy::{prelude::Image, render::render_resource::TextureFormat};
+
+use crate::{
+    color::{decode_texels, TransferFunction},
+    cubemap_data::CubemapData,
+    mip_level_byte_range,
+};
+
+/// Where the texels of a cubemap come from.
+///
+/// The analysis and resampling functions of this crate read their input
+/// through this trait, so they work the same on a Bevy [`Image`], six
+/// `image` crate buffers or a raw float slice. Texels are linear RGBA in the
+/// face order of [`FACE_COUNT`](crate::cubemap::FACE_COUNT).
+pub trait EnvmapSource {
+    /// Width and height of the faces of the top mip level.
+    fn face_size(&self) -> u32;
+
+    fn mip_level_count(&self) -> u32 {
+        1
+    }
+
+    /// Texels of one face of a mip level in row-major order.
+    fn face_texels(&self, face: u32, mip_level: u32) -> Vec<[f32; 4]>;
+
+    /// A single texel. The default decodes the whole face, so sources that can
+    /// address texels directly should override it.
+    fn texel(&self, face: u32, mip_level: u32, x: u32, y: u32) -> [f32; 4] {
+        let size = mip_size(self.face_size(), mip_level);
+        self.face_texels(face, mip_level)[(y * size + x) as usize]
+    }
+}
+
+/// Face size of `mip_level` in a chain starting at `face_size`.
+pub fn mip_size(face_size: u32, mip_level: u32) -> u32 {
+    (face_size >> mip_level).max(1)
+}
+
+/// Copies any source into a `Rgba16Float` cubemap image.
+pub fn source_to_image(source: &dyn EnvmapSource) -> Image {
+    CubemapData::from_source(source).to_image()
+}
+
+/// Fails unless [`Image`]s of `format` can be read as an [`EnvmapSource`],
+/// which decodes the uncompressed formats [`decode_texels`] reads. Compressed
+/// images need [`crate::color::linearize`] first.
+pub fn check_readable(format: TextureFormat) -> std::io::Result<()> {
+    decode_texels(format, &[]).map(|_| ())
+}
+
+/// Decodes texels of `format`, linearizing the color channels of `*Srgb`
+/// formats like [`crate::color::linearize`]. Panics on formats
+/// [`check_readable`] rejects.
+fn linear_texels(format: TextureFormat, data: &[u8]) -> Vec<[f32; 4]> {
+    let texels = decode_texels(format, data).unwrap_or_else(|error| panic!("{error}"));
+    if !format.is_srgb() {
+        return texels;
+    }
+    let srgb = |c: f32| TransferFunction::Srgb.to_linear(c);
+    texels
+        .into_iter()
+        .map(|[r, g, b, a]| [srgb(r), srgb(g), srgb(b), a])
+        .collect()
+}
+
+/// Uncompressed cubemap images in any format [`decode_texels`] can read,
+/// with `*Srgb` formats linearized. Reading other formats panics.
+impl EnvmapSource for Image {
+    fn face_size(&self) -> u32 {
+        self.texture_descriptor.size.width
+    }
+
+    fn mip_level_count(&self) -> u32 {
+        self.texture_descriptor.mip_level_count
+    }
+
+    fn face_texels(&self, face: u32, mip_level: u32) -> Vec<[f32; 4]> {
+        let (byte_range, _, _) = mip_level_byte_range(self, mip_level, face);
+        linear_texels(self.texture_descriptor.format, &self.data[byte_range])
+    }
+
+    fn texel(&self, face: u32, mip_level: u32, x: u32, y: u32) -> [f32; 4] {
+        let format = self.texture_descriptor.format;
+        let block_size = format.block_copy_size(None).unwrap();
+        let (byte_range, width, _) = mip_level_byte_range(self, mip_level, face);
+        let start = byte_range.start + ((y * width + x) * block_size) as usize;
+        linear_texels(format, &self.data[start..start + block_size as usize])[0]
+    }
+}
+
+/// Six faces of the same size, without mips.
+impl EnvmapSource for [image::Rgba32FImage; 6] {
+    fn face_size(&self) -> u32 {
+        self[0].width()
+    }
+
+    fn face_texels(&self, face: u32, _mip_level: u32) -> Vec<[f32; 4]> {
+        self[face as usize].pixels().map(|pixel| pixel.0).collect()
+    }
+
+    fn texel(&self, face: u32, _mip_level: u32, x: u32, y: u32) -> [f32; 4] {
+        self[face as usize].get_pixel(x, y).0
+    }
+}
+
+/// Interleaved RGBA floats laid out like [`Image`] data: every mip of face 0,
+/// then every mip of face 1, ...
+#[derive(Clone, Copy, Debug)]
+pub struct RawCubemap<'a> {
+    pub face_size: u32,
+    pub mip_level_count: u32,
+    pub data: &'a [f32],
+}
+
+impl RawCubemap<'_> {
+    /// Offset of the first float of a face's mip level in `data`.
+    fn offset(&self, face: u32, mip_level: u32) -> usize {
+        let level_len = |mip_level: u32| (mip_size(self.face_size, mip_level) as usize).pow(2) * 4;
+        let face_len = (0..self.mip_level_count).map(level_len).sum::<usize>();
+        face as usize * face_len + (0..mip_level).map(level_len).sum::<usize>()
+    }
+}
+
+impl EnvmapSource for RawCubemap<'_> {
+    fn face_size(&self) -> u32 {
+        self.face_size
+    }
+
+    fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+
+    fn face_texels(&self, face: u32, mip_level: u32) -> Vec<[f32; 4]> {
+        let offset = self.offset(face, mip_level);
+        let len = (mip_size(self.face_size, mip_level) as usize).pow(2) * 4;
+        self.data[offset..offset + len]
+            .chunks_exact(4)
+            .map(|t| [t[0], t[1], t[2], t[3]])
+            .collect()
+    }
+
+    fn texel(&self, face: u32, mip_level: u32, x: u32, y: u32) -> [f32; 4] {
+        let size = mip_size(self.face_size, mip_level);
+        let start = self.offset(face, mip_level) + ((y * size + x) * 4) as usize;
+        let t = &self.data[start..start + 4];
+        [t[0], t[1], t[2], t[3]]
+    }
+}
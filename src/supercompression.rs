@@ -0,0 +1,178 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use ktx2::SupercompressionScheme;
+
+/// Supercompression applied to each level of a KTX2 file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Supercompression {
+    None,
+    /// Zstandard at the given compression level, 0 selects zstd's default.
+    Zstandard {
+        level: i32,
+    },
+}
+
+impl Default for Supercompression {
+    fn default() -> Self {
+        Supercompression::Zstandard { level: 0 }
+    }
+}
+
+impl Supercompression {
+    /// Value of the `supercompressionScheme` header field.
+    pub fn scheme(self) -> Option<SupercompressionScheme> {
+        match self {
+            Supercompression::None => None,
+            Supercompression::Zstandard { .. } => Some(SupercompressionScheme::Zstandard),
+        }
+    }
+
+    pub fn compress(self, bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Supercompression::None => Ok(bytes.to_vec()),
+            Supercompression::Zstandard { level } => zstd::bulk::compress(bytes, level),
+        }
+    }
+
+    /// Like [`Supercompression::compress`], but stores levels smaller than
+    /// `min_compressed_size` bytes without compressing them. Every level of a
+    /// zstd file has to be a zstd frame, so those are wrapped in frames of raw
+    /// blocks, which cost a few bytes of overhead and decode as a plain copy.
+    pub fn compress_level(
+        self,
+        bytes: &[u8],
+        min_compressed_size: usize,
+    ) -> std::io::Result<Vec<u8>> {
+        match self {
+            Supercompression::Zstandard { .. } if bytes.len() < min_compressed_size => {
+                Ok(stored_zstd_frame(bytes))
+            }
+            _ => self.compress(bytes),
+        }
+    }
+}
+
+/// Largest block a zstd frame may contain.
+const ZSTD_MAX_BLOCK_SIZE: usize = 128 * 1024;
+
+/// A zstd frame holding `bytes` in raw blocks, without any compression.
+fn stored_zstd_frame(bytes: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(bytes.len() + 16);
+    frame.extend_from_slice(&0xFD2F_B528u32.to_le_bytes());
+    // Frame header descriptor: single segment, so no window descriptor, with
+    // the smallest content size field that fits.
+    let len = bytes.len();
+    if len < 256 {
+        frame.push(0x20);
+        frame.push(len as u8);
+    } else if len < 256 + 0x1_0000 {
+        frame.push(0x60);
+        frame.extend_from_slice(&((len - 256) as u16).to_le_bytes());
+    } else {
+        frame.push(0xA0);
+        frame.extend_from_slice(&(len as u32).to_le_bytes());
+    }
+
+    let mut chunks = bytes.chunks(ZSTD_MAX_BLOCK_SIZE).peekable();
+    if chunks.peek().is_none() {
+        // An empty frame still needs its last block.
+        frame.extend_from_slice(&[1, 0, 0]);
+    }
+    while let Some(chunk) = chunks.next() {
+        // Block header: last block flag, block type 0 (raw), block size.
+        let last = chunks.peek().is_none() as u32;
+        let header = last | (chunk.len() as u32) << 3;
+        frame.extend_from_slice(&header.to_le_bytes()[..3]);
+        frame.extend_from_slice(chunk);
+    }
+    frame
+}
+
+/// Undoes the supercompression of a level. BasisLZ levels need the global data
+/// of the file to be decoded and aren't supported.
+pub fn decompress(
+    scheme: Option<SupercompressionScheme>,
+    bytes: &[u8],
+    uncompressed_length: usize,
+) -> std::io::Result<Vec<u8>> {
+    match scheme {
+        None => Ok(bytes.to_vec()),
+        Some(SupercompressionScheme::Zstandard) => {
+            zstd::bulk::decompress(bytes, uncompressed_length)
+        }
+        Some(scheme) => Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("Decompressing {scheme:?} levels is not supported"),
+        )),
+    }
+}
+
+/// Vendor `supercompressionScheme` of levels compressed with a
+/// [`ZstdDictionary`]. Labeling them Zstandard would make standard loaders
+/// fail on them midway instead of rejecting the file.
+pub const ZSTD_DICTIONARY_SCHEME: u32 = 0x1_0001;
+
+/// Magic number at the start of trained zstd dictionaries.
+const DICTIONARY_MAGIC: [u8; 4] = [0x37, 0xA4, 0x30, 0xEC];
+
+/// A zstd dictionary shared by many small files, such as the hundreds of
+/// low resolution probes of a scene, which compress poorly on their own.
+///
+/// Files compressed with a dictionary use the vendor scheme
+/// [`ZSTD_DICTIONARY_SCHEME`], record the dictionary id under
+/// [`crate::metadata::ZSTD_DICTIONARY_KEY`] and can only be decoded with the
+/// same dictionary. Standard KTX2 loaders reject them, so ship the dictionary
+/// next to the files and decode them with this crate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZstdDictionary {
+    pub bytes: Vec<u8>,
+}
+
+impl ZstdDictionary {
+    /// Trains a dictionary of at most `max_size` bytes on `samples`. zstd needs
+    /// a good number of samples, so pass individual faces rather than whole files.
+    pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> std::io::Result<Self> {
+        Ok(Self {
+            bytes: zstd::dict::from_samples(samples, max_size)?,
+        })
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            bytes: std::fs::read(path)?,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, &self.bytes)
+    }
+
+    /// [`ZSTD_DICTIONARY_SCHEME`] as a header value.
+    pub fn scheme() -> SupercompressionScheme {
+        SupercompressionScheme::new(ZSTD_DICTIONARY_SCHEME).unwrap()
+    }
+
+    /// Id from the dictionary header, 0 for raw content dictionaries.
+    pub fn id(&self) -> u32 {
+        match self.bytes.get(..8) {
+            Some(header) if header[..4] == DICTIONARY_MAGIC => {
+                u32::from_le_bytes(header[4..].try_into().unwrap())
+            }
+            _ => 0,
+        }
+    }
+
+    /// Compresses at `level`, 0 selecting zstd's default.
+    pub fn compress(&self, bytes: &[u8], level: i32) -> std::io::Result<Vec<u8>> {
+        zstd::bulk::Compressor::with_dictionary(level, &self.bytes)?.compress(bytes)
+    }
+
+    pub fn decompress(&self, bytes: &[u8], uncompressed_length: usize) -> std::io::Result<Vec<u8>> {
+        zstd::bulk::Decompressor::with_dictionary(&self.bytes)?
+            .decompress(bytes, uncompressed_length)
+    }
+}
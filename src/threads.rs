@@ -0,0 +1,79 @@
+//! Threads the CPU-heavy stages run on.
+//!
+//! Prefiltering, resampling and encoding split their work with rayon, which by
+//! default uses one thread per core. Limit that for the whole process with
+//! [`set_global_thread_count`], or per call with [`Threads`], e.g. through
+//! [`EncodeSettings::threads`](crate::pipeline::EncodeSettings::threads).
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+/// Where parallel work of an operation runs.
+#[derive(Clone, Debug, Default)]
+pub enum Threads {
+    /// The pool the caller runs in, rayon's global pool unless called from
+    /// inside another pool.
+    #[default]
+    Current,
+    /// A pool of this many threads, created on first use and shared by every
+    /// later call with the same count.
+    Count(usize),
+    /// An existing pool, e.g. one shared with the rest of the application.
+    Pool(Arc<ThreadPool>),
+}
+
+impl Threads {
+    /// Runs `f` so that the parallel work it starts uses these threads.
+    pub fn install<R: Send>(&self, f: impl FnOnce() -> R + Send) -> R {
+        match self {
+            Threads::Current => f(),
+            Threads::Count(count) => thread_pool(*count).install(f),
+            Threads::Pool(pool) => pool.install(f),
+        }
+    }
+}
+
+/// Same threads for [`Threads::Pool`], otherwise the same setting.
+impl PartialEq for Threads {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Threads::Current, Threads::Current) => true,
+            (Threads::Count(a), Threads::Count(b)) => a == b,
+            (Threads::Pool(a), Threads::Pool(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// The pool of `count` threads, built once per count.
+fn thread_pool(count: usize) -> Arc<ThreadPool> {
+    static POOLS: OnceLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = OnceLock::new();
+    let count = count.max(1);
+    let mut pools = POOLS.get_or_init(Default::default).lock().unwrap();
+    pools
+        .entry(count)
+        .or_insert_with(|| {
+            Arc::new(
+                ThreadPoolBuilder::new()
+                    .num_threads(count)
+                    .thread_name(|i| format!("envmap-worker-{i}"))
+                    .build()
+                    .unwrap(),
+            )
+        })
+        .clone()
+}
+
+/// Limits rayon's global pool to `count` threads. Has to be called before
+/// anything uses the pool, and fails if it was already initialized.
+pub fn set_global_thread_count(count: usize) -> std::io::Result<()> {
+    ThreadPoolBuilder::new()
+        .num_threads(count.max(1))
+        .thread_name(|i| format!("envmap-worker-{i}"))
+        .build_global()
+        .map_err(std::io::Error::other)
+}
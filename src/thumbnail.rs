@@ -0,0 +1,61 @@
+//! Small tone-mapped previews embedded in the metadata, so asset browsers can
+//! show a file without decoding its HDR levels.
+
+use std::f32::consts::PI;
+
+use bevy::{math::Vec3, prelude::Image};
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+
+use crate::{color::TransferFunction, cubemap::sample_bilinear, cubemap_data::CubemapData};
+
+/// Renders the first layer of an `Rgba16Float` cubemap as a `width`×`width / 2`
+/// equirectangular PNG, Reinhard tone-mapped to sRGB.
+pub fn render_thumbnail(image: &Image, width: u32) -> std::io::Result<Vec<u8>> {
+    let width = width.max(2);
+    let height = width / 2;
+
+    // Sample the smallest level that still has about a texel per thumbnail
+    // pixel, larger levels would alias.
+    let descriptor = &image.texture_descriptor;
+    let mip_level = (0..descriptor.mip_level_count)
+        .take_while(|level| descriptor.size.width >> level >= width / 4)
+        .last()
+        .unwrap_or(0);
+    let data = CubemapData::from_image(image);
+    let face_size = data.mip_size(mip_level);
+    let faces = data.cube(0, mip_level);
+
+    let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+    for y in 0..height {
+        let polar = PI * (y as f32 + 0.5) / height as f32;
+        for x in 0..width {
+            // The center of the thumbnail looks towards -Z, as Bevy's cameras do.
+            let azimuth = 2.0 * PI * (x as f32 + 0.5) / width as f32 - PI;
+            let dir = Vec3::new(
+                polar.sin() * azimuth.sin(),
+                polar.cos(),
+                -polar.sin() * azimuth.cos(),
+            );
+            let texel = sample_bilinear(&faces, face_size, dir);
+            pixels.extend(texel[..3].iter().map(|c| tone_map(*c)));
+        }
+    }
+
+    rgb8_png(&pixels, width, height)
+}
+
+/// Encodes row-major 8-bit RGB pixels as PNG.
+pub(crate) fn rgb8_png(pixels: &[u8], width: u32, height: u32) -> std::io::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(pixels, width, height, ColorType::Rgb8)
+        .map_err(std::io::Error::other)?;
+    Ok(png)
+}
+
+/// Reinhard tone mapping followed by the sRGB transfer function.
+fn tone_map(linear: f32) -> u8 {
+    let linear = linear.max(0.0);
+    let srgb = TransferFunction::Srgb.from_linear(linear / (1.0 + linear));
+    (srgb * 255.0 + 0.5) as u8
+}
@@ -0,0 +1,270 @@
+//! Time-of-day sequences: one environment map per sun position, packed into a
+//! cube array so the runtime can blend between neighbouring layers.
+//!
+//! Sequences are baked either from the [`NishitaSky`] model with
+//! [`bake_sky_sequence`], or from the scene by sweeping a directional light
+//! with [`TimeOfDayBakePlugin`]. The hour of each layer is recorded under
+//! [`TIME_OF_DAY_KEY`].
+
+use std::{f32::consts::PI, path::PathBuf};
+
+use bevy::{prelude::*, tasks::AsyncComputeTaskPool};
+
+use crate::{
+    capture::{CubemapCapture, CubemapCapturePlugin, CubemapCaptured},
+    cubemap::stack_cubemap_layers,
+    metadata::{string_value, TIME_OF_DAY_KEY},
+    output::should_write,
+    pipeline::{process, EncodeSettings},
+    progress::CancellationToken,
+    sky::NishitaSky,
+};
+
+/// Evenly spaced hours of a day the sun is swept across.
+///
+/// The sun rises in +X at 6:00, culminates at `max_elevation` towards +Z at
+/// 12:00 and sets in -X at 18:00.
+#[derive(Clone, Debug)]
+pub struct TimeOfDaySequence {
+    pub start_hour: f32,
+    pub end_hour: f32,
+    /// Number of baked steps, including both ends.
+    pub steps: u32,
+    /// Elevation of the sun at noon in radians.
+    pub max_elevation: f32,
+}
+
+impl Default for TimeOfDaySequence {
+    fn default() -> Self {
+        Self {
+            start_hour: 6.0,
+            end_hour: 18.0,
+            steps: 7,
+            max_elevation: 60f32.to_radians(),
+        }
+    }
+}
+
+impl TimeOfDaySequence {
+    /// Hour of every step.
+    pub fn hours(&self) -> Vec<f32> {
+        if self.steps <= 1 {
+            return vec![self.start_hour];
+        }
+        (0..self.steps)
+            .map(|step| {
+                let t = step as f32 / (self.steps - 1) as f32;
+                self.start_hour + (self.end_hour - self.start_hour) * t
+            })
+            .collect()
+    }
+
+    /// Unit direction towards the sun at `hour`.
+    pub fn sun_direction(&self, hour: f32) -> Vec3 {
+        let angle = (hour - 6.0) / 12.0 * PI;
+        Vec3::new(
+            angle.cos(),
+            angle.sin() * self.max_elevation.sin(),
+            angle.sin() * self.max_elevation.cos(),
+        )
+        .normalize()
+    }
+}
+
+/// Bakes `sky` at every step of `sequence` into a cube array, one layer per step.
+/// Fails on invalid sky settings, see [`NishitaSky::validate`].
+pub fn bake_sky_sequence(
+    sky: &NishitaSky,
+    sequence: &TimeOfDaySequence,
+    face_size: u32,
+) -> std::io::Result<Image> {
+    let steps = sequence
+        .hours()
+        .into_iter()
+        .map(|hour| {
+            NishitaSky {
+                sun_direction: sequence.sun_direction(hour),
+                ..sky.clone()
+            }
+            .bake(face_size)
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    Ok(stack_cubemap_layers(&steps.iter().collect::<Vec<_>>()))
+}
+
+/// Metadata value listing the hour of each step, for [`TIME_OF_DAY_KEY`].
+pub fn time_of_day_value(hours: &[f32]) -> Vec<u8> {
+    let hours = hours.iter().map(f32::to_string).collect::<Vec<_>>();
+    string_value(&hours.join(","))
+}
+
+/// Processes every step with `settings` and writes them as one cube array,
+/// recording `hours` in the metadata.
+pub fn encode_sequence(
+    steps: &[Image],
+    hours: &[f32],
+    output_path: &std::path::Path,
+    settings: &EncodeSettings,
+) -> std::io::Result<()> {
+    if steps.len() != hours.len() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "Time-of-day sequence has {} steps but {} hours",
+                steps.len(),
+                hours.len()
+            ),
+        ));
+    }
+    if !should_write(output_path, settings.overwrite)? {
+        return Ok(());
+    }
+    let cancel = CancellationToken::new();
+    let steps = steps
+        .iter()
+        .map(|step| process(step, settings, &(), &cancel))
+        .collect::<Result<Vec<_>, _>>()?;
+    let array = stack_cubemap_layers(&steps.iter().collect::<Vec<_>>());
+    let settings = settings
+        .clone()
+        .with_metadata(TIME_OF_DAY_KEY, time_of_day_value(hours));
+    crate::write_ktx2(&array, output_path, &settings)
+}
+
+/// Bakes [`BakeTimeOfDay`] requests, adding [`CubemapCapturePlugin`] if needed.
+pub struct TimeOfDayBakePlugin;
+
+impl Plugin for TimeOfDayBakePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.is_plugin_added::<CubemapCapturePlugin>() {
+            app.add_plugins(CubemapCapturePlugin);
+        }
+        app.add_event::<BakeTimeOfDay>()
+            .init_resource::<TimeOfDayBakes>()
+            .add_systems(Update, (start_time_of_day_bakes, advance_time_of_day_bakes));
+    }
+}
+
+/// Requests a time-of-day sequence captured at `probe`, pointing `light`
+/// (usually a `DirectionalLight`) away from the sun at every step. The light's
+/// transform is restored afterwards.
+#[derive(Event, Clone, Debug)]
+pub struct BakeTimeOfDay {
+    pub light: Entity,
+    /// Entity whose `GlobalTransform` the scene is captured from.
+    pub probe: Entity,
+    pub sequence: TimeOfDaySequence,
+    pub face_size: u32,
+    /// Entities hidden while capturing, e.g. the player.
+    pub exclude: Vec<Entity>,
+    pub output_path: PathBuf,
+    pub settings: EncodeSettings,
+}
+
+/// A running scene bake and the steps captured so far.
+struct TimeOfDayBake {
+    request: BakeTimeOfDay,
+    hours: Vec<f32>,
+    steps: Vec<Image>,
+    light_transform: Transform,
+}
+
+#[derive(Resource, Default)]
+struct TimeOfDayBakes(Vec<TimeOfDayBake>);
+
+/// Points `light` along the sunlight of `hour` and captures the next step.
+fn capture_step(
+    commands: &mut Commands,
+    lights: &mut Query<&mut Transform>,
+    bake: &TimeOfDayBake,
+    hour: f32,
+) {
+    if let Ok(mut transform) = lights.get_mut(bake.request.light) {
+        let sun = bake.request.sequence.sun_direction(hour);
+        let up = if sun.y.abs() > 0.999 {
+            Vec3::Z
+        } else {
+            Vec3::Y
+        };
+        *transform = transform.looking_to(-sun, up);
+    }
+    commands.entity(bake.request.probe).insert(CubemapCapture {
+        face_size: bake.request.face_size,
+        exclude: bake.request.exclude.clone(),
+    });
+}
+
+fn start_time_of_day_bakes(
+    mut commands: Commands,
+    mut requests: EventReader<BakeTimeOfDay>,
+    mut bakes: ResMut<TimeOfDayBakes>,
+    mut lights: Query<&mut Transform>,
+) {
+    for request in requests.read() {
+        let Ok(light_transform) = lights.get(request.light).map(|transform| *transform) else {
+            error!("Time-of-day light {:?} has no Transform", request.light);
+            continue;
+        };
+        let bake = TimeOfDayBake {
+            hours: request.sequence.hours(),
+            request: request.clone(),
+            steps: Vec::new(),
+            light_transform,
+        };
+        capture_step(&mut commands, &mut lights, &bake, bake.hours[0]);
+        bakes.0.push(bake);
+    }
+}
+
+fn advance_time_of_day_bakes(
+    mut commands: Commands,
+    mut captured: EventReader<CubemapCaptured>,
+    mut bakes: ResMut<TimeOfDayBakes>,
+    mut images: ResMut<Assets<Image>>,
+    mut lights: Query<&mut Transform>,
+) {
+    for event in captured.read() {
+        let Some(index) = bakes
+            .0
+            .iter()
+            .position(|bake| bake.request.probe == event.entity)
+        else {
+            continue;
+        };
+        let Some(image) = images.remove(&event.image) else {
+            continue;
+        };
+
+        let bake = &mut bakes.0[index];
+        bake.steps.push(image);
+        if let Some(&hour) = bake.hours.get(bake.steps.len()) {
+            capture_step(&mut commands, &mut lights, bake, hour);
+            continue;
+        }
+
+        let bake = bakes.0.remove(index);
+        if let Ok(mut transform) = lights.get_mut(bake.request.light) {
+            *transform = bake.light_transform;
+        }
+        AsyncComputeTaskPool::get()
+            .spawn(async move {
+                let request = bake.request;
+                match encode_sequence(
+                    &bake.steps,
+                    &bake.hours,
+                    &request.output_path,
+                    &request.settings,
+                ) {
+                    Ok(()) => info!(
+                        "Baked time-of-day sequence {}",
+                        request.output_path.display()
+                    ),
+                    Err(e) => error!(
+                        "Failed to bake time-of-day sequence {}: {e}",
+                        request.output_path.display()
+                    ),
+                }
+            })
+            .detach();
+    }
+}
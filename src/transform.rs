@@ -0,0 +1,44 @@
+use bevy::{math::Quat, prelude::Image};
+
+use crate::{
+    cubemap::{sample_bilinear, FACE_COUNT},
+    cubemap_data::CubemapData,
+    source::{mip_size, source_to_image, EnvmapSource},
+};
+
+/// Rotates the environment of a linear cubemap by `rotation`, so that what
+/// was visible in direction `d` ends up in direction `rotation * d`. Every mip
+/// level is resampled from the same level of the input, which keeps
+/// prefiltered mip chains valid. Returns a `Rgba16Float` cubemap.
+pub fn rotate_cubemap(source: &dyn EnvmapSource, rotation: Quat) -> Image {
+    if rotation == Quat::IDENTITY {
+        return source_to_image(source);
+    }
+
+    let inverse = rotation.inverse();
+    let levels = (0..source.mip_level_count())
+        .map(|mip_level| {
+            (0..FACE_COUNT)
+                .map(|face| source.face_texels(face, mip_level))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let face_size = source.face_size();
+    let mut rotated = CubemapData::new(face_size, source.mip_level_count(), 1);
+    rotated.fill(|_, mip_level, dir| {
+        let size = mip_size(face_size, mip_level);
+        sample_bilinear(&levels[mip_level as usize], size, inverse * dir)
+    });
+    rotated.to_image()
+}
+
+/// Rotation for [`rotate_cubemap`] that straightens the horizon of a panorama
+/// shot with a tilted camera, looking up by `pitch` and rolled clockwise by
+/// `roll` radians, both as seen looking towards -Z. Compose it with a yaw on
+/// the left, e.g. `Quat::from_rotation_y(yaw) * horizon_correction(pitch, roll)`.
+pub fn horizon_correction(pitch: f32, roll: f32) -> Quat {
+    // The capture shows the world rotated by the inverse of the camera
+    // orientation, so rotating by the orientation undoes it.
+    Quat::from_rotation_x(pitch) * Quat::from_rotation_z(-roll)
+}
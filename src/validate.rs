@@ -0,0 +1,712 @@
+//! Checks KTX2 files against the constraints of the specification, so our own
+//! output (and any other file) can be verified without external tools.
+//!
+//! <https://github.khronos.org/KTX-Specification/>
+
+use std::{fmt, path::Path};
+
+use crate::{
+    cubemap::ALL_FACES,
+    dfd::parse_dfd,
+    metadata::{CUBEMAP_INCOMPLETE_KEY, ORIENTATION_KEY},
+    orientation::Orientation,
+    supercompression::{decompress, ZSTD_DICTIONARY_SCHEME},
+};
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+const HEADER_LENGTH: usize = 80;
+const LEVEL_INDEX_LENGTH: usize = 24;
+
+/// Keys with the reserved `KTX` / `ktx` prefix defined by the specification.
+const KNOWN_RESERVED_KEYS: &[&str] = &[
+    "KTXanimData",
+    "KTXastcDecodeMode",
+    "KTXcubemapIncomplete",
+    "KTXdxgiFormat__",
+    "KTXglFormat",
+    "KTXmetalPixelFormat",
+    "KTXorientation",
+    "KTXswizzle",
+    "KTXwriter",
+    "KTXwriterScParams",
+];
+
+/// How badly a file breaks the specification.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Allowed, but likely unintended or unsupported by loaders.
+    Warning,
+    /// Violates a constraint of the specification.
+    Error,
+}
+
+/// One violated constraint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Issue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+/// True if none of `issues` is an error.
+pub fn is_valid(issues: &[Issue]) -> bool {
+    issues.iter().all(|issue| issue.severity != Severity::Error)
+}
+
+/// Reads and [`validate`]s a file.
+pub fn validate_file(path: &Path) -> std::io::Result<Vec<Issue>> {
+    Ok(validate(&std::fs::read(path)?))
+}
+
+/// Size in bytes and width and height in texels of a texel block, for the
+/// formats whose layout is known without looking at the Data-Format Descriptor.
+struct FormatInfo {
+    type_size: u32,
+    block_size: u32,
+    block_width: u32,
+    block_height: u32,
+}
+
+fn format_info(vk_format: u32) -> Option<FormatInfo> {
+    let (type_size, block_size, block_width, block_height) = match vk_format {
+        // R8G8B8A8_UNORM, R8G8B8A8_SRGB
+        37 | 43 => (1, 4, 1, 1),
+        // R16G16B16A16_SFLOAT
+        97 => (2, 8, 1, 1),
+        // R32G32B32A32_SFLOAT
+        109 => (4, 16, 1, 1),
+        // B10G11R11_UFLOAT_PACK32, E5B9G9R9_UFLOAT_PACK32
+        122 | 123 => (4, 4, 1, 1),
+        // BC6H_UFLOAT_BLOCK, BC6H_SFLOAT_BLOCK
+        143 | 144 => (1, 16, 4, 4),
+        _ => return None,
+    };
+    Some(FormatInfo {
+        type_size,
+        block_size,
+        block_width,
+        block_height,
+    })
+}
+
+struct Validator<'a> {
+    bytes: &'a [u8],
+    issues: Vec<Issue>,
+}
+
+impl<'a> Validator<'a> {
+    fn error(&mut self, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Error,
+            message,
+        });
+    }
+
+    fn warning(&mut self, message: String) {
+        self.issues.push(Issue {
+            severity: Severity::Warning,
+            message,
+        });
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    /// The `length` bytes at `offset`, or `None` with an error if they run past
+    /// the end of the file.
+    fn region(&mut self, what: &str, offset: u64, length: u64) -> Option<&'a [u8]> {
+        let bytes = self.bytes;
+        let region = usize::try_from(offset)
+            .ok()
+            .zip(usize::try_from(length).ok())
+            .and_then(|(offset, length)| bytes.get(offset..offset.checked_add(length)?));
+        if region.is_none() {
+            self.error(format!(
+                "{what} at {offset}..{} runs past the end of the {}-byte file",
+                offset.saturating_add(length),
+                bytes.len()
+            ));
+        }
+        region
+    }
+}
+
+/// Checks `bytes` against the KTX2 specification: header fields, index table
+/// ordering and alignment, agreement of the Data-Format Descriptor with the
+/// header, key/value data layout, level sizes and cubemap completeness.
+/// An empty result means the file is valid.
+pub fn validate(bytes: &[u8]) -> Vec<Issue> {
+    let mut v = Validator {
+        bytes,
+        issues: Vec::new(),
+    };
+
+    if bytes.len() < HEADER_LENGTH || bytes[..12] != IDENTIFIER {
+        v.error("Missing the KTX2 identifier".to_string());
+        return v.issues;
+    }
+
+    let vk_format = v.u32_at(12);
+    let type_size = v.u32_at(16);
+    let width = v.u32_at(20);
+    let height = v.u32_at(24);
+    let depth = v.u32_at(28);
+    let layer_count = v.u32_at(32);
+    let face_count = v.u32_at(36);
+    let level_count = v.u32_at(40);
+    let scheme = v.u32_at(44);
+    let dfd_offset = v.u32_at(48) as u64;
+    let dfd_length = v.u32_at(52) as u64;
+    let kvd_offset = v.u32_at(56) as u64;
+    let kvd_length = v.u32_at(60) as u64;
+    let sgd_offset = v.u64_at(64);
+    let sgd_length = v.u64_at(72);
+
+    // Header fields
+    let format = format_info(vk_format);
+    if let Some(format) = &format {
+        if type_size != format.type_size {
+            v.error(format!(
+                "typeSize is {type_size}, but vkFormat {vk_format} needs {}",
+                format.type_size
+            ));
+        }
+    } else if vk_format == 0 && type_size != 1 {
+        v.error(format!(
+            "typeSize must be 1 for VK_FORMAT_UNDEFINED, not {type_size}"
+        ));
+    }
+    if width == 0 {
+        v.error("pixelWidth must not be 0".to_string());
+    }
+    if height == 0 && depth != 0 {
+        v.error("pixelDepth must be 0 when pixelHeight is 0".to_string());
+    }
+    match face_count {
+        1 => {}
+        6 => {
+            if width != height {
+                v.error(format!(
+                    "Cubemap faces must be square, not {width}x{height}"
+                ));
+            }
+            if depth != 0 {
+                v.error(format!("Cubemaps must have a pixelDepth of 0, not {depth}"));
+            }
+        }
+        _ => v.error(format!("faceCount must be 1 or 6, not {face_count}")),
+    }
+    let max_dimension = width.max(height).max(depth).max(1);
+    let max_levels = 32 - max_dimension.leading_zeros();
+    if level_count > max_levels {
+        v.error(format!(
+            "levelCount is {level_count}, but a {max_dimension}-texel image has at most {max_levels} levels"
+        ));
+    }
+    match scheme {
+        0 | 2 | 3 => {}
+        1 => {
+            if vk_format != 0 {
+                v.error("BasisLZ supercompression needs VK_FORMAT_UNDEFINED".to_string());
+            }
+        }
+        ZSTD_DICTIONARY_SCHEME => v.error(
+            "Levels are compressed with a zstd dictionary, which standard loaders can't decode"
+                .to_string(),
+        ),
+        0x10000..=0x1FFFF => v.warning(format!("Vendor supercompression scheme {scheme:#x}")),
+        _ => v.error(format!("Unknown supercompressionScheme {scheme}")),
+    }
+
+    // Index table ordering: level index, DFD, KVD, SGD, then the levels.
+    let level_index_count = level_count.max(1) as usize;
+    let level_index_end = (HEADER_LENGTH + level_index_count * LEVEL_INDEX_LENGTH) as u64;
+    if (bytes.len() as u64) < level_index_end {
+        v.error("Level index runs past the end of the file".to_string());
+        return v.issues;
+    }
+    if dfd_offset != level_index_end {
+        v.error(format!(
+            "dfdByteOffset is {dfd_offset}, but the DFD must follow the level index at {level_index_end}"
+        ));
+    }
+    if dfd_offset % 4 != 0 {
+        v.error(format!("dfdByteOffset {dfd_offset} is not 4-byte aligned"));
+    }
+    let mut data_start = dfd_offset + dfd_length;
+    if kvd_length == 0 {
+        if kvd_offset != 0 {
+            v.error("kvdByteOffset must be 0 without key/value data".to_string());
+        }
+    } else {
+        if kvd_offset != data_start {
+            v.error(format!(
+                "kvdByteOffset is {kvd_offset}, but the key/value data must follow the DFD at {data_start}"
+            ));
+        }
+        if kvd_offset % 4 != 0 {
+            v.error(format!("kvdByteOffset {kvd_offset} is not 4-byte aligned"));
+        }
+        data_start = kvd_offset + kvd_length;
+    }
+    if sgd_length == 0 {
+        if sgd_offset != 0 {
+            v.error("sgdByteOffset must be 0 without supercompression global data".to_string());
+        }
+    } else {
+        if sgd_offset % 8 != 0 || sgd_offset < data_start {
+            v.error(format!(
+                "sgdByteOffset {sgd_offset} must be 8-byte aligned and follow the key/value data"
+            ));
+        }
+        let Some(sgd_end) = sgd_offset.checked_add(sgd_length) else {
+            v.error(format!(
+                "Supercompression global data at {sgd_offset} with a length of {sgd_length} overflows"
+            ));
+            return v.issues;
+        };
+        data_start = sgd_end;
+        if matches!(scheme, 0 | 2 | 3) {
+            v.error(format!(
+                "supercompressionScheme {scheme} has no global data, but sgdByteLength is {sgd_length}"
+            ));
+        }
+    }
+
+    // Data-Format Descriptor
+    let mut block_size = format.as_ref().map(|format| format.block_size);
+    let mut block_dimensions = format
+        .as_ref()
+        .map(|format| (format.block_width, format.block_height));
+    if let Some(dfd_bytes) = v.region("Data-Format Descriptor", dfd_offset, dfd_length) {
+        let total_size = dfd_bytes
+            .get(..4)
+            .map(|size| u32::from_le_bytes(size.try_into().unwrap()));
+        if total_size != Some(dfd_length as u32) {
+            v.error(format!(
+                "DFD totalSize {total_size:?} does not match dfdByteLength {dfd_length}"
+            ));
+        }
+        match parse_dfd(dfd_bytes) {
+            Ok(dfd) => {
+                let dfd_block_size = dfd.bytes_planes[0] as u32;
+                let dfd_dimensions = (
+                    dfd.texel_block_dimensions[0] as u32 + 1,
+                    dfd.texel_block_dimensions[1] as u32 + 1,
+                );
+                if scheme != 1 {
+                    match block_size {
+                        Some(size) if size != dfd_block_size => v.error(format!(
+                            "DFD bytesPlane0 is {dfd_block_size}, but vkFormat {vk_format} has {size}-byte blocks"
+                        )),
+                        Some(_) => {}
+                        None => block_size = Some(dfd_block_size),
+                    }
+                }
+                match block_dimensions {
+                    Some(dimensions) if dimensions != dfd_dimensions => v.error(format!(
+                        "DFD texel block is {}x{}, but vkFormat {vk_format} has {}x{} blocks",
+                        dfd_dimensions.0, dfd_dimensions.1, dimensions.0, dimensions.1
+                    )),
+                    Some(_) => {}
+                    None => block_dimensions = Some(dfd_dimensions),
+                }
+            }
+            Err(e) => v.error(format!("Invalid Data-Format Descriptor: {e}")),
+        }
+    }
+
+    // Key/value data
+    let key_values = match v.region("Key/value data", kvd_offset, kvd_length) {
+        Some(kvd) => validate_key_values(&mut v, kvd),
+        None => Vec::new(),
+    };
+    let value = |key: &str| {
+        key_values
+            .iter()
+            .find(|(existing, _)| *existing == key)
+            .map(|(_, value)| *value)
+    };
+
+    if let Some(orientation) = value(ORIENTATION_KEY) {
+        if Orientation::parse(orientation).is_none() {
+            v.error(format!(
+                "Invalid {ORIENTATION_KEY} value {}",
+                String::from_utf8_lossy(orientation)
+            ));
+        }
+    }
+
+    // Cubemap completeness
+    if let Some(faces) = value(CUBEMAP_INCOMPLETE_KEY) {
+        let present = faces.first().copied().unwrap_or(0);
+        if faces.len() != 1 || present & !ALL_FACES != 0 || present == 0 {
+            v.error(format!(
+                "{CUBEMAP_INCOMPLETE_KEY} must be one byte with some of bits 0 to 5 set, not {faces:?}"
+            ));
+        } else {
+            if face_count != 1 {
+                v.error(format!(
+                    "Incomplete cubemaps must have a faceCount of 1, not {face_count}"
+                ));
+            }
+            if width != height {
+                v.error(format!(
+                    "Cubemap faces must be square, not {width}x{height}"
+                ));
+            }
+            let face_layers = present.count_ones();
+            if layer_count.max(1) % face_layers != 0 {
+                v.error(format!(
+                    "layerCount {layer_count} is not a multiple of the {face_layers} faces present"
+                ));
+            }
+        }
+    }
+
+    // Levels
+    let level_alignment = match (scheme, block_size) {
+        (0, Some(size)) if size > 0 => lcm(size as u64, 4),
+        _ => 1,
+    };
+    let mut previous_start = None;
+    for level in 0..level_count.max(1) {
+        let index = HEADER_LENGTH + level as usize * LEVEL_INDEX_LENGTH;
+        let offset = v.u64_at(index);
+        let length = v.u64_at(index + 8);
+        let uncompressed_length = v.u64_at(index + 16);
+
+        if offset < data_start {
+            v.error(format!(
+                "Level {level} at {offset} overlaps the data before the levels, which ends at {data_start}"
+            ));
+        }
+        if offset % level_alignment != 0 {
+            v.error(format!(
+                "Level {level} at {offset} is not {level_alignment}-byte aligned"
+            ));
+        }
+        let Some(end) = offset.checked_add(length) else {
+            v.error(format!(
+                "Level {level} at {offset} with a length of {length} overflows"
+            ));
+            continue;
+        };
+        // Smaller levels come first in the file.
+        if let Some(previous_start) = previous_start {
+            if end > previous_start {
+                v.error(format!(
+                    "Level {level} must be stored before level {}",
+                    level - 1
+                ));
+            }
+        }
+        previous_start = Some(offset);
+
+        let mut expected = None;
+        if let (Some(size), Some((block_width, block_height))) = (block_size, block_dimensions) {
+            let level_width = width.checked_shr(level).unwrap_or(0).max(1) as u64;
+            let level_height = height.checked_shr(level).unwrap_or(0).max(1) as u64;
+            let level_depth = depth.checked_shr(level).unwrap_or(0).max(1) as u64;
+            expected = level_width
+                .div_ceil(block_width as u64)
+                .checked_mul(level_height.div_ceil(block_height as u64))
+                .and_then(|texels| texels.checked_mul(level_depth))
+                .and_then(|texels| texels.checked_mul(layer_count.max(1) as u64))
+                .and_then(|texels| texels.checked_mul(face_count as u64))
+                .and_then(|blocks| blocks.checked_mul(size as u64));
+            match expected {
+                None => v.error(format!("The size of level {level} overflows")),
+                Some(expected) if scheme != 1 && uncompressed_length != expected => {
+                    v.error(format!(
+                        "Level {level} holds {uncompressed_length} bytes, but its size needs {expected}"
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+
+        let Some(data) = v.region(&format!("Level {level}"), offset, length) else {
+            continue;
+        };
+        match scheme {
+            0 if uncompressed_length != length => v.error(format!(
+                "Level {level} has a byteLength of {length} but an uncompressedByteLength of {uncompressed_length} without supercompression"
+            )),
+            1 if uncompressed_length != 0 => v.error(format!(
+                "Level {level} must have an uncompressedByteLength of 0 with BasisLZ"
+            )),
+            // uncompressedByteLength is untrusted, so don't let it size the
+            // buffer beyond the level size, whose mismatch is reported above.
+            2 if expected.is_some_and(|expected| uncompressed_length > expected) => {}
+            2 if expected.is_none() && uncompressed_length > bytes.len() as u64 => {
+                v.warning(format!(
+                    "Level {level} claims {uncompressed_length} bytes, more than the file holds, and wasn't decompressed"
+                ))
+            }
+            2 => {
+                let decompressed = decompress(
+                    Some(ktx2::SupercompressionScheme::Zstandard),
+                    data,
+                    uncompressed_length as usize,
+                );
+                match decompressed {
+                    Ok(decompressed) if decompressed.len() as u64 == uncompressed_length => {}
+                    Ok(decompressed) => v.error(format!(
+                        "Level {level} decompresses to {} bytes instead of {uncompressed_length}",
+                        decompressed.len()
+                    )),
+                    Err(e) => v.error(format!("Level {level} fails to decompress: {e}")),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    v.issues
+}
+
+/// Checks the layout of the key/value data and returns the entries that could
+/// be read, with keys that are not valid UTF-8 skipped.
+fn validate_key_values<'a>(v: &mut Validator, kvd: &'a [u8]) -> Vec<(&'a str, &'a [u8])> {
+    let mut entries = Vec::new();
+    let mut previous_key: Option<&[u8]> = None;
+    let mut offset = 0;
+    while offset < kvd.len() {
+        let Some(length) = kvd.get(offset..offset + 4) else {
+            v.error(format!("Truncated key/value entry at {offset}"));
+            return entries;
+        };
+        let length = u32::from_le_bytes(length.try_into().unwrap()) as usize;
+        let Some(entry) = kvd.get(offset + 4..offset + 4 + length) else {
+            v.error(format!(
+                "Key/value entry at {offset} runs past the key/value data"
+            ));
+            return entries;
+        };
+        let Some(key_length) = entry.iter().position(|&byte| byte == 0) else {
+            v.error(format!("Key at {offset} is not NUL-terminated"));
+            return entries;
+        };
+        let key = &entry[..key_length];
+
+        match std::str::from_utf8(key) {
+            Ok(key) => {
+                if (key.starts_with("KTX") || key.starts_with("ktx"))
+                    && !KNOWN_RESERVED_KEYS.contains(&key)
+                {
+                    v.warning(format!("Unknown key {key} with the reserved KTX prefix"));
+                }
+                entries.push((key, &entry[key_length + 1..]));
+            }
+            Err(_) => v.error(format!("Key at {offset} is not valid UTF-8")),
+        }
+        if previous_key.is_some_and(|previous| previous >= key) {
+            v.error(format!(
+                "Key {} is not sorted after the previous key",
+                String::from_utf8_lossy(key)
+            ));
+        }
+        previous_key = Some(key);
+
+        let end = offset + 4 + length;
+        let padded_end = end.next_multiple_of(4);
+        if kvd
+            .get(end..padded_end)
+            .is_some_and(|padding| padding.iter().any(|&byte| byte != 0))
+        {
+            v.warning(format!(
+                "Non-zero padding after key {}",
+                String::from_utf8_lossy(key)
+            ));
+        }
+        offset = padded_end;
+    }
+    entries
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    fn gcd(a: u64, b: u64) -> u64 {
+        if b == 0 {
+            a
+        } else {
+            gcd(b, a % b)
+        }
+    }
+    a / gcd(a, b) * b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        color::read_texels, cubemap::face_bit, generate::gradient_cubemap, ktx2_reader::KTX2File,
+        pipeline::EncodeSettings, supercompression::Supercompression, write_ktx2, OutputFormat,
+    };
+
+    fn encoded(settings: &EncodeSettings) -> Vec<u8> {
+        let image = gradient_cubemap(16, [2.0, 1.5, 1.0, 1.0], [0.1, 0.2, 0.3, 1.0]);
+        let path = std::env::temp_dir().join(format!(
+            "validate_{}_{:?}.ktx2",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        write_ktx2(&image, &path, settings).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        bytes
+    }
+
+    #[test]
+    fn own_output_is_valid() {
+        let formats = [
+            OutputFormat::Rgb9e5,
+            OutputFormat::LogLuv32,
+            OutputFormat::Rgbm { range: 6.0 },
+            OutputFormat::Rgbd { range: 255.0 },
+            OutputFormat::Rgba16Float,
+            OutputFormat::Rgba8 { range: 1.0 },
+            OutputFormat::B10g11r11,
+            OutputFormat::Bc6h,
+        ];
+        for format in formats {
+            for supercompression in [
+                Supercompression::None,
+                Supercompression::Zstandard { level: 0 },
+            ] {
+                let settings = EncodeSettings::default()
+                    .with_format(format)
+                    .with_supercompression(supercompression)
+                    .with_layer_names(&["specular"]);
+                let issues = validate(&encoded(&settings));
+                assert!(
+                    issues.is_empty(),
+                    "{format:?} with {supercompression:?}: {issues:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn incomplete_cubemap_is_valid() {
+        let settings = EncodeSettings::default().with_cubemap_faces(ALL_FACES & !face_bit(3));
+        let bytes = encoded(&settings);
+        assert_eq!(u32::from_le_bytes(bytes[32..36].try_into().unwrap()), 5);
+        assert_eq!(u32::from_le_bytes(bytes[36..40].try_into().unwrap()), 1);
+        let issues = validate(&bytes);
+        assert!(issues.is_empty(), "{issues:?}");
+    }
+
+    #[test]
+    fn detects_broken_files() {
+        let valid = encoded(&EncodeSettings::default().with_layer_names(&["specular"]));
+
+        assert!(!is_valid(&validate(&valid[..40])));
+
+        let mut wrong_face_count = valid.clone();
+        wrong_face_count[36..40].copy_from_slice(&5u32.to_le_bytes());
+        assert!(!is_valid(&validate(&wrong_face_count)));
+
+        let mut truncated = valid.clone();
+        truncated.truncate(valid.len() - 1);
+        assert!(!is_valid(&validate(&truncated)));
+
+        let mut wrong_dfd_offset = valid.clone();
+        let dfd_offset = u32::from_le_bytes(wrong_dfd_offset[48..52].try_into().unwrap());
+        wrong_dfd_offset[48..52].copy_from_slice(&(dfd_offset + 4).to_le_bytes());
+        assert!(!is_valid(&validate(&wrong_dfd_offset)));
+
+        // Lengths near u64::MAX must be reported rather than overflow or be
+        // allocated.
+        let mut overflowing_level = valid.clone();
+        overflowing_level[88..96].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(!is_valid(&validate(&overflowing_level)));
+
+        let mut huge_uncompressed_length = valid;
+        huge_uncompressed_length[96..104].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(!is_valid(&validate(&huge_uncompressed_length)));
+    }
+
+    #[test]
+    fn bc6h_output_decodes() {
+        let source = gradient_cubemap(16, [2.0, 1.5, 1.0, 1.0], [0.1, 0.2, 0.3, 1.0]);
+        let settings = EncodeSettings::default().with_format(OutputFormat::Bc6h);
+        let decoded = KTX2File::parse(&encoded(&settings))
+            .unwrap()
+            .to_image()
+            .unwrap();
+        let expected = read_texels(&source).unwrap();
+        let texels = read_texels(&decoded).unwrap();
+        assert_eq!(texels.len(), expected.len());
+        for (texel, expected) in texels.iter().zip(&expected) {
+            for c in 0..3 {
+                let error = (texel[c] - expected[c]).abs();
+                assert!(error <= expected[c] * 0.1, "{texel:?} != {expected:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn packed_outputs_decode() {
+        let source = gradient_cubemap(16, [2.0, 1.5, 1.0, 1.0], [0.1, 0.2, 0.3, 1.0]);
+        let expected = read_texels(&source).unwrap();
+        let formats = [
+            OutputFormat::LogLuv32,
+            OutputFormat::Rgbm { range: 6.0 },
+            OutputFormat::Rgbd { range: 255.0 },
+            OutputFormat::Rgba8 { range: 4.0 },
+        ];
+        for format in formats {
+            let settings = EncodeSettings::default().with_format(format);
+            let decoded = KTX2File::parse(&encoded(&settings))
+                .unwrap()
+                .to_image()
+                .unwrap();
+            let texels = read_texels(&decoded).unwrap();
+            assert_eq!(texels.len(), expected.len());
+            for (texel, expected) in texels.iter().zip(&expected) {
+                for c in 0..3 {
+                    let error = (texel[c] - expected[c]).abs();
+                    assert!(
+                        error <= expected[c] * 0.1,
+                        "{format:?}: {texel:?} != {expected:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn global_data_round_trips() {
+        let mut file = KTX2File::parse(&encoded(&EncodeSettings::default())).unwrap();
+        file.sgd_bytes = vec![1, 2, 3, 4, 5];
+        let mut bytes = Vec::new();
+        file.write(&mut bytes).unwrap();
+
+        let sgd_offset = u64::from_le_bytes(bytes[64..72].try_into().unwrap());
+        assert!(sgd_offset % 8 == 0);
+        let parsed = KTX2File::parse(&bytes).unwrap();
+        assert_eq!(parsed.sgd_bytes, file.sgd_bytes);
+        assert_eq!(parsed.levels[0].bytes, file.levels[0].bytes);
+
+        // Only BasisLZ has global data, so the layout is fine but the scheme isn't.
+        let issues = validate(&bytes);
+        assert_eq!(issues.len(), 1, "{issues:?}");
+        assert!(issues[0].message.contains("global data"), "{issues:?}");
+    }
+}
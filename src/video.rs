@@ -0,0 +1,193 @@
+//! Video files as animated environments, e.g. sky time-lapses captured as
+//! equirectangular video. Frames are decoded with FFmpeg, converted to
+//! cubemaps and written as one cube array, one layer per frame.
+//!
+//! Needs the `video` feature and the FFmpeg libraries installed.
+
+use std::{
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+use bevy::{
+    prelude::Image,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+    },
+};
+use ffmpeg_next::{
+    format::Pixel,
+    frame,
+    media::Type,
+    software::scaling::{self, Flags},
+};
+
+use crate::{
+    color::{linearize, TransferFunction},
+    cubemap::stack_cubemap_layers,
+    equirect::{Equirect, EquirectAspect},
+    metadata::{string_value, FRAME_TIMES_KEY},
+    output::should_write,
+    pipeline::{process, EncodeSettings},
+    progress::CancellationToken,
+};
+
+/// Which frames of a video are decoded, see [`decode_frames`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VideoFrames {
+    /// Keep every `step`th frame, starting with the first.
+    pub step: u32,
+    /// Stop after this many kept frames.
+    pub max_frames: Option<u32>,
+    /// Transfer function the frames are encoded with. Most LDR video is close
+    /// to sRGB, HDR video has to be encoded linearly.
+    pub transfer: TransferFunction,
+}
+
+impl Default for VideoFrames {
+    fn default() -> Self {
+        Self {
+            step: 1,
+            max_frames: None,
+            transfer: TransferFunction::Srgb,
+        }
+    }
+}
+
+fn ffmpeg_error(e: ffmpeg_next::Error) -> Error {
+    Error::other(format!("Decoding video failed: {e}"))
+}
+
+/// Decodes the frames of the best video stream of `path` selected by `frames`,
+/// as linear `Rgba16Float` 2D images together with their time in seconds.
+/// Reading stops once `frames.max_frames` are kept.
+pub fn decode_frames(path: &Path, frames: &VideoFrames) -> std::io::Result<Vec<(f32, Image)>> {
+    ffmpeg_next::init().map_err(ffmpeg_error)?;
+    let mut input = ffmpeg_next::format::input(&path).map_err(ffmpeg_error)?;
+    let stream = input
+        .streams()
+        .best(Type::Video)
+        .ok_or_else(|| Error::other(format!("{} has no video stream", path.display())))?;
+    let stream_index = stream.index();
+    let time_base = f64::from(stream.time_base()) as f32;
+    let mut decoder = ffmpeg_next::codec::context::Context::from_parameters(stream.parameters())
+        .and_then(|context| context.decoder().video())
+        .map_err(ffmpeg_error)?;
+    let mut scaler = scaling::Context::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGBA64LE,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )
+    .map_err(ffmpeg_error)?;
+
+    let max_frames = frames.max_frames.map_or(usize::MAX, |max| max as usize);
+    let mut decoded_count = 0;
+    let mut kept = Vec::new();
+    // Returns whether `max_frames` have been kept.
+    let mut receive = |decoder: &mut ffmpeg_next::decoder::Video| -> std::io::Result<bool> {
+        let mut decoded = frame::Video::empty();
+        while kept.len() < max_frames && decoder.receive_frame(&mut decoded).is_ok() {
+            let index = decoded_count;
+            decoded_count += 1;
+            if index % frames.step.max(1) != 0 {
+                continue;
+            }
+            let mut rgba = frame::Video::empty();
+            scaler.run(&decoded, &mut rgba).map_err(ffmpeg_error)?;
+            let time = decoded.timestamp().unwrap_or(0) as f32 * time_base;
+            kept.push((time, frame_to_image(&rgba, frames.transfer)?));
+        }
+        Ok(kept.len() >= max_frames)
+    };
+
+    let mut done = max_frames == 0;
+    for (stream, packet) in input.packets() {
+        if done {
+            break;
+        }
+        if stream.index() != stream_index {
+            continue;
+        }
+        decoder.send_packet(&packet).map_err(ffmpeg_error)?;
+        done = receive(&mut decoder)?;
+    }
+    if !done {
+        decoder.send_eof().map_err(ffmpeg_error)?;
+        receive(&mut decoder)?;
+    }
+    Ok(kept)
+}
+
+/// Copies a `RGBA64LE` frame, skipping the row padding, and linearizes it.
+fn frame_to_image(rgba: &frame::Video, transfer: TransferFunction) -> std::io::Result<Image> {
+    let (width, height) = (rgba.width(), rgba.height());
+    let stride = rgba.stride(0);
+    let row_bytes = width as usize * 8;
+    let data = rgba
+        .data(0)
+        .chunks(stride)
+        .take(height as usize)
+        .flat_map(|row| {
+            row[..row_bytes].chunks_exact(2).flat_map(|channel| {
+                let value = u16::from_le_bytes([channel[0], channel[1]]) as f32 / 65535.0;
+                value.to_le_bytes()
+            })
+        })
+        .collect();
+    let image = Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba32Float,
+        RenderAssetUsages::default(),
+    );
+    linearize(&image, Some(transfer))
+}
+
+/// Converts equirectangular `frames` to cubemaps of `face_size`, processes
+/// them with `settings` and writes them as one cube array, recording the time
+/// of each frame under [`FRAME_TIMES_KEY`]. Fails on an empty `frames`.
+pub fn encode_frames(
+    frames: &[(f32, Image)],
+    face_size: u32,
+    output_path: &Path,
+    settings: &EncodeSettings,
+) -> std::io::Result<()> {
+    if frames.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "No video frames to encode",
+        ));
+    }
+    if !should_write(output_path, settings.overwrite)? {
+        return Ok(());
+    }
+    let cancel = CancellationToken::new();
+    let layers = frames
+        .iter()
+        .map(|(_, frame)| {
+            let cubemap = Equirect::from_image(frame)?
+                .to_two_to_one(EquirectAspect::default())
+                .to_cubemap(face_size);
+            process(&cubemap, settings, &(), &cancel)
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let array = stack_cubemap_layers(&layers.iter().collect::<Vec<_>>());
+    let times = frames
+        .iter()
+        .map(|(time, _)| time.to_string())
+        .collect::<Vec<_>>();
+    let settings = settings
+        .clone()
+        .with_metadata(FRAME_TIMES_KEY, string_value(&times.join(",")));
+    crate::write_ktx2(&array, output_path, &settings)
+}